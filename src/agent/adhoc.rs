@@ -0,0 +1,262 @@
+//! Ad-hoc commands (XEP-0050).
+//!
+//! The slash commands (`/new`, `/forget`, `/status`, `/tier`) are also
+//! exposed as ad-hoc commands, so clients like Gajim and Conversations
+//! show them as menus with data forms (XEP-0004). Admins listed in
+//! `[agent] admin_jids` additionally get commands acting on other users'
+//! conversations.
+//!
+//! This module holds the command catalog and the state of multi-step
+//! commands; the runtime executes them with the same code as the slash
+//! commands.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a multi-step command may wait for the next form
+const SESSION_TIMEOUT_SECS: u64 = 600;
+
+/// An ad-hoc command offered by the agent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdhocCommand {
+    /// Command node (`new`, `status`, ...)
+    pub node: &'static str,
+    /// Name shown by clients
+    pub name: &'static str,
+    /// Only listed and executable for admins
+    pub admin: bool,
+}
+
+/// All commands, in menu order.
+pub const COMMANDS: [AdhocCommand; 5] = [
+    AdhocCommand {
+        node: "new",
+        name: "Start a new conversation",
+        admin: false,
+    },
+    AdhocCommand {
+        node: "forget",
+        name: "Forget my history",
+        admin: false,
+    },
+    AdhocCommand {
+        node: "status",
+        name: "Agent status",
+        admin: false,
+    },
+    AdhocCommand {
+        node: "tier",
+        name: "Model tier",
+        admin: false,
+    },
+    AdhocCommand {
+        node: "admin-session",
+        name: "Manage a conversation (admin)",
+        admin: true,
+    },
+];
+
+/// Looks up a command by node.
+pub fn find(node: &str) -> Option<&'static AdhocCommand> {
+    COMMANDS.iter().find(|c| c.node == node)
+}
+
+/// What a requester may do with ad-hoc commands
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    /// Not allowed to talk to the agent: no commands at all
+    Denied,
+    /// Commands acting on the requester's own conversation
+    User,
+    /// User commands plus admin commands
+    Admin,
+}
+
+impl Access {
+    /// Commands visible to the requester, in menu order.
+    pub fn commands(self) -> Vec<&'static AdhocCommand> {
+        COMMANDS.iter().filter(|c| self.allows(c)).collect()
+    }
+
+    /// Whether the requester may execute `command`.
+    pub fn allows(self, command: &AdhocCommand) -> bool {
+        match self {
+            Access::Denied => false,
+            Access::User => !command.admin,
+            Access::Admin => true,
+        }
+    }
+}
+
+/// Operation of the `admin-session` command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ManageAction {
+    /// Archive the session (like `/new`)
+    NewSession,
+    /// Erase history, profile and memory (like `/forget`)
+    Forget,
+}
+
+impl ManageAction {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "new" => Some(ManageAction::NewSession),
+            "forget" => Some(ManageAction::Forget),
+            _ => None,
+        }
+    }
+}
+
+/// Form a multi-step command is waiting for
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// `forget`: confirmation
+    ConfirmForget,
+    /// `tier`: tier selection
+    SelectTier,
+    /// `admin-session`: target conversation and action
+    ManageTarget,
+    /// `admin-session`: confirmation of `action` on `jid`
+    ManageConfirm { jid: String, action: ManageAction },
+}
+
+struct Session {
+    node: String,
+    /// Full JID that started the command
+    owner: String,
+    step: Step,
+    started: Instant,
+}
+
+/// Multi-step commands waiting for their next form, by session id.
+///
+/// Sessions are bound to the full JID that started them and expire after
+/// [`SESSION_TIMEOUT_SECS`]. Kept in memory only.
+pub struct AdhocSessions {
+    sessions: Mutex<HashMap<String, Session>>,
+    timeout: Duration,
+}
+
+impl AdhocSessions {
+    pub fn new() -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            timeout: Duration::from_secs(SESSION_TIMEOUT_SECS),
+        }
+    }
+
+    /// Stores the step `owner` must complete next. Reuses `session_id`
+    /// when continuing a session, otherwise creates one. Returns the id.
+    pub fn save(&self, session_id: Option<&str>, node: &str, owner: &str, step: Step) -> String {
+        let id = session_id
+            .map(String::from)
+            .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, s| s.started.elapsed() < self.timeout);
+        sessions.insert(
+            id.clone(),
+            Session {
+                node: node.to_string(),
+                owner: owner.to_string(),
+                step,
+                started: Instant::now(),
+            },
+        );
+        id
+    }
+
+    /// Removes and returns the pending step of a session. Returns None if
+    /// the session is unknown, expired, or belongs to another node or JID.
+    pub fn take(&self, session_id: &str, node: &str, owner: &str) -> Option<Step> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get(session_id)?;
+        if session.node != node || session.owner != owner {
+            return None;
+        }
+        let session = sessions.remove(session_id)?;
+        (session.started.elapsed() < self.timeout).then_some(session.step)
+    }
+}
+
+impl Default for AdhocSessions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether `jid` is a bare JID that can safely name a conversation
+/// directory (`local@domain`, no resource or path separators).
+pub fn is_conversation_jid(jid: &str) -> bool {
+    match jid.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.is_empty()
+                && !domain.contains('@')
+                && !jid
+                    .chars()
+                    .any(|c| c == '/' || c == '\\' || c.is_whitespace() || c.is_control())
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access_filters_commands() {
+        let nodes = |access: Access| access.commands().iter().map(|c| c.node).collect::<Vec<_>>();
+        assert!(nodes(Access::Denied).is_empty());
+        assert_eq!(nodes(Access::User), vec!["new", "forget", "status", "tier"]);
+        assert!(nodes(Access::Admin).contains(&"admin-session"));
+        assert!(!Access::User.allows(find("admin-session").unwrap()));
+        assert!(find("unknown").is_none());
+    }
+
+    #[test]
+    fn test_sessions_are_bound_to_owner_and_node() {
+        let sessions = AdhocSessions::new();
+        let id = sessions.save(None, "forget", "alice@localhost/phone", Step::ConfirmForget);
+
+        assert_eq!(sessions.take(&id, "forget", "alice@localhost/laptop"), None);
+        assert_eq!(sessions.take(&id, "tier", "alice@localhost/phone"), None);
+        assert_eq!(
+            sessions.take(&id, "forget", "alice@localhost/phone"),
+            Some(Step::ConfirmForget)
+        );
+        // A step can only be completed once
+        assert_eq!(sessions.take(&id, "forget", "alice@localhost/phone"), None);
+
+        let next = sessions.save(
+            Some(&id),
+            "forget",
+            "alice@localhost/phone",
+            Step::SelectTier,
+        );
+        assert_eq!(next, id);
+    }
+
+    #[test]
+    fn test_sessions_expire() {
+        let sessions = AdhocSessions {
+            sessions: Mutex::new(HashMap::new()),
+            timeout: Duration::ZERO,
+        };
+        let id = sessions.save(None, "tier", "alice@localhost/phone", Step::SelectTier);
+        assert_eq!(sessions.take(&id, "tier", "alice@localhost/phone"), None);
+    }
+
+    #[test]
+    fn test_is_conversation_jid() {
+        assert!(is_conversation_jid("alice@localhost"));
+        assert!(is_conversation_jid("lobby@conference.localhost"));
+        assert!(!is_conversation_jid("localhost"));
+        assert!(!is_conversation_jid("alice@localhost/res"));
+        assert!(!is_conversation_jid("../x@y"));
+        assert!(!is_conversation_jid("a@b@c"));
+        assert!(!is_conversation_jid("@localhost"));
+        assert!(!is_conversation_jid("alice@ localhost"));
+    }
+}
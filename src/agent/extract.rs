@@ -0,0 +1,719 @@
+//! Text extraction for non-image attachments.
+//!
+//! Turns textual files (plain text, Markdown, logs, source code), CSV,
+//! HTML, Office/OpenDocument documents and ZIP archives into plain text
+//! that can be sent to the LLM as an `InputContentBlock::Text`.
+//!
+//! The format is chosen by MIME type, then by filename extension (HTTP
+//! Upload services often serve everything as `application/octet-stream`).
+//! Output is capped to a character budget: long files keep their head and
+//! tail, since the end of a log or a CSV is often as useful as the start.
+//!
+//! PDFs are sent natively to providers that read them; [`pdf_to_text`]
+//! extracts their text layer for the others.
+//!
+//! Extraction is synchronous (zip inflate, XML parsing) — call it from
+//! `tokio::task::spawn_blocking`.
+
+use std::io::{Cursor, Read};
+
+use anyhow::{anyhow, bail, Context, Result};
+use tracing::warn;
+
+/// Default character budget for one extracted attachment (~15k tokens).
+pub const MAX_TEXT_CHARS: usize = 60_000;
+
+/// Maximum characters of PDF text sent to models that cannot read PDFs.
+const MAX_PDF_TEXT_CHARS: usize = 50_000;
+
+/// Number of CSV rows rendered in the table preview.
+const CSV_PREVIEW_ROWS: usize = 20;
+
+/// Maximum width of a CSV preview cell.
+const CSV_CELL_CHARS: usize = 40;
+
+/// Maximum number of entries listed for a ZIP archive.
+const ZIP_MAX_LISTED: usize = 200;
+
+/// Character budget for one extracted ZIP entry.
+const ZIP_ENTRY_CHARS: usize = 10_000;
+
+/// ZIP entries larger than this (uncompressed) are listed but not read.
+const ZIP_ENTRY_MAX_BYTES: u64 = 5 * 1024 * 1024;
+
+/// Bytes inspected to decide whether a "text" file is actually binary.
+const BINARY_SNIFF_BYTES: usize = 8192;
+
+/// Extractable attachment format.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextFormat {
+    /// Plain text, Markdown, logs — sent as is
+    Plain,
+    /// Source code or structured text — fenced with a language hint
+    Code(&'static str),
+    /// HTML page — converted to text
+    Html,
+    /// Comma/tab/semicolon separated values — table preview + raw data
+    Csv,
+    /// Word document (`word/document.xml`)
+    Docx,
+    /// OpenDocument text, spreadsheet or presentation (`content.xml`)
+    OpenDocument,
+    /// ZIP archive — entry listing + extraction of textual entries
+    Zip,
+}
+
+/// Detects the extractable format of a file from its MIME type and name.
+///
+/// Returns `None` for formats that cannot be turned into text (audio,
+/// video, executables, ...).
+pub fn detect_format(mime: &str, filename: &str) -> Option<TextFormat> {
+    let ext = extension(filename);
+
+    match mime {
+        "text/csv" | "text/tab-separated-values" => return Some(TextFormat::Csv),
+        "text/html" | "application/xhtml+xml" => return Some(TextFormat::Html),
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => {
+            return Some(TextFormat::Docx)
+        }
+        "application/vnd.oasis.opendocument.text"
+        | "application/vnd.oasis.opendocument.spreadsheet"
+        | "application/vnd.oasis.opendocument.presentation" => {
+            return Some(TextFormat::OpenDocument)
+        }
+        "application/zip" | "application/x-zip-compressed" => {
+            // DOCX/ODT files are ZIPs and are sometimes served as such
+            return Some(match ext.as_str() {
+                "docx" => TextFormat::Docx,
+                "odt" | "ods" | "odp" => TextFormat::OpenDocument,
+                _ => TextFormat::Zip,
+            });
+        }
+        _ => {}
+    }
+
+    if let Some(format) = format_from_extension(&ext) {
+        return Some(format);
+    }
+
+    match mime {
+        "application/json" => Some(TextFormat::Code("json")),
+        "application/xml" => Some(TextFormat::Code("xml")),
+        "application/javascript" => Some(TextFormat::Code("javascript")),
+        "application/x-yaml" | "application/yaml" => Some(TextFormat::Code("yaml")),
+        "application/toml" => Some(TextFormat::Code("toml")),
+        "application/x-sh" => Some(TextFormat::Code("bash")),
+        "application/sql" => Some(TextFormat::Code("sql")),
+        m if m.starts_with("text/") => Some(TextFormat::Plain),
+        _ => None,
+    }
+}
+
+/// Maps a lowercase extension to a format.
+fn format_from_extension(ext: &str) -> Option<TextFormat> {
+    let language = match ext {
+        "txt" | "text" | "md" | "markdown" | "rst" | "log" | "ini" | "cfg" | "conf" => {
+            return Some(TextFormat::Plain)
+        }
+        "csv" | "tsv" => return Some(TextFormat::Csv),
+        "html" | "htm" | "xhtml" => return Some(TextFormat::Html),
+        "docx" => return Some(TextFormat::Docx),
+        "odt" | "ods" | "odp" => return Some(TextFormat::OpenDocument),
+        "zip" => return Some(TextFormat::Zip),
+        "rs" => "rust",
+        "py" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "ts" => "typescript",
+        "tsx" => "tsx",
+        "jsx" => "jsx",
+        "go" => "go",
+        "java" => "java",
+        "kt" | "kts" => "kotlin",
+        "swift" => "swift",
+        "c" | "h" => "c",
+        "cpp" | "cc" | "cxx" | "hpp" => "cpp",
+        "cs" => "csharp",
+        "rb" => "ruby",
+        "php" => "php",
+        "erl" | "hrl" => "erlang",
+        "ex" | "exs" => "elixir",
+        "lua" => "lua",
+        "sh" | "bash" | "zsh" => "bash",
+        "sql" => "sql",
+        "css" => "css",
+        "json" | "jsonl" => "json",
+        "xml" | "svg" => "xml",
+        "yaml" | "yml" => "yaml",
+        "toml" => "toml",
+        "diff" | "patch" => "diff",
+        _ => return None,
+    };
+    Some(TextFormat::Code(language))
+}
+
+/// Lowercase extension of a filename, or an empty string.
+fn extension(filename: &str) -> String {
+    match filename.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => ext.to_lowercase(),
+        _ => String::new(),
+    }
+}
+
+/// Extracts the text of a file, within `max_chars` characters.
+pub fn extract_text(data: &[u8], format: &TextFormat, max_chars: usize) -> Result<String> {
+    match format {
+        TextFormat::Plain => Ok(truncate_middle(&decode_text(data)?, max_chars)),
+        TextFormat::Code(language) => {
+            let text = truncate_middle(&decode_text(data)?, max_chars);
+            Ok(format!("```{language}\n{}\n```", text.trim_end()))
+        }
+        TextFormat::Html => {
+            let text =
+                html2text::from_read(data, 100).map_err(|e| anyhow!("Cannot convert HTML: {e}"))?;
+            Ok(truncate_middle(&text, max_chars))
+        }
+        TextFormat::Csv => csv_to_text(&decode_text(data)?, max_chars),
+        TextFormat::Docx => {
+            let xml =
+                zip_entry_string(data, "word/document.xml").context("Not a valid DOCX document")?;
+            Ok(truncate_middle(&xml_to_text(&xml)?, max_chars))
+        }
+        TextFormat::OpenDocument => {
+            let xml =
+                zip_entry_string(data, "content.xml").context("Not a valid OpenDocument file")?;
+            Ok(truncate_middle(&xml_to_text(&xml)?, max_chars))
+        }
+        TextFormat::Zip => zip_to_text(data, max_chars),
+    }
+}
+
+/// Extracts the text layer of a PDF as a text part for providers without
+/// document support (Ollama). Scanned PDFs without a text layer and
+/// malformed ones yield a placeholder: `pdf_extract` can panic on broken
+/// input, which is caught here.
+pub fn pdf_to_text(data: &[u8]) -> String {
+    let text = std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem(data));
+    match text {
+        Ok(Ok(text)) if !text.trim().is_empty() => {
+            let text = text.trim();
+            if text.chars().count() > MAX_PDF_TEXT_CHARS {
+                let truncated: String = text.chars().take(MAX_PDF_TEXT_CHARS).collect();
+                format!("[PDF document, text truncated]\n{truncated}")
+            } else {
+                format!("[PDF document]\n{text}")
+            }
+        }
+        Ok(Ok(_)) => "[PDF document: no extractable text (scanned?)]".to_string(),
+        Ok(Err(e)) => {
+            warn!("PDF text extraction failed: {e}");
+            "[PDF document: text extraction failed]".to_string()
+        }
+        Err(_) => {
+            warn!("PDF text extraction panicked");
+            "[PDF document: text extraction failed]".to_string()
+        }
+    }
+}
+
+/// Decodes text as UTF-8 (lossy), rejecting binary content.
+fn decode_text(data: &[u8]) -> Result<String> {
+    let sniff = &data[..data.len().min(BINARY_SNIFF_BYTES)];
+    if sniff.contains(&0) {
+        bail!("File looks binary, not text");
+    }
+    let text = String::from_utf8_lossy(data);
+    Ok(text.strip_prefix('\u{feff}').unwrap_or(&text).to_string())
+}
+
+/// Caps `text` to `max_chars` characters, keeping the head (two thirds)
+/// and the tail (one third) around an omission marker.
+pub fn truncate_middle(text: &str, max_chars: usize) -> String {
+    let total = text.chars().count();
+    if total <= max_chars {
+        return text.to_string();
+    }
+    let head_chars = max_chars * 2 / 3;
+    let tail_chars = max_chars - head_chars;
+    let omitted = total - head_chars - tail_chars;
+
+    let head_end = text
+        .char_indices()
+        .nth(head_chars)
+        .map_or(text.len(), |(i, _)| i);
+    let tail_start = text
+        .char_indices()
+        .nth(total - tail_chars)
+        .map_or(text.len(), |(i, _)| i);
+
+    format!(
+        "{}\n\n[... {omitted} characters omitted ...]\n\n{}",
+        &text[..head_end],
+        &text[tail_start..]
+    )
+}
+
+// ── CSV ─────────────────────────────────────────────────
+
+/// Guesses the delimiter from the first line: tab, semicolon or comma.
+fn sniff_delimiter(text: &str) -> u8 {
+    let first = text.lines().next().unwrap_or("");
+    let count = |c: char| first.matches(c).count();
+    let (tabs, semicolons, commas) = (count('\t'), count(';'), count(','));
+    if tabs > 0 && tabs >= semicolons && tabs >= commas {
+        b'\t'
+    } else if semicolons > commas {
+        b';'
+    } else {
+        b','
+    }
+}
+
+/// Renders a CSV file as a summary line, a Markdown table preview of the
+/// first rows, and the raw data (truncated).
+fn csv_to_text(text: &str, max_chars: usize) -> Result<String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(sniff_delimiter(text))
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+
+    let mut preview: Vec<Vec<String>> = Vec::new();
+    let mut rows = 0usize;
+    let mut columns = 0usize;
+    for record in reader.records() {
+        let record = record.context("Invalid CSV")?;
+        rows += 1;
+        columns = columns.max(record.len());
+        if preview.len() <= CSV_PREVIEW_ROWS {
+            preview.push(record.iter().map(preview_cell).collect());
+        }
+    }
+
+    let data_rows = rows.saturating_sub(1);
+    let mut out =
+        format!("CSV: {data_rows} data rows × {columns} columns (first row treated as header)\n\n");
+
+    if let Some((header, body)) = preview.split_first() {
+        let row = |cells: &[String]| {
+            let mut cells = cells.to_vec();
+            cells.resize(columns, String::new());
+            format!("| {} |\n", cells.join(" | "))
+        };
+        out.push_str(&row(header));
+        out.push_str(&format!("|{}\n", "---|".repeat(columns)));
+        for cells in body {
+            out.push_str(&row(cells));
+        }
+        if data_rows > body.len() {
+            out.push_str(&format!(
+                "\n(preview: first {} of {data_rows} rows)\n",
+                body.len()
+            ));
+        }
+    }
+
+    let budget = max_chars
+        .saturating_sub(out.chars().count())
+        .max(max_chars / 4);
+    out.push_str(&format!(
+        "\nRaw data:\n```csv\n{}\n```",
+        truncate_middle(text, budget).trim_end()
+    ));
+    Ok(out)
+}
+
+/// Flattens and shortens a cell for the Markdown preview.
+fn preview_cell(cell: &str) -> String {
+    let flat = cell.replace(['\n', '\r'], " ").replace('|', "\\|");
+    if flat.chars().count() > CSV_CELL_CHARS {
+        let short: String = flat.chars().take(CSV_CELL_CHARS - 1).collect();
+        format!("{short}…")
+    } else {
+        flat
+    }
+}
+
+// ── Office / OpenDocument ───────────────────────────────
+
+/// Reads a UTF-8 entry of a ZIP container (DOCX, ODT).
+fn zip_entry_string(data: &[u8], name: &str) -> Result<String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data))?;
+    let entry = archive.by_name(name)?;
+    let mut xml = String::new();
+    entry
+        .take(ZIP_ENTRY_MAX_BYTES * 4)
+        .read_to_string(&mut xml)?;
+    Ok(xml)
+}
+
+/// Extracts paragraphs from WordprocessingML or OpenDocument XML.
+///
+/// Text is collected inside `w:t` (Word) and `text:p`/`text:h`
+/// (OpenDocument); paragraph ends become newlines, and tab/line-break
+/// elements become `\t`/`\n`.
+fn xml_to_text(xml: &str) -> Result<String> {
+    use quick_xml::events::Event;
+    use quick_xml::Reader;
+
+    let mut reader = Reader::from_str(xml);
+    let mut buf = Vec::new();
+    let mut out = String::new();
+    // Depth of text-bearing elements we are inside of
+    let mut text_depth = 0usize;
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref e) => {
+                if matches!(e.local_name().as_ref(), b"t" | b"p" | b"h") {
+                    text_depth += 1;
+                }
+            }
+            Event::End(ref e) => match e.local_name().as_ref() {
+                b"t" => text_depth = text_depth.saturating_sub(1),
+                b"p" | b"h" => {
+                    text_depth = text_depth.saturating_sub(1);
+                    out.push('\n');
+                }
+                _ => {}
+            },
+            Event::Empty(ref e) if text_depth > 0 || e.local_name().as_ref() == b"br" => {
+                match e.local_name().as_ref() {
+                    b"tab" => out.push('\t'),
+                    b"br" | b"cr" | b"line-break" => out.push('\n'),
+                    b"s" => out.push(' '),
+                    _ => {}
+                }
+            }
+            Event::Text(ref e) if text_depth > 0 => out.push_str(&e.unescape()?),
+            Event::CData(ref e) if text_depth > 0 => {
+                out.push_str(&String::from_utf8_lossy(e.as_ref()));
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(out.trim().to_string())
+}
+
+// ── ZIP archives ────────────────────────────────────────
+
+/// Lists the entries of a ZIP archive and extracts its textual entries,
+/// each within its own budget. Nested archives are listed, not opened.
+fn zip_to_text(data: &[u8], max_chars: usize) -> Result<String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data)).context("Not a valid ZIP archive")?;
+
+    let mut listing = Vec::new();
+    let mut total_size = 0u64;
+    for i in 0..archive.len() {
+        let entry = archive.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+        total_size += entry.size();
+        listing.push((i, entry.name().to_string(), entry.size()));
+    }
+
+    let mut out = format!(
+        "ZIP archive: {} files, {} uncompressed\n\n",
+        listing.len(),
+        human_size(total_size)
+    );
+    for (_, name, size) in listing.iter().take(ZIP_MAX_LISTED) {
+        out.push_str(&format!("- {name} ({})\n", human_size(*size)));
+    }
+    if listing.len() > ZIP_MAX_LISTED {
+        out.push_str(&format!("- ... {} more\n", listing.len() - ZIP_MAX_LISTED));
+    }
+
+    let mut skipped = 0usize;
+    for (index, name, size) in &listing {
+        let Some(format) = detect_format("", name) else {
+            continue;
+        };
+        if format == TextFormat::Zip || *size > ZIP_ENTRY_MAX_BYTES {
+            continue;
+        }
+        let remaining = max_chars.saturating_sub(out.chars().count());
+        if remaining < 200 {
+            skipped += 1;
+            continue;
+        }
+
+        let mut bytes = Vec::new();
+        archive
+            .by_index(*index)?
+            .take(ZIP_ENTRY_MAX_BYTES)
+            .read_to_end(&mut bytes)?;
+        let budget = remaining.min(ZIP_ENTRY_CHARS);
+        let text = extract_text(&bytes, &format, budget)
+            .unwrap_or_else(|e| format!("[could not extract: {e}]"));
+        out.push_str(&format!("\n--- {name} ---\n{}\n", text.trim_end()));
+    }
+    if skipped > 0 {
+        out.push_str(&format!(
+            "\n[{skipped} more textual entries not extracted: size limit reached]\n"
+        ));
+    }
+
+    Ok(out.trim_end().to_string())
+}
+
+/// Formats a byte size as a human-readable string.
+fn human_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        format!("{bytes}B")
+    } else if bytes < 1024 * 1024 {
+        format!("{}KB", bytes / 1024)
+    } else {
+        format!("{:.1}MB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Builds an in-memory ZIP archive from (name, content) pairs.
+    fn make_zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        for (name, content) in entries {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    // ── Format detection ────────────────────────────────
+
+    #[test]
+    fn test_detect_format_by_mime() {
+        assert_eq!(
+            detect_format("text/plain", "notes"),
+            Some(TextFormat::Plain)
+        );
+        assert_eq!(detect_format("text/csv", "data"), Some(TextFormat::Csv));
+        assert_eq!(detect_format("text/html", "page"), Some(TextFormat::Html));
+        assert_eq!(
+            detect_format("application/json", "data"),
+            Some(TextFormat::Code("json"))
+        );
+        assert_eq!(
+            detect_format(
+                "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+                "report"
+            ),
+            Some(TextFormat::Docx)
+        );
+        assert_eq!(
+            detect_format("application/vnd.oasis.opendocument.text", "report"),
+            Some(TextFormat::OpenDocument)
+        );
+        assert_eq!(
+            detect_format("application/zip", "bundle.zip"),
+            Some(TextFormat::Zip)
+        );
+        assert_eq!(detect_format("video/mp4", "clip.mp4"), None);
+        assert_eq!(detect_format("application/octet-stream", "a.bin"), None);
+    }
+
+    #[test]
+    fn test_detect_format_by_extension() {
+        assert_eq!(
+            detect_format("application/octet-stream", "main.rs"),
+            Some(TextFormat::Code("rust"))
+        );
+        // Extension wins over a generic text MIME type
+        assert_eq!(
+            detect_format("text/plain", "script.py"),
+            Some(TextFormat::Code("python"))
+        );
+        assert_eq!(detect_format("", "build.LOG"), Some(TextFormat::Plain));
+        assert_eq!(detect_format("", "data.tsv"), Some(TextFormat::Csv));
+        assert_eq!(
+            detect_format("application/zip", "report.docx"),
+            Some(TextFormat::Docx)
+        );
+        assert_eq!(
+            detect_format("", "sheet.ods"),
+            Some(TextFormat::OpenDocument)
+        );
+        assert_eq!(detect_format("", "md"), None);
+    }
+
+    // ── Plain text ──────────────────────────────────────
+
+    #[test]
+    fn test_extract_plain_and_code() {
+        let text = extract_text(b"\xef\xbb\xbfhello\n", &TextFormat::Plain, 100).unwrap();
+        assert_eq!(text, "hello\n");
+
+        let code = extract_text(b"fn main() {}\n", &TextFormat::Code("rust"), 100).unwrap();
+        assert_eq!(code, "```rust\nfn main() {}\n```");
+    }
+
+    #[test]
+    fn test_extract_rejects_binary() {
+        let err = extract_text(b"ELF\0\0\x01", &TextFormat::Plain, 100).unwrap_err();
+        assert!(err.to_string().contains("binary"));
+    }
+
+    #[test]
+    fn test_truncate_middle_keeps_head_and_tail() {
+        let text: String = (0..100).map(|i| format!("line {i}\n")).collect();
+        let truncated = truncate_middle(&text, 90);
+        assert!(truncated.starts_with("line 0\n"));
+        assert!(truncated.ends_with("line 99\n"));
+        assert!(truncated.contains("characters omitted"));
+        assert!(!truncated.contains("line 50\n"));
+
+        assert_eq!(truncate_middle("short", 90), "short");
+        // Multi-byte characters are never split
+        assert!(truncate_middle(&"é".repeat(50), 10).starts_with("éééééé\n"));
+    }
+
+    // ── CSV ─────────────────────────────────────────────
+
+    #[test]
+    fn test_csv_preview() {
+        let mut csv = String::from("name,age\n\"Doe, Jane\",42\nBob|Smith,7\n");
+        for i in 0..30 {
+            csv.push_str(&format!("user{i},{i}\n"));
+        }
+        let text = extract_text(csv.as_bytes(), &TextFormat::Csv, MAX_TEXT_CHARS).unwrap();
+        assert!(text.starts_with("CSV: 32 data rows × 2 columns"));
+        assert!(text.contains("| name | age |\n|---|---|\n| Doe, Jane | 42 |"));
+        assert!(text.contains("| Bob\\|Smith | 7 |"));
+        assert!(text.contains("(preview: first 20 of 32 rows)"));
+        assert!(text.contains("```csv\nname,age\n"));
+        assert!(text.contains("user29,29"));
+    }
+
+    #[test]
+    fn test_csv_semicolon_delimiter() {
+        let text = extract_text(b"a;b;c\n1;2;3\n", &TextFormat::Csv, 1000).unwrap();
+        assert!(text.starts_with("CSV: 1 data rows × 3 columns"));
+        assert!(text.contains("| 1 | 2 | 3 |"));
+    }
+
+    // ── Office / OpenDocument ───────────────────────────
+
+    #[test]
+    fn test_extract_docx() {
+        let document = br#"<?xml version="1.0" encoding="UTF-8"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main">
+  <w:body>
+    <w:p><w:r><w:t>Quarterly</w:t></w:r><w:r><w:t xml:space="preserve"> report &amp; notes</w:t></w:r></w:p>
+    <w:p><w:r><w:t>Revenue</w:t><w:tab/><w:t>42</w:t></w:r></w:p>
+  </w:body>
+</w:document>"#;
+        let docx = make_zip(&[("word/document.xml", document)]);
+        let text = extract_text(&docx, &TextFormat::Docx, 1000).unwrap();
+        assert_eq!(text, "Quarterly report & notes\nRevenue\t42");
+
+        let err = extract_text(b"not a zip", &TextFormat::Docx, 1000).unwrap_err();
+        assert!(err.to_string().contains("DOCX"));
+    }
+
+    #[test]
+    fn test_extract_odt() {
+        let content = br#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0"
+    xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0">
+  <office:body><office:text>
+    <text:h>Title</text:h>
+    <text:p>First <text:span>line</text:span><text:line-break/>second</text:p>
+  </office:text></office:body>
+</office:document-content>"#;
+        let odt = make_zip(&[
+            ("mimetype", b"application/vnd.oasis.opendocument.text"),
+            ("content.xml", content),
+        ]);
+        let text = extract_text(&odt, &TextFormat::OpenDocument, 1000).unwrap();
+        assert_eq!(text, "Title\nFirst line\nsecond");
+    }
+
+    // ── ZIP archives ────────────────────────────────────
+
+    #[test]
+    fn test_extract_zip_lists_and_extracts_entries() {
+        let inner = make_zip(&[("nested.txt", b"hidden")]);
+        let archive = make_zip(&[
+            ("README.md", b"# Project"),
+            ("src/main.rs", b"fn main() {}"),
+            ("logo.png", b"\x89PNG"),
+            ("nested.zip", &inner),
+        ]);
+        let text = extract_text(&archive, &TextFormat::Zip, MAX_TEXT_CHARS).unwrap();
+        assert!(text.starts_with("ZIP archive: 4 files"));
+        assert!(text.contains("- logo.png (4B)"));
+        assert!(text.contains("--- README.md ---\n# Project"));
+        assert!(text.contains("--- src/main.rs ---\n```rust\nfn main() {}\n```"));
+        assert!(!text.contains("--- logo.png ---"));
+        // Nested archives are listed but not opened
+        assert!(text.contains("- nested.zip"));
+        assert!(!text.contains("hidden"));
+    }
+
+    #[test]
+    fn test_extract_zip_respects_budget() {
+        let big = "x".repeat(5000);
+        let archive = make_zip(&[
+            ("a.txt", big.as_bytes()),
+            ("b.txt", big.as_bytes()),
+            ("c.txt", big.as_bytes()),
+        ]);
+        let text = extract_text(&archive, &TextFormat::Zip, 3000).unwrap();
+        assert!(text.contains("--- a.txt ---"));
+        assert!(text.contains("characters omitted"));
+        assert!(text.contains("2 more textual entries not extracted"));
+    }
+
+    // ── PDF ─────────────────────────────────────────────
+
+    /// Builds a minimal one-page PDF showing `text`.
+    fn minimal_pdf(text: &str) -> Vec<u8> {
+        let stream = format!("BT /F1 12 Tf 72 712 Td ({text}) Tj ET");
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] \
+             /Contents 4 0 R /Resources << /Font << /F1 5 0 R >> >> >>"
+                .to_string(),
+            format!("<< /Length {} >>\nstream\n{stream}\nendstream", stream.len()),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_string(),
+        ];
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::new();
+        for (i, obj) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend_from_slice(format!("{} 0 obj\n{obj}\nendobj\n", i + 1).as_bytes());
+        }
+        let xref = pdf.len();
+        pdf.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+        for offset in offsets {
+            pdf.extend_from_slice(format!("{offset:010} 00000 n \n").as_bytes());
+        }
+        pdf.extend_from_slice(
+            format!(
+                "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+                objects.len() + 1
+            )
+            .as_bytes(),
+        );
+        pdf
+    }
+
+    #[test]
+    fn test_pdf_to_text() {
+        let text = pdf_to_text(&minimal_pdf("Quarterly report"));
+        assert!(text.starts_with("[PDF document]\n"));
+        assert!(text.contains("Quarterly report"));
+
+        assert_eq!(pdf_to_text(b"pdf"), "[PDF document: text extraction failed]");
+    }
+}
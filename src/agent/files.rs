@@ -2,7 +2,9 @@
 ///
 /// Downloads files from HTTP Upload URLs, validates them (size, MIME type),
 /// and converts supported types to Anthropic API content blocks for
-/// multi-modal LLM processing.
+/// multi-modal LLM processing. Textual files, Office documents and ZIP
+/// archives are converted to text (see `agent::extract`), audio files are
+/// transcribed (see `agent::transcribe`).
 ///
 /// All I/O in this module uses `tokio::fs` to avoid blocking the async
 /// runtime (images/PDFs can be several MB).
@@ -15,6 +17,10 @@ use reqwest::Client;
 use tokio::sync::Semaphore;
 use tracing::{debug, info, warn};
 
+use crate::agent::extract::{self, MAX_TEXT_CHARS};
+use crate::agent::images;
+use crate::agent::transcribe;
+use crate::config::AttachmentsConfig;
 use crate::llm::{DocumentSource, ImageSource, InputContentBlock};
 
 /// Maximum file size: 25 MB
@@ -26,10 +32,16 @@ const DOWNLOAD_TIMEOUT_SECS: u64 = 30;
 /// File category based on MIME type
 #[derive(Debug, Clone, PartialEq)]
 pub enum FileCategory {
-    /// Image file (jpeg, png, gif, webp) — sent as image content block
+    /// Image file (jpeg, png, gif, webp; bmp, tiff, heic converted) — sent
+    /// as image content block
     Image,
     /// Document file (pdf) — sent as document content block
     Document,
+    /// Text-extractable file (text, code, CSV, DOCX/ODT, ZIP) — sent as text
+    Text,
+    /// Audio file (voice messages) — transcribed and sent as text when
+    /// `[attachments.transcription]` is configured
+    Audio,
     /// Unsupported type — stored but not sent to LLM
     Other,
 }
@@ -105,6 +117,19 @@ impl FileDownloader {
     ///
     /// Returns a `DownloadedFile` with metadata about the download.
     pub async fn download(&self, url: &str, files_dir: &Path) -> Result<DownloadedFile> {
+        self.download_limited(url, files_dir, MAX_FILE_SIZE).await
+    }
+
+    /// Same as [`download`](Self::download), with a lower size limit
+    /// (e.g. per MUC room). Limits above 25MB are capped.
+    pub async fn download_limited(
+        &self,
+        url: &str,
+        files_dir: &Path,
+        max_size: u64,
+    ) -> Result<DownloadedFile> {
+        let max_size = max_size.min(MAX_FILE_SIZE);
+
         // Acquire semaphore permit (limits concurrent downloads)
         let _permit = self
             .semaphore
@@ -137,11 +162,11 @@ impl FileDownloader {
 
         // Check Content-Length before downloading
         if let Some(content_length) = response.content_length() {
-            if content_length > MAX_FILE_SIZE {
+            if content_length > max_size {
                 return Err(anyhow!(
                     "File too large: {} bytes (max {})",
                     content_length,
-                    MAX_FILE_SIZE
+                    max_size
                 ));
             }
         }
@@ -165,11 +190,11 @@ impl FileDownloader {
         let bytes = response.bytes().await?;
         let size = bytes.len() as u64;
 
-        if size > MAX_FILE_SIZE {
+        if size > max_size {
             return Err(anyhow!(
                 "File too large: {} bytes (max {})",
                 size,
-                MAX_FILE_SIZE
+                max_size
             ));
         }
 
@@ -180,7 +205,7 @@ impl FileDownloader {
             content_type
         };
 
-        let category = categorize_mime(&mime_type);
+        let category = categorize(&mime_type, &url_filename);
 
         // Save to disk with UUID prefix (async I/O to avoid blocking the runtime)
         tokio::fs::create_dir_all(files_dir).await?;
@@ -208,48 +233,101 @@ impl FileDownloader {
     }
 }
 
+/// A downloaded file converted for the LLM.
+#[derive(Debug)]
+pub struct FileContent {
+    /// Content block to send
+    pub block: InputContentBlock,
+    /// Human-readable size of the payload actually sent, when it differs
+    /// from the download (e.g. a downscaled image)
+    pub sent_size: Option<String>,
+    /// Transcript of an audio file, stored with the attachment metadata
+    pub transcript: Option<String>,
+}
+
+impl From<InputContentBlock> for FileContent {
+    fn from(block: InputContentBlock) -> Self {
+        Self {
+            block,
+            sent_size: None,
+            transcript: None,
+        }
+    }
+}
+
 /// Converts a downloaded file to an Anthropic API content block.
 ///
-/// Returns `Some(InputContentBlock)` for supported types (images, PDFs),
-/// or `None` for unsupported types.
+/// Returns `Some(FileContent)` for supported types (images, PDFs,
+/// extractable text, audio), or `None` for unsupported types. Images are
+/// preprocessed (see `agent::images`) unless disabled in `[attachments]`.
 ///
 /// Uses `tokio::fs::read` to avoid blocking the async runtime on large files.
-pub async fn file_to_content_block(file: &DownloadedFile) -> Result<Option<InputContentBlock>> {
+pub async fn file_to_content_block(
+    file: &DownloadedFile,
+    config: &AttachmentsConfig,
+) -> Result<Option<FileContent>> {
     match file.category {
-        FileCategory::Image => {
+        FileCategory::Image => image_content(file, config).await.map(Some),
+        FileCategory::Document => {
             let data = tokio::fs::read(&file.path).await?;
             let encoded = base64::engine::general_purpose::STANDARD.encode(&data);
+            // Extracted once here rather than on every agentic round
+            let text = tokio::task::spawn_blocking(move || extract::pdf_to_text(&data))
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("PDF text extraction failed for {}: {e}", file.filename);
+                    "[PDF document: text extraction failed]".to_string()
+                });
             debug!(
-                "Encoded image {} ({}) as base64 ({} chars)",
+                "Encoded document {} ({}) as base64 ({} chars)",
                 file.filename,
                 file.mime_type,
                 encoded.len()
             );
-            Ok(Some(InputContentBlock::Image {
-                source: ImageSource {
-                    source_type: "base64".to_string(),
-                    media_type: file.mime_type.clone(),
-                    data: encoded,
-                },
-            }))
+            Ok(Some(
+                InputContentBlock::Document {
+                    source: DocumentSource {
+                        source_type: "base64".to_string(),
+                        media_type: file.mime_type.clone(),
+                        data: encoded,
+                        text: Some(text),
+                    },
+                }
+                .into(),
+            ))
         }
-        FileCategory::Document => {
+        FileCategory::Text => {
             let data = tokio::fs::read(&file.path).await?;
-            let encoded = base64::engine::general_purpose::STANDARD.encode(&data);
-            debug!(
-                "Encoded document {} ({}) as base64 ({} chars)",
+            let format = extract::detect_format(&file.mime_type, &file.filename)
+                .ok_or_else(|| anyhow!("No text extractor for {}", file.mime_type))?;
+            let extracted = tokio::task::spawn_blocking(move || {
+                extract::extract_text(&data, &format, MAX_TEXT_CHARS)
+            })
+            .await?;
+            let header = format!(
+                "[File: {} ({}, {})]",
                 file.filename,
                 file.mime_type,
-                encoded.len()
+                file.human_size()
             );
-            Ok(Some(InputContentBlock::Document {
-                source: DocumentSource {
-                    source_type: "base64".to_string(),
-                    media_type: file.mime_type.clone(),
-                    data: encoded,
-                },
-            }))
+            let text = match extracted {
+                Ok(text) => {
+                    debug!(
+                        "Extracted {} chars of text from {} ({})",
+                        text.chars().count(),
+                        file.filename,
+                        file.mime_type
+                    );
+                    format!("{header}\n\n{text}")
+                }
+                Err(e) => {
+                    warn!("Text extraction failed for {}: {e}", file.filename);
+                    format!("{header}\n\n[Could not extract text: {e}]")
+                }
+            };
+            Ok(Some(InputContentBlock::Text { text }.into()))
         }
+        FileCategory::Audio => audio_content(file, config).await.map(Some),
         FileCategory::Other => {
             warn!(
                 "Unsupported file type {} — stored but not sent to LLM",
@@ -260,17 +338,161 @@ pub async fn file_to_content_block(file: &DownloadedFile) -> Result<Option<Input
     }
 }
 
+/// Builds the content block of an image, preprocessing it when enabled.
+///
+/// If preprocessing fails, a natively supported image (JPEG, PNG, GIF,
+/// WebP) is sent as is; other formats are replaced by a text note.
+async fn image_content(file: &DownloadedFile, config: &AttachmentsConfig) -> Result<FileContent> {
+    let data = tokio::fs::read(&file.path).await?;
+    let native = is_native_image(&file.mime_type);
+
+    let error = if config.preprocess_images {
+        let mime = file.mime_type.clone();
+        let config = config.clone();
+        let (data, result) = tokio::task::spawn_blocking(move || {
+            let result = images::preprocess_image(&data, &mime, &config);
+            (data, result)
+        })
+        .await?;
+        match result {
+            Ok(processed) => {
+                debug!(
+                    "Preprocessed image {}: {}x{} {} ({}) → {}x{} {} ({})",
+                    file.filename,
+                    processed.original_dimensions.0,
+                    processed.original_dimensions.1,
+                    file.mime_type,
+                    format_size(data.len() as u64),
+                    processed.dimensions.0,
+                    processed.dimensions.1,
+                    processed.mime_type,
+                    format_size(processed.data.len() as u64)
+                );
+                return Ok(FileContent {
+                    block: image_block(&processed.mime_type, &processed.data),
+                    sent_size: Some(format_size(processed.data.len() as u64)),
+                    transcript: None,
+                });
+            }
+            Err(e) if native => {
+                warn!("Image preprocessing failed for {}, sending original: {e}", file.filename);
+                return Ok(image_block(&file.mime_type, &data).into());
+            }
+            Err(e) => e.to_string(),
+        }
+    } else if native {
+        return Ok(image_block(&file.mime_type, &data).into());
+    } else {
+        format!("{} is not supported by the model", file.mime_type)
+    };
+
+    warn!("Cannot send image {}: {error}", file.filename);
+    Ok(InputContentBlock::Text {
+        text: format!(
+            "[Image received: {} ({}) — could not be converted: {error}]",
+            file.filename, file.mime_type
+        ),
+    }
+    .into())
+}
+
+/// Builds the content block of an audio file: its transcript, or a note
+/// asking the LLM to tell the user it could not be listened to.
+async fn audio_content(file: &DownloadedFile, config: &AttachmentsConfig) -> Result<FileContent> {
+    let header = format!(
+        "[Voice message: {} ({}, {})]",
+        file.filename,
+        file.mime_type,
+        file.human_size()
+    );
+    let error = match config.transcription.as_ref() {
+        Some(transcription) => {
+            let data = tokio::fs::read(&file.path).await?;
+            match transcribe::transcribe(transcription, &file.filename, &file.mime_type, data).await
+            {
+                Ok(transcript) => {
+                    debug!(
+                        "Transcribed {} ({}): {} chars",
+                        file.filename,
+                        file.mime_type,
+                        transcript.chars().count()
+                    );
+                    let text = if transcript.is_empty() {
+                        format!("{header}\n\n[No speech detected]")
+                    } else {
+                        format!("{header}\n\nTranscript:\n{transcript}")
+                    };
+                    return Ok(FileContent {
+                        block: InputContentBlock::Text { text },
+                        sent_size: None,
+                        transcript: Some(transcript),
+                    });
+                }
+                Err(e) => {
+                    warn!("Transcription failed for {}: {e}", file.filename);
+                    format!("transcription failed: {e}")
+                }
+            }
+        }
+        None => "voice transcription is not configured".to_string(),
+    };
+
+    Ok(InputContentBlock::Text {
+        text: format!(
+            "{header}\n\n[Could not be transcribed ({error}). Let the user know you \
+             cannot listen to this message and ask them to send it as text.]"
+        ),
+    }
+    .into())
+}
+
+/// Base64-encodes image data into an image content block.
+fn image_block(mime_type: &str, data: &[u8]) -> InputContentBlock {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(data);
+    debug!(
+        "Encoded image ({}) as base64 ({} chars)",
+        mime_type,
+        encoded.len()
+    );
+    InputContentBlock::Image {
+        source: ImageSource {
+            source_type: "base64".to_string(),
+            media_type: mime_type.to_string(),
+            data: encoded,
+        },
+    }
+}
+
+/// Image types accepted by the LLM APIs without conversion.
+fn is_native_image(mime: &str) -> bool {
+    matches!(mime, "image/jpeg" | "image/png" | "image/gif" | "image/webp")
+}
+
 /// Categorizes a MIME type into a file category.
 fn categorize_mime(mime: &str) -> FileCategory {
     match mime {
-        "image/jpeg" | "image/png" | "image/gif" | "image/webp" => FileCategory::Image,
+        "image/jpeg" | "image/png" | "image/gif" | "image/webp" | "image/bmp" | "image/tiff"
+        | "image/heic" | "image/heif" => FileCategory::Image,
         "application/pdf" => FileCategory::Document,
+        m if m.starts_with("audio/") => FileCategory::Audio,
+        m if extract::detect_format(m, "").is_some() => FileCategory::Text,
         _ => FileCategory::Other,
     }
 }
 
+/// Categorizes a file by MIME type, falling back to the filename extension
+/// for text-extractable formats (e.g. `main.rs` served as `text/x-rust`).
+pub(crate) fn categorize(mime: &str, filename: &str) -> FileCategory {
+    match categorize_mime(mime) {
+        FileCategory::Other if extract::detect_format(mime, filename).is_some() => {
+            FileCategory::Text
+        }
+        category => category,
+    }
+}
+
 /// Guesses MIME type from a filename extension.
-fn mime_from_extension(filename: &str) -> String {
+pub(crate) fn mime_from_extension(filename: &str) -> String {
     let ext = filename
         .rsplit('.')
         .next()
@@ -281,9 +503,30 @@ fn mime_from_extension(filename: &str) -> String {
         "png" => "image/png",
         "gif" => "image/gif",
         "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "tif" | "tiff" => "image/tiff",
+        "heic" => "image/heic",
+        "heif" => "image/heif",
         "pdf" => "application/pdf",
-        "txt" => "text/plain",
+        "txt" | "log" => "text/plain",
+        "md" | "markdown" => "text/markdown",
+        "csv" => "text/csv",
+        "tsv" => "text/tab-separated-values",
         "html" | "htm" => "text/html",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "odt" => "application/vnd.oasis.opendocument.text",
+        "ods" => "application/vnd.oasis.opendocument.spreadsheet",
+        "odp" => "application/vnd.oasis.opendocument.presentation",
+        "zip" => "application/zip",
+        "ogg" | "oga" | "opus" => "audio/ogg",
+        "m4a" => "audio/mp4",
+        "mp3" => "audio/mpeg",
+        "aac" => "audio/aac",
+        "wav" => "audio/wav",
+        "weba" => "audio/webm",
+        "flac" => "audio/flac",
         _ => "application/octet-stream",
     }
     .to_string()
@@ -297,7 +540,7 @@ fn sanitize_filename(name: &str) -> String {
 }
 
 /// Formats a byte size as a human-readable string.
-fn format_size(bytes: u64) -> String {
+pub(crate) fn format_size(bytes: u64) -> String {
     if bytes < 1024 {
         format!("{bytes}B")
     } else if bytes < 1024 * 1024 {
@@ -317,6 +560,9 @@ mod tests {
         assert_eq!(categorize_mime("image/png"), FileCategory::Image);
         assert_eq!(categorize_mime("image/gif"), FileCategory::Image);
         assert_eq!(categorize_mime("image/webp"), FileCategory::Image);
+        assert_eq!(categorize_mime("image/bmp"), FileCategory::Image);
+        assert_eq!(categorize_mime("image/tiff"), FileCategory::Image);
+        assert_eq!(categorize_mime("image/heic"), FileCategory::Image);
     }
 
     #[test]
@@ -324,9 +570,32 @@ mod tests {
         assert_eq!(categorize_mime("application/pdf"), FileCategory::Document);
     }
 
+    #[test]
+    fn test_categorize_mime_text() {
+        assert_eq!(categorize_mime("text/plain"), FileCategory::Text);
+        assert_eq!(categorize_mime("text/csv"), FileCategory::Text);
+        assert_eq!(categorize_mime("application/json"), FileCategory::Text);
+        assert_eq!(categorize_mime("application/zip"), FileCategory::Text);
+    }
+
+    #[test]
+    fn test_categorize_with_extension_fallback() {
+        assert_eq!(categorize("text/x-rust", "main.rs"), FileCategory::Text);
+        assert_eq!(categorize("application/octet-stream", "main.rs"), FileCategory::Text);
+        assert_eq!(categorize("application/octet-stream", "a.bin"), FileCategory::Other);
+        assert_eq!(categorize("image/png", "logo.png"), FileCategory::Image);
+    }
+
+    #[test]
+    fn test_categorize_mime_audio() {
+        assert_eq!(categorize_mime("audio/ogg"), FileCategory::Audio);
+        assert_eq!(categorize_mime("audio/mp4"), FileCategory::Audio);
+        assert_eq!(categorize("application/octet-stream", "voice.m4a"), FileCategory::Other);
+        assert_eq!(categorize(&mime_from_extension("voice.m4a"), "voice.m4a"), FileCategory::Audio);
+    }
+
     #[test]
     fn test_categorize_mime_other() {
-        assert_eq!(categorize_mime("text/plain"), FileCategory::Other);
         assert_eq!(categorize_mime("video/mp4"), FileCategory::Other);
         assert_eq!(
             categorize_mime("application/octet-stream"),
@@ -341,8 +610,19 @@ mod tests {
         assert_eq!(mime_from_extension("image.png"), "image/png");
         assert_eq!(mime_from_extension("anim.gif"), "image/gif");
         assert_eq!(mime_from_extension("photo.webp"), "image/webp");
+        assert_eq!(mime_from_extension("IMG_0001.HEIC"), "image/heic");
+        assert_eq!(mime_from_extension("scan.tif"), "image/tiff");
         assert_eq!(mime_from_extension("doc.pdf"), "application/pdf");
         assert_eq!(mime_from_extension("readme.txt"), "text/plain");
+        assert_eq!(mime_from_extension("notes.md"), "text/markdown");
+        assert_eq!(mime_from_extension("data.csv"), "text/csv");
+        assert_eq!(
+            mime_from_extension("report.docx"),
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+        );
+        assert_eq!(mime_from_extension("bundle.zip"), "application/zip");
+        assert_eq!(mime_from_extension("voice.opus"), "audio/ogg");
+        assert_eq!(mime_from_extension("voice.m4a"), "audio/mp4");
         assert_eq!(mime_from_extension("unknown.xyz"), "application/octet-stream");
         assert_eq!(mime_from_extension("noext"), "application/octet-stream");
     }
@@ -391,7 +671,11 @@ mod tests {
             category: FileCategory::Image,
         };
 
-        let block = file_to_content_block(&file).await.unwrap().unwrap();
+        let block = file_to_content_block(&file, &AttachmentsConfig::default())
+            .await
+            .unwrap()
+            .unwrap()
+            .block;
         match block {
             InputContentBlock::Image { source } => {
                 assert_eq!(source.source_type, "base64");
@@ -406,6 +690,68 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_file_to_content_block_image_preprocessed() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("photo.bmp");
+        let img = image::RgbImage::from_pixel(2000, 1000, image::Rgb([90, 120, 200]));
+        img.save_with_format(&file_path, image::ImageFormat::Bmp).unwrap();
+        let size = std::fs::metadata(&file_path).unwrap().len();
+
+        let file = DownloadedFile {
+            path: file_path,
+            filename: "photo.bmp".to_string(),
+            mime_type: "image/bmp".to_string(),
+            size,
+            category: FileCategory::Image,
+        };
+
+        let content = file_to_content_block(&file, &AttachmentsConfig::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(content.sent_size.is_some());
+        match content.block {
+            InputContentBlock::Image { source } => {
+                assert_eq!(source.media_type, "image/jpeg");
+                let decoded = base64::engine::general_purpose::STANDARD
+                    .decode(&source.data)
+                    .unwrap();
+                let sent = image::load_from_memory(&decoded).unwrap();
+                assert_eq!((sent.width(), sent.height()), (1568, 784));
+            }
+            _ => panic!("Expected Image content block"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_to_content_block_unconvertible_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("photo.heic");
+        std::fs::write(&file_path, b"\0\0\0\x18ftypheic\0\0\0\0").unwrap();
+
+        let file = DownloadedFile {
+            path: file_path,
+            filename: "photo.heic".to_string(),
+            mime_type: "image/heic".to_string(),
+            size: 20,
+            category: FileCategory::Image,
+        };
+
+        let content = file_to_content_block(&file, &AttachmentsConfig::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(content.sent_size.is_none());
+        match content.block {
+            InputContentBlock::Text { text } => {
+                assert!(text.starts_with("[Image received: photo.heic (image/heic)"));
+                assert!(text.contains("HEIC/HEIF images cannot be converted"));
+            }
+            _ => panic!("Expected Text content block"),
+        }
+    }
+
     #[tokio::test]
     async fn test_file_to_content_block_document() {
         let dir = tempfile::tempdir().unwrap();
@@ -420,16 +766,77 @@ mod tests {
             category: FileCategory::Document,
         };
 
-        let block = file_to_content_block(&file).await.unwrap().unwrap();
+        let block = file_to_content_block(&file, &AttachmentsConfig::default())
+            .await
+            .unwrap()
+            .unwrap()
+            .block;
         match block {
             InputContentBlock::Document { source } => {
                 assert_eq!(source.source_type, "base64");
                 assert_eq!(source.media_type, "application/pdf");
+                assert_eq!(source.text.as_deref(), Some("[PDF document: text extraction failed]"));
             }
             _ => panic!("Expected Document content block"),
         }
     }
 
+    #[tokio::test]
+    async fn test_file_to_content_block_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("build.log");
+        std::fs::write(&file_path, b"error: linker failed\n").unwrap();
+
+        let file = DownloadedFile {
+            path: file_path,
+            filename: "build.log".to_string(),
+            mime_type: "text/plain".to_string(),
+            size: 21,
+            category: FileCategory::Text,
+        };
+
+        let block = file_to_content_block(&file, &AttachmentsConfig::default())
+            .await
+            .unwrap()
+            .unwrap()
+            .block;
+        match block {
+            InputContentBlock::Text { text } => {
+                assert_eq!(text, "[File: build.log (text/plain, 21B)]\n\nerror: linker failed\n");
+            }
+            _ => panic!("Expected Text content block"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_to_content_block_text_extraction_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("report.docx");
+        std::fs::write(&file_path, b"not a zip").unwrap();
+
+        let file = DownloadedFile {
+            path: file_path,
+            filename: "report.docx".to_string(),
+            mime_type: "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+                .to_string(),
+            size: 9,
+            category: FileCategory::Text,
+        };
+
+        let block = file_to_content_block(&file, &AttachmentsConfig::default())
+            .await
+            .unwrap()
+            .unwrap()
+            .block;
+        match block {
+            InputContentBlock::Text { text } => {
+                assert!(text.starts_with("[File: report.docx"));
+                assert!(text.contains("[Could not extract text: Not a valid DOCX document]"));
+            }
+            _ => panic!("Expected Text content block"),
+        }
+    }
+
     #[tokio::test]
     async fn test_file_to_content_block_other_returns_none() {
         let dir = tempfile::tempdir().unwrap();
@@ -444,10 +851,40 @@ mod tests {
             category: FileCategory::Other,
         };
 
-        let block = file_to_content_block(&file).await.unwrap();
+        let block = file_to_content_block(&file, &AttachmentsConfig::default())
+            .await
+            .unwrap();
         assert!(block.is_none());
     }
 
+    #[tokio::test]
+    async fn test_file_to_content_block_audio_without_transcription() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("voice.ogg");
+        std::fs::write(&file_path, b"OggS fake").unwrap();
+
+        let file = DownloadedFile {
+            path: file_path,
+            filename: "voice.ogg".to_string(),
+            mime_type: "audio/ogg".to_string(),
+            size: 9,
+            category: FileCategory::Audio,
+        };
+
+        let content = file_to_content_block(&file, &AttachmentsConfig::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(content.transcript.is_none());
+        match content.block {
+            InputContentBlock::Text { text } => {
+                assert!(text.starts_with("[Voice message: voice.ogg (audio/ogg, 9B)]"));
+                assert!(text.contains("voice transcription is not configured"));
+            }
+            other => panic!("Expected Text block, got {other:?}"),
+        }
+    }
+
     #[tokio::test]
     async fn test_downloader_rejects_http_urls() {
         let downloader = FileDownloader::new(1);
@@ -0,0 +1,214 @@
+//! Image preprocessing for attachments sent to the LLM.
+//!
+//! Phone photos are often 12+ megapixels and several MB, well above what
+//! providers accept or need. Before an image is sent, it is:
+//!
+//! - decoded and rotated upright (EXIF orientation),
+//! - downscaled so its longest side fits `image_max_edge`,
+//! - re-encoded as JPEG (photos) or PNG (transparency, screenshots),
+//!   which drops all metadata — EXIF, GPS location, camera details.
+//!
+//! BMP, TIFF and WebP are converted the same way. HEIC/HEIF cannot be
+//! decoded (no pure-Rust decoder), and is reported as such.
+//!
+//! Decoding is CPU-bound — call it from `tokio::task::spawn_blocking`.
+
+use std::io::Cursor;
+
+use anyhow::{bail, Context, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+
+use crate::config::AttachmentsConfig;
+
+/// Re-encoded PNGs larger than this are sent as JPEG instead (when the
+/// image has no transparency).
+const MAX_PNG_BYTES: usize = 2 * 1024 * 1024;
+
+/// An image ready to be sent to the LLM.
+#[derive(Debug)]
+pub struct ProcessedImage {
+    /// Encoded image data
+    pub data: Vec<u8>,
+    /// MIME type of `data` (`image/jpeg` or `image/png`)
+    pub mime_type: String,
+    /// Dimensions of the original image (after orientation)
+    pub original_dimensions: (u32, u32),
+    /// Dimensions of the sent image
+    pub dimensions: (u32, u32),
+}
+
+/// Returns true for HEIC/HEIF images, detected by MIME type or by the
+/// `ftyp` box brand.
+pub fn is_heic(mime: &str, data: &[u8]) -> bool {
+    if matches!(
+        mime,
+        "image/heic" | "image/heif" | "image/heic-sequence" | "image/heif-sequence"
+    ) {
+        return true;
+    }
+    data.len() >= 12
+        && &data[4..8] == b"ftyp"
+        && matches!(
+            &data[8..12],
+            b"heic" | b"heix" | b"heim" | b"heis" | b"mif1" | b"msf1"
+        )
+}
+
+/// Decodes, orients, downscales and re-encodes an image.
+pub fn preprocess_image(
+    data: &[u8],
+    mime: &str,
+    config: &AttachmentsConfig,
+) -> Result<ProcessedImage> {
+    if is_heic(mime, data) {
+        bail!("HEIC/HEIF images cannot be converted (no decoder available)");
+    }
+
+    let reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .context("Cannot read image")?;
+    let source_format = reader.format();
+    let mut decoder = reader.into_decoder().context("Unsupported image format")?;
+    let orientation = decoder.orientation().ok();
+    let mut img = DynamicImage::from_decoder(decoder).context("Cannot decode image")?;
+    if let Some(orientation) = orientation {
+        img.apply_orientation(orientation);
+    }
+
+    let original_dimensions = (img.width(), img.height());
+    let max_edge = config.image_max_edge.max(1);
+    if img.width() > max_edge || img.height() > max_edge {
+        // `resize` keeps the aspect ratio, fitting within the bounds
+        img = img.resize(max_edge, max_edge, FilterType::Lanczos3);
+    }
+    let dimensions = (img.width(), img.height());
+
+    let has_alpha = img.color().has_alpha();
+    let prefer_png = has_alpha || source_format == Some(ImageFormat::Png);
+    let (data, mime_type) = if prefer_png {
+        let png = encode_png(&img)?;
+        if has_alpha || png.len() <= MAX_PNG_BYTES {
+            (png, "image/png")
+        } else {
+            (encode_jpeg(&img, config.image_quality)?, "image/jpeg")
+        }
+    } else {
+        (encode_jpeg(&img, config.image_quality)?, "image/jpeg")
+    };
+
+    Ok(ProcessedImage {
+        data,
+        mime_type: mime_type.to_string(),
+        original_dimensions,
+        dimensions,
+    })
+}
+
+fn encode_png(img: &DynamicImage) -> Result<Vec<u8>> {
+    let mut out = Cursor::new(Vec::new());
+    img.write_to(&mut out, ImageFormat::Png)
+        .context("Cannot encode PNG")?;
+    Ok(out.into_inner())
+}
+
+fn encode_jpeg(img: &DynamicImage, quality: u8) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let encoder = JpegEncoder::new_with_quality(&mut out, quality.clamp(1, 100));
+    img.to_rgb8()
+        .write_with_encoder(encoder)
+        .context("Cannot encode JPEG")?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+    fn config(max_edge: u32) -> AttachmentsConfig {
+        AttachmentsConfig {
+            image_max_edge: max_edge,
+            ..Default::default()
+        }
+    }
+
+    fn encode(img: DynamicImage, format: ImageFormat) -> Vec<u8> {
+        let mut out = Cursor::new(Vec::new());
+        img.write_to(&mut out, format).unwrap();
+        out.into_inner()
+    }
+
+    /// Inserts a minimal EXIF APP1 segment (with a GPS marker string)
+    /// right after the JPEG SOI marker.
+    fn with_exif(jpeg: &[u8]) -> Vec<u8> {
+        let payload = b"Exif\0\0II*\0\x08\0\0\0\0\0GPS 48.8566N 2.3522E";
+        let len = (payload.len() + 2) as u16;
+        let mut out = jpeg[..2].to_vec();
+        out.extend_from_slice(&[0xFF, 0xE1]);
+        out.extend_from_slice(&len.to_be_bytes());
+        out.extend_from_slice(payload);
+        out.extend_from_slice(&jpeg[2..]);
+        out
+    }
+
+    #[test]
+    fn test_downscales_large_photo_to_jpeg() {
+        let photo = DynamicImage::ImageRgb8(RgbImage::from_pixel(2000, 1500, Rgb([200, 100, 50])));
+        let data = encode(photo, ImageFormat::Jpeg);
+
+        let processed = preprocess_image(&data, "image/jpeg", &config(1568)).unwrap();
+        assert_eq!(processed.mime_type, "image/jpeg");
+        assert_eq!(processed.original_dimensions, (2000, 1500));
+        assert_eq!(processed.dimensions, (1568, 1176));
+        assert!(processed.data.len() < data.len());
+    }
+
+    #[test]
+    fn test_strips_exif() {
+        let photo = DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 64, Rgb([10, 20, 30])));
+        let data = with_exif(&encode(photo, ImageFormat::Jpeg));
+        assert!(data.windows(4).any(|w| w == b"Exif"));
+
+        let processed = preprocess_image(&data, "image/jpeg", &config(1568)).unwrap();
+        assert_eq!(processed.dimensions, (64, 64));
+        assert!(!processed.data.windows(4).any(|w| w == b"Exif"));
+        assert!(!processed.data.windows(3).any(|w| w == b"GPS"));
+    }
+
+    #[test]
+    fn test_keeps_png_with_transparency() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1200, 400, Rgba([0, 0, 0, 128])));
+        let data = encode(img, ImageFormat::Png);
+
+        let processed = preprocess_image(&data, "image/png", &config(600)).unwrap();
+        assert_eq!(processed.mime_type, "image/png");
+        assert_eq!(processed.dimensions, (600, 200));
+    }
+
+    #[test]
+    fn test_converts_bmp_and_tiff() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(32, 16, Rgb([1, 2, 3])));
+        for (format, mime) in [
+            (ImageFormat::Bmp, "image/bmp"),
+            (ImageFormat::Tiff, "image/tiff"),
+        ] {
+            let data = encode(img.clone(), format);
+            let processed = preprocess_image(&data, mime, &config(1568)).unwrap();
+            assert_eq!(processed.mime_type, "image/jpeg");
+            assert_eq!(processed.dimensions, (32, 16));
+        }
+    }
+
+    #[test]
+    fn test_rejects_heic_and_garbage() {
+        let heic = b"\0\0\0\x18ftypheic\0\0\0\0mif1heic";
+        assert!(is_heic("application/octet-stream", heic));
+        assert!(is_heic("image/heic", b""));
+        let err = preprocess_image(heic, "image/heic", &config(1568)).unwrap_err();
+        assert!(err.to_string().contains("HEIC"));
+
+        assert!(preprocess_image(b"not an image", "image/png", &config(1568)).is_err());
+    }
+}
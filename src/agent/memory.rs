@@ -1,8 +1,11 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, OpenOptions};
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use tracing::info;
 
 use crate::llm::{Message, MessageContent};
@@ -16,6 +19,40 @@ pub struct Attachment {
     pub filename: String,
     pub mime_type: String,
     pub size: String,
+    /// Size actually sent to the LLM, when the file was converted
+    /// (e.g. a downscaled image). Absent when sent as downloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_size: Option<String>,
+    /// Name of the stored copy in the JID's `files/` directory
+    /// (`{uuid}_{filename}`), used by the `file_read` skill.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stored_as: Option<String>,
+    /// Source URL (XEP-0066 OOB), recorded for MUC attachments so they
+    /// can be downloaded when the agent is mentioned later.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Transcript of an audio attachment (voice message), so later turns
+    /// can refer to what was said.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcript: Option<String>,
+}
+
+/// A downloaded file in a JID's `files/` directory, joined with its
+/// attachment entry in the session history (current or archived).
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredFile {
+    /// On-disk name (`{uuid}_{filename}`)
+    pub stored_as: String,
+    /// Path of the stored file
+    pub path: PathBuf,
+    /// Original filename (from history, or the on-disk name without the UUID)
+    pub filename: String,
+    /// MIME type recorded in history, if found
+    pub mime_type: Option<String>,
+    /// File size in bytes
+    pub size: u64,
+    /// Timestamp of the message that carried the file, if found
+    pub received: Option<String>,
 }
 
 /// Structured reaction metadata stored in JSONL session entries.
@@ -26,6 +63,69 @@ pub struct Attachment {
 pub struct Reaction {
     pub message_id: String,
     pub emojis: Vec<String>,
+    /// Acknowledgement sent by the runtime (`[reactions]`), kept out of
+    /// the LLM context
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ack: bool,
+}
+
+/// Corrections (XEP-0308) and retraction (XEP-0424) applied to a message.
+///
+/// The message's `content` holds the corrected text; the text first
+/// received is kept here for audit.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+pub struct MessageEdit {
+    /// Content before the first correction
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original: Option<String>,
+    /// When the message was last corrected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub corrected: Option<String>,
+    /// When the message was retracted. Retracted messages stay in the
+    /// file but are no longer sent to the LLM.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retracted: Option<String>,
+}
+
+/// Delivery and read state of an agent message, reported by the
+/// contact's client with receipts (XEP-0184) and chat markers (XEP-0333).
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+pub struct Delivery {
+    /// When the message reached a client of the contact
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub received: Option<String>,
+    /// When the message was shown to the contact
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub displayed: Option<String>,
+}
+
+/// Room and lifecycle metadata of a message, boxed to keep
+/// [`SessionEntry`] small. Flattened: on disk, these are plain fields of
+/// the message line.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+pub struct MessageMeta {
+    /// Id assigned by the room (XEP-0359), for room messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stanza_id: Option<String>,
+    /// Sender's occupant id (XEP-0421), for room messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub occupant_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edit: Option<MessageEdit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivery: Option<Delivery>,
+}
+
+impl MessageMeta {
+    /// Ids given by a room to a message: its stanza id (XEP-0359) and
+    /// the sender's occupant id (XEP-0421).
+    pub fn room(stanza_id: Option<&str>, occupant_id: Option<&str>) -> Self {
+        Self {
+            stanza_id: stanza_id.map(String::from),
+            occupant_id: occupant_id.map(String::from),
+            ..Default::default()
+        }
+    }
 }
 
 /// A single entry in a JSONL session file.
@@ -57,6 +157,8 @@ pub enum SessionEntry {
         attachments: Option<Vec<Attachment>>,
         #[serde(skip_serializing_if = "Option::is_none")]
         reaction: Option<Reaction>,
+        #[serde(flatten)]
+        meta: Box<MessageMeta>,
     },
 }
 
@@ -82,6 +184,30 @@ pub struct WorkspaceContext {
     pub user_memory: Option<String>,
 }
 
+/// A conversation thread (XEP-0201) with its own session, for `/threads`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadSummary {
+    /// Thread id (XEP-0201), as recorded by [`Memory::record_thread`];
+    /// the directory name (hex SHA-1 of the id) if it was not recorded
+    pub id: String,
+    /// Messages in the thread's current session
+    pub messages: usize,
+    /// Timestamp of the last message
+    pub last: Option<String>,
+}
+
+/// A message of the current session that can be referred to by id,
+/// e.g. to react to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageRef {
+    /// Id to refer to the message: the stanza id assigned by the room
+    /// (XEP-0359) for room messages, the message id otherwise
+    pub id: String,
+    pub role: String,
+    pub sender: Option<String>,
+    pub content: String,
+}
+
 /// Persistent conversational memory per user.
 /// Stores conversation history as JSONL and user context as
 /// markdown files for transparency and portability.
@@ -96,8 +222,13 @@ pub struct WorkspaceContext {
 ///   {base_path}/{jid}/knowledge.jsonl      — structured knowledge store (key/value)
 ///   {base_path}/{jid}/sessions/             — archived sessions
 ///   {base_path}/{jid}/sessions/{ts}.jsonl   — archived session file
+///   {base_path}/{jid}/threads/{sha1}/       — session of a conversation thread
+///   {base_path}/{jid}/threads/{sha1}/thread — the thread's id
 pub struct Memory {
     base_path: PathBuf,
+    /// One lock per session file, held by every write to it (see
+    /// [`Memory::session_lock`])
+    session_locks: Mutex<HashMap<String, Arc<Mutex<()>>>>,
 }
 
 impl Memory {
@@ -130,6 +261,7 @@ impl Memory {
 
         Ok(Self {
             base_path: path.to_path_buf(),
+            session_locks: Mutex::new(HashMap::new()),
         })
     }
 
@@ -139,6 +271,15 @@ impl Memory {
         &self.base_path
     }
 
+    /// Lock of a session file. Spawned tasks append to a session while
+    /// others rewrite it (corrections, receipts): an entry appended between
+    /// the read and the rename of a rewrite would be lost, so appends,
+    /// rewrites and archiving all hold it.
+    fn session_lock(&self, jid: &str) -> Arc<Mutex<()>> {
+        let mut locks = self.session_locks.lock().unwrap();
+        Arc::clone(locks.entry(jid.to_string()).or_default())
+    }
+
     /// Returns the per-user directory, creating it if needed
     fn user_dir(&self, jid: &str) -> Result<PathBuf> {
         let dir = self.base_path.join(jid);
@@ -338,6 +479,310 @@ impl Memory {
         attachments: Option<Vec<Attachment>>,
         reaction: Option<Reaction>,
     ) -> Result<()> {
+        // Only store non-empty attachment lists
+        let attachments = attachments.filter(|a| !a.is_empty());
+
+        let entry = SessionEntry::Message {
+            role: role.to_string(),
+            content: content.to_string(),
+            msg_id: msg_id.map(|s| s.to_string()),
+            sender: sender.map(|s| s.to_string()),
+            ts: Some(chrono::Utc::now().to_rfc3339()),
+            attachments,
+            reaction,
+            meta: Box::default(),
+        };
+        self.append_entry(jid, &entry)
+    }
+
+    /// Appends a message from a room occupant, with the ids the room
+    /// assigned (`MessageMeta::room`): retractions in rooms refer to the
+    /// stanza id (XEP-0359), corrections and retractions are checked
+    /// against the occupant id (XEP-0421).
+    pub fn store_room_message(
+        &self,
+        room_jid: &str,
+        content: &str,
+        msg_id: Option<&str>,
+        meta: MessageMeta,
+        sender: &str,
+        attachments: Option<Vec<Attachment>>,
+    ) -> Result<()> {
+        let entry = SessionEntry::Message {
+            role: "user".to_string(),
+            content: content.to_string(),
+            msg_id: msg_id.map(|s| s.to_string()),
+            sender: Some(sender.to_string()),
+            ts: Some(chrono::Utc::now().to_rfc3339()),
+            attachments: attachments.filter(|a| !a.is_empty()),
+            reaction: None,
+            meta: Box::new(meta),
+        };
+        self.append_entry(room_jid, &entry)
+    }
+
+    /// Appends a message fetched from the server archive (XEP-0313),
+    /// keeping its original timestamp.
+    pub fn store_archived_message(
+        &self,
+        jid: &str,
+        role: &str,
+        content: &str,
+        msg_id: Option<&str>,
+        sender: Option<&str>,
+        ts: &str,
+    ) -> Result<()> {
+        let entry = SessionEntry::Message {
+            role: role.to_string(),
+            content: content.to_string(),
+            msg_id: msg_id.map(|s| s.to_string()),
+            sender: sender.map(|s| s.to_string()),
+            ts: Some(ts.to_string()),
+            attachments: None,
+            reaction: None,
+            meta: Box::default(),
+        };
+        self.append_entry(jid, &entry)
+    }
+
+    /// Replaces the content of a message corrected by its sender
+    /// (XEP-0308). The first content is kept in the entry's `edit` for
+    /// audit. `msg_id` is the id of the original message, `sender` and
+    /// `occupant_id` must match the stored ones (see [`same_author`]; None
+    /// for the agent's own replies).
+    ///
+    /// Returns false if no such message is in the current session.
+    pub fn correct_message(
+        &self,
+        jid: &str,
+        msg_id: &str,
+        sender: Option<&str>,
+        occupant_id: Option<&str>,
+        content: &str,
+    ) -> Result<bool> {
+        self.edit_message(jid, msg_id, sender, occupant_id, |stored, edit| {
+            if edit.original.is_none() {
+                edit.original = Some(std::mem::take(stored));
+            }
+            *stored = content.to_string();
+            edit.corrected = Some(chrono::Utc::now().to_rfc3339());
+        })
+    }
+
+    /// Marks a message retracted by its sender (XEP-0424). The entry stays
+    /// in the file but is no longer part of the LLM context.
+    ///
+    /// Returns false if no such message is in the current session.
+    pub fn retract_message(
+        &self,
+        jid: &str,
+        msg_id: &str,
+        sender: Option<&str>,
+        occupant_id: Option<&str>,
+    ) -> Result<bool> {
+        self.edit_message(jid, msg_id, sender, occupant_id, |_, edit| {
+            edit.retracted = Some(chrono::Utc::now().to_rfc3339());
+        })
+    }
+
+    /// Rewrites the session file with `apply` run on the edit record of
+    /// the last message matching `msg_id` and its author.
+    fn edit_message(
+        &self,
+        jid: &str,
+        msg_id: &str,
+        sender: Option<&str>,
+        occupant_id: Option<&str>,
+        apply: impl FnOnce(&mut String, &mut MessageEdit),
+    ) -> Result<bool> {
+        self.update_message(jid, msg_id, sender, occupant_id, |entry| {
+            if let SessionEntry::Message { content, meta, .. } = entry {
+                let mut changes = meta.edit.take().unwrap_or_default();
+                apply(content, &mut changes);
+                meta.edit = Some(changes);
+            }
+        })
+    }
+
+    /// Records that the contact's client received or displayed the agent
+    /// message `msg_id` (XEP-0184 receipts, XEP-0333 chat markers).
+    ///
+    /// Returns false if no such message is in the current session.
+    pub fn record_delivery(&self, jid: &str, msg_id: &str, displayed: bool) -> Result<bool> {
+        self.update_message(jid, msg_id, None, None, |entry| {
+            if let SessionEntry::Message { meta, .. } = entry {
+                let status = meta.delivery.get_or_insert_with(Delivery::default);
+                let now = Some(chrono::Utc::now().to_rfc3339());
+                if displayed {
+                    status.displayed = status.displayed.take().or(now);
+                } else {
+                    status.received = status.received.take().or(now);
+                }
+            }
+        })
+    }
+
+    /// Number of agent messages sent after the last one the contact read.
+    /// A displayed marker covers all earlier messages (XEP-0333).
+    ///
+    /// None when no message of the session was marked displayed: the
+    /// contact's clients may not send markers at all.
+    pub fn unread_replies(&self, jid: &str) -> Result<Option<usize>> {
+        let path = self.base_path.join(jid).join("history.jsonl");
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&path)?;
+        let mut unread = None;
+        for line in content.lines() {
+            let Ok(SessionEntry::Message { role, msg_id: Some(_), meta, .. }) =
+                serde_json::from_str(line)
+            else {
+                continue;
+            };
+            if role != "assistant" || meta.edit.is_some_and(|e| e.retracted.is_some()) {
+                continue;
+            }
+            if meta.delivery.is_some_and(|d| d.displayed.is_some()) {
+                unread = Some(0);
+            } else if let Some(count) = unread.as_mut() {
+                *count += 1;
+            }
+        }
+        Ok(unread)
+    }
+
+    /// Rewrites the session file with `apply` run on the last message
+    /// matching `msg_id` (message or room stanza id) and its author.
+    fn update_message(
+        &self,
+        jid: &str,
+        msg_id: &str,
+        sender: Option<&str>,
+        occupant_id: Option<&str>,
+        apply: impl FnOnce(&mut SessionEntry),
+    ) -> Result<bool> {
+        let lock = self.session_lock(jid);
+        let _guard = lock.lock().unwrap();
+        let path = self.base_path.join(jid).join("history.jsonl");
+        if !path.exists() {
+            return Ok(false);
+        }
+
+        let content = fs::read_to_string(&path)?;
+        let mut lines: Vec<String> = content.lines().map(String::from).collect();
+        let target = lines.iter().rposition(|line| {
+            matches!(
+                serde_json::from_str(line),
+                Ok(SessionEntry::Message { msg_id: id, sender: s, meta, .. })
+                    if (id.as_deref() == Some(msg_id) || meta.stanza_id.as_deref() == Some(msg_id))
+                        && same_author(s.as_deref(), meta.occupant_id.as_deref(), sender, occupant_id)
+            )
+        });
+        let Some(index) = target else {
+            return Ok(false);
+        };
+
+        let mut entry: SessionEntry = serde_json::from_str(&lines[index])?;
+        apply(&mut entry);
+        lines[index] = serde_json::to_string(&entry)?;
+
+        // Write to a temporary file first so a crash never truncates history
+        let tmp_path = path.with_extension("jsonl.tmp");
+        fs::write(&tmp_path, lines.join("\n") + "\n")?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(true)
+    }
+
+    /// Messages of the current session that have an id, oldest first.
+    /// Retracted messages and reactions are left out.
+    pub fn message_refs(&self, jid: &str) -> Result<Vec<MessageRef>> {
+        let path = self.base_path.join(jid).join("history.jsonl");
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let content = fs::read_to_string(&path)?;
+        Ok(content
+            .lines()
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(SessionEntry::Message {
+                    role,
+                    content,
+                    msg_id,
+                    sender,
+                    reaction: None,
+                    meta,
+                    ..
+                }) if meta.edit.as_ref().is_none_or(|e| e.retracted.is_none()) => {
+                    Some(MessageRef {
+                        id: meta.stanza_id.or(msg_id)?,
+                        role,
+                        sender,
+                        content,
+                    })
+                }
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// The agent's current reactions to `message_id`: the last reaction
+    /// set it sent for that message (reaction updates replace each other).
+    pub fn own_reactions(&self, jid: &str, message_id: &str) -> Result<Vec<String>> {
+        let path = self.base_path.join(jid).join("history.jsonl");
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let content = fs::read_to_string(&path)?;
+        let emojis = content.lines().rev().find_map(|line| match serde_json::from_str(line) {
+            Ok(SessionEntry::Message {
+                role,
+                reaction: Some(reaction),
+                ..
+            }) if role == "assistant" && reaction.message_id == message_id => Some(reaction.emojis),
+            _ => None,
+        });
+        Ok(emojis.unwrap_or_default())
+    }
+
+    /// Id of the agent's reply to the user message `msg_id`, if that reply
+    /// is the last message of the session (nothing was said since).
+    pub fn last_reply_to(&self, jid: &str, msg_id: &str) -> Result<Option<String>> {
+        let path = self.base_path.join(jid).join("history.jsonl");
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&path)?;
+        let messages: Vec<(String, Option<String>)> = content
+            .lines()
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(SessionEntry::Message { role, msg_id, meta, .. })
+                    if meta.edit.as_ref().is_none_or(|e| e.retracted.is_none()) =>
+                {
+                    Some((role, msg_id))
+                }
+                _ => None,
+            })
+            .collect();
+        Ok(match messages.as_slice() {
+            [.., (user, Some(id)), (assistant, reply)]
+                if user == "user" && id == msg_id && assistant == "assistant" =>
+            {
+                reply.clone()
+            }
+            _ => None,
+        })
+    }
+
+    /// Appends an entry to the JSONL session file, writing the session
+    /// header first if the file is new.
+    fn append_entry(&self, jid: &str, entry: &SessionEntry) -> Result<()> {
+        let lock = self.session_lock(jid);
+        let _guard = lock.lock().unwrap();
         let path = self.user_dir(jid)?.join("history.jsonl");
         let is_new = !path.exists();
 
@@ -357,19 +802,7 @@ impl Memory {
             writeln!(file, "{header_json}")?;
         }
 
-        // Only store non-empty attachment lists
-        let attachments = attachments.filter(|a| !a.is_empty());
-
-        let entry = SessionEntry::Message {
-            role: role.to_string(),
-            content: content.to_string(),
-            msg_id: msg_id.map(|s| s.to_string()),
-            sender: sender.map(|s| s.to_string()),
-            ts: Some(chrono::Utc::now().to_rfc3339()),
-            attachments,
-            reaction,
-        };
-        let json = serde_json::to_string(&entry)?;
+        let json = serde_json::to_string(entry)?;
         writeln!(file, "{json}")?;
 
         Ok(())
@@ -397,6 +830,8 @@ impl Memory {
     /// and clears the current history so the LLM starts fresh.
     /// Returns a human-readable summary of what happened.
     pub fn new_session(&self, jid: &str) -> Result<String> {
+        let lock = self.session_lock(jid);
+        let _guard = lock.lock().unwrap();
         let user_dir = self.user_dir(jid)?;
         let history_path = user_dir.join("history.jsonl");
 
@@ -482,6 +917,8 @@ impl Memory {
         let history_jsonl = user_dir.join("history.jsonl");
         let history_md = user_dir.join("history.md");
         if history_jsonl.exists() {
+            let lock = self.session_lock(jid);
+            let _guard = lock.lock().unwrap();
             let content = fs::read_to_string(&history_jsonl)?;
             let count = parse_session(&content).len();
             fs::remove_file(&history_jsonl)?;
@@ -495,6 +932,26 @@ impl Memory {
             }
         }
 
+        // Erase the current session of each thread
+        let threads_dir = user_dir.join("threads");
+        if threads_dir.exists() {
+            let mut thread_count = 0;
+            for entry in fs::read_dir(&threads_dir)? {
+                let entry = entry?;
+                let history = entry.path().join("history.jsonl");
+                if history.exists() {
+                    let session = format!("{jid}/threads/{}", entry.file_name().to_string_lossy());
+                    let lock = self.session_lock(&session);
+                    let _guard = lock.lock().unwrap();
+                    fs::remove_file(&history)?;
+                    thread_count += 1;
+                }
+            }
+            if thread_count > 0 {
+                erased.push(format!("{thread_count} threads"));
+            }
+        }
+
         // Erase user profile (user.md and legacy context.md)
         let user_md = user_dir.join("user.md");
         let context_md = user_dir.join("context.md");
@@ -547,6 +1004,96 @@ impl Memory {
         }
     }
 
+    // ── Threads (XEP-0201) ────────────────────────────────
+
+    /// Memory key of the session of a conversation thread:
+    /// `{jid}/threads/{hash}`, so that each thread gets its own
+    /// `history.jsonl` (and archived sessions) under the JID directory.
+    /// Messages without a thread use the JID's default session.
+    ///
+    /// Thread ids come from the sender and may contain any character: the
+    /// directory is the hex SHA-1 of the raw id, so distinct ids never share
+    /// a session and never escape the JID directory.
+    pub fn thread_session(jid: &str, thread: Option<&str>) -> String {
+        match thread.filter(|t| !t.is_empty()) {
+            Some(thread) => format!("{jid}/threads/{}", hex::encode(Sha1::digest(thread.as_bytes()))),
+            None => jid.to_string(),
+        }
+    }
+
+    /// Records the id of a thread in its session directory (`thread`
+    /// file), since the directory is named after its hash. `/threads`
+    /// lists threads by this id. Does nothing without a thread.
+    pub fn record_thread(&self, jid: &str, thread: Option<&str>) -> Result<()> {
+        let Some(thread) = thread.filter(|t| !t.is_empty()) else {
+            return Ok(());
+        };
+        let path = self.user_dir(&Self::thread_session(jid, Some(thread)))?.join("thread");
+        if !path.exists() {
+            fs::write(&path, thread)?;
+        }
+        Ok(())
+    }
+
+    /// Memory keys of the current sessions of a JID: its default session,
+    /// then those of its threads.
+    pub fn sessions(&self, jid: &str) -> Result<Vec<String>> {
+        let mut sessions = vec![jid.to_string()];
+        let threads_dir = self.base_path.join(jid).join("threads");
+        if threads_dir.exists() {
+            for entry in fs::read_dir(&threads_dir)? {
+                let id = entry?.file_name().to_string_lossy().to_string();
+                sessions.push(format!("{jid}/threads/{id}"));
+            }
+        }
+        Ok(sessions)
+    }
+
+    /// Runs `update` on each current session of a JID until it reports a
+    /// match, for stanzas that refer to a message without telling its
+    /// thread (retractions, receipts and markers).
+    pub fn update_any_session(
+        &self,
+        jid: &str,
+        mut update: impl FnMut(&str) -> Result<bool>,
+    ) -> Result<bool> {
+        for session in self.sessions(jid)? {
+            if update(&session)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Threads of a JID with messages in their current session, most
+    /// recently active first.
+    pub fn threads(&self, jid: &str) -> Result<Vec<ThreadSummary>> {
+        let threads_dir = self.base_path.join(jid).join("threads");
+        if !threads_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut threads = Vec::new();
+        for entry in fs::read_dir(&threads_dir)? {
+            let entry = entry?;
+            let history = entry.path().join("history.jsonl");
+            if !history.exists() {
+                continue;
+            }
+            let dir = entry.file_name().to_string_lossy().to_string();
+            let key = format!("{jid}/threads/{dir}");
+            let messages = self.message_count(&key)?;
+            if messages == 0 {
+                continue;
+            }
+            let (last, _) = self.session_marks(&key)?;
+            let id = fs::read_to_string(entry.path().join("thread")).unwrap_or(dir);
+            threads.push(ThreadSummary { id, messages, last });
+        }
+        threads.sort_by(|a, b| b.last.cmp(&a.last).then_with(|| a.id.cmp(&b.id)));
+        Ok(threads)
+    }
+
     // ── File storage ──────────────────────────────────────
 
     /// Returns the files directory for a JID, creating it if needed.
@@ -557,6 +1104,14 @@ impl Memory {
         Ok(dir)
     }
 
+    /// Returns the sub-agent transcript directory for a JID, creating it if needed.
+    /// Layout: `{base_path}/{jid}/subagents/`
+    pub fn subagents_dir(&self, jid: &str) -> Result<PathBuf> {
+        let dir = self.base_path.join(jid).join("subagents");
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
     /// Number of downloaded files stored for a JID
     pub fn file_count(&self, jid: &str) -> Result<usize> {
         let dir = self.base_path.join(jid).join("files");
@@ -570,6 +1125,130 @@ impl Memory {
         Ok(count)
     }
 
+    /// Lists the files stored for a JID, newest first.
+    ///
+    /// Each file is matched to its `Attachment` entry (by `stored_as`) in
+    /// the current and archived sessions, threads included, to recover the original name,
+    /// MIME type and reception time. Files without a history entry (e.g.
+    /// downloaded before `stored_as` was recorded) are still listed.
+    pub fn stored_files(&self, jid: &str) -> Result<Vec<StoredFile>> {
+        let user_dir = self.base_path.join(jid);
+        let dir = user_dir.join("files");
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        // stored_as → (attachment, message timestamp)
+        let mut known: HashMap<String, (Attachment, Option<String>)> = HashMap::new();
+        let mut session_files = Vec::new();
+        for session in self.sessions(jid)? {
+            let session_dir = self.base_path.join(session);
+            session_files.push(session_dir.join("history.jsonl"));
+            if let Ok(entries) = fs::read_dir(session_dir.join("sessions")) {
+                session_files.extend(
+                    entries
+                        .filter_map(|e| e.ok())
+                        .map(|e| e.path())
+                        .filter(|p| p.extension().is_some_and(|ext| ext == "jsonl")),
+                );
+            }
+        }
+        for path in session_files {
+            let Ok(content) = fs::read_to_string(&path) else {
+                continue;
+            };
+            for line in content.lines() {
+                let Ok(SessionEntry::Message {
+                    attachments: Some(attachments),
+                    ts,
+                    ..
+                }) = serde_json::from_str(line)
+                else {
+                    continue;
+                };
+                for attachment in attachments {
+                    if let Some(stored_as) = attachment.stored_as.clone() {
+                        known.insert(stored_as, (attachment, ts.clone()));
+                    }
+                }
+            }
+        }
+
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&dir)?.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            let stored_as = entry.file_name().to_string_lossy().to_string();
+            let modified = metadata.modified().ok();
+            let file = match known.remove(&stored_as) {
+                Some((attachment, received)) => StoredFile {
+                    filename: attachment.filename,
+                    mime_type: Some(attachment.mime_type),
+                    received,
+                    stored_as,
+                    path,
+                    size: metadata.len(),
+                },
+                None => StoredFile {
+                    filename: strip_uuid_prefix(&stored_as).to_string(),
+                    mime_type: None,
+                    received: None,
+                    stored_as,
+                    path,
+                    size: metadata.len(),
+                },
+            };
+            files.push((modified, file));
+        }
+
+        files.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.stored_as.cmp(&b.1.stored_as)));
+        Ok(files.into_iter().map(|(_, file)| file).collect())
+    }
+
+    /// Returns the last `limit` attachments with a source URL in the
+    /// current session, oldest first.
+    ///
+    /// A URL shared several times is returned once, at its latest position,
+    /// keeping the `stored_as` of any earlier download.
+    pub fn recent_attachments(&self, jid: &str, limit: usize) -> Result<Vec<Attachment>> {
+        let path = self.base_path.join(jid).join("history.jsonl");
+        if limit == 0 || !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let content = fs::read_to_string(&path)?;
+        let mut recent: Vec<Attachment> = Vec::new();
+        for line in content.lines() {
+            let Ok(SessionEntry::Message {
+                attachments: Some(attachments),
+                ..
+            }) = serde_json::from_str(line)
+            else {
+                continue;
+            };
+            for mut attachment in attachments {
+                let Some(url) = attachment.url.as_deref() else {
+                    continue;
+                };
+                if let Some(pos) = recent.iter().position(|a| a.url.as_deref() == Some(url)) {
+                    let previous = recent.remove(pos);
+                    if attachment.stored_as.is_none() {
+                        attachment.stored_as = previous.stored_as;
+                    }
+                }
+                recent.push(attachment);
+            }
+        }
+
+        let start = recent.len().saturating_sub(limit);
+        Ok(recent.split_off(start))
+    }
+
     /// Total number of messages in the current session for a JID
     pub fn message_count(&self, jid: &str) -> Result<usize> {
         let path = self.base_path.join(jid).join("history.jsonl");
@@ -582,6 +1261,37 @@ impl Memory {
         Ok(parse_session(&content).len())
     }
 
+    /// Timestamp and ids of the messages in the current session, used to
+    /// merge archived messages without duplicates. The timestamp is the
+    /// latest one, None for an empty session.
+    pub fn session_marks(&self, jid: &str) -> Result<(Option<String>, HashSet<String>)> {
+        let path = self.base_path.join(jid).join("history.jsonl");
+        if !path.exists() {
+            return Ok((None, HashSet::new()));
+        }
+
+        let content = fs::read_to_string(&path)?;
+        let mut last_ts = None;
+        let mut ids = HashSet::new();
+        for line in content.lines() {
+            let Ok(SessionEntry::Message { msg_id, ts, meta, .. }) = serde_json::from_str(line)
+            else {
+                continue;
+            };
+            ids.extend(msg_id);
+            ids.extend(meta.stanza_id);
+            if ts.is_some() {
+                last_ts = ts;
+            }
+        }
+        Ok((last_ts, ids))
+    }
+
+    /// Whether anything was ever stored for a JID
+    pub fn has_conversation(&self, jid: &str) -> bool {
+        self.base_path.join(jid).is_dir()
+    }
+
     /// Number of archived sessions for a JID
     pub fn session_count(&self, jid: &str) -> Result<usize> {
         let sessions_dir = self.base_path.join(jid).join("sessions");
@@ -717,6 +1427,24 @@ impl Memory {
 
 // ── JSONL session parsing ─────────────────────────────
 
+/// Whether a stored message was written by the given author, for
+/// corrections and retractions. In rooms, the occupant id (XEP-0421)
+/// follows the sender across nick changes and cannot be taken over by
+/// another occupant: when either side has one, both must be equal. The
+/// sender label (`{nick}@muc`, or the bare JID) is compared only when
+/// neither has one.
+fn same_author(
+    stored_sender: Option<&str>,
+    stored_occupant_id: Option<&str>,
+    sender: Option<&str>,
+    occupant_id: Option<&str>,
+) -> bool {
+    match (stored_occupant_id, occupant_id) {
+        (None, None) => stored_sender == sender,
+        (stored, incoming) => stored == incoming,
+    }
+}
+
 /// Builds a `Message` for the LLM from a session entry.
 ///
 /// Only conversational context is passed to the model — runtime metadata
@@ -805,8 +1533,17 @@ fn parse_session(content: &str) -> Vec<Message> {
                 sender,
                 attachments,
                 reaction,
+                meta,
                 ..
             } => {
+                // Retracted messages (XEP-0424) are kept for audit only
+                if meta.edit.is_some_and(|e| e.retracted.is_some()) {
+                    continue;
+                }
+                // Acknowledgement reactions are runtime noise
+                if reaction.as_ref().is_some_and(|r| r.ack) {
+                    continue;
+                }
                 // Reconstruct display text from structured metadata + content
                 let display = build_display_content(&content, &attachments, &reaction);
                 if display.is_empty() {
@@ -830,6 +1567,14 @@ fn parse_session(content: &str) -> Vec<Message> {
     messages
 }
 
+/// Strips the `{uuid}_` prefix of a stored file name, if present.
+fn strip_uuid_prefix(stored_as: &str) -> &str {
+    match stored_as.split_once('_') {
+        Some((prefix, rest)) if uuid::Uuid::parse_str(prefix).is_ok() => rest,
+        _ => stored_as,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1115,6 +1860,230 @@ mod tests {
         assert_eq!(memory.message_count("user@test").unwrap(), 3);
     }
 
+    #[test]
+    fn test_session_marks_with_archived_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory = Memory::open(dir.path()).unwrap();
+
+        let (last, ids) = memory.session_marks("user@test").unwrap();
+        assert!(last.is_none() && ids.is_empty());
+
+        memory
+            .store_archived_message("user@test", "user", "Hi", Some("m1"), Some("user@test"), "2026-10-18T10:00:00+00:00")
+            .unwrap();
+        memory
+            .store_archived_message("user@test", "assistant", "Hello", None, None, "2026-10-18T10:01:00+00:00")
+            .unwrap();
+
+        let (last, ids) = memory.session_marks("user@test").unwrap();
+        assert_eq!(last.as_deref(), Some("2026-10-18T10:01:00+00:00"));
+        assert_eq!(ids, HashSet::from(["m1".to_string()]));
+        assert_eq!(memory.message_count("user@test").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_correct_and_retract_message() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory = Memory::open(dir.path()).unwrap();
+        let jid = "user@test";
+
+        memory.store_message_structured(jid, "user", "Capital of Frence?", Some("m1"), Some(jid)).unwrap();
+        memory.store_message_structured(jid, "assistant", "Did you mean France?", Some("r1"), None).unwrap();
+        assert_eq!(memory.last_reply_to(jid, "m1").unwrap().as_deref(), Some("r1"));
+
+        // Only the sender can correct its message
+        assert!(!memory.correct_message(jid, "m1", Some("other@test"), None, "Hacked").unwrap());
+        assert!(!memory.correct_message(jid, "unknown", Some(jid), None, "x").unwrap());
+        assert!(memory.correct_message(jid, "m1", Some(jid), None, "Capital of France?").unwrap());
+        assert!(memory.correct_message(jid, "m1", Some(jid), None, "Capital of France??").unwrap());
+
+        let history = memory.get_history(jid, 10).unwrap();
+        assert_eq!(text(&history[0].content), "Capital of France??");
+        // The first version is kept for audit
+        let raw = fs::read_to_string(dir.path().join(jid).join("history.jsonl")).unwrap();
+        assert!(raw.contains("\"original\":\"Capital of Frence?\""));
+
+        // Retracted messages leave the LLM context, not the file
+        assert!(memory.retract_message(jid, "m1", Some(jid), None).unwrap());
+        let history = memory.get_history(jid, 10).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].role, "assistant");
+        assert_eq!(memory.last_reply_to(jid, "m1").unwrap(), None);
+        let raw = fs::read_to_string(dir.path().join(jid).join("history.jsonl")).unwrap();
+        assert!(raw.contains("\"retracted\""));
+        assert_eq!(raw.lines().count(), 3);
+    }
+
+    #[test]
+    fn test_record_delivery_and_unread_replies() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory = Memory::open(dir.path()).unwrap();
+        let jid = "user@test";
+
+        memory.store_message_structured(jid, "user", "Hi", Some("m1"), Some(jid)).unwrap();
+        memory.store_message_structured(jid, "assistant", "Hello!", Some("r1"), None).unwrap();
+        memory.store_message_structured(jid, "assistant", "Anything else?", Some("r2"), None).unwrap();
+        // Unknown until the client sends a displayed marker
+        assert_eq!(memory.unread_replies(jid).unwrap(), None);
+
+        // Only the agent's own messages are tracked
+        assert!(!memory.record_delivery(jid, "m1", true).unwrap());
+        assert!(memory.record_delivery(jid, "r1", false).unwrap());
+        assert_eq!(memory.unread_replies(jid).unwrap(), None);
+        assert!(memory.record_delivery(jid, "r1", true).unwrap());
+        assert_eq!(memory.unread_replies(jid).unwrap(), Some(1));
+        assert!(memory.record_delivery(jid, "r2", true).unwrap());
+        assert_eq!(memory.unread_replies(jid).unwrap(), Some(0));
+
+        let raw = fs::read_to_string(dir.path().join(jid).join("history.jsonl")).unwrap();
+        assert!(raw.contains("\"delivery\":{\"received\""));
+        assert_eq!(memory.get_history(jid, 10).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_concurrent_appends_survive_rewrites() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory = Arc::new(Memory::open(dir.path()).unwrap());
+        let jid = "user@test";
+        memory.store_message_structured(jid, "assistant", "Hello!", Some("r1"), None).unwrap();
+
+        let writer = {
+            let memory = Arc::clone(&memory);
+            std::thread::spawn(move || {
+                for i in 0..200 {
+                    memory.store_message(jid, "user", &format!("message {i}")).unwrap();
+                }
+            })
+        };
+        for i in 0..200 {
+            assert!(memory.record_delivery(jid, "r1", i % 2 == 0).unwrap());
+        }
+        writer.join().unwrap();
+
+        assert_eq!(memory.message_count(jid).unwrap(), 201);
+    }
+
+    #[test]
+    fn test_room_message_matched_by_stanza_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory = Memory::open(dir.path()).unwrap();
+        let room = "lobby@conference.localhost";
+
+        let meta = MessageMeta::room(Some("s-1"), None);
+        memory.store_room_message(room, "Oops", Some("m1"), meta, "alice@muc", None).unwrap();
+        assert!(memory.session_marks(room).unwrap().1.contains("s-1"));
+        assert!(!memory.retract_message(room, "s-1", Some("bob@muc"), None).unwrap());
+        assert!(memory.retract_message(room, "s-1", Some("alice@muc"), None).unwrap());
+        assert!(memory.get_history(room, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_room_edits_require_same_occupant_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory = Memory::open(dir.path()).unwrap();
+        let room = "lobby@conference.localhost";
+
+        let meta = MessageMeta::room(Some("s-1"), Some("occ-alice"));
+        memory.store_room_message(room, "Hi", Some("m1"), meta, "alice@muc", None).unwrap();
+        // Someone else who took the nick "alice"
+        let alice = Some("alice@muc");
+        assert!(!memory.correct_message(room, "m1", alice, Some("occ-mallory"), "Hacked").unwrap());
+        assert!(!memory.retract_message(room, "s-1", alice, None).unwrap());
+        // The same occupant, renamed since
+        assert!(memory.correct_message(room, "m1", Some("alicia@muc"), Some("occ-alice"), "Hello").unwrap());
+
+        // A message stored without occupant id only matches by nick
+        let meta = MessageMeta::room(Some("s-2"), None);
+        memory.store_room_message(room, "Yo", Some("m2"), meta, "bob@muc", None).unwrap();
+        assert!(!memory.retract_message(room, "s-2", Some("bob@muc"), Some("occ-bob")).unwrap());
+        assert!(memory.retract_message(room, "s-2", Some("bob@muc"), None).unwrap());
+
+        let history = memory.get_history(room, 10).unwrap();
+        let MessageContent::Text(text) = &history[0].content else {
+            panic!("Expected text");
+        };
+        assert_eq!(history.len(), 1);
+        assert!(text.ends_with("Hello"));
+    }
+
+    #[test]
+    fn test_thread_sessions_are_separate() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory = Memory::open(dir.path()).unwrap();
+        let jid = "user@test";
+
+        assert_eq!(Memory::thread_session(jid, None), jid);
+        assert_eq!(Memory::thread_session(jid, Some("")), jid);
+        let t1 = "b38304293448f1f88fb12aa2d2e855f066c0c3d7";
+        assert_eq!(Memory::thread_session(jid, Some("t-1")), format!("user@test/threads/{t1}"));
+        // Thread ids never escape the JID directory, nor share one
+        assert!(!Memory::thread_session(jid, Some("../x")).contains(".."));
+        assert_ne!(
+            Memory::thread_session(jid, Some("a/b")),
+            Memory::thread_session(jid, Some("a_b"))
+        );
+
+        let thread = Memory::thread_session(jid, Some("t-1"));
+        memory.store_message(jid, "user", "Default session").unwrap();
+        memory.store_message(&thread, "user", "In the thread").unwrap();
+        memory.store_message(&thread, "assistant", "Thread answer").unwrap();
+        assert_eq!(memory.get_history(jid, 10).unwrap().len(), 1);
+        assert_eq!(memory.get_history(&thread, 10).unwrap().len(), 2);
+        assert!(dir.path().join(format!("user@test/threads/{t1}/history.jsonl")).exists());
+
+        let threads = memory.threads(jid).unwrap();
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].id, t1);
+        assert_eq!(threads[0].messages, 2);
+        assert!(threads[0].last.is_some());
+
+        // Listed by their id once it is recorded
+        memory.record_thread(jid, Some("t-1")).unwrap();
+        memory.record_thread(jid, None).unwrap();
+        assert_eq!(memory.threads(jid).unwrap()[0].id, "t-1");
+
+        let result = memory.forget(jid).unwrap();
+        assert!(result.contains("1 threads"));
+        assert!(memory.threads(jid).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_message_refs_and_own_reactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory = Memory::open(dir.path()).unwrap();
+        let room = "room@muc.test";
+
+        memory
+            .store_room_message(room, "Ship it?", Some("m1"), MessageMeta::room(Some("s-1"), None), "alice@muc", None)
+            .unwrap();
+        memory
+            .store_message_structured(room, "assistant", "Yes", Some("out-1"), None)
+            .unwrap();
+        memory.store_message_structured(room, "user", "no id", None, None).unwrap();
+        let ack = |emojis: &[&str]| Reaction {
+            message_id: "s-1".to_string(),
+            emojis: emojis.iter().map(|e| e.to_string()).collect(),
+            ack: true,
+        };
+        memory
+            .store_message_full(room, "assistant", "", None, None, None, Some(ack(&["👀"])))
+            .unwrap();
+        memory
+            .store_message_full(room, "assistant", "", None, None, None, Some(ack(&["✅"])))
+            .unwrap();
+
+        // Room messages are referred to by stanza id; reactions are not messages
+        let refs = memory.message_refs(room).unwrap();
+        let ids: Vec<&str> = refs.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["s-1", "out-1"]);
+        assert_eq!(refs[0].sender.as_deref(), Some("alice@muc"));
+
+        // The last update is the current set; acknowledgements stay out of the LLM context
+        assert_eq!(memory.own_reactions(room, "s-1").unwrap(), ["✅"]);
+        assert!(memory.own_reactions(room, "out-1").unwrap().is_empty());
+        assert_eq!(memory.get_history(room, 10).unwrap().len(), 3);
+    }
+
     // ── Session tests ─────────────────────────────────────
 
     #[test]
@@ -1534,6 +2503,123 @@ mod tests {
         assert_eq!(memory.file_count("user@test").unwrap(), 2);
     }
 
+    #[test]
+    fn test_stored_files_maps_history_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory = Memory::open(dir.path()).unwrap();
+        let jid = "user@test";
+        let files_dir = memory.files_dir(jid).unwrap();
+        assert!(memory.stored_files(jid).unwrap().is_empty());
+
+        let uuid = "0b5e4a8c-6f0e-4c1e-9d55-2f1f4b1c7a10";
+        let known = format!("{uuid}_invoice.pdf");
+        fs::write(files_dir.join(&known), b"%PDF").unwrap();
+        // Downloaded before `stored_as` was recorded: no history entry
+        fs::write(files_dir.join(format!("{uuid}_old_notes.txt")), b"notes").unwrap();
+
+        memory
+            .store_message_full(
+                jid,
+                "user",
+                "Here is the invoice",
+                None,
+                Some(jid),
+                Some(vec![Attachment {
+                    filename: "Invoice March.pdf".to_string(),
+                    mime_type: "application/pdf".to_string(),
+                    size: "4B".to_string(),
+                    sent_size: None,
+                    stored_as: Some(known.clone()),
+                    url: None,
+                    transcript: None,
+                }]),
+                None,
+            )
+            .unwrap();
+        // Entries of archived sessions are found too
+        memory.new_session(jid).unwrap();
+
+        let files = memory.stored_files(jid).unwrap();
+        assert_eq!(files.len(), 2);
+        let invoice = files.iter().find(|f| f.stored_as == known).unwrap();
+        assert_eq!(invoice.filename, "Invoice March.pdf");
+        assert_eq!(invoice.mime_type.as_deref(), Some("application/pdf"));
+        assert_eq!(invoice.size, 4);
+        assert!(invoice.received.is_some());
+        let notes = files.iter().find(|f| f.stored_as != known).unwrap();
+        assert_eq!(notes.filename, "old_notes.txt");
+        assert_eq!(notes.mime_type, None);
+
+        // Files sent inside a thread are described by the thread's session
+        let in_thread = format!("{uuid}_plan.txt");
+        fs::write(files_dir.join(&in_thread), b"plan").unwrap();
+        let thread = Memory::thread_session(jid, Some("t-1"));
+        let attachment = Attachment {
+            filename: "Plan.txt".to_string(),
+            mime_type: "text/plain".to_string(),
+            size: "4B".to_string(),
+            sent_size: None,
+            stored_as: Some(in_thread.clone()),
+            url: None,
+            transcript: None,
+        };
+        memory
+            .store_message_full(&thread, "user", "", None, Some(jid), Some(vec![attachment]), None)
+            .unwrap();
+        let files = memory.stored_files(jid).unwrap();
+        let plan = files.iter().find(|f| f.stored_as == in_thread).unwrap();
+        assert_eq!(plan.filename, "Plan.txt");
+
+        // Other JIDs see nothing
+        assert!(memory.stored_files("other@test").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_recent_attachments() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory = Memory::open(dir.path()).unwrap();
+        let room = "lobby@conference.test";
+        let shared = |name: &str, stored_as: Option<&str>| Attachment {
+            filename: name.to_string(),
+            mime_type: "unknown".to_string(),
+            size: "unknown".to_string(),
+            sent_size: None,
+            stored_as: stored_as.map(str::to_string),
+            url: Some(format!("https://upload.test/{name}")),
+            transcript: None,
+        };
+        let store = |attachments: Vec<Attachment>| {
+            memory
+                .store_message_full(room, "user", "", None, Some("alice@muc"), Some(attachments), None)
+                .unwrap();
+        };
+
+        assert!(memory.recent_attachments(room, 3).unwrap().is_empty());
+
+        store(vec![shared("a.png", Some("u1_a.png")), shared("b.pdf", None)]);
+        store(vec![shared("c.txt", None)]);
+        // Re-shared URL moves to the end and keeps the earlier download
+        store(vec![shared("a.png", None)]);
+        memory.store_message(room, "user", "no files here").unwrap();
+
+        let recent = memory.recent_attachments(room, 2).unwrap();
+        let names: Vec<&str> = recent.iter().map(|a| a.filename.as_str()).collect();
+        assert_eq!(names, vec!["c.txt", "a.png"]);
+        assert_eq!(recent[1].stored_as.as_deref(), Some("u1_a.png"));
+
+        assert_eq!(memory.recent_attachments(room, 10).unwrap().len(), 3);
+        assert!(memory.recent_attachments(room, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_strip_uuid_prefix() {
+        assert_eq!(
+            strip_uuid_prefix("0b5e4a8c-6f0e-4c1e-9d55-2f1f4b1c7a10_my_file.txt"),
+            "my_file.txt"
+        );
+        assert_eq!(strip_uuid_prefix("my_file.txt"), "my_file.txt");
+    }
+
     #[test]
     fn test_forget_erases_files() {
         let dir = tempfile::tempdir().unwrap();
@@ -1696,6 +2782,7 @@ not valid json
             ts: Some("2025-02-08T19:00:00Z".to_string()),
             attachments: None,
             reaction: None,
+            meta: Box::default(),
         };
 
         let json = serde_json::to_string(&entry).unwrap();
@@ -1713,6 +2800,7 @@ not valid json
             ts: None,
             attachments: None,
             reaction: None,
+            meta: Box::default(),
         };
 
         let json = serde_json::to_string(&entry).unwrap();
@@ -1720,6 +2808,15 @@ not valid json
         assert!(!json.contains("sender"));
         assert!(!json.contains("ts"));
         assert!(!json.contains("attachments"));
+        assert!(!json.contains("meta"));
+
+        // Boxed metadata is written as plain fields of the line
+        let line = r#"{"type":"message","role":"user","content":"Hi","stanza_id":"s-1","edit":{"retracted":"2025-02-08T19:00:00Z"}}"#;
+        let SessionEntry::Message { meta, .. } = serde_json::from_str(line).unwrap() else {
+            panic!("Expected Message");
+        };
+        assert_eq!(meta.stanza_id.as_deref(), Some("s-1"));
+        assert!(meta.edit.unwrap().retracted.is_some());
     }
 
     #[test]
@@ -1752,8 +2849,13 @@ not valid json
                 filename: "photo.png".to_string(),
                 mime_type: "image/png".to_string(),
                 size: "926KB".to_string(),
+                sent_size: None,
+                stored_as: None,
+                url: None,
+                transcript: None,
             }]),
             reaction: None,
+            meta: Box::default(),
         };
 
         let json = serde_json::to_string(&entry).unwrap();
@@ -1778,7 +2880,9 @@ not valid json
             reaction: Some(Reaction {
                 message_id: "msg-001".to_string(),
                 emojis: vec!["👍".to_string(), "🎉".to_string()],
+                ack: false,
             }),
+            meta: Box::default(),
         };
 
         let json = serde_json::to_string(&entry).unwrap();
@@ -1815,11 +2919,19 @@ not valid json
                 filename: "photo.png".to_string(),
                 mime_type: "image/png".to_string(),
                 size: "926KB".to_string(),
+                sent_size: None,
+                stored_as: None,
+                url: None,
+                transcript: None,
             },
             Attachment {
                 filename: "doc.pdf".to_string(),
                 mime_type: "application/pdf".to_string(),
                 size: "1.2MB".to_string(),
+                sent_size: None,
+                stored_as: None,
+                url: None,
+                transcript: None,
             },
         ];
 
@@ -1855,6 +2967,10 @@ not valid json
             filename: "photo.png".to_string(),
             mime_type: "image/png".to_string(),
             size: "926KB".to_string(),
+            sent_size: None,
+            stored_as: None,
+            url: None,
+            transcript: None,
         }];
 
         memory
@@ -1878,6 +2994,28 @@ not valid json
         assert!(msg_text.contains("What is this?"));
     }
 
+    #[test]
+    fn test_attachment_sent_size_serialization() {
+        let mut att = Attachment {
+            filename: "photo.jpg".to_string(),
+            mime_type: "image/jpeg".to_string(),
+            size: "4.1MB".to_string(),
+            sent_size: None,
+            stored_as: None,
+            url: None,
+            transcript: None,
+        };
+        let json = serde_json::to_string(&att).unwrap();
+        assert!(!json.contains("sent_size"));
+        // Entries written before `sent_size` existed still parse
+        let parsed: Attachment = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, att);
+
+        att.sent_size = Some("312KB".to_string());
+        let json = serde_json::to_string(&att).unwrap();
+        assert!(json.contains(r#""sent_size":"312KB""#));
+    }
+
     #[test]
     fn test_get_history_attachment_only_no_body() {
         let dir = tempfile::tempdir().unwrap();
@@ -1887,6 +3025,10 @@ not valid json
             filename: "photo.png".to_string(),
             mime_type: "image/png".to_string(),
             size: "500KB".to_string(),
+            sent_size: None,
+            stored_as: None,
+            url: None,
+            transcript: None,
         }];
 
         memory
@@ -1913,11 +3055,19 @@ not valid json
                 filename: "a.jpg".to_string(),
                 mime_type: "image/jpeg".to_string(),
                 size: "200KB".to_string(),
+                sent_size: None,
+                stored_as: None,
+                url: None,
+                transcript: None,
             },
             Attachment {
                 filename: "b.pdf".to_string(),
                 mime_type: "application/pdf".to_string(),
                 size: "1MB".to_string(),
+                sent_size: None,
+                stored_as: None,
+                url: None,
+                transcript: None,
             },
         ]);
         let result = build_display_content("Check these", &atts, &None);
@@ -1932,6 +3082,7 @@ not valid json
         let reaction = Some(Reaction {
             message_id: "msg-001".to_string(),
             emojis: vec!["👍".to_string()],
+            ack: false,
         });
         let result = build_display_content("", &None, &reaction);
         assert!(result.contains(r#""message_id":"msg-001""#));
@@ -2040,6 +3191,7 @@ not valid json
         let reaction = Reaction {
             message_id: "msg-001".to_string(),
             emojis: vec!["👍".to_string()],
+            ack: false,
         };
         memory
             .store_message_full(
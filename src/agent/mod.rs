@@ -1,3 +1,9 @@
+pub mod adhoc;
+pub mod extract;
 pub mod files;
+pub mod images;
 pub mod memory;
+pub mod occupants;
 pub mod runtime;
+pub mod subagent;
+pub mod transcribe;
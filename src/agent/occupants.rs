@@ -0,0 +1,204 @@
+//! Room occupants (XEP-0045), tracked from the presences rooms send.
+//!
+//! On join, a room sends the presence of every occupant, then ours
+//! (status 110), and one presence per later change. Each carries the
+//! occupant's role and affiliation, the real JID when the room discloses
+//! it, and a stable occupant id (XEP-0421) in rooms supporting it.
+//! The roster is kept in memory and rebuilt on every join.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use crate::xmpp::stanzas::{IncomingPresence, MucAffiliation, MucRole, PresenceType};
+
+/// An occupant of a room
+#[derive(Debug, Clone, PartialEq)]
+pub struct Occupant {
+    pub nick: String,
+    /// Real JID, when the room discloses it
+    pub jid: Option<String>,
+    pub role: MucRole,
+    pub affiliation: MucAffiliation,
+    /// Stable occupant id (XEP-0421)
+    pub occupant_id: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Room {
+    /// Our nick, as confirmed by the room (it may differ from the one
+    /// requested, status 210)
+    own_nick: Option<String>,
+    /// Occupants by nick, including the agent
+    occupants: BTreeMap<String, Occupant>,
+}
+
+/// Occupants of the rooms the agent is in.
+#[derive(Debug, Default)]
+pub struct OccupantRoster {
+    rooms: Mutex<HashMap<String, Room>>,
+}
+
+impl OccupantRoster {
+    /// Applies a presence from `room@service/nick`. Presences without
+    /// `muc#user` data (contacts, the room itself) are ignored.
+    pub fn apply(&self, presence: &IncomingPresence) {
+        let Some(muc) = &presence.muc else {
+            return;
+        };
+        let Some((room_jid, nick)) = presence.from.split_once('/') else {
+            return;
+        };
+        let mut rooms = self.rooms.lock().unwrap();
+        let room = rooms.entry(room_jid.to_string()).or_default();
+
+        if presence.presence_type == PresenceType::Unavailable {
+            room.occupants.remove(nick);
+            if muc.is_self() {
+                match &muc.new_nick {
+                    // Our nick changed (303): the new presence follows
+                    Some(new_nick) => room.own_nick = Some(new_nick.clone()),
+                    // We left or were removed from the room
+                    None => {
+                        rooms.remove(room_jid);
+                    }
+                }
+            }
+            return;
+        }
+        if presence.presence_type != PresenceType::Available {
+            return;
+        }
+
+        if muc.is_self() {
+            room.own_nick = Some(nick.to_string());
+        }
+        room.occupants.insert(
+            nick.to_string(),
+            Occupant {
+                nick: nick.to_string(),
+                jid: muc.jid.clone(),
+                role: muc.role,
+                affiliation: muc.affiliation,
+                occupant_id: muc.occupant_id.clone(),
+            },
+        );
+    }
+
+    /// Our nick in a room, once the room confirmed the join.
+    pub fn own_nick(&self, room_jid: &str) -> Option<String> {
+        self.rooms.lock().unwrap().get(room_jid)?.own_nick.clone()
+    }
+
+    /// Our own occupant entry in a room.
+    pub fn own_occupant(&self, room_jid: &str) -> Option<Occupant> {
+        let rooms = self.rooms.lock().unwrap();
+        let room = rooms.get(room_jid)?;
+        room.occupants.get(room.own_nick.as_deref()?).cloned()
+    }
+
+    /// An occupant of a room, by nick.
+    pub fn get(&self, room_jid: &str, nick: &str) -> Option<Occupant> {
+        self.rooms.lock().unwrap().get(room_jid)?.occupants.get(nick).cloned()
+    }
+
+    /// The occupants of a room other than the agent, by nick.
+    pub fn others(&self, room_jid: &str) -> Vec<Occupant> {
+        let rooms = self.rooms.lock().unwrap();
+        let Some(room) = rooms.get(room_jid) else {
+            return Vec::new();
+        };
+        room.occupants
+            .values()
+            .filter(|o| Some(&o.nick) != room.own_nick.as_ref())
+            .cloned()
+            .collect()
+    }
+
+    /// Forgets every room, e.g. after a disconnection: rooms send their
+    /// occupants again when rejoined.
+    pub fn clear(&self) {
+        self.rooms.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xmpp::stanzas::MucPresence;
+
+    const ROOM: &str = "lobby@conference.localhost";
+
+    fn presence(nick: &str, presence_type: PresenceType, muc: MucPresence) -> IncomingPresence {
+        IncomingPresence {
+            from: format!("{ROOM}/{nick}"),
+            presence_type,
+            muc: Some(muc),
+        }
+    }
+
+    #[test]
+    fn test_roster_tracks_join_nick_change_and_leave() {
+        let roster = OccupantRoster::default();
+        roster.apply(&presence(
+            "alice",
+            PresenceType::Available,
+            MucPresence {
+                role: MucRole::Moderator,
+                affiliation: MucAffiliation::Owner,
+                jid: Some("alice@localhost/phone".to_string()),
+                occupant_id: Some("occ-a".to_string()),
+                ..Default::default()
+            },
+        ));
+        roster.apply(&presence(
+            "bot-2",
+            PresenceType::Available,
+            MucPresence {
+                role: MucRole::Participant,
+                status_codes: vec![110, 210],
+                occupant_id: Some("occ-bot".to_string()),
+                ..Default::default()
+            },
+        ));
+        assert_eq!(roster.own_nick(ROOM).as_deref(), Some("bot-2"));
+        assert_eq!(roster.own_occupant(ROOM).unwrap().occupant_id.as_deref(), Some("occ-bot"));
+        let alice = roster.get(ROOM, "alice").unwrap();
+        assert_eq!(alice.jid.as_deref(), Some("alice@localhost/phone"));
+        assert_eq!(alice.role, MucRole::Moderator);
+        assert_eq!(roster.others(ROOM), [alice]);
+
+        // Nick change (303): unavailable for the old nick, then the new one
+        roster.apply(&presence(
+            "alice",
+            PresenceType::Unavailable,
+            MucPresence {
+                new_nick: Some("alicia".to_string()),
+                status_codes: vec![303],
+                ..Default::default()
+            },
+        ));
+        roster.apply(&presence("alicia", PresenceType::Available, MucPresence::default()));
+        let nicks: Vec<String> = roster.others(ROOM).into_iter().map(|o| o.nick).collect();
+        assert_eq!(nicks, ["alicia"]);
+
+        // Presences without muc#user data are not occupants
+        roster.apply(&IncomingPresence {
+            from: "carol@localhost/pc".to_string(),
+            presence_type: PresenceType::Available,
+            muc: None,
+        });
+        assert!(roster.others("carol@localhost").is_empty());
+
+        // Our own unavailable presence: we left the room
+        roster.apply(&presence(
+            "bot-2",
+            PresenceType::Unavailable,
+            MucPresence {
+                status_codes: vec![110],
+                ..Default::default()
+            },
+        ));
+        assert_eq!(roster.own_nick(ROOM), None);
+        assert!(roster.others(ROOM).is_empty());
+    }
+}
@@ -1,22 +1,39 @@
-use std::sync::Arc;
-use std::time::Duration;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use chrono::Local;
 use tokio::sync::mpsc;
 use tracing::{debug, error, info, warn};
 
-use crate::agent::files::{file_to_content_block, FileDownloader};
-use crate::config::Config;
+use crate::agent::files::{self, file_to_content_block, DownloadedFile, FileDownloader};
+use crate::config::{
+    Activation, AttachmentsConfig, CatchUpPolicy, Config, ConnectionMode, HistorySource, JoinHistory,
+    MamConfig, ReactionsConfig, RoomConfig,
+};
+use crate::llm::tiers::{self, RouteSignals};
 use crate::llm::{
-    InputContentBlock, LlmClient, Message, MessageContent, StopReason, ToolDefinition,
+    GenerationParams, InputContentBlock, LlmClient, LlmRegistry, Message, MessageContent, StopReason, Tier,
+    ToolDefinition,
 };
 use crate::xmpp::component::{ChatState, DisconnectReason, XmppCommand, XmppEvent};
-use crate::xmpp::stanzas::{self, MessageType, OobData, PresenceType};
+use crate::xmpp::iq::{self, IqReply, IqRouter, IqTracker};
+use crate::xmpp::mam::MamClient;
+use crate::xmpp::{split, styling};
+use crate::xmpp::upload::HttpUploader;
+use crate::xmpp::stanzas::{
+    self, ArchivedMessage, CarbonDirection, CarbonMessage, ChatMarker, ChildElement, CommandForm, CommandRequest, CommandResponse, DiscoIdentity, DiscoInfo,
+    FormField, IncomingIq, IncomingMarker, IncomingMessage, IncomingRetraction, IqType, MessageReply, MessageType, MucHistory, OobData, PresenceType, NS_COMMANDS, NS_DATA,
+    NS_DISCO_INFO, NS_DISCO_ITEMS,
+};
 
-use crate::skills::{SkillContext, SkillRegistry};
+use crate::skills::{OutgoingFile, OutgoingReaction, SkillContext, SkillRegistry};
 
-use super::memory::{build_message_for_llm, Attachment, Memory, Reaction, WorkspaceContext};
+use super::adhoc::{self, Access, AdhocSessions, ManageAction, Step};
+use super::memory::{build_message_for_llm, Attachment, Memory, MessageMeta, Reaction, WorkspaceContext};
+use super::occupants::OccupantRoster;
+use super::subagent::{self, SpawnContext, SPAWN_AGENT_TOOL};
 
 /// Maximum number of history messages sent to the LLM
 const MAX_HISTORY: usize = 20;
@@ -31,27 +48,69 @@ const MAX_TOOL_ROUNDS: usize = 10;
 /// calls the LLM, and sends back responses.
 pub struct AgentRuntime {
     config: Config,
-    llm: Arc<dyn LlmClient>,
+    llms: Arc<LlmRegistry>,
     memory: Arc<Memory>,
     file_downloader: Arc<FileDownloader>,
     skills: Arc<SkillRegistry>,
+    /// Tiers pinned per conversation with `/tier` (bare JID → tier).
+    /// Kept in memory only — pins reset on restart.
+    tier_pins: Mutex<HashMap<String, Tier>>,
+    /// Last reasoning summary per conversation (`/reasoning`).
+    reasoning: Arc<ReasoningLog>,
+    /// Outbound IQs waiting for their result (disco, upload slots).
+    iq: Arc<IqTracker>,
+    /// Answers incoming IQ requests (disco, ping, version, time).
+    iq_router: IqRouter,
+    /// Multi-step ad-hoc commands (XEP-0050) waiting for a form.
+    adhoc: AdhocSessions,
+    /// XEP-0363 uploads for files returned by skills.
+    uploader: Arc<HttpUploader>,
+    /// Archive queries (XEP-0313).
+    mam: Arc<MamClient>,
+    /// Contacts whose archive was already fetched in this run, so only
+    /// their first message waits for the history import.
+    mam_contacts: Mutex<HashSet<String>>,
+    /// Last reply sent by a human operator from another client, per
+    /// contact (bare JID), for `[carbons] takeover_mins`.
+    takeovers: Mutex<HashMap<String, Instant>>,
+    /// Occupants of the joined rooms, from their presences.
+    occupants: OccupantRoster,
     start_time: std::time::Instant,
 }
 
 impl AgentRuntime {
     pub fn new(
         config: Config,
-        llm: Arc<dyn LlmClient>,
+        llms: LlmRegistry,
         memory: Arc<Memory>,
         file_downloader: Arc<FileDownloader>,
         skills: SkillRegistry,
     ) -> Self {
+        let iq = Arc::new(IqTracker::new());
+        let uploader = Arc::new(HttpUploader::new(&config, Arc::clone(&iq)));
+        let mam = Arc::new(MamClient::new(Arc::clone(&iq)));
+        let component = matches!(config.server.mode, ConnectionMode::Component { .. });
+        let iq_router = IqRouter::new(iq::agent_disco_info(
+            &config.agent.name,
+            component,
+            &skills.skill_names(),
+        ));
         Self {
             config,
-            llm,
+            llms: Arc::new(llms),
             memory,
             file_downloader,
             skills: Arc::new(skills),
+            tier_pins: Mutex::new(HashMap::new()),
+            reasoning: Arc::new(ReasoningLog::default()),
+            iq,
+            iq_router,
+            adhoc: AdhocSessions::new(),
+            uploader,
+            mam,
+            mam_contacts: Mutex::new(HashSet::new()),
+            takeovers: Mutex::new(HashMap::new()),
+            occupants: OccupantRoster::default(),
             start_time: std::time::Instant::now(),
         }
     }
@@ -76,26 +135,56 @@ impl AgentRuntime {
         // send a ping right after connecting.
         ping_interval.tick().await;
 
+        // Archived history (XEP-0313) is fetched by spawned tasks and
+        // imported here, so memory writes stay on the event loop
+        let (sync_tx, mut sync_rx) = mpsc::channel::<ArchiveSync>(16);
+        // Messages held back until their sender's history was imported
+        let mut deferred: VecDeque<XmppEvent> = VecDeque::new();
+
         loop {
-            let event = tokio::select! {
-                event = event_rx.recv() => {
-                    match event {
-                        Some(e) => e,
-                        None => return Ok(DisconnectReason::ConnectionLost),
+            let event = match deferred.pop_front() {
+                Some(event) => event,
+                None => tokio::select! {
+                    event = event_rx.recv() => {
+                        match event {
+                            Some(e) => e,
+                            None => return Ok(DisconnectReason::ConnectionLost),
+                        }
                     }
-                }
-                _ = ping_interval.tick(), if ping_enabled => {
-                    if cmd_tx.send(XmppCommand::Ping).await.is_err() {
-                        warn!("Failed to send keepalive ping (channel closed)");
-                        return Ok(DisconnectReason::ConnectionLost);
+                    Some(sync) = sync_rx.recv() => {
+                        if let Some(msg) = self.apply_archive_sync(sync, &cmd_tx).await {
+                            deferred.push_back(XmppEvent::Message(msg));
+                        }
+                        continue;
                     }
-                    continue;
-                }
+                    _ = ping_interval.tick(), if ping_enabled => {
+                        if cmd_tx.send(XmppCommand::Ping).await.is_err() {
+                            warn!("Failed to send keepalive ping (channel closed)");
+                            return Ok(DisconnectReason::ConnectionLost);
+                        }
+                        continue;
+                    }
+                },
             };
 
             match event {
                 XmppEvent::Connected => {
                     info!("✓ Agent is online and ready");
+                    // The upload service may differ after a reconnect, and
+                    // rooms send their occupants again when rejoined
+                    self.uploader.reset();
+                    self.occupants.clear();
+
+                    // Initial presence with entity capabilities (XEP-0115).
+                    // Components have no presence broadcast of their own.
+                    let caps = self.iq_router.caps().clone();
+                    if !matches!(self.config.server.mode, ConnectionMode::Component { .. }) {
+                        let _ = cmd_tx
+                            .send(XmppCommand::SendPresence {
+                                caps: Some(caps.clone()),
+                            })
+                            .await;
+                    }
 
                     // Join configured MUC rooms (XEP-0045)
                     for room in &self.config.rooms {
@@ -104,9 +193,37 @@ impl AgentRuntime {
                             .send(XmppCommand::JoinMuc {
                                 room: room.jid.clone(),
                                 nick: room.nick.clone(),
+                                history: self.join_history(room),
+                                caps: Some(caps.clone()),
                             })
                             .await;
                     }
+
+                    // Copies of messages handled by other clients (XEP-0280)
+                    if self.config.carbons.enabled {
+                        if let Some(account) = self.account_jid() {
+                            let iq = Arc::clone(&self.iq);
+                            let cmd_tx = cmd_tx.clone();
+                            let account = account.to_string();
+                            tokio::spawn(async move {
+                                let enable = stanzas::build_carbons_enable();
+                                match iq.request(&cmd_tx, &account, IqType::Set, enable).await {
+                                    Ok(result) if result.iq_type == IqType::Result => {
+                                        info!("Message carbons enabled");
+                                    }
+                                    Ok(_) => warn!("Server refused to enable message carbons"),
+                                    Err(e) => warn!("Failed to enable message carbons: {e}"),
+                                }
+                            });
+                        }
+                    }
+
+                    // Bootstrap and catch up from the server archives
+                    if self.config.mam.enabled {
+                        for target in self.archive_targets() {
+                            self.spawn_archive_fetch(target, None, &sync_tx, &cmd_tx);
+                        }
+                    }
                 }
                 XmppEvent::Message(msg) => {
                     let bare_from = stanzas::bare_jid(&msg.from);
@@ -122,40 +239,242 @@ impl AgentRuntime {
                             }
                         };
 
-                        // Filter self-messages (MUC reflects bot's own messages)
+                        // Our nick as confirmed by the room, which may change it
+                        let nick = self
+                            .occupants
+                            .own_nick(bare_from)
+                            .unwrap_or_else(|| room_config.nick.clone());
+
+                        // Filter self-messages (MUC reflects bot's own messages),
+                        // by nick or occupant id (XEP-0421)
                         let sender_nick = msg.from.split('/').nth(1).unwrap_or("");
-                        if sender_nick == room_config.nick {
+                        let own_id = self.occupants.own_occupant(bare_from).and_then(|o| o.occupant_id);
+                        if sender_nick == nick || (own_id.is_some() && msg.occupant_id == own_id) {
+                            continue;
+                        }
+
+                        // Replayed history overlapping what we stored is skipped
+                        let delayed = msg.delay.is_some();
+                        if delayed && self.is_known_message(bare_from, &msg) {
+                            debug!("Skipping already stored message from {} in {bare_from}", msg.from);
                             continue;
                         }
 
-                        // Store ALL room messages to history (for full context)
+                        // Threaded messages (XEP-0201) have their own session
+                        let session = Memory::thread_session(bare_from, msg.thread.as_deref());
+                        if let Err(e) = self.memory.record_thread(bare_from, msg.thread.as_deref()) {
+                            warn!("Failed to record thread of {bare_from}: {e}");
+                        }
+
+                        // Corrections (XEP-0308) update the stored message and
+                        // are never answered. Unknown originals are new messages.
+                        if let Some(replace_id) = &msg.replace_id {
+                            let sender = format!("{sender_nick}@muc");
+                            let corrected = self.memory.correct_message(
+                                &session,
+                                replace_id,
+                                Some(&sender),
+                                msg.occupant_id.as_deref(),
+                                &msg.body,
+                            );
+                            match corrected {
+                                Ok(true) => {
+                                    debug!("{sender_nick} corrected message {replace_id} in {bare_from}");
+                                    continue;
+                                }
+                                Ok(false) => {}
+                                Err(e) => {
+                                    error!("Failed to store MUC correction: {e}");
+                                    continue;
+                                }
+                            }
+                        }
+
+                        // Mentions (references or the nick) are answered, or
+                        // every message or keywords with the room's
+                        // `activation`; replayed history only with
+                        // `answer_delayed`. In rooms with
+                        // `download_attachments`, the message's files (and
+                        // recent earlier ones) are downloaded for the LLM.
+                        let mentioned = mentions_agent(bare_from, &nick, &msg);
+                        let activated = activates(&room_config, mentioned, &msg.body);
+                        let answer = activated && (!delayed || room_config.answer_delayed);
+                        let clean_body = strip_mention(&nick, &msg.body);
+                        let (trigger, clean_body) = self.split_tier_trigger(&clean_body);
+                        let is_command = trigger.is_none() && clean_body.starts_with('/');
+                        let download = answer && !is_command && room_config.download_attachments;
+
+                        // Earlier attachments are looked up before this message is stored
+                        let earlier = if download {
+                            self.memory
+                                .recent_attachments(&session, room_config.recent_attachments)
+                                .unwrap_or_else(|e| {
+                                    warn!("Failed to read recent attachments in {bare_from}: {e}");
+                                    Vec::new()
+                                })
+                        } else {
+                            Vec::new()
+                        };
+                        let download = download && (!msg.oob.is_empty() || !earlier.is_empty());
+
+                        // Store ALL room messages to history (for full context).
+                        // Messages with downloaded files are stored once processed.
                         let sender_label = format!("{sender_nick}@muc");
-                        let muc_attachments = build_oob_attachments(&msg.oob);
-                        if let Err(e) = self.memory.store_message_full(
-                            bare_from,
-                            "user",
-                            &msg.body,
-                            msg.id.as_deref(),
-                            Some(&sender_label),
-                            muc_attachments,
-                            None,
-                        ) {
-                            error!("Failed to store MUC message: {e}");
+                        if !download {
+                            let muc_attachments = build_oob_attachments(&msg.oob);
+                            if let Err(e) = self.memory.store_room_message(
+                                &session,
+                                &msg.body,
+                                msg.id.as_deref(),
+                                MessageMeta::room(msg.stanza_id.as_deref(), msg.occupant_id.as_deref()),
+                                &sender_label,
+                                muc_attachments,
+                            ) {
+                                error!("Failed to store MUC message: {e}");
+                            }
                         }
 
-                        // Only respond if the bot is mentioned
-                        if !is_mentioned(&room_config.nick, &msg.body) {
+                        if !answer {
+                            if activated {
+                                info!("Not answering delayed message from {sender_nick} in {bare_from}");
+                            }
                             continue;
                         }
 
-                        info!("MUC mention from {sender_nick} in {bare_from}");
+                        if mentioned {
+                            info!("MUC mention from {sender_nick} in {bare_from}");
+                        } else {
+                            info!(
+                                "MUC message from {sender_nick} in {bare_from} ({:?} activation)",
+                                room_config.activation
+                            );
+                        }
+                        self.mark_displayed(&msg, &cmd_tx).await;
+
+                        // Answers reply to the mention (XEP-0461), which needs
+                        // the room's stanza id, in the mention's thread
+                        let thread = msg.thread.clone();
+                        let reply = msg.stanza_id.as_ref().map(|id| MessageReply {
+                            to: msg.from.clone(),
+                            id: id.clone(),
+                        });
+                        let target = ReactionTarget::of(&msg, &session);
+
+                        if download {
+                            // ── Mention with room attachments ──────────
+                            // Download + LLM call in a spawned task, as for 1:1
+                            let _ = cmd_tx
+                                .send(XmppCommand::SendChatState {
+                                    to: bare_from.to_string(),
+                                    state: ChatState::Composing,
+                                    msg_type: "groupchat".to_string(),
+                                })
+                                .await;
+
+                            acknowledge_task(
+                                &self.config.reactions, &self.memory, &cmd_tx, &target, AckStage::Working,
+                            ).await;
+
+                            let request = MucAttachmentRequest {
+                                route: TierRoute {
+                                    trigger,
+                                    pinned: self.pinned_tier(bare_from),
+                                },
+                                room: room_config,
+                                from: msg.from.clone(),
+                                sender_label,
+                                body: msg.body.clone(),
+                                text: clean_body,
+                                msg_id: msg.id.clone(),
+                                stanza_id: msg.stanza_id.clone(),
+                                occupant_id: msg.occupant_id.clone(),
+                                thread,
+                                reply,
+                                oob: msg.oob.clone(),
+                                earlier,
+                            };
+                            let downloader = Arc::clone(&self.file_downloader);
+                            let memory = Arc::clone(&self.memory);
+                            let skills = Arc::clone(&self.skills);
+                            let llms = Arc::clone(&self.llms);
+                            let reasoning = Arc::clone(&self.reasoning);
+                            let config = self.config.clone();
+                            let uploader = Arc::clone(&self.uploader);
+                            let cmd_tx_clone = cmd_tx.clone();
+
+                            tokio::spawn(async move {
+                                let room_jid = request.room.jid.clone();
+                                let result = handle_muc_message_with_attachments(
+                                    &request, &downloader, &memory, &llms, &config, &skills,
+                                ).await;
 
-                        // Strip mention prefix before sending to LLM
-                        let clean_body = strip_mention(&room_config.nick, &msg.body);
+                                match result {
+                                    Ok(outcome) => {
+                                        if config.llm.reasoning_summary {
+                                            reasoning.record(&room_jid, &outcome.reasoning);
+                                        }
+                                        let out_id = uuid::Uuid::new_v4().to_string();
+                                        let session = Memory::thread_session(
+                                            &room_jid,
+                                            request.thread.as_deref(),
+                                        );
+                                        if let Err(e) = memory.store_message_structured(
+                                            &session,
+                                            "assistant",
+                                            &outcome.text,
+                                            Some(&out_id),
+                                            None,
+                                        ) {
+                                            error!("Failed to store MUC response: {e}");
+                                        }
+                                        let answer = AnswerTarget {
+                                            thread: request.thread.clone(),
+                                            reply: request.reply.clone(),
+                                            mention: Some(request.from.clone()),
+                                            ..AnswerTarget::new(
+                                                &config, &room_jid, &room_jid, "groupchat", Some(out_id),
+                                            )
+                                        };
+                                        send_answer(
+                                            &config, &uploader, &cmd_tx_clone, answer, outcome.text, outcome.files,
+                                        ).await;
+                                        deliver_reactions(&memory, &cmd_tx_clone, &target, outcome.reactions)
+                                            .await;
+                                        acknowledge_task(
+                                            &config.reactions, &memory, &cmd_tx_clone, &target, AckStage::Done,
+                                        ).await;
+                                    }
+                                    Err(e) => {
+                                        error!("Error processing MUC attachment message: {e}");
+                                        acknowledge_task(
+                                            &config.reactions, &memory, &cmd_tx_clone, &target, AckStage::Failed,
+                                        ).await;
+                                        let _ = cmd_tx_clone
+                                            .send(XmppCommand::SendChatState {
+                                                to: room_jid.clone(),
+                                                state: ChatState::Paused,
+                                                msg_type: "groupchat".to_string(),
+                                            })
+                                            .await;
+                                        let _ = cmd_tx_clone
+                                            .send(XmppCommand::SendMucMessage {
+                                                to: room_jid,
+                                                body: format!("Sorry, an error occurred: {e}"),
+                                                id: None,
+                                                thread: request.thread.clone(),
+                                                reply: None,
+                                                mention: None,
+                                            })
+                                            .await;
+                                    }
+                                }
+                            });
+                            continue;
+                        }
 
                         // Process via LLM using room JID as memory key
-                        let response = if clean_body.starts_with('/') {
-                            self.handle_command(&msg.from, &clean_body)
+                        let response = if is_command {
+                            self.handle_command(&msg.from, &clean_body).map(LoopOutcome::from)
                         } else {
                             // Send <composing/> to the room before the LLM call
                             let _ = cmd_tx
@@ -165,34 +484,52 @@ impl AgentRuntime {
                                     msg_type: "groupchat".to_string(),
                                 })
                                 .await;
+                            acknowledge_task(
+                                &self.config.reactions, &self.memory, &cmd_tx, &target, AckStage::Working,
+                            ).await;
 
-                            self.handle_muc_message(bare_from, &clean_body).await
+                            self.handle_muc_message(bare_from, &session, &clean_body, trigger).await
                         };
 
                         let room_jid = bare_from.to_string();
                         match response {
-                            Ok(text) => {
+                            Ok(outcome) => {
                                 // Generate outbound message id
                                 let out_id = uuid::Uuid::new_v4().to_string();
                                 if let Err(e) = self.memory.store_message_structured(
-                                    &room_jid,
+                                    &session,
                                     "assistant",
-                                    &text,
+                                    &outcome.text,
                                     Some(&out_id),
                                     None,
                                 ) {
                                     error!("Failed to store MUC response: {e}");
                                 }
-                                let _ = cmd_tx
-                                    .send(XmppCommand::SendMucMessage {
-                                        to: room_jid,
-                                        body: text,
-                                        id: Some(out_id),
-                                    })
-                                    .await;
+                                let answer = AnswerTarget {
+                                    thread,
+                                    reply,
+                                    mention: Some(msg.from.clone()),
+                                    // Command output is plain text
+                                    styling: !is_command && self.config.message_styling(&room_jid),
+                                    ..AnswerTarget::new(&self.config, &room_jid, &room_jid, "groupchat", Some(out_id))
+                                };
+                                send_answer(
+                                    &self.config, &self.uploader, &cmd_tx, answer, outcome.text, outcome.files,
+                                ).await;
+                                deliver_reactions(&self.memory, &cmd_tx, &target, outcome.reactions).await;
+                                if !is_command {
+                                    acknowledge_task(
+                                        &self.config.reactions, &self.memory, &cmd_tx, &target, AckStage::Done,
+                                    ).await;
+                                }
                             }
                             Err(e) => {
                                 error!("Error processing MUC message: {e}");
+                                if !is_command {
+                                    acknowledge_task(
+                                        &self.config.reactions, &self.memory, &cmd_tx, &target, AckStage::Failed,
+                                    ).await;
+                                }
                                 // Send <paused/> to indicate the agent stopped generating
                                 let _ = cmd_tx
                                     .send(XmppCommand::SendChatState {
@@ -206,6 +543,9 @@ impl AgentRuntime {
                                         to: room_jid,
                                         body: format!("Sorry, an error occurred: {e}"),
                                         id: None,
+                                        thread,
+                                        reply: None,
+                                        mention: None,
                                     })
                                     .await;
                             }
@@ -228,11 +568,63 @@ impl AgentRuntime {
                             continue;
                         }
 
+                        // Offline messages already imported from the archive
+                        if msg.delay.is_some() && self.is_known_message(bare_from, &msg) {
+                            debug!("Skipping already stored message from {}", msg.from);
+                            continue;
+                        }
+
+                        // First contact: import the archived history first
+                        if let Some(target) = self.first_contact_target(bare_from) {
+                            info!("First message from {bare_from}, fetching archived history");
+                            self.spawn_archive_fetch(target, Some(msg.clone()), &sync_tx, &cmd_tx);
+                            continue;
+                        }
+
+                        // Receipt (XEP-0184) and `received` marker (XEP-0333)
+                        self.acknowledge(&msg, &cmd_tx).await;
+
+                        // Corrections (XEP-0308) of a stored message
+                        if let Some(replace_id) = &msg.replace_id {
+                            if self.handle_correction(&msg, replace_id, &cmd_tx).await {
+                                continue;
+                            }
+                        }
+
                         info!("Processing message from {}", msg.from);
                         debug!("Message body from {}: {}", msg.from, msg.body);
+                        if let Err(e) = self.memory.record_thread(bare_from, msg.thread.as_deref()) {
+                            warn!("Failed to record thread of {bare_from}: {e}");
+                        }
+
+                        // Explicit tier triggers (`/deep ...`) are regular messages
+                        let (trigger, body) = self.split_tier_trigger(&msg.body);
+                        let is_command = trigger.is_none() && body.starts_with('/');
+
+                        // Human takeover: an operator is answering this contact
+                        if !is_command && self.in_takeover(bare_from) {
+                            info!("Operator is handling {bare_from}, not answering");
+                            let session = Memory::thread_session(bare_from, msg.thread.as_deref());
+                            if let Err(e) = self.memory.store_message_full(
+                                &session,
+                                "user",
+                                &msg.body,
+                                msg.id.as_deref(),
+                                Some(bare_from),
+                                build_oob_attachments(&msg.oob),
+                                None,
+                            ) {
+                                error!("Failed to store message: {e}");
+                            }
+                            continue;
+                        }
+
+                        self.mark_displayed(&msg, &cmd_tx).await;
+                        let session = Memory::thread_session(bare_from, msg.thread.as_deref());
+                        let target = ReactionTarget::of(&msg, &session);
 
                         // Slash commands are intercepted before the LLM
-                        if msg.body.starts_with('/') {
+                        if is_command {
                             let response = self.handle_command(&msg.from, &msg.body);
                             match response {
                                 Ok(text) => {
@@ -241,6 +633,7 @@ impl AgentRuntime {
                                             to: msg.from.clone(),
                                             body: text,
                                             id: None,
+                                            thread: msg.thread.clone(),
                                         })
                                         .await;
                                 }
@@ -251,6 +644,7 @@ impl AgentRuntime {
                                             to: msg.from.clone(),
                                             body: format!("Sorry, an error occurred: {e}"),
                                             id: None,
+                                            thread: msg.thread.clone(),
                                         })
                                         .await;
                                 }
@@ -267,36 +661,64 @@ impl AgentRuntime {
                                 })
                                 .await;
 
+                            acknowledge_task(
+                                &self.config.reactions, &self.memory, &cmd_tx, &target, AckStage::Working,
+                            ).await;
+
                             let downloader = Arc::clone(&self.file_downloader);
                             let memory = Arc::clone(&self.memory);
                             let skills = Arc::clone(&self.skills);
-                            let llm = Arc::clone(&self.llm);
+                            let llms = Arc::clone(&self.llms);
+                            let reasoning = Arc::clone(&self.reasoning);
                             let config = self.config.clone();
+                            let uploader = Arc::clone(&self.uploader);
                             let cmd_tx_clone = cmd_tx.clone();
                             let from = msg.from.clone();
-                            let body = msg.body.clone();
                             let msg_id = msg.id.clone();
                             let oob_list = msg.oob.clone();
+                            let thread = msg.thread.clone();
+                            let route = TierRoute {
+                                trigger,
+                                pinned: self.pinned_tier(stanzas::bare_jid(&msg.from)),
+                            };
+
+                            // Reply id, stored with the reply so it can be corrected
+                            let out_id = uuid::Uuid::new_v4().to_string();
 
                             tokio::spawn(async move {
                                 let result = handle_message_with_attachments(
-                                    &from, &body, msg_id.as_deref(), &oob_list,
-                                    &downloader, &memory, llm.as_ref(), &config, &skills,
+                                    &from, &body, msg_id.as_deref(), &out_id, thread.as_deref(),
+                                    &oob_list, &downloader, &memory, &llms, route, &config, &skills,
                                 ).await;
 
                                 match result {
-                                    Ok(text) => {
-                                        let out_id = uuid::Uuid::new_v4().to_string();
-                                        let _ = cmd_tx_clone
-                                            .send(XmppCommand::SendMessage {
-                                                to: from,
-                                                body: text,
-                                                id: Some(out_id),
-                                            })
+                                    Ok(outcome) => {
+                                        if config.llm.reasoning_summary {
+                                            reasoning.record(
+                                                stanzas::bare_jid(&from),
+                                                &outcome.reasoning,
+                                            );
+                                        }
+                                        let answer = AnswerTarget {
+                                            thread: thread.clone(),
+                                            ..AnswerTarget::new(
+                                                &config, stanzas::bare_jid(&from), &from, "chat", Some(out_id),
+                                            )
+                                        };
+                                        send_answer(
+                                            &config, &uploader, &cmd_tx_clone, answer, outcome.text, outcome.files,
+                                        ).await;
+                                        deliver_reactions(&memory, &cmd_tx_clone, &target, outcome.reactions)
                                             .await;
+                                        acknowledge_task(
+                                            &config.reactions, &memory, &cmd_tx_clone, &target, AckStage::Done,
+                                        ).await;
                                     }
                                     Err(e) => {
                                         error!("Error processing attachment message: {e}");
+                                        acknowledge_task(
+                                            &config.reactions, &memory, &cmd_tx_clone, &target, AckStage::Failed,
+                                        ).await;
                                         let _ = cmd_tx_clone
                                             .send(XmppCommand::SendChatState {
                                                 to: from.clone(),
@@ -309,6 +731,7 @@ impl AgentRuntime {
                                                 to: from,
                                                 body: format!("Sorry, an error occurred: {e}"),
                                                 id: None,
+                                                thread: thread.clone(),
                                             })
                                             .await;
                                     }
@@ -325,21 +748,42 @@ impl AgentRuntime {
                                 })
                                 .await;
 
-                            let response = self.handle_message(&msg.from, &msg.body, msg.id.as_deref()).await;
+                            acknowledge_task(
+                                &self.config.reactions, &self.memory, &cmd_tx, &target, AckStage::Working,
+                            ).await;
+
+                            let out_id = uuid::Uuid::new_v4().to_string();
+                            let response = self
+                                .handle_message(
+                                    &msg.from,
+                                    &body,
+                                    msg.id.as_deref(),
+                                    &out_id,
+                                    msg.thread.as_deref(),
+                                    trigger,
+                                )
+                                .await;
 
                             match response {
-                                Ok(text) => {
-                                    let out_id = uuid::Uuid::new_v4().to_string();
-                                    let _ = cmd_tx
-                                        .send(XmppCommand::SendMessage {
-                                            to: msg.from.clone(),
-                                            body: text,
-                                            id: Some(out_id),
-                                        })
+                                Ok(outcome) => {
+                                    let answer = AnswerTarget {
+                                        thread: msg.thread.clone(),
+                                        ..AnswerTarget::new(&self.config, bare_from, &msg.from, "chat", Some(out_id))
+                                    };
+                                    send_answer(
+                                        &self.config, &self.uploader, &cmd_tx, answer, outcome.text, outcome.files,
+                                    ).await;
+                                    deliver_reactions(&self.memory, &cmd_tx, &target, outcome.reactions)
                                         .await;
+                                    acknowledge_task(
+                                        &self.config.reactions, &self.memory, &cmd_tx, &target, AckStage::Done,
+                                    ).await;
                                 }
                                 Err(e) => {
                                     error!("Error processing message: {e}");
+                                    acknowledge_task(
+                                        &self.config.reactions, &self.memory, &cmd_tx, &target, AckStage::Failed,
+                                    ).await;
                                     let _ = cmd_tx
                                         .send(XmppCommand::SendChatState {
                                             to: msg.from.clone(),
@@ -352,6 +796,7 @@ impl AgentRuntime {
                                             to: msg.from.clone(),
                                             body: format!("Sorry, an error occurred: {e}"),
                                             id: None,
+                                            thread: msg.thread.clone(),
                                         })
                                         .await;
                                 }
@@ -362,6 +807,24 @@ impl AgentRuntime {
                 XmppEvent::Presence(pres) => {
                     let bare_jid = stanzas::bare_jid(&pres.from);
 
+                    // Room occupants (XEP-0045) are tracked, not contacts.
+                    // Only configured rooms: anyone can add a muc#user payload
+                    if let Some(muc) = &pres.muc {
+                        if self.config.find_room(bare_jid).is_none() {
+                            warn!("Ignoring occupant presence from {}: not a configured room", pres.from);
+                            continue;
+                        }
+                        self.occupants.apply(&pres);
+                        debug!(
+                            "Occupant presence from {}: {:?} ({}, {})",
+                            pres.from,
+                            pres.presence_type,
+                            muc.role.as_str(),
+                            muc.affiliation.as_str()
+                        );
+                        continue;
+                    }
+
                     // Domain-level security check for subscription requests
                     if matches!(pres.presence_type, PresenceType::Subscribe)
                         && !self.config.is_domain_allowed(&pres.from)
@@ -426,6 +889,7 @@ impl AgentRuntime {
                     let reaction_meta = Reaction {
                         message_id: reaction.message_id.clone(),
                         emojis: reaction.emojis.clone(),
+                        ack: false,
                     };
 
                     let sender_label = if is_muc {
@@ -468,7 +932,8 @@ impl AgentRuntime {
                     let response = self.handle_reaction(bare_from).await;
 
                     match response {
-                        Ok(text) => {
+                        Ok(outcome) => {
+                            let text = outcome.text;
                             let out_id = uuid::Uuid::new_v4().to_string();
                             let jid_key = bare_from.to_string();
                             if let Err(e) = self.memory.store_message_structured(
@@ -480,23 +945,18 @@ impl AgentRuntime {
                             ) {
                                 error!("Failed to store reaction response: {e}");
                             }
-                            if is_muc {
-                                let _ = cmd_tx
-                                    .send(XmppCommand::SendMucMessage {
-                                        to: jid_key,
-                                        body: text,
-                                        id: Some(out_id),
-                                    })
-                                    .await;
-                            } else {
-                                let _ = cmd_tx
-                                    .send(XmppCommand::SendMessage {
-                                        to: reply_to,
-                                        body: text,
-                                        id: Some(out_id),
-                                    })
-                                    .await;
-                            }
+                            let to = if is_muc { jid_key } else { reply_to };
+                            let answer =
+                                AnswerTarget::new(&self.config, bare_from, &to, msg_type_str, Some(out_id));
+                            send_answer(&self.config, &self.uploader, &cmd_tx, answer, text, outcome.files)
+                                .await;
+                            let target = ReactionTarget {
+                                to,
+                                msg_type: msg_type_str,
+                                session: bare_from.to_string(),
+                                current: None,
+                            };
+                            deliver_reactions(&self.memory, &cmd_tx, &target, outcome.reactions).await;
                         }
                         Err(e) => {
                             error!("Error processing reaction: {e}");
@@ -510,6 +970,40 @@ impl AgentRuntime {
                         }
                     }
                 }
+                XmppEvent::Iq(iq) => {
+                    let Some(iq) = self.iq.resolve(iq) else {
+                        continue;
+                    };
+                    if !matches!(iq.iq_type, IqType::Get | IqType::Set) {
+                        // Results and errors are never answered (RFC 6120 §8.2.3)
+                        debug!(
+                            "Ignoring unmatched IQ {} ({}) from {} to {}",
+                            iq.id,
+                            iq.iq_type.as_str(),
+                            iq.from.as_deref().unwrap_or("server"),
+                            iq.to
+                        );
+                        continue;
+                    }
+                    let reply = self
+                        .handle_adhoc_iq(&iq)
+                        .unwrap_or_else(|| self.iq_router.handle(&iq));
+                    debug!(
+                        "Answering IQ {} from {} with {}",
+                        iq.id,
+                        iq.from.as_deref().unwrap_or("server"),
+                        reply.iq_type.as_str()
+                    );
+                    let _ = cmd_tx.send(reply.into_command(&iq)).await;
+                }
+                XmppEvent::Carbon(carbon) => self.handle_carbon(carbon),
+                XmppEvent::Retraction(retraction) => self.handle_retraction(&retraction),
+                XmppEvent::Marker(marker) => self.handle_marker(&marker),
+                XmppEvent::Archived(archived) => {
+                    if !self.mam.collect(archived) {
+                        debug!("Dropping archive result that matches no query");
+                    }
+                }
                 XmppEvent::StreamError(condition) => {
                     error!("XMPP stream error: {condition}");
                     if condition == "conflict" {
@@ -533,6 +1027,17 @@ impl AgentRuntime {
 
     // ── Slash commands ────────────────────────────────────
 
+    /// Real JID of a message sender: in a configured room, the occupant's
+    /// JID when the room discloses it; `from` itself otherwise.
+    fn sender_jid(&self, from: &str) -> Option<String> {
+        match from.split_once('/') {
+            Some((room, nick)) if self.config.find_room(room).is_some() => {
+                self.occupants.get(room, nick)?.jid
+            }
+            _ => Some(from.to_string()),
+        }
+    }
+
     /// Handles a slash command. Returns the response text.
     /// These are intercepted by the runtime and never reach the LLM.
     fn handle_command(&self, from: &str, body: &str) -> Result<String> {
@@ -540,12 +1045,18 @@ impl AgentRuntime {
         let parts: Vec<&str> = body.splitn(2, ' ').collect();
         let command = parts[0].to_lowercase();
 
-        info!("Slash command from {bare_jid}: {command}");
+        match self.sender_jid(from) {
+            Some(jid) if jid != from => info!("Slash command from {from} ({jid}): {command}"),
+            _ => info!("Slash command from {bare_jid}: {command}"),
+        }
 
         match command.as_str() {
             "/new" | "/reset" => self.cmd_new_session(bare_jid),
             "/forget" => self.cmd_forget(bare_jid),
             "/status" => self.cmd_status(bare_jid),
+            "/threads" => self.cmd_threads(bare_jid),
+            "/tier" => Ok(self.cmd_tier(bare_jid, parts.get(1).copied())),
+            "/reasoning" => Ok(self.cmd_reasoning(from)),
             "/help" => Ok(self.cmd_help()),
             "/ping" => Ok("pong".to_string()),
             _ => Ok(format!(
@@ -602,16 +1113,23 @@ impl AgentRuntime {
 
         // Context-specific section: room info vs. user info
         let context_info = if is_room {
+            let occupants = self.occupants.others(bare_jid).len();
             format!(
                 "Room: {bare_jid}\n\
+                 Occupants: {occupants}\n\
                  Room messages: {msg_count}\n\
                  Archived sessions: {session_count}{file_info}{knowledge_info}"
             )
         } else {
             let has_profile = self.memory.has_user_profile(bare_jid)?;
             let has_memory = self.memory.get_user_memory(bare_jid)?.is_some();
+            // Known only when the contact's client sends chat markers
+            let unread_info = match self.memory.unread_replies(bare_jid)? {
+                Some(unread) => format!("\nUnread replies: {unread}"),
+                None => String::new(),
+            };
             format!(
-                "Your session: {msg_count} messages\n\
+                "Your session: {msg_count} messages{unread_info}\n\
                  Archived sessions: {session_count}{file_info}{knowledge_info}\n\
                  User profile: {}\n\
                  User memory: {}",
@@ -648,6 +1166,29 @@ impl AgentRuntime {
             "Keepalive: disabled".to_string()
         };
 
+        let tiers_info = if self.llms.has_tiers() {
+            let tier = match self.pinned_tier(bare_jid) {
+                Some(t) => format!("{t} (pinned)"),
+                None => "auto".to_string(),
+            };
+            format!(
+                "\nTiers: {}\nTier: {tier}",
+                self.llms.describe_tiers().join(", ")
+            )
+        } else {
+            String::new()
+        };
+
+        // Effective generation parameters: [llm] defaults + room/user overrides
+        let overrides = self.config.generation_overrides(bare_jid);
+        let generation = self.config.llm.generation.merged(&overrides);
+        let generation_info = if overrides.is_empty() {
+            format!("Generation: {}", generation.describe())
+        } else {
+            let source = if is_room { "room" } else { "user" };
+            format!("Generation: {} ({source} override)", generation.describe())
+        };
+
         let session_timeout_info = if self.config.session.idle_timeout_mins > 0 {
             format!(
                 "Session timeout: {}m idle",
@@ -661,7 +1202,8 @@ impl AgentRuntime {
             "{} — status\n\
              Uptime: {hours}h {minutes}m\n\
              Mode: {}\n\
-             LLM: {}\n\
+             LLM: {}{tiers_info}\n\
+             {generation_info}\n\
              {skills_info}\n\
              {keepalive_info}\n\
              {session_timeout_info}\n\
@@ -670,13 +1212,35 @@ impl AgentRuntime {
              {domain_info}",
             self.config.agent.name,
             self.config.server.mode_description(),
-            self.llm.description(),
+            self.llms.default_client().description(),
             yn(has_instructions),
             yn(has_identity),
             yn(has_personality),
         ))
     }
 
+    /// /threads — Conversation threads (XEP-0201) with messages in their
+    /// session, most recent first.
+    fn cmd_threads(&self, bare_jid: &str) -> Result<String> {
+        let threads = self.memory.threads(bare_jid)?;
+        if threads.is_empty() {
+            return Ok("No active threads.".to_string());
+        }
+        let lines: Vec<String> = threads
+            .iter()
+            .map(|t| {
+                let last = t
+                    .last
+                    .as_deref()
+                    .and_then(parse_stamp)
+                    .map(|ts| format!(", last {}", ts.format("%Y-%m-%d %H:%M UTC")))
+                    .unwrap_or_default();
+                format!("- {}: {} messages{last}", t.id, t.messages)
+            })
+            .collect();
+        Ok(format!("Active threads ({}):\n{}", threads.len(), lines.join("\n")))
+    }
+
     /// /help — List available commands
     fn cmd_help(&self) -> String {
         "\
@@ -684,1155 +1248,5119 @@ Commands:\n\
   /new     — Start a new conversation (archive current session)\n\
   /forget  — Erase your history, profile, and memory\n\
   /status  — Agent info, uptime, session stats\n\
+  /threads — Conversation threads with their own session\n\
+  /tier    — Show or pin the model tier (fast, standard, deep, vision, auto)\n\
+  /reasoning — Last reasoning summary (debug, admins)\n\
   /ping    — Check if the agent is alive\n\
   /help    — This message"
             .to_string()
     }
 
-    // ── LLM message handling ─────────────────────────────
-
-    /// Calls the LLM with optional tool support, running the agentic loop.
+    /// /tier — Show the model tier, or pin one for this conversation.
     ///
-    /// Delegates to the free `agentic_loop()` function. When no skills are
-    /// registered, this is equivalent to a single `llm.complete()` call.
-    async fn call_llm_with_tools(
-        &self,
-        system_prompt: &str,
-        messages: &mut Vec<Message>,
-        jid: &str,
-    ) -> Result<(String, u32, u32)> {
-        let context = SkillContext {
-            jid: jid.to_string(),
-            base_path: self.memory.base_path().to_path_buf(),
-        };
-        agentic_loop(system_prompt, messages, self.llm.as_ref(), &self.skills, &context).await
-    }
-
-    /// Processes an incoming message and produces a response via LLM.
-    /// `msg_id` is the inbound XMPP stanza id (stored as structured metadata).
-    async fn handle_message(&self, from: &str, body: &str, msg_id: Option<&str>) -> Result<String> {
-        // Bare JID for memory (without resource)
-        let bare_jid = stanzas::bare_jid(from);
-
-        // Auto-archive stale sessions before loading history
-        self.memory.check_session_freshness(bare_jid, self.config.session.idle_timeout_mins)?;
-
-        // Retrieve conversation history and workspace context
-        let history = self.memory.get_history(bare_jid, MAX_HISTORY)?;
-        let workspace = self.memory.get_workspace_context(bare_jid)?;
-
-        // Build system prompt from workspace files
-        let system_prompt = self.build_system_prompt(&workspace);
-
-        // Build message list for LLM — 1:1 chat, no sender prefix needed
-        let mut messages = history;
-        messages.push(build_message_for_llm(
-            "user".to_string(),
-            body.to_string(),
-            None,
-        ));
+    /// `/tier <name>` pins a tier; `/tier auto` returns to per-message routing.
+    fn cmd_tier(&self, bare_jid: &str, arg: Option<&str>) -> String {
+        if !self.llms.has_tiers() {
+            return format!(
+                "Model tiers are not configured. All requests use {}.",
+                self.llms.default_client().description()
+            );
+        }
 
-        // Agentic loop (returns immediately if no tools registered)
-        let (text, input_tokens, output_tokens) =
-            self.call_llm_with_tools(&system_prompt, &mut messages, bare_jid).await?;
+        let arg = arg.map(str::trim).unwrap_or("");
+        if arg.is_empty() {
+            let current = match self.pinned_tier(bare_jid) {
+                Some(t) => format!("Tier: {t} (pinned)"),
+                None => "Tier: auto".to_string(),
+            };
+            return format!(
+                "{current}\nAvailable tiers:\n  {}",
+                self.llms.describe_tiers().join("\n  ")
+            );
+        }
 
-        // Generate outbound message id
-        let out_id = uuid::Uuid::new_v4().to_string();
+        let mut pins = self.tier_pins.lock().unwrap();
+        if arg.eq_ignore_ascii_case("auto") {
+            pins.remove(bare_jid);
+            return "Tier routing set to auto for this conversation.".to_string();
+        }
+        match Tier::parse(arg) {
+            Some(tier) if self.llms.has(tier) => {
+                pins.insert(bare_jid.to_string(), tier);
+                format!(
+                    "Tier pinned to {tier} ({}) for this conversation.",
+                    self.llms.get(tier).description()
+                )
+            }
+            Some(tier) => format!("Tier '{tier}' is not configured."),
+            None => format!(
+                "Unknown tier: {arg}\nUse one of: fast, standard, deep, vision, auto."
+            ),
+        }
+    }
 
-        // Persist messages with structured metadata (clean content, metadata as fields)
-        self.memory.store_message_structured(bare_jid, "user", body, msg_id, Some(bare_jid))?;
-        self.memory
-            .store_message_structured(bare_jid, "assistant", &text, Some(&out_id), None)?;
+    /// /reasoning — Debug: show the model's last reasoning summary.
+    ///
+    /// Requires `[llm] reasoning_summary = true` (and a thinking budget for
+    /// the model to produce any reasoning). The summary is kept in memory
+    /// only, never in the conversation history.
+    ///
+    /// Admins only: in rooms, the summary is the room's, and covers answers
+    /// given to other occupants.
+    fn cmd_reasoning(&self, from: &str) -> String {
+        if !self.config.llm.reasoning_summary {
+            return "Reasoning summaries are disabled (set reasoning_summary = true in [llm])."
+                .to_string();
+        }
+        if !self.sender_jid(from).is_some_and(|jid| self.config.is_admin(&jid)) {
+            return "The reasoning summary is only available to admins.".to_string();
+        }
+        match self.reasoning.get(stanzas::bare_jid(from)) {
+            Some(summary) => format!("Last reasoning:\n{summary}"),
+            None => "No reasoning recorded for this conversation yet.".to_string(),
+        }
+    }
 
-        info!(
-            "Response to {bare_jid}: {} chars ({} tokens used)",
-            text.len(),
-            input_tokens + output_tokens
-        );
+    // ── Carbons (XEP-0280) ───────────────────────────────
 
-        Ok(text)
+    /// Records a message another client of the account sent or received,
+    /// so the history matches what the contact saw. Messages sent by an
+    /// operator are stored as assistant turns and start a takeover.
+    fn handle_carbon(&self, carbon: CarbonMessage) {
+        let CarbonMessage { direction, message } = carbon;
+        let peer = match direction {
+            CarbonDirection::Received => stanzas::bare_jid(&message.from),
+            CarbonDirection::Sent => stanzas::bare_jid(&message.to),
+        };
+        if self.config.find_room(peer).is_some()
+            || !self.config.is_domain_allowed(peer)
+            || !self.config.is_allowed(peer)
+        {
+            debug!("Ignoring carbon for {peer}");
+            return;
+        }
+        let stored = match direction {
+            CarbonDirection::Received => self.memory.store_message_structured(
+                peer,
+                "user",
+                &message.body,
+                message.id.as_deref(),
+                Some(peer),
+            ),
+            CarbonDirection::Sent => {
+                info!("Operator replied to {peer} from {}", message.from);
+                if self.config.carbons.takeover_mins > 0 {
+                    self.takeovers.lock().unwrap().insert(peer.to_string(), Instant::now());
+                }
+                self.memory.store_message_structured(
+                    peer,
+                    "assistant",
+                    &message.body,
+                    message.id.as_deref(),
+                    None,
+                )
+            }
+        };
+        if let Err(e) = stored {
+            error!("Failed to store carbon for {peer}: {e}");
+        }
     }
 
-    /// Processes a reaction via LLM.
-    /// The reaction is already stored in history by the caller.
-    /// The LLM decides whether a response is warranted based on the full context.
-    /// Returns the LLM response text (caller stores and sends it).
-    async fn handle_reaction(&self, jid: &str) -> Result<String> {
-        // Auto-archive stale sessions before loading history
-        self.memory.check_session_freshness(jid, self.config.session.idle_timeout_mins)?;
+    /// Whether an operator replied to a contact within the last
+    /// `takeover_mins`, in which case the agent stays quiet.
+    fn in_takeover(&self, bare_jid: &str) -> bool {
+        let window = Duration::from_secs(self.config.carbons.takeover_mins * 60);
+        let mut takeovers = self.takeovers.lock().unwrap();
+        match takeovers.get(bare_jid) {
+            Some(since) if since.elapsed() < window => true,
+            Some(_) => {
+                takeovers.remove(bare_jid);
+                info!("Takeover of {bare_jid} expired, the agent answers again");
+                false
+            }
+            None => false,
+        }
+    }
 
-        let history = self.memory.get_history(jid, MAX_HISTORY)?;
-        let workspace = self.memory.get_workspace_context(jid)?;
-        let system_prompt = self.build_system_prompt(&workspace);
+    // ── Corrections (XEP-0308) and retractions (XEP-0424) ─
 
-        // The reaction is already the last entry in history (stored by caller)
-        let mut messages = history;
+    /// Applies a 1:1 correction to the stored message and, with
+    /// `regenerate_on_correction`, corrects the agent's reply to it.
+    ///
+    /// Returns false if the original message is unknown, in which case the
+    /// correction is processed as a new message.
+    async fn handle_correction(
+        &self,
+        msg: &IncomingMessage,
+        replace_id: &str,
+        cmd_tx: &mpsc::Sender<XmppCommand>,
+    ) -> bool {
+        let bare_jid = stanzas::bare_jid(&msg.from);
+        let session = Memory::thread_session(bare_jid, msg.thread.as_deref());
+        match self.memory.correct_message(&session, replace_id, Some(bare_jid), None, &msg.body) {
+            Ok(true) => info!("{bare_jid} corrected message {replace_id}"),
+            Ok(false) => return false,
+            Err(e) => {
+                error!("Failed to store correction from {bare_jid}: {e}");
+                return true;
+            }
+        }
+        if !self.config.session.regenerate_on_correction {
+            return true;
+        }
+        // Only the last exchange is regenerated: later turns built on it
+        let reply_id = match self.memory.last_reply_to(&session, replace_id) {
+            Ok(Some(reply_id)) => reply_id,
+            Ok(None) => return true,
+            Err(e) => {
+                warn!("Failed to read the session of {bare_jid}: {e}");
+                return true;
+            }
+        };
 
-        let (text, input_tokens, output_tokens) =
-            self.call_llm_with_tools(&system_prompt, &mut messages, jid).await?;
+        let _ = cmd_tx
+            .send(XmppCommand::SendChatState {
+                to: msg.from.clone(),
+                state: ChatState::Composing,
+                msg_type: "chat".to_string(),
+            })
+            .await;
+        match self.regenerate_reply(bare_jid, &session, &msg.body).await {
+            Ok(outcome) => {
+                if let Err(e) =
+                    self.memory.correct_message(&session, &reply_id, None, None, &outcome.text)
+                {
+                    error!("Failed to store corrected response: {e}");
+                }
+                let answer = AnswerTarget {
+                    replace_id: Some(reply_id),
+                    ..AnswerTarget::new(
+                        &self.config,
+                        bare_jid,
+                        &msg.from,
+                        "chat",
+                        Some(uuid::Uuid::new_v4().to_string()),
+                    )
+                };
+                send_answer(&self.config, &self.uploader, cmd_tx, answer, outcome.text, outcome.files)
+                    .await;
+                // Reactions go to the corrected message
+                let target = ReactionTarget {
+                    current: msg.replace_id.clone(),
+                    ..ReactionTarget::of(msg, &session)
+                };
+                deliver_reactions(&self.memory, cmd_tx, &target, outcome.reactions).await;
+            }
+            Err(e) => {
+                error!("Error regenerating response for {bare_jid}: {e}");
+                let _ = cmd_tx
+                    .send(XmppCommand::SendChatState {
+                        to: msg.from.clone(),
+                        state: ChatState::Paused,
+                        msg_type: "chat".to_string(),
+                    })
+                    .await;
+            }
+        }
+        true
+    }
 
+    /// Answers the corrected last message of `session` again. The previous
+    /// reply (the last history entry) is left out of the context.
+    async fn regenerate_reply(&self, jid: &str, session: &str, body: &str) -> Result<LoopOutcome> {
+        let mut messages = self.memory.get_history(session, MAX_HISTORY)?;
+        if messages.last().is_some_and(|m| m.role == "assistant") {
+            messages.pop();
+        }
+        let workspace = self.memory.get_workspace_context(jid)?;
+        let system_prompt = self.build_system_prompt(&workspace);
+        let tier = self.select_tier(jid, body, None).await;
+        let outcome = self.call_llm_with_tools(&system_prompt, &mut messages, jid, session, tier).await?;
         info!(
-            "Reaction response to {jid}: {} chars ({} tokens used)",
-            text.len(),
-            input_tokens + output_tokens
+            "Corrected response to {jid}: {} chars ({} tokens used)",
+            outcome.text.len(),
+            outcome.input_tokens + outcome.output_tokens
         );
-
-        Ok(text)
+        Ok(outcome)
     }
 
-    /// Processes a MUC message via LLM.
-    /// The user message is already stored in history by the caller.
-    /// Returns the LLM response text (caller stores the assistant message).
-    async fn handle_muc_message(&self, room_jid: &str, _body: &str) -> Result<String> {
-        // Auto-archive stale sessions before loading history
-        self.memory.check_session_freshness(room_jid, self.config.session.idle_timeout_mins)?;
-
-        // Retrieve conversation history and workspace context
-        let history = self.memory.get_history(room_jid, MAX_HISTORY)?;
-        let workspace = self.memory.get_workspace_context(room_jid)?;
-
-        // Build system prompt
-        let system_prompt = self.build_system_prompt(&workspace);
-
-        // The user message is already the last entry in history (stored by caller)
-        let mut messages = history;
-
-        // Agentic loop (returns immediately if no tools registered)
-        let (text, input_tokens, output_tokens) =
-            self.call_llm_with_tools(&system_prompt, &mut messages, room_jid).await?;
+    /// Removes a retracted message from the LLM context. Only the sender
+    /// of a message may retract it.
+    fn handle_retraction(&self, retraction: &IncomingRetraction) {
+        let bare_from = stanzas::bare_jid(&retraction.from);
+        let sender = if retraction.message_type == MessageType::GroupChat {
+            if self.config.find_room(bare_from).is_none() {
+                return;
+            }
+            match retraction.from.split_once('/') {
+                Some((_, nick)) if !nick.is_empty() => format!("{nick}@muc"),
+                _ => return,
+            }
+        } else {
+            if !self.config.is_domain_allowed(bare_from) || !self.config.is_allowed(bare_from) {
+                return;
+            }
+            bare_from.to_string()
+        };
+        let id = &retraction.message_id;
+        let occupant_id = retraction.occupant_id.as_deref();
+        // Retractions do not tell the thread: any session of the JID
+        let retracted = self.memory.update_any_session(bare_from, |session| {
+            self.memory.retract_message(session, id, Some(&sender), occupant_id)
+        });
+        match retracted {
+            Ok(true) => info!("{} retracted message {id}", retraction.from),
+            Ok(false) => debug!("Retraction of unknown message {id} from {}", retraction.from),
+            Err(e) => error!("Failed to retract message {id} from {}: {e}", retraction.from),
+        }
+    }
 
-        info!(
-            "MUC response to {room_jid}: {} chars ({} tokens used)",
-            text.len(),
-            input_tokens + output_tokens
-        );
+    // ── Receipts (XEP-0184) and chat markers (XEP-0333) ──
 
-        Ok(text)
+    /// Acknowledges a 1:1 message on arrival: a receipt when the sender
+    /// asked for one, and a `received` marker for markable messages.
+    async fn acknowledge(&self, msg: &IncomingMessage, cmd_tx: &mpsc::Sender<XmppCommand>) {
+        let Some(id) = &msg.id else {
+            return;
+        };
+        if msg.receipt_requested {
+            let _ = cmd_tx
+                .send(XmppCommand::SendReceipt {
+                    to: msg.from.clone(),
+                    id: id.clone(),
+                })
+                .await;
+        }
+        if msg.markable {
+            let _ = cmd_tx
+                .send(XmppCommand::SendMarker {
+                    to: msg.from.clone(),
+                    id: id.clone(),
+                    marker: ChatMarker::Received,
+                    msg_type: "chat".to_string(),
+                })
+                .await;
+        }
     }
 
-    /// Builds the system prompt from workspace files.
-    ///
-    /// Assembly order:
-    /// 1. identity.md (who the agent is)
-    /// 2. personality.md (how the agent behaves)
-    /// 3. instructions.md (rules and constraints)
-    /// 4. Hardcoded fallback if none of the 3 global files exist
-    /// 5. Per-JID user.md under "About this user"
-    /// 6. Per-JID memory.md under "Notes and memory"
-    fn build_system_prompt(&self, ctx: &WorkspaceContext) -> String {
-        build_system_prompt_static(&self.config.agent.name, ctx)
+    /// Sends a `displayed` marker for a markable message the agent is
+    /// about to answer. Room markers refer to the room's stanza id.
+    async fn mark_displayed(&self, msg: &IncomingMessage, cmd_tx: &mpsc::Sender<XmppCommand>) {
+        if !msg.markable {
+            return;
+        }
+        let (to, id, msg_type) = if msg.message_type == MessageType::GroupChat {
+            (stanzas::bare_jid(&msg.from), msg.stanza_id.as_ref(), "groupchat")
+        } else {
+            (msg.from.as_str(), msg.id.as_ref(), "chat")
+        };
+        let Some(id) = id else {
+            return;
+        };
+        let _ = cmd_tx
+            .send(XmppCommand::SendMarker {
+                to: to.to_string(),
+                id: id.clone(),
+                marker: ChatMarker::Displayed,
+                msg_type: msg_type.to_string(),
+            })
+            .await;
     }
-}
 
-// ── Attachment handling (runs in spawned tasks) ──────────
+    /// Records in the session that a contact received or read one of the
+    /// agent's messages. Room markers are not tracked.
+    fn handle_marker(&self, marker: &IncomingMarker) {
+        if marker.message_type == MessageType::GroupChat {
+            return;
+        }
+        if !self.config.is_domain_allowed(&marker.from) || !self.config.is_allowed(&marker.from) {
+            return;
+        }
+        let bare_from = stanzas::bare_jid(&marker.from);
+        let id = &marker.message_id;
+        let displayed = marker.marker != ChatMarker::Received;
+        let recorded = self.memory.update_any_session(bare_from, |session| {
+            self.memory.record_delivery(session, id, displayed)
+        });
+        match recorded {
+            Ok(true) => debug!("{bare_from}: message {id} {}", marker.marker.as_str()),
+            Ok(false) => debug!("{} marker from {bare_from} for unknown message {id}", marker.marker.as_str()),
+            Err(e) => warn!("Failed to record delivery of {id} to {bare_from}: {e}"),
+        }
+    }
 
-/// Extracts structured attachment metadata from OOB elements.
-///
-/// For MUC messages (where files are not downloaded), creates `Attachment` structs
-/// with the filename extracted from the URL. MIME type and size are unknown.
-/// Returns `None` if no OOB elements are present.
-fn build_oob_attachments(oob_list: &[OobData]) -> Option<Vec<Attachment>> {
-    if oob_list.is_empty() {
-        return None;
+    // ── Room history (XEP-0045) ──────────────────────────
+
+    /// History to request when joining a room, per its `join_history`.
+    fn join_history(&self, room: &RoomConfig) -> MucHistory {
+        match room.join_history {
+            JoinHistory::None => MucHistory::None,
+            JoinHistory::All => MucHistory::Full,
+            JoinHistory::Since => {
+                let last = self.memory.session_marks(&room.jid).ok().and_then(|(ts, _)| ts);
+                match last.as_deref().and_then(parse_stamp) {
+                    Some(ts) => MucHistory::Since(ts.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)),
+                    None => MucHistory::None,
+                }
+            }
+        }
     }
-    let atts: Vec<Attachment> = oob_list
-        .iter()
-        .map(|oob| {
-            let filename = oob
-                .url
-                .rsplit('/')
-                .next()
-                .filter(|s| !s.is_empty())
-                .unwrap_or("file")
-                .to_string();
-            Attachment {
-                filename,
-                mime_type: "unknown".to_string(),
-                size: "unknown".to_string(),
+
+    /// Whether a delayed message (room history, offline storage, ...) is
+    /// already in the conversation history, by message or stanza id. A
+    /// threaded message is looked up in its thread's session, and in the
+    /// default one where archive imports store it.
+    fn is_known_message(&self, jid: &str, msg: &IncomingMessage) -> bool {
+        if msg.id.is_none() && msg.stanza_id.is_none() {
+            return false;
+        }
+        let mut sessions = vec![jid.to_string()];
+        if msg.thread.is_some() {
+            sessions.insert(0, Memory::thread_session(jid, msg.thread.as_deref()));
+        }
+        sessions.iter().any(|session| match self.memory.session_marks(session) {
+            Ok((_, ids)) => [&msg.id, &msg.stanza_id]
+                .into_iter()
+                .flatten()
+                .any(|id| ids.contains(id)),
+            Err(e) => {
+                warn!("Failed to read the session of {session}: {e}");
+                false
             }
         })
-        .collect();
-    Some(atts)
-}
-
-/// Agentic tool-use loop (free function for use from both methods and spawned tasks).
-///
-/// If skills are registered, tool definitions are passed to the LLM. When the LLM
-/// responds with `tool_use` blocks, the corresponding skills are executed and results
-/// fed back in a loop until the LLM produces a final text response.
-///
-/// `messages` is mutated during the loop (intermediate tool turns are appended).
-/// Only the final text response is returned — intermediate tool calls are not
-/// stored in persistent history.
-///
-/// Returns `(final_text, total_input_tokens, total_output_tokens)`.
-async fn agentic_loop(
-    system_prompt: &str,
-    messages: &mut Vec<Message>,
-    llm: &dyn LlmClient,
-    skills: &SkillRegistry,
-    context: &SkillContext,
-) -> Result<(String, u32, u32)> {
-    // Build tool definitions (None if no skills registered)
-    let tool_defs: Option<Vec<ToolDefinition>> = if skills.is_empty() {
-        None
-    } else {
-        Some(skills.tool_definitions())
-    };
-    let tools_ref = tool_defs.as_deref();
+    }
 
-    let mut total_input = 0u32;
-    let mut total_output = 0u32;
+    // ── Message archive (XEP-0313) ───────────────────────
 
-    for round in 0..MAX_TOOL_ROUNDS {
-        let response = llm.complete(system_prompt, messages, tools_ref).await?;
+    /// Bare JID of the account in client mode, whose archive holds the
+    /// 1:1 conversations. Components have no archive of their own.
+    fn account_jid(&self) -> Option<&str> {
+        match &self.config.server.mode {
+            ConnectionMode::Client { jid, .. } => Some(stanzas::bare_jid(jid)),
+            ConnectionMode::Component { .. } => None,
+        }
+    }
 
-        total_input = total_input.saturating_add(response.input_tokens);
-        total_output = total_output.saturating_add(response.output_tokens);
+    /// Builds the archive query for a conversation from its local history.
+    /// Returns None when there is nothing to fetch.
+    fn archive_target(&self, jid: &str, room_nick: Option<&str>) -> Option<ArchiveTarget> {
+        let mam = &self.config.mam;
+        let (since, _) = self.memory.session_marks(jid).unwrap_or_else(|e| {
+            warn!("Failed to read the session of {jid}: {e}");
+            (None, HashSet::new())
+        });
+        if since.is_none() && mam.bootstrap_messages == 0 {
+            return None;
+        }
+        let replace =
+            mam.history == HistorySource::Archive && since.is_some() && mam.bootstrap_messages > 0;
+        Some(ArchiveTarget {
+            jid: jid.to_string(),
+            room_nick: room_nick.map(String::from),
+            since,
+            replace,
+        })
+    }
 
-        // If no tool calls, we're done — return the text response
-        if response.stop_reason != StopReason::ToolUse || response.tool_calls.is_empty() {
-            return Ok((response.text, total_input, total_output));
+    /// Conversations synchronized on connect: the configured rooms and,
+    /// in client mode, the contacts listed in `allowed_jids`.
+    fn archive_targets(&self) -> Vec<ArchiveTarget> {
+        let mut targets: Vec<ArchiveTarget> = self
+            .config
+            .rooms
+            .iter()
+            .filter_map(|room| self.archive_target(&room.jid, Some(&room.nick)))
+            .collect();
+        if self.account_jid().is_some() {
+            let mut contacts = self.mam_contacts.lock().unwrap();
+            for jid in &self.config.agent.allowed_jids {
+                if jid == "*" || !jid.contains('@') || self.config.find_room(jid).is_some() {
+                    continue;
+                }
+                contacts.insert(jid.clone());
+                targets.extend(self.archive_target(jid, None));
+            }
         }
+        targets
+    }
 
-        // Log tool calls
-        for tc in &response.tool_calls {
-            info!(
-                "Tool call [round {}/{}]: {}",
-                round + 1,
-                MAX_TOOL_ROUNDS,
-                tc.name,
-            );
-            debug!("Tool input for {}: {}", tc.name, tc.input);
+    /// Archive query for the first message of a contact this run, when
+    /// there is no local history to continue (e.g. `allowed_jids = ["*"]`).
+    fn first_contact_target(&self, bare_jid: &str) -> Option<ArchiveTarget> {
+        if !self.config.mam.enabled || self.account_jid().is_none() {
+            return None;
+        }
+        if !self.mam_contacts.lock().unwrap().insert(bare_jid.to_string())
+            || self.memory.has_conversation(bare_jid)
+        {
+            return None;
         }
+        self.archive_target(bare_jid, None)
+    }
 
-        // Append assistant message with the raw content blocks (text + tool_use)
-        messages.push(Message {
-            role: "assistant".to_string(),
-            content: MessageContent::Blocks(response.content_blocks),
+    /// Fetches a conversation's archive in a spawned task (queries wait
+    /// for IQ results, which only the event loop reads). The result is
+    /// sent back on `sync_tx`, even on failure, so a deferred message is
+    /// always processed.
+    fn spawn_archive_fetch(
+        &self,
+        target: ArchiveTarget,
+        deferred: Option<IncomingMessage>,
+        sync_tx: &mpsc::Sender<ArchiveSync>,
+        cmd_tx: &mpsc::Sender<XmppCommand>,
+    ) {
+        let mam = Arc::clone(&self.mam);
+        let config = self.config.mam.clone();
+        let account = self.account_jid().map(String::from);
+        let sync_tx = sync_tx.clone();
+        let cmd_tx = cmd_tx.clone();
+        tokio::spawn(async move {
+            let messages = fetch_archive(&mam, &cmd_tx, &config, account.as_deref(), &target)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Failed to fetch the archive of {}: {e}", target.jid);
+                    Vec::new()
+                });
+            let sync = ArchiveSync {
+                target,
+                messages,
+                deferred,
+            };
+            if sync_tx.send(sync).await.is_err() {
+                debug!("Archive fetched after the event loop stopped");
+            }
         });
+    }
 
-        // Execute each tool call and collect results
-        let mut result_blocks = Vec::new();
-        for tc in &response.tool_calls {
-            let result_content = match skills.get(&tc.name) {
-                Some(skill) => match skill.execute(tc.input.clone(), context).await {
-                    Ok(output) => output,
-                    Err(e) => {
-                        warn!("Skill {} failed: {e}", tc.name);
-                        format!("Error: {e}")
+    /// Imports fetched archive messages into the conversation history and
+    /// applies the catch-up policy. Returns the deferred message, to be
+    /// processed now that its history is known.
+    async fn apply_archive_sync(
+        &self,
+        sync: ArchiveSync,
+        cmd_tx: &mpsc::Sender<XmppCommand>,
+    ) -> Option<IncomingMessage> {
+        let ArchiveSync {
+            target,
+            messages,
+            deferred,
+        } = sync;
+        let missed = match self.import_archive(&target, &messages) {
+            Ok(missed) => missed,
+            Err(e) => {
+                error!("Failed to import the archive of {}: {e}", target.jid);
+                Vec::new()
+            }
+        };
+        // A deferred message is answered directly, it covers the catch-up
+        if deferred.is_none() && !missed.is_empty() {
+            self.catch_up(&target, &missed, cmd_tx).await;
+        }
+        deferred
+    }
+
+    /// Stores the archive messages missing from the local history, with
+    /// their original timestamps. Returns the bodies of the user messages
+    /// that arrived after the local history ended (missed while offline).
+    fn import_archive(
+        &self,
+        target: &ArchiveTarget,
+        messages: &[ArchivedMessage],
+    ) -> Result<Vec<String>> {
+        let jid = &target.jid;
+        if messages.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (_, known) = self.memory.session_marks(jid)?;
+        if target.replace {
+            self.memory.new_session(jid)?;
+        }
+        let since = target.since.as_deref().and_then(parse_stamp);
+
+        let mut imported = 0;
+        let mut missed = Vec::new();
+        for message in messages {
+            let msg_id = message.id.as_deref().unwrap_or(&message.archive_id);
+            let stamp = message.stamp.as_deref().and_then(parse_stamp);
+            let newer = match (since, stamp) {
+                (Some(since), Some(stamp)) => stamp > since,
+                _ => true,
+            };
+            let known = known.contains(msg_id);
+            if !target.replace && (known || !newer) {
+                continue;
+            }
+            let (role, sender) = match &target.room_nick {
+                Some(nick) => match message.from.split_once('/') {
+                    Some((_, from_nick)) if from_nick == nick => ("assistant", None),
+                    Some((_, from_nick)) if !from_nick.is_empty() => {
+                        ("user", Some(format!("{from_nick}@muc")))
                     }
+                    _ => continue,
                 },
-                None => {
-                    warn!("Unknown skill requested: {}", tc.name);
-                    format!("Error: unknown tool '{}'", tc.name)
+                None if stanzas::bare_jid(&message.from) == jid.as_str() => {
+                    ("user", Some(jid.clone()))
                 }
+                None => ("assistant", None),
             };
-
-            info!("Tool result for {}: {} chars", tc.name, result_content.len());
-
-            result_blocks.push(InputContentBlock::ToolResult {
-                tool_use_id: tc.id.clone(),
-                content: result_content,
-            });
+            let ts = stamp
+                .map(|s| s.to_rfc3339())
+                .unwrap_or_else(|| chrono::Utc::now().to_rfc3339());
+            self.memory.store_archived_message(
+                jid,
+                role,
+                &message.body,
+                Some(msg_id),
+                sender.as_deref(),
+                &ts,
+            )?;
+            imported += 1;
+            if role == "user" && since.is_some() && newer && !known {
+                missed.push(message.body.clone());
+            }
         }
-
-        // Append user message with tool_result blocks
-        messages.push(Message {
-            role: "user".to_string(),
-            content: MessageContent::Blocks(result_blocks),
-        });
+        info!(
+            "Imported {imported} archived message(s) into {jid} ({} missed)",
+            missed.len()
+        );
+        Ok(missed)
     }
 
-    // Exhausted all rounds — make one final call without tools to force a text response
-    warn!(
-        "Agentic loop exhausted {} rounds, forcing final response",
-        MAX_TOOL_ROUNDS
-    );
-    let response = llm.complete(system_prompt, messages, None).await?;
-    total_input = total_input.saturating_add(response.input_tokens);
-    total_output = total_output.saturating_add(response.output_tokens);
-    Ok((response.text, total_input, total_output))
-}
-
-/// Handles a 1:1 message with OOB file attachments.
-///
-/// Downloads each file, converts supported types to Anthropic API content blocks,
-/// and sends a multi-modal message to the LLM. Runs in a spawned task.
-#[allow(clippy::too_many_arguments)]
-async fn handle_message_with_attachments(
-    from: &str,
-    body: &str,
-    msg_id: Option<&str>,
-    oob_list: &[OobData],
-    downloader: &FileDownloader,
-    memory: &Memory,
-    llm: &dyn LlmClient,
-    config: &Config,
-    skills: &SkillRegistry,
-) -> Result<String> {
-    let bare_jid = stanzas::bare_jid(from);
-    let files_dir = memory.files_dir(bare_jid)?;
-
-    info!(
-        "Processing {} attachment(s) from {bare_jid}",
-        oob_list.len()
-    );
+    /// Applies the `[mam] catch_up` policy to messages missed while offline.
+    async fn catch_up(
+        &self,
+        target: &ArchiveTarget,
+        missed: &[String],
+        cmd_tx: &mpsc::Sender<XmppCommand>,
+    ) {
+        let jid = target.jid.as_str();
+        let policy = self.config.mam.catch_up;
+        if policy == CatchUpPolicy::Ignore {
+            info!("{} message(s) missed in {jid}, catch-up disabled", missed.len());
+            return;
+        }
+        // Rooms: only when the agent was activated, as for live messages
+        let addressed = match (&target.room_nick, self.config.find_room(jid)) {
+            (Some(nick), Some(room)) => {
+                missed.iter().any(|body| activates(room, is_mentioned(nick, body), body))
+            }
+            (Some(_), None) => false,
+            (None, _) => self.config.is_domain_allowed(jid) && self.config.is_allowed(jid),
+        };
+        if !addressed {
+            return;
+        }
 
-    // Download files sequentially (semaphore inside FileDownloader handles concurrency)
-    let mut content_blocks: Vec<InputContentBlock> = Vec::new();
-    let mut attachment_meta: Vec<Attachment> = Vec::new();
+        let msg_type = if target.room_nick.is_some() { "groupchat" } else { "chat" };
+        let _ = cmd_tx
+            .send(XmppCommand::SendChatState {
+                to: jid.to_string(),
+                state: ChatState::Composing,
+                msg_type: msg_type.to_string(),
+            })
+            .await;
 
-    for (i, oob) in oob_list.iter().enumerate() {
-        debug!("Downloading attachment {}/{}: {}", i + 1, oob_list.len(), oob.url);
-        match downloader.download(&oob.url, &files_dir).await {
-            Ok(file) => {
-                info!(
-                    "Downloaded {} ({}, {})",
-                    file.filename, file.mime_type, file.human_size()
-                );
-                attachment_meta.push(Attachment {
-                    filename: file.filename.clone(),
-                    mime_type: file.mime_type.clone(),
-                    size: file.human_size(),
-                });
-                match file_to_content_block(&file).await {
-                    Ok(Some(block)) => content_blocks.push(block),
-                    Ok(None) => {
-                        // Unsupported type — add text note
-                        content_blocks.push(InputContentBlock::Text {
-                            text: format!(
-                                "[File received: {} ({}) — unsupported type, cannot analyze]",
-                                file.filename, file.mime_type
-                            ),
-                        });
-                    }
-                    Err(e) => {
-                        warn!("Failed to encode file {}: {e}", file.filename);
-                        content_blocks.push(InputContentBlock::Text {
-                            text: format!(
-                                "[File received: {} — encoding error]",
-                                file.filename
-                            ),
-                        });
-                    }
-                }
-            }
+        let instruction = (policy == CatchUpPolicy::Summarize).then(|| {
+            format!(
+                "[You were offline. The last {} message(s) above arrived while you were away. \
+                 Reply once: briefly summarize them and answer what is still pending.]",
+                missed.len()
+            )
+        });
+        let last = missed.last().map(String::as_str).unwrap_or("");
+        let outcome = match self.reply_to_history(jid, instruction, last).await {
+            Ok(outcome) => outcome,
             Err(e) => {
-                warn!("Failed to download {}: {e}", oob.url);
-                content_blocks.push(InputContentBlock::Text {
-                    text: format!("[File download failed: {e}]"),
-                });
-                attachment_meta.push(Attachment {
-                    filename: "unknown".to_string(),
-                    mime_type: "unknown".to_string(),
-                    size: "download failed".to_string(),
-                });
+                error!("Error catching up on {jid}: {e}");
+                let _ = cmd_tx
+                    .send(XmppCommand::SendChatState {
+                        to: jid.to_string(),
+                        state: ChatState::Paused,
+                        msg_type: msg_type.to_string(),
+                    })
+                    .await;
+                return;
             }
+        };
+
+        let out_id = uuid::Uuid::new_v4().to_string();
+        if let Err(e) =
+            self.memory.store_message_structured(jid, "assistant", &outcome.text, Some(&out_id), None)
+        {
+            error!("Failed to store catch-up response: {e}");
         }
+        let answer = AnswerTarget::new(&self.config, jid, jid, msg_type, Some(out_id));
+        send_answer(&self.config, &self.uploader, cmd_tx, answer, outcome.text, outcome.files).await;
+        let target = ReactionTarget {
+            to: jid.to_string(),
+            msg_type,
+            session: jid.to_string(),
+            current: None,
+        };
+        deliver_reactions(&self.memory, cmd_tx, &target, outcome.reactions).await;
     }
 
-    // Add text body if present
-    if !body.is_empty() {
-        content_blocks.push(InputContentBlock::Text {
-            text: body.to_string(),
-        });
-    }
+    /// Answers a conversation from its stored history, optionally with an
+    /// extra instruction appended as the last user turn (not stored).
+    async fn reply_to_history(
+        &self,
+        jid: &str,
+        instruction: Option<String>,
+        last_body: &str,
+    ) -> Result<LoopOutcome> {
+        let mut messages = self.memory.get_history(jid, MAX_HISTORY)?;
+        let workspace = self.memory.get_workspace_context(jid)?;
+        let system_prompt = self.build_system_prompt(&workspace);
+        if let Some(instruction) = instruction {
+            messages.push(build_message_for_llm("user".to_string(), instruction, None));
+        }
+        let tier = self.select_tier(jid, last_body, None).await;
+        let outcome = self.call_llm_with_tools(&system_prompt, &mut messages, jid, jid, tier).await?;
+        info!(
+            "Catch-up response to {jid}: {} chars ({} tokens used)",
+            outcome.text.len(),
+            outcome.input_tokens + outcome.output_tokens
+        );
+        Ok(outcome)
+    }
+
+    // ── Ad-hoc commands (XEP-0050) ───────────────────────
+
+    /// Ad-hoc command access: the same domain and JID checks as slash
+    /// commands in 1:1 chats, plus `admin_jids` for admin commands.
+    fn adhoc_access(&self, from: Option<&str>) -> Access {
+        let Some(from) = from else {
+            return Access::Denied;
+        };
+        if !self.config.is_domain_allowed(from) || !self.config.is_allowed(from) {
+            Access::Denied
+        } else if self.config.is_admin(from) {
+            Access::Admin
+        } else {
+            Access::User
+        }
+    }
+
+    /// Answers ad-hoc command requests and the service discovery of the
+    /// command nodes. Returns None for other IQs, left to the [`IqRouter`].
+    fn handle_adhoc_iq(&self, iq: &IncomingIq) -> Option<IqReply> {
+        let payload = iq.payload.as_ref()?;
+        let namespace = payload.namespace.as_deref().unwrap_or_default();
+        let access = self.adhoc_access(iq.from.as_deref());
+        match (iq.iq_type, payload.name.as_str(), namespace) {
+            (IqType::Get, "query", NS_DISCO_ITEMS) if payload.get_attr("node") == Some(NS_COMMANDS) => {
+                // Unauthorized requesters get an empty list
+                let commands: Vec<(&str, &str)> = access
+                    .commands()
+                    .iter()
+                    .map(|c| (c.node, c.name))
+                    .collect();
+                Some(IqReply::result(stanzas::build_command_items(&iq.to, &commands)))
+            }
+            (IqType::Get, "query", NS_DISCO_INFO) => {
+                let node = payload.get_attr("node")?;
+                let (kind, name) = if node == NS_COMMANDS {
+                    ("command-list", "Commands")
+                } else {
+                    let command = adhoc::find(node).filter(|c| access.allows(c))?;
+                    ("command-node", command.name)
+                };
+                let info = DiscoInfo {
+                    identities: vec![DiscoIdentity {
+                        category: "automation".to_string(),
+                        kind: kind.to_string(),
+                        name: name.to_string(),
+                    }],
+                    features: vec![NS_COMMANDS.to_string(), NS_DATA.to_string()],
+                    forms: Vec::new(),
+                };
+                Some(IqReply::result(stanzas::build_disco_info_result(Some(node), &info)))
+            }
+            (IqType::Set, "command", NS_COMMANDS) => Some(self.handle_adhoc_command(iq, payload, access)),
+            _ => None,
+        }
+    }
+
+    /// Executes one stage of an ad-hoc command.
+    fn handle_adhoc_command(&self, iq: &IncomingIq, payload: &ChildElement, access: Access) -> IqReply {
+        let Some(request) = stanzas::parse_command(payload) else {
+            return IqReply::error("modify", "bad-request");
+        };
+        let Some(command) = adhoc::find(&request.node) else {
+            return IqReply::error("cancel", "item-not-found");
+        };
+        let from = iq.from.as_deref().unwrap_or("server");
+        if !access.allows(command) {
+            warn!("Unauthorized ad-hoc command '{}' from {from}, rejecting", command.node);
+            return IqReply::error("auth", "forbidden");
+        }
+        if !matches!(request.action.as_str(), "execute" | "next" | "complete" | "cancel") {
+            return IqReply {
+                iq_type: IqType::Error,
+                payload: stanzas::build_command_error("modify", "bad-request", "bad-action"),
+            };
+        }
+        let step = match request.session_id.as_deref() {
+            Some(id) => match self.adhoc.take(id, command.node, from) {
+                Some(step) => Some(step),
+                None => {
+                    return IqReply {
+                        iq_type: IqType::Error,
+                        payload: stanzas::build_command_error("modify", "bad-request", "bad-sessionid"),
+                    }
+                }
+            },
+            None => None,
+        };
+
+        let bare_jid = stanzas::bare_jid(from);
+        info!("Ad-hoc command from {bare_jid}: {} ({})", command.node, request.action);
+
+        let outcome = match step {
+            _ if request.action == "cancel" => Ok((CommandResponse::canceled(), None)),
+            None => self.adhoc_start(command.node, bare_jid),
+            Some(step) => self.adhoc_continue(step, &request, bare_jid),
+        };
+        let (response, next) = outcome.unwrap_or_else(|e| {
+            error!("Error processing ad-hoc command: {e}");
+            (CommandResponse::failed(format!("Sorry, an error occurred: {e}")), None)
+        });
+        let session_id = match next {
+            Some(step) => self.adhoc.save(request.session_id.as_deref(), command.node, from, step),
+            None => request
+                .session_id
+                .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string()),
+        };
+        IqReply::result(stanzas::build_command_response(command.node, &session_id, &response))
+    }
+
+    /// First stage of a command: runs it, or returns the form it needs.
+    fn adhoc_start(&self, node: &str, bare_jid: &str) -> Result<(CommandResponse, Option<Step>)> {
+        Ok(match node {
+            "new" => (CommandResponse::completed(self.cmd_new_session(bare_jid)?), None),
+            "status" => (CommandResponse::completed(self.cmd_status(bare_jid)?), None),
+            "forget" => (
+                CommandResponse::form(forget_form(), "complete"),
+                Some(Step::ConfirmForget),
+            ),
+            "tier" if self.llms.has_tiers() => (
+                CommandResponse::form(self.tier_form(bare_jid), "complete"),
+                Some(Step::SelectTier),
+            ),
+            "tier" => (CommandResponse::completed(self.cmd_tier(bare_jid, None)), None),
+            "admin-session" => (
+                CommandResponse::form(manage_target_form(), "next"),
+                Some(Step::ManageTarget),
+            ),
+            _ => anyhow::bail!("Unknown command: {node}"),
+        })
+    }
+
+    /// Next stage of a multi-step command, with the submitted form.
+    /// Invalid forms are presented again with an error note.
+    fn adhoc_continue(
+        &self,
+        step: Step,
+        request: &CommandRequest,
+        bare_jid: &str,
+    ) -> Result<(CommandResponse, Option<Step>)> {
+        Ok(match step {
+            Step::ConfirmForget if request.flag("confirm") => {
+                (CommandResponse::completed(self.cmd_forget(bare_jid)?), None)
+            }
+            Step::ConfirmForget => (CommandResponse::completed("Nothing was erased."), None),
+            Step::SelectTier => match request.value("tier") {
+                Some(tier) => (CommandResponse::completed(self.cmd_tier(bare_jid, Some(tier))), None),
+                None => (
+                    CommandResponse::form(self.tier_form(bare_jid), "complete")
+                        .with_error("Select a tier."),
+                    Some(Step::SelectTier),
+                ),
+            },
+            Step::ManageTarget => {
+                let jid = request.value("jid").map(stanzas::bare_jid).unwrap_or_default();
+                let action = request.value("action").and_then(ManageAction::parse);
+                let invalid = if !adhoc::is_conversation_jid(jid) {
+                    Some(format!("'{jid}' is not a valid JID."))
+                } else if !self.memory.has_conversation(jid) {
+                    Some(format!("There is no conversation with {jid}."))
+                } else if action.is_none() {
+                    Some("Select an action.".to_string())
+                } else {
+                    None
+                };
+                match (invalid, action) {
+                    (None, Some(action)) => (
+                        CommandResponse::form(self.manage_confirm_form(jid, action)?, "complete"),
+                        Some(Step::ManageConfirm {
+                            jid: jid.to_string(),
+                            action,
+                        }),
+                    ),
+                    (invalid, _) => (
+                        CommandResponse::form(manage_target_form(), "next")
+                            .with_error(invalid.unwrap_or_default()),
+                        Some(Step::ManageTarget),
+                    ),
+                }
+            }
+            Step::ManageConfirm { jid, action } if request.flag("confirm") => {
+                let result = match action {
+                    ManageAction::NewSession => self.memory.new_session(&jid)?,
+                    ManageAction::Forget => self.memory.forget(&jid)?,
+                };
+                info!("Admin {bare_jid} ran {action:?} on the conversation with {jid}");
+                (CommandResponse::completed(format!("{jid}: {result}")), None)
+            }
+            Step::ManageConfirm { .. } => (
+                CommandResponse::completed("Cancelled. Nothing was changed."),
+                None,
+            ),
+        })
+    }
+
+    /// Tier selection form, preset to the pinned tier.
+    fn tier_form(&self, bare_jid: &str) -> CommandForm {
+        let current = self
+            .pinned_tier(bare_jid)
+            .map(|t| t.as_str())
+            .unwrap_or("auto");
+        let mut options = vec![("auto: pick per message".to_string(), "auto".to_string())];
+        options.extend(
+            Tier::ALL
+                .iter()
+                .filter(|t| self.llms.has(**t))
+                .map(|t| {
+                    (
+                        format!("{t}: {}", self.llms.get(*t).description()),
+                        t.as_str().to_string(),
+                    )
+                }),
+        );
+        CommandForm {
+            title: "Model tier".to_string(),
+            instructions: Some(
+                "Pin a model tier for this conversation, or let the agent pick one per message."
+                    .to_string(),
+            ),
+            fields: vec![FormField {
+                var: "tier".to_string(),
+                kind: "list-single".to_string(),
+                label: Some("Tier".to_string()),
+                required: true,
+                values: vec![current.to_string()],
+                options,
+            }],
+        }
+    }
+
+    /// Confirmation form of `admin-session`, with the conversation size.
+    fn manage_confirm_form(&self, jid: &str, action: ManageAction) -> Result<CommandForm> {
+        let messages = self.memory.message_count(jid)?;
+        let sessions = self.memory.session_count(jid)?;
+        let label = match action {
+            ManageAction::NewSession => format!("Archive the session of {jid}"),
+            ManageAction::Forget => format!("Erase the history, profile and memory of {jid}"),
+        };
+        Ok(CommandForm {
+            title: "Manage a conversation".to_string(),
+            instructions: None,
+            fields: vec![
+                FormField {
+                    var: "summary".to_string(),
+                    kind: "fixed".to_string(),
+                    values: vec![format!(
+                        "{jid}: {messages} messages in the current session, {sessions} archived sessions."
+                    )],
+                    ..Default::default()
+                },
+                FormField {
+                    var: "confirm".to_string(),
+                    kind: "boolean".to_string(),
+                    label: Some(label),
+                    required: true,
+                    ..Default::default()
+                },
+            ],
+        })
+    }
+
+    // ── Tier routing ─────────────────────────────────────
+
+    /// Returns the tier pinned with `/tier` for a conversation, if any.
+    fn pinned_tier(&self, bare_jid: &str) -> Option<Tier> {
+        self.tier_pins.lock().unwrap().get(bare_jid).copied()
+    }
+
+    /// Splits an explicit tier trigger (`/deep question`) off a message.
+    ///
+    /// Only active when tiers are configured, so `/deep` stays an unknown
+    /// command otherwise.
+    fn split_tier_trigger(&self, body: &str) -> (Option<Tier>, String) {
+        if self.llms.has_tiers() {
+            if let Some((tier, text)) = tiers::parse_trigger(body) {
+                return (Some(tier), text.to_string());
+            }
+        }
+        (None, body.to_string())
+    }
+
+    /// Picks the tier for a request in a conversation.
+    async fn select_tier(&self, jid: &str, body: &str, trigger: Option<Tier>) -> Tier {
+        let signals = RouteSignals {
+            body,
+            has_attachments: false,
+            trigger,
+            pinned: self.pinned_tier(jid),
+            room: self.config.find_room(jid).and_then(|r| r.tier),
+            model_override: self.config.generation_overrides(jid).model.is_some(),
+        };
+        let tier = self.llms.select_tier(&signals).await;
+        if self.llms.has_tiers() {
+            debug!("Routing request for {jid} to {tier} tier");
+        }
+        tier
+    }
+
+    // ── LLM message handling ─────────────────────────────
+
+    /// Calls the LLM with optional tool support, running the agentic loop.
+    ///
+    /// Delegates to the free `agentic_loop()` function. When no skills are
+    /// registered, this is equivalent to a single `llm.complete()` call.
+    async fn call_llm_with_tools(
+        &self,
+        system_prompt: &str,
+        messages: &mut Vec<Message>,
+        jid: &str,
+        session: &str,
+        tier: Tier,
+    ) -> Result<LoopOutcome> {
+        let context = SkillContext {
+            jid: jid.to_string(),
+            session: session.to_string(),
+            base_path: self.memory.base_path().to_path_buf(),
+        };
+        let spawn_agent = self.config.skills.spawn_agent_enabled();
+        let params = self.config.generation_overrides(jid);
+        let outcome = tiered_agentic_loop(
+            system_prompt, messages, &self.llms, tier, &params, &self.skills, &context, spawn_agent,
+        )
+        .await?;
+        if self.config.llm.reasoning_summary {
+            self.reasoning.record(jid, &outcome.reasoning);
+        }
+        Ok(outcome)
+    }
+
+    /// Processes an incoming message and produces a response via LLM.
+    /// `msg_id` is the inbound XMPP stanza id (stored as structured metadata),
+    /// `out_id` the id the reply is sent and stored with.
+    /// `thread` selects the session of a conversation thread (XEP-0201).
+    /// `trigger` is an explicit tier requested with `/deep ...` (already stripped from `body`).
+    async fn handle_message(
+        &self,
+        from: &str,
+        body: &str,
+        msg_id: Option<&str>,
+        out_id: &str,
+        thread: Option<&str>,
+        trigger: Option<Tier>,
+    ) -> Result<LoopOutcome> {
+        // Bare JID for memory (without resource)
+        let bare_jid = stanzas::bare_jid(from);
+        let session = Memory::thread_session(bare_jid, thread);
+
+        // Auto-archive stale sessions before loading history
+        self.memory.check_session_freshness(&session, self.config.session.idle_timeout_mins)?;
+
+        // Retrieve conversation history and workspace context
+        let history = self.memory.get_history(&session, MAX_HISTORY)?;
+        let workspace = self.memory.get_workspace_context(bare_jid)?;
+
+        // Build system prompt from workspace files
+        let system_prompt = self.build_system_prompt(&workspace);
+
+        // Build message list for LLM — 1:1 chat, no sender prefix needed
+        let mut messages = history;
+        messages.push(build_message_for_llm(
+            "user".to_string(),
+            body.to_string(),
+            None,
+        ));
+
+        // Agentic loop (returns immediately if no tools registered)
+        let tier = self.select_tier(bare_jid, body, trigger).await;
+        let outcome =
+            self.call_llm_with_tools(&system_prompt, &mut messages, bare_jid, &session, tier).await?;
+        let text = &outcome.text;
+
+        // Persist messages with structured metadata (clean content, metadata as fields)
+        self.memory.store_message_structured(&session, "user", body, msg_id, Some(bare_jid))?;
+        self.memory
+            .store_message_structured(&session, "assistant", text, Some(out_id), None)?;
+
+        info!(
+            "Response to {bare_jid}: {} chars ({} tokens used)",
+            text.len(),
+            outcome.input_tokens + outcome.output_tokens
+        );
+
+        Ok(outcome)
+    }
+
+    /// Processes a reaction via LLM.
+    /// The reaction is already stored in history by the caller.
+    /// The LLM decides whether a response is warranted based on the full context.
+    /// Returns the LLM outcome (caller stores and sends the response).
+    async fn handle_reaction(&self, jid: &str) -> Result<LoopOutcome> {
+        // Auto-archive stale sessions before loading history
+        self.memory.check_session_freshness(jid, self.config.session.idle_timeout_mins)?;
+
+        let history = self.memory.get_history(jid, MAX_HISTORY)?;
+        let workspace = self.memory.get_workspace_context(jid)?;
+        let system_prompt = self.build_system_prompt(&workspace);
+
+        // The reaction is already the last entry in history (stored by caller)
+        let mut messages = history;
+
+        let tier = self.select_tier(jid, "", None).await;
+        let outcome = self.call_llm_with_tools(&system_prompt, &mut messages, jid, jid, tier).await?;
+
+        info!(
+            "Reaction response to {jid}: {} chars ({} tokens used)",
+            outcome.text.len(),
+            outcome.input_tokens + outcome.output_tokens
+        );
+
+        Ok(outcome)
+    }
+
+    /// Processes a MUC message via LLM.
+    /// The user message is already stored in history by the caller, in
+    /// `session` (the room JID, or a thread of the room).
+    /// Returns the LLM outcome (caller stores the assistant message).
+    async fn handle_muc_message(
+        &self,
+        room_jid: &str,
+        session: &str,
+        body: &str,
+        trigger: Option<Tier>,
+    ) -> Result<LoopOutcome> {
+        // Auto-archive stale sessions before loading history
+        self.memory.check_session_freshness(session, self.config.session.idle_timeout_mins)?;
+
+        // Retrieve conversation history and workspace context
+        let history = self.memory.get_history(session, MAX_HISTORY)?;
+        let workspace = self.memory.get_workspace_context(room_jid)?;
+
+        // Build system prompt
+        let system_prompt = self.build_system_prompt(&workspace);
+
+        // The user message is already the last entry in history (stored by caller)
+        let mut messages = history;
+
+        // Agentic loop (returns immediately if no tools registered)
+        let tier = self.select_tier(room_jid, body, trigger).await;
+        let outcome =
+            self.call_llm_with_tools(&system_prompt, &mut messages, room_jid, session, tier).await?;
+
+        info!(
+            "MUC response to {room_jid}: {} chars ({} tokens used)",
+            outcome.text.len(),
+            outcome.input_tokens + outcome.output_tokens
+        );
+
+        Ok(outcome)
+    }
+
+    /// Builds the system prompt from workspace files.
+    ///
+    /// Assembly order:
+    /// 1. identity.md (who the agent is)
+    /// 2. personality.md (how the agent behaves)
+    /// 3. instructions.md (rules and constraints)
+    /// 4. Hardcoded fallback if none of the 3 global files exist
+    /// 5. Per-JID user.md under "About this user"
+    /// 6. Per-JID memory.md under "Notes and memory"
+    fn build_system_prompt(&self, ctx: &WorkspaceContext) -> String {
+        build_system_prompt_static(&self.config.agent.name, ctx)
+    }
+}
+
+// ── Attachment handling (runs in spawned tasks) ──────────
+
+/// Extracts structured attachment metadata from OOB elements.
+///
+/// For MUC messages (where files are not downloaded), creates `Attachment` structs
+/// with the filename extracted from the URL. MIME type and size are unknown; the
+/// URL is kept so the file can be fetched if the agent is mentioned later.
+/// Returns `None` if no OOB elements are present.
+fn build_oob_attachments(oob_list: &[OobData]) -> Option<Vec<Attachment>> {
+    if oob_list.is_empty() {
+        return None;
+    }
+    let atts: Vec<Attachment> = oob_list
+        .iter()
+        .map(|oob| {
+            let filename = oob
+                .url
+                .rsplit('/')
+                .next()
+                .filter(|s| !s.is_empty())
+                .unwrap_or("file")
+                .to_string();
+            Attachment {
+                filename,
+                mime_type: "unknown".to_string(),
+                size: "unknown".to_string(),
+                sent_size: None,
+                stored_as: None,
+                url: Some(oob.url.clone()),
+                transcript: None,
+            }
+        })
+        .collect();
+    Some(atts)
+}
+
+/// Agentic tool-use loop (free function for use from both methods and spawned tasks).
+///
+/// If skills are registered, tool definitions are passed to the LLM. When the LLM
+/// responds with `tool_use` blocks, the corresponding skills are executed and results
+/// fed back in a loop until the LLM produces a final text response.
+///
+/// `messages` is mutated during the loop (intermediate tool turns are appended).
+/// Only the final text response is returned — intermediate tool calls are not
+/// stored in persistent history.
+///
+/// When `spawn` is `Some`, the runtime-provided `spawn_agent` tool is offered
+/// too (see [`subagent`]). Sub-agents run this same loop with `spawn: None`,
+/// which keeps spawning one level deep. Sub-agent tokens are included in the
+/// returned totals.
+///
+/// Extended thinking blocks are part of `content_blocks` and are passed back
+/// with the tool results, as the Anthropic API requires.
+///
+/// `params` are the conversation's generation overrides, sent with every call.
+pub(super) async fn agentic_loop(
+    system_prompt: &str,
+    messages: &mut Vec<Message>,
+    llm: &dyn LlmClient,
+    params: &GenerationParams,
+    skills: &SkillRegistry,
+    context: &SkillContext,
+    spawn: Option<&SpawnContext<'_>>,
+) -> Result<LoopOutcome> {
+    // Build tool definitions (None if no skills registered and no spawn_agent)
+    let mut tool_defs: Vec<ToolDefinition> = skills.tool_definitions();
+    if let Some(spawn) = spawn {
+        tool_defs.push(subagent::tool_definition(skills, spawn.llms));
+    }
+    let tool_defs: Option<Vec<ToolDefinition>> = if tool_defs.is_empty() {
+        None
+    } else {
+        Some(tool_defs)
+    };
+    let tools_ref = tool_defs.as_deref();
+
+    let mut outcome = LoopOutcome::default();
+
+    for round in 0..MAX_TOOL_ROUNDS {
+        let response = llm.complete(system_prompt, messages, tools_ref, params).await?;
+        outcome.add_response(&response);
+
+        // If no tool calls, we're done — return the text response
+        if response.stop_reason != StopReason::ToolUse || response.tool_calls.is_empty() {
+            outcome.text = response.text;
+            return Ok(outcome);
+        }
+
+        // Log tool calls
+        for tc in &response.tool_calls {
+            info!(
+                "Tool call [round {}/{}]: {}",
+                round + 1,
+                MAX_TOOL_ROUNDS,
+                tc.name,
+            );
+            debug!("Tool input for {}: {}", tc.name, tc.input);
+        }
+
+        // Append assistant message with the raw content blocks (thinking + text + tool_use)
+        messages.push(Message {
+            role: "assistant".to_string(),
+            content: MessageContent::Blocks(response.content_blocks),
+        });
+
+        // Execute each tool call and collect results
+        let mut result_blocks = Vec::new();
+        // Content returned by skills (e.g. a re-read file), sent after the results
+        let mut injected_blocks = Vec::new();
+        for tc in &response.tool_calls {
+            if let (SPAWN_AGENT_TOOL, Some(spawn)) = (tc.name.as_str(), spawn) {
+                let result_content = match subagent::run(spawn, skills, &tc.input, context).await {
+                    Ok(child) => {
+                        outcome.input_tokens = outcome.input_tokens.saturating_add(child.input_tokens);
+                        outcome.output_tokens =
+                            outcome.output_tokens.saturating_add(child.output_tokens);
+                        outcome.files.extend(child.files);
+                        outcome.reactions.extend(child.reactions);
+                        child.text
+                    }
+                    Err(e) => {
+                        warn!("Sub-agent failed: {e}");
+                        format!("Error: {e}")
+                    }
+                };
+                result_blocks.push(InputContentBlock::ToolResult {
+                    tool_use_id: tc.id.clone(),
+                    content: result_content,
+                });
+                continue;
+            }
+
+            let result_content = match skills.get(&tc.name) {
+                Some(skill) => match skill.execute_with_content(tc.input.clone(), context).await {
+                    Ok(output) => {
+                        injected_blocks.extend(output.content);
+                        outcome.files.extend(output.files);
+                        outcome.reactions.extend(output.reactions);
+                        output.text
+                    }
+                    Err(e) => {
+                        warn!("Skill {} failed: {e}", tc.name);
+                        format!("Error: {e}")
+                    }
+                },
+                None => {
+                    warn!("Unknown skill requested: {}", tc.name);
+                    format!("Error: unknown tool '{}'", tc.name)
+                }
+            };
+
+            info!("Tool result for {}: {} chars", tc.name, result_content.len());
+
+            result_blocks.push(InputContentBlock::ToolResult {
+                tool_use_id: tc.id.clone(),
+                content: result_content,
+            });
+        }
+
+        // Append user message with tool_result blocks (tool results must come first)
+        result_blocks.extend(injected_blocks);
+        messages.push(Message {
+            role: "user".to_string(),
+            content: MessageContent::Blocks(result_blocks),
+        });
+    }
+
+    // Exhausted all rounds — make one final call without tools to force a text response
+    warn!(
+        "Agentic loop exhausted {} rounds, forcing final response",
+        MAX_TOOL_ROUNDS
+    );
+    let response = llm.complete(system_prompt, messages, None, params).await?;
+    outcome.add_response(&response);
+    outcome.text = response.text;
+    Ok(outcome)
+}
+
+/// Result of an agentic loop run.
+#[derive(Debug, Default)]
+pub struct LoopOutcome {
+    /// Final text response.
+    pub text: String,
+    /// Total input tokens across all rounds (sub-agents included).
+    pub input_tokens: u32,
+    /// Total output tokens across all rounds (sub-agents included).
+    pub output_tokens: u32,
+    /// Extended thinking text of each round that had some, oldest first.
+    /// Never stored in history.
+    pub reasoning: Vec<String>,
+    /// Files returned by skills (sub-agents included), sent after the reply.
+    pub files: Vec<OutgoingFile>,
+    /// Reactions requested by skills (sub-agents included), sent after the reply.
+    pub reactions: Vec<OutgoingReaction>,
+}
+
+impl From<String> for LoopOutcome {
+    fn from(text: String) -> Self {
+        Self {
+            text,
+            ..Default::default()
+        }
+    }
+}
+
+impl LoopOutcome {
+    /// Accumulates token usage and thinking from one LLM response.
+    fn add_response(&mut self, response: &crate::llm::LlmResponse) {
+        self.input_tokens = self.input_tokens.saturating_add(response.input_tokens);
+        self.output_tokens = self.output_tokens.saturating_add(response.output_tokens);
+        if !response.thinking.trim().is_empty() {
+            self.reasoning.push(response.thinking.trim().to_string());
+        }
+    }
+}
+
+/// Last reasoning summary per conversation, for the `/reasoning` command.
+///
+/// Only populated when `[llm] reasoning_summary = true`. Kept in memory
+/// (never written to history) and overwritten on each LLM response.
+#[derive(Default)]
+struct ReasoningLog {
+    entries: Mutex<HashMap<String, String>>,
+}
+
+impl ReasoningLog {
+    /// Records the reasoning of a loop run, replacing the previous one.
+    /// Runs without thinking leave the previous summary in place.
+    fn record(&self, jid: &str, reasoning: &[String]) {
+        if reasoning.is_empty() {
+            return;
+        }
+        let summary = summarize_reasoning(reasoning);
+        self.entries.lock().unwrap().insert(jid.to_string(), summary);
+    }
+
+    fn get(&self, jid: &str) -> Option<String> {
+        self.entries.lock().unwrap().get(jid).cloned()
+    }
+}
+
+/// Maximum length of a reasoning summary, in characters.
+const REASONING_SUMMARY_MAX_CHARS: usize = 1500;
+
+/// Builds a short reasoning summary: the thinking of each round, separated
+/// by round markers, truncated to [`REASONING_SUMMARY_MAX_CHARS`].
+fn summarize_reasoning(reasoning: &[String]) -> String {
+    let joined = if reasoning.len() == 1 {
+        reasoning[0].clone()
+    } else {
+        reasoning
+            .iter()
+            .enumerate()
+            .map(|(i, r)| format!("[round {}] {r}", i + 1))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    if joined.chars().count() <= REASONING_SUMMARY_MAX_CHARS {
+        return joined;
+    }
+    let truncated: String = joined.chars().take(REASONING_SUMMARY_MAX_CHARS).collect();
+    format!("{truncated}…")
+}
+
+/// Confirmation form of the `forget` ad-hoc command.
+fn forget_form() -> CommandForm {
+    CommandForm {
+        title: "Forget my history".to_string(),
+        instructions: Some(
+            "This erases your conversation history, profile and memory. \
+             Archived sessions are kept."
+                .to_string(),
+        ),
+        fields: vec![FormField {
+            var: "confirm".to_string(),
+            kind: "boolean".to_string(),
+            label: Some("Erase my history".to_string()),
+            required: true,
+            ..Default::default()
+        }],
+    }
+}
+
+/// First form of the `admin-session` ad-hoc command.
+fn manage_target_form() -> CommandForm {
+    CommandForm {
+        title: "Manage a conversation".to_string(),
+        instructions: Some("Archive or erase the conversation with a user or room.".to_string()),
+        fields: vec![
+            FormField {
+                var: "jid".to_string(),
+                kind: "jid-single".to_string(),
+                label: Some("User or room JID".to_string()),
+                required: true,
+                ..Default::default()
+            },
+            FormField {
+                var: "action".to_string(),
+                kind: "list-single".to_string(),
+                label: Some("Action".to_string()),
+                required: true,
+                values: vec!["new".to_string()],
+                options: vec![
+                    ("Archive the session and start a new one".to_string(), "new".to_string()),
+                    ("Erase history, profile and memory".to_string(), "forget".to_string()),
+                ],
+            },
+        ],
+    }
+}
+
+/// Runs the agentic loop on the client for `tier`, with fast-tier escalation.
+///
+/// When the fast tier is used and `[llm.routing] escalation` is enabled, the
+/// fast model is told to answer `ESCALATE` if it is not confident. In that
+/// case the next tier continues the conversation, with the tool calls the
+/// fast tier made and their results: skills are not run twice.
+///
+/// `spawn_agent` enables the runtime-provided `spawn_agent` tool.
+#[allow(clippy::too_many_arguments)]
+async fn tiered_agentic_loop(
+    system_prompt: &str,
+    messages: &mut Vec<Message>,
+    llms: &LlmRegistry,
+    tier: Tier,
+    params: &GenerationParams,
+    skills: &SkillRegistry,
+    context: &SkillContext,
+    spawn_agent: bool,
+) -> Result<LoopOutcome> {
+    let client = llms.get(tier);
+    let spawn = SpawnContext { llms, tier, params };
+    let spawn = spawn_agent.then_some(&spawn);
+    let next = tier.escalate().filter(|_| tier == Tier::Fast);
+    let next = match next {
+        Some(next) if llms.routing().escalation && llms.has(Tier::Fast) => next,
+        _ => {
+            return agentic_loop(
+                system_prompt, messages, client.as_ref(), params, skills, context, spawn,
+            )
+            .await
+        }
+    };
+
+    let start = messages.len();
+    let fast_prompt = tiers::with_escalation_instruction(system_prompt);
+    let mut fast =
+        agentic_loop(&fast_prompt, messages, client.as_ref(), params, skills, context, spawn)
+            .await?;
+    if !tiers::is_escalation(&fast.text) {
+        return Ok(fast);
+    }
+
+    info!("Fast tier escalated request for {} to {next} tier", context.jid);
+    // Thinking blocks are signed for the fast model: only keep its tool turns
+    for message in &mut messages[start..] {
+        if let MessageContent::Blocks(blocks) = &mut message.content {
+            blocks.retain(|block| {
+                !matches!(
+                    block,
+                    InputContentBlock::Thinking { .. } | InputContentBlock::RedactedThinking { .. }
+                )
+            });
+        }
+    }
+    let spawn = SpawnContext { llms, tier: next, params };
+    let spawn = spawn_agent.then_some(&spawn);
+    let outcome = agentic_loop(
+        system_prompt, messages, llms.get(next).as_ref(), params, skills, context, spawn,
+    )
+    .await?;
+    fast.text = outcome.text;
+    fast.input_tokens = fast.input_tokens.saturating_add(outcome.input_tokens);
+    fast.output_tokens = fast.output_tokens.saturating_add(outcome.output_tokens);
+    fast.reasoning.extend(outcome.reasoning);
+    fast.files.extend(outcome.files);
+    fast.reactions.extend(outcome.reactions);
+    Ok(fast)
+}
+
+/// Tier routing inputs captured before spawning an attachment task.
+#[derive(Debug, Clone, Copy)]
+struct TierRoute {
+    /// Explicit tier trigger (`/deep ...`).
+    trigger: Option<Tier>,
+    /// Tier pinned for the conversation with `/tier`.
+    pinned: Option<Tier>,
+}
+
+// ── Answers ──────────────────────────────────────────────
+
+/// File name of long answers uploaded instead of sent (see [`send_answer`]).
+const ANSWER_FILENAME: &str = "answer.md";
+
+/// Length of the excerpt sent with an uploaded answer, in bytes.
+const ANSWER_EXCERPT_BYTES: usize = 600;
+
+/// Where and how a model answer is sent (see [`send_answer`]).
+#[derive(Debug, Clone)]
+struct AnswerTarget {
+    /// Full JID in 1:1 chats, room JID in rooms
+    to: String,
+    msg_type: &'static str,
+    /// Id of the answer in history, carried by its first message
+    id: Option<String>,
+    thread: Option<String>,
+    /// The occupant message answered (XEP-0461), rooms only
+    reply: Option<MessageReply>,
+    /// Occupant JID of the asker, addressed by nick, rooms only
+    mention: Option<String>,
+    /// Message the answer corrects (XEP-0308), 1:1 only
+    replace_id: Option<String>,
+    /// Convert the Markdown to Message Styling (XEP-0393)
+    styling: bool,
+}
+
+impl AnswerTarget {
+    /// An answer in conversation `jid` (bare JID or room), styled as
+    /// configured for it.
+    fn new(config: &Config, jid: &str, to: &str, msg_type: &'static str, id: Option<String>) -> Self {
+        Self {
+            to: to.to_string(),
+            msg_type,
+            id,
+            thread: None,
+            reply: None,
+            mention: None,
+            replace_id: None,
+            styling: config.message_styling(jid),
+        }
+    }
+
+    /// Message carrying one part of the answer. Only the first part has
+    /// the id, the reply and mention references and the correction; all
+    /// stay in the thread.
+    fn command(&self, body: String, first: bool) -> XmppCommand {
+        let id = if first { self.id.clone() } else { None };
+        match (&self.replace_id, self.msg_type) {
+            (Some(replace_id), _) if first => XmppCommand::SendCorrection {
+                to: self.to.clone(),
+                body,
+                id,
+                replace_id: replace_id.clone(),
+            },
+            (_, "groupchat") => XmppCommand::SendMucMessage {
+                to: self.to.clone(),
+                body,
+                id,
+                thread: self.thread.clone(),
+                reply: if first { self.reply.clone() } else { None },
+                mention: if first { self.mention.clone() } else { None },
+            },
+            _ => XmppCommand::SendMessage {
+                to: self.to.clone(),
+                body,
+                id,
+                thread: self.thread.clone(),
+            },
+        }
+    }
+}
+
+/// A long answer uploaded as a file, with the messages sent instead if
+/// the upload fails.
+struct AnswerUpload {
+    file: OutgoingFile,
+    fallback: Vec<XmppCommand>,
+}
+
+/// Sends a model answer, then the files returned by skills.
+///
+/// The answer is converted to Message Styling when enabled, and split
+/// into consecutive messages when longer than `[output] max_body_bytes`.
+/// Room answers start with the asker's nick. The parts are queued in
+/// order on the command channel, which the
+/// connection writes one stanza at a time, so they arrive in order.
+/// Answers over `upload_above_bytes` are uploaded as a Markdown file
+/// after a short excerpt; if the upload fails, a note and the parts are
+/// sent instead.
+async fn send_answer(
+    config: &Config,
+    uploader: &Arc<HttpUploader>,
+    cmd_tx: &mpsc::Sender<XmppCommand>,
+    target: AnswerTarget,
+    text: String,
+    files: Vec<OutgoingFile>,
+) {
+    let mut body = if target.styling {
+        styling::markdown_to_styling(&text)
+    } else {
+        text.clone()
+    };
+    if let Some((_, nick)) = target.mention.as_deref().and_then(|jid| jid.split_once('/')) {
+        body = address_answer(nick, &body);
+    }
+    let parts = split::split_body(&body, config.output.max_body_bytes);
+
+    let upload = config.output.upload_above_bytes.filter(|max| body.len() > *max);
+    let upload = if upload.is_some() {
+        let excerpt = split::split_body(&body, ANSWER_EXCERPT_BYTES).swap_remove(0);
+        let note = format!(
+            "(The full answer is long: it follows as {ANSWER_FILENAME}, {} KB.)",
+            text.len().div_ceil(1024)
+        );
+        let _ = cmd_tx.send(target.command(format!("{excerpt}\n\n{note}"), true)).await;
+        Some(AnswerUpload {
+            file: OutgoingFile {
+                filename: ANSWER_FILENAME.to_string(),
+                mime_type: "text/markdown".to_string(),
+                data: text.into_bytes(),
+            },
+            fallback: answer_fallback(&target, parts),
+        })
+    } else {
+        for (i, part) in parts.into_iter().enumerate() {
+            let _ = cmd_tx.send(target.command(part, i == 0)).await;
+        }
+        None
+    };
+
+    spawn_file_delivery(
+        Arc::clone(uploader),
+        cmd_tx.clone(),
+        target.to,
+        target.msg_type,
+        target.thread,
+        upload,
+        files,
+    );
+}
+
+/// Messages sent when the upload of a long answer fails. The excerpt
+/// already went out, so a short note announces that the whole answer
+/// follows, starting again from its beginning.
+fn answer_fallback(target: &AnswerTarget, parts: Vec<String>) -> Vec<XmppCommand> {
+    let note = format!("(Could not upload {ANSWER_FILENAME}: the full answer follows.)");
+    std::iter::once(note)
+        .chain(parts)
+        .map(|part| target.command(part, false))
+        .collect()
+}
+
+/// Uploads a long answer (see [`send_answer`]) and sends the files
+/// returned by skills (see [`OutgoingFile`]), in order.
+///
+/// Runs in a spawned task: uploads wait for IQ results, which only the
+/// event loop reads. Failures are reported to the recipient, in the
+/// thread of the answer.
+fn spawn_file_delivery(
+    uploader: Arc<HttpUploader>,
+    cmd_tx: mpsc::Sender<XmppCommand>,
+    to: String,
+    msg_type: &'static str,
+    thread: Option<String>,
+    answer: Option<AnswerUpload>,
+    files: Vec<OutgoingFile>,
+) {
+    if answer.is_none() && files.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        if let Some(answer) = answer {
+            if let Err(e) = uploader.send_file(&cmd_tx, &to, msg_type, &answer.file).await {
+                warn!("Failed to upload the answer to {to}, sending it as messages: {e}");
+                for command in answer.fallback {
+                    let _ = cmd_tx.send(command).await;
+                }
+            }
+        }
+        for file in files {
+            let url = match uploader.send_file(&cmd_tx, &to, msg_type, &file).await {
+                Ok(url) => url,
+                Err(e) => {
+                    error!("Failed to send file {} to {to}: {e}", file.filename);
+                    let body = format!("Sorry, I could not send the file '{}': {e}", file.filename);
+                    let command = delivery_error(&to, msg_type, thread.clone(), body);
+                    let _ = cmd_tx.send(command).await;
+                    continue;
+                }
+            };
+            info!("Sent file {} to {to}: {url}", file.filename);
+        }
+    });
+}
+
+/// Message reporting a failed file delivery to `to`, in `thread`.
+fn delivery_error(to: &str, msg_type: &str, thread: Option<String>, body: String) -> XmppCommand {
+    if msg_type == "groupchat" {
+        XmppCommand::SendMucMessage {
+            to: to.to_string(),
+            body,
+            id: None,
+            thread,
+            reply: None,
+            mention: None,
+        }
+    } else {
+        XmppCommand::SendMessage { to: to.to_string(), body, id: None, thread }
+    }
+}
+
+// ── Reactions (XEP-0444) ─────────────────────────────────
+
+/// Where the agent's reactions in a conversation go: the recipient, the
+/// session they are recorded in, and the message being answered.
+#[derive(Debug, Clone)]
+struct ReactionTarget {
+    /// Full JID in 1:1 chats, room JID in rooms
+    to: String,
+    msg_type: &'static str,
+    /// Memory key of the session (see [`Memory::thread_session`])
+    session: String,
+    /// Id of the message being answered: the room's stanza id in rooms
+    current: Option<String>,
+}
+
+impl ReactionTarget {
+    /// Target for the answer to `msg`, recorded in `session`.
+    fn of(msg: &IncomingMessage, session: &str) -> Self {
+        let (to, msg_type, current) = if msg.message_type == MessageType::GroupChat {
+            let current = msg.stanza_id.clone().or_else(|| msg.id.clone());
+            (stanzas::bare_jid(&msg.from).to_string(), "groupchat", current)
+        } else {
+            (msg.from.clone(), "chat", msg.id.clone())
+        };
+        Self {
+            to,
+            msg_type,
+            session: session.to_string(),
+            current,
+        }
+    }
+}
+
+/// Stage of an answer, for acknowledgement reactions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AckStage {
+    Working,
+    Done,
+    Failed,
+}
+
+/// Updates the agent's reactions to `message_id`: the set last recorded
+/// in the session, without `remove` and with `add`. The whole set is sent,
+/// as each update replaces the previous one, and recorded in history.
+async fn update_reactions(
+    memory: &Memory,
+    cmd_tx: &mpsc::Sender<XmppCommand>,
+    target: &ReactionTarget,
+    message_id: &str,
+    add: &[String],
+    remove: &[String],
+    ack: bool,
+) {
+    let current = match memory.own_reactions(&target.session, message_id) {
+        Ok(current) => current,
+        Err(e) => {
+            warn!("Failed to read reactions in {}: {e}", target.session);
+            Vec::new()
+        }
+    };
+    let mut emojis: Vec<String> = current.iter().filter(|e| !remove.contains(e)).cloned().collect();
+    for emoji in add {
+        if !emojis.contains(emoji) {
+            emojis.push(emoji.clone());
+        }
+    }
+    if emojis == current {
+        return;
+    }
+
+    let _ = cmd_tx
+        .send(XmppCommand::SendReaction {
+            to: target.to.clone(),
+            message_id: message_id.to_string(),
+            emojis: emojis.clone(),
+            msg_type: target.msg_type.to_string(),
+        })
+        .await;
+    let reaction = Reaction {
+        message_id: message_id.to_string(),
+        emojis,
+        ack,
+    };
+    if let Err(e) =
+        memory.store_message_full(&target.session, "assistant", "", None, None, None, Some(reaction))
+    {
+        error!("Failed to store reaction: {e}");
+    }
+}
+
+/// Sends the reactions requested by skills (see [`OutgoingReaction`]),
+/// after the reply. Reactions without a message id go to the message
+/// being answered.
+async fn deliver_reactions(
+    memory: &Memory,
+    cmd_tx: &mpsc::Sender<XmppCommand>,
+    target: &ReactionTarget,
+    reactions: Vec<OutgoingReaction>,
+) {
+    for reaction in reactions {
+        let Some(message_id) = reaction.message_id.or_else(|| target.current.clone()) else {
+            warn!("No message to react to in {}", target.session);
+            continue;
+        };
+        info!("Reacting {} to {message_id} in {}", reaction.emojis.join(" "), target.session);
+        update_reactions(memory, cmd_tx, target, &message_id, &reaction.emojis, &[], false).await;
+    }
+}
+
+/// Acknowledgement reactions (`[reactions] acknowledge`): `working` on
+/// the message being answered, replaced by `done` once the answer is
+/// sent, or just removed if it failed.
+async fn acknowledge_task(
+    config: &ReactionsConfig,
+    memory: &Memory,
+    cmd_tx: &mpsc::Sender<XmppCommand>,
+    target: &ReactionTarget,
+    stage: AckStage,
+) {
+    let Some(message_id) = target.current.as_deref().filter(|_| config.acknowledge) else {
+        return;
+    };
+    let emoji = |e: &str| -> Vec<String> {
+        Some(e.trim().to_string()).filter(|e| !e.is_empty()).into_iter().collect()
+    };
+    let (working, done) = (emoji(&config.working), emoji(&config.done));
+    let (add, remove) = match stage {
+        AckStage::Working => (working, Vec::new()),
+        AckStage::Done => (done, working),
+        AckStage::Failed => (Vec::new(), working),
+    };
+    update_reactions(memory, cmd_tx, target, message_id, &add, &remove, true).await;
+}
+
+/// Handles a 1:1 message with OOB file attachments.
+///
+/// Downloads each file, converts supported types to Anthropic API content blocks,
+/// and sends a multi-modal message to the LLM. Runs in a spawned task.
+/// Returns the loop outcome; the response text is already stored in history.
+#[allow(clippy::too_many_arguments)]
+async fn handle_message_with_attachments(
+    from: &str,
+    body: &str,
+    msg_id: Option<&str>,
+    out_id: &str,
+    thread: Option<&str>,
+    oob_list: &[OobData],
+    downloader: &FileDownloader,
+    memory: &Memory,
+    llms: &LlmRegistry,
+    route: TierRoute,
+    config: &Config,
+    skills: &SkillRegistry,
+) -> Result<LoopOutcome> {
+    let bare_jid = stanzas::bare_jid(from);
+    let files_dir = memory.files_dir(bare_jid)?;
+
+    info!(
+        "Processing {} attachment(s) from {bare_jid}",
+        oob_list.len()
+    );
+
+    // Download files sequentially (semaphore inside FileDownloader handles concurrency)
+    let mut content_blocks: Vec<InputContentBlock> = Vec::new();
+    let mut attachment_meta: Vec<Attachment> = Vec::new();
+
+    for (i, oob) in oob_list.iter().enumerate() {
+        debug!("Downloading attachment {}/{}: {}", i + 1, oob_list.len(), oob.url);
+        let downloaded = downloader.download(&oob.url, &files_dir).await;
+        let (block, meta) = attachment_content(downloaded, &oob.url, &config.attachments).await;
+        content_blocks.push(block);
+        attachment_meta.push(meta);
+    }
+
+    // Add text body if present
+    if !body.is_empty() {
+        content_blocks.push(InputContentBlock::Text {
+            text: body.to_string(),
+        });
+    }
+
+    // Auto-archive stale sessions before loading history
+    let session = Memory::thread_session(bare_jid, thread);
+    memory.check_session_freshness(&session, config.session.idle_timeout_mins)?;
+
+    // Build the multi-modal message with structured JSON metadata block
+    let history = memory.get_history(&session, MAX_HISTORY)?;
+    let workspace = memory.get_workspace_context(bare_jid)?;
+    let system_prompt = build_system_prompt_static(&config.agent.name, &workspace);
+
+    // Build multi-modal message — content blocks only, no runtime metadata
+    let mut messages = history;
+    messages.push(Message {
+        role: "user".to_string(),
+        content: MessageContent::Blocks(content_blocks),
+    });
+
+    debug!("Calling LLM with {} messages (including attachment)", messages.len());
+
+    // Agentic loop (returns immediately if no tools registered)
+    let context = SkillContext {
+        jid: bare_jid.to_string(),
+        session: session.clone(),
+        base_path: memory.base_path().to_path_buf(),
+    };
+    let params = config.generation_overrides(bare_jid);
+    let signals = RouteSignals {
+        body,
+        has_attachments: true,
+        trigger: route.trigger,
+        pinned: route.pinned,
+        room: None,
+        model_override: params.model.is_some(),
+    };
+    let tier = llms.select_tier(&signals).await;
+    let outcome = tiered_agentic_loop(
+        &system_prompt,
+        &mut messages,
+        llms,
+        tier,
+        &params,
+        skills,
+        &context,
+        config.skills.spawn_agent_enabled(),
+    )
+    .await?;
+
+    // Store messages in history — attachments as structured metadata, not text labels
+    let attachments = if attachment_meta.is_empty() {
+        None
+    } else {
+        Some(attachment_meta)
+    };
+    memory.store_message_full(&session, "user", body, msg_id, Some(bare_jid), attachments, None)?;
+    memory.store_message_structured(&session, "assistant", &outcome.text, Some(out_id), None)?;
+
+    info!(
+        "Attachment response to {bare_jid}: {} chars ({} tokens used)",
+        outcome.text.len(),
+        outcome.input_tokens + outcome.output_tokens
+    );
+
+    Ok(outcome)
+}
+
+// ── Message archive (XEP-0313) ───────────────────────────
+
+/// A conversation to synchronize from its archive.
+#[derive(Debug, Clone)]
+struct ArchiveTarget {
+    /// Bare JID of the conversation (room or contact)
+    jid: String,
+    /// The agent's nick, for rooms
+    room_nick: Option<String>,
+    /// Timestamp of the last local message, None without local history
+    since: Option<String>,
+    /// Start a new session with the archive's recent messages
+    /// (`history = "archive"`) instead of appending the missed ones
+    replace: bool,
+}
+
+/// Archive messages fetched for a conversation, sent back to the event loop.
+struct ArchiveSync {
+    target: ArchiveTarget,
+    messages: Vec<ArchivedMessage>,
+    /// First message of a contact, held back until its history is imported
+    deferred: Option<IncomingMessage>,
+}
+
+/// Parses an RFC 3339 timestamp (XEP-0082 or session `ts`) into UTC.
+fn parse_stamp(stamp: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(stamp)
+        .ok()
+        .map(|dt| dt.with_timezone(&chrono::Utc))
+}
+
+/// Queries the archive for a conversation: the messages since the local
+/// history ended, or the most recent ones to bootstrap (or replace) it.
+async fn fetch_archive(
+    mam: &MamClient,
+    cmd_tx: &mpsc::Sender<XmppCommand>,
+    config: &MamConfig,
+    account: Option<&str>,
+    target: &ArchiveTarget,
+) -> Result<Vec<ArchivedMessage>> {
+    // Rooms have their own archive; contacts are in the account's one
+    let (archive, own, with) = match (&target.room_nick, account) {
+        (Some(_), _) => (target.jid.as_str(), false, None),
+        (None, Some(account)) => (account, true, Some(target.jid.as_str())),
+        (None, None) => return Ok(Vec::new()),
+    };
+    match target.since.as_deref().and_then(parse_stamp) {
+        Some(since) if !target.replace => {
+            let start = since.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+            mam.since(cmd_tx, archive, own, with, &start, config.max_catch_up, config.page_size)
+                .await
+        }
+        _ => {
+            mam.recent(cmd_tx, archive, own, with, config.bootstrap_messages)
+                .await
+        }
+    }
+}
+
+/// A MUC mention to be answered with the room's attachments.
+#[derive(Debug, Clone)]
+struct MucAttachmentRequest {
+    /// Room configuration (JID, size limit)
+    room: RoomConfig,
+    /// Occupant JID of the sender, addressed in the answer
+    from: String,
+    /// Sender label stored in history (`{nick}@muc`)
+    sender_label: String,
+    /// Message body as received (stored in history)
+    body: String,
+    /// Body with the mention and tier trigger stripped (sent to the LLM)
+    text: String,
+    /// Inbound stanza id
+    msg_id: Option<String>,
+    /// Id assigned by the room (XEP-0359)
+    stanza_id: Option<String>,
+    /// Sender's occupant id (XEP-0421)
+    occupant_id: Option<String>,
+    /// Conversation thread (XEP-0201), answered in its own session
+    thread: Option<String>,
+    /// The mentioning message, which the answer replies to (XEP-0461)
+    reply: Option<MessageReply>,
+    /// Files attached to the mentioning message
+    oob: Vec<OobData>,
+    /// Earlier room attachments to include, oldest first
+    earlier: Vec<Attachment>,
+    /// Tier routing inputs
+    route: TierRoute,
+}
+
+/// Handles a MUC mention in a room with `download_attachments`.
+///
+/// Downloads the files attached to the mentioning message and the earlier
+/// room attachments into the room's `files/` directory (earlier files that
+/// were already downloaded are reused), and sends them to the LLM as for
+/// 1:1 messages. Runs in a spawned task.
+/// The mentioning message is stored with its attachment metadata; the caller
+/// stores the response.
+async fn handle_muc_message_with_attachments(
+    request: &MucAttachmentRequest,
+    downloader: &FileDownloader,
+    memory: &Memory,
+    llms: &LlmRegistry,
+    config: &Config,
+    skills: &SkillRegistry,
+) -> Result<LoopOutcome> {
+    let room_jid = request.room.jid.as_str();
+    let files_dir = memory.files_dir(room_jid)?;
+    let max_size = request.room.max_attachment_bytes();
+
+    info!(
+        "Processing {} attachment(s) and {} earlier one(s) in {room_jid}",
+        request.oob.len(),
+        request.earlier.len()
+    );
+
+    let mut content_blocks: Vec<InputContentBlock> = Vec::new();
+
+    for earlier in &request.earlier {
+        let Some(url) = earlier.url.as_deref() else {
+            continue;
+        };
+        content_blocks.push(InputContentBlock::Text {
+            text: format!("[Shared earlier in the room: {}]", earlier.filename),
+        });
+        let downloaded = match stored_attachment(&files_dir, earlier).await {
+            Some(file) => Ok(file),
+            None => downloader.download_limited(url, &files_dir, max_size).await,
+        };
+        let (block, _) = attachment_content(downloaded, url, &config.attachments).await;
+        content_blocks.push(block);
+    }
+
+    let mut attachment_meta: Vec<Attachment> = Vec::new();
+    for oob in &request.oob {
+        let downloaded = downloader.download_limited(&oob.url, &files_dir, max_size).await;
+        let (block, mut meta) = attachment_content(downloaded, &oob.url, &config.attachments).await;
+        meta.url = Some(oob.url.clone());
+        content_blocks.push(block);
+        attachment_meta.push(meta);
+    }
+
+    // Sender label as in room history, so the LLM knows who is asking
+    content_blocks.push(InputContentBlock::Text {
+        text: format!("{}: {}", request.sender_label, request.text).trim_end().to_string(),
+    });
+
+    let session = Memory::thread_session(room_jid, request.thread.as_deref());
+    memory.check_session_freshness(&session, config.session.idle_timeout_mins)?;
+
+    let history = memory.get_history(&session, MAX_HISTORY)?;
+    let workspace = memory.get_workspace_context(room_jid)?;
+    let system_prompt = build_system_prompt_static(&config.agent.name, &workspace);
+
+    let mut messages = history;
+    messages.push(Message {
+        role: "user".to_string(),
+        content: MessageContent::Blocks(content_blocks),
+    });
+
+    let context = SkillContext {
+        jid: room_jid.to_string(),
+        session: session.clone(),
+        base_path: memory.base_path().to_path_buf(),
+    };
+    let params = config.generation_overrides(room_jid);
+    let signals = RouteSignals {
+        body: &request.text,
+        has_attachments: true,
+        trigger: request.route.trigger,
+        pinned: request.route.pinned,
+        room: request.room.tier,
+        model_override: params.model.is_some(),
+    };
+    let tier = llms.select_tier(&signals).await;
+    let outcome = tiered_agentic_loop(
+        &system_prompt,
+        &mut messages,
+        llms,
+        tier,
+        &params,
+        skills,
+        &context,
+        config.skills.spawn_agent_enabled(),
+    )
+    .await?;
+
+    memory.store_room_message(
+        &session,
+        &request.body,
+        request.msg_id.as_deref(),
+        MessageMeta::room(request.stanza_id.as_deref(), request.occupant_id.as_deref()),
+        &request.sender_label,
+        Some(attachment_meta),
+    )?;
+
+    info!(
+        "MUC attachment response to {room_jid}: {} chars ({} tokens used)",
+        outcome.text.len(),
+        outcome.input_tokens + outcome.output_tokens
+    );
+
+    Ok(outcome)
+}
+
+/// Returns the stored copy of an earlier attachment, if it was already
+/// downloaded and is still on disk.
+async fn stored_attachment(files_dir: &std::path::Path, attachment: &Attachment) -> Option<DownloadedFile> {
+    let stored_as = attachment.stored_as.as_deref()?;
+    let path = files_dir.join(stored_as);
+    let size = tokio::fs::metadata(&path).await.ok()?.len();
+    let mime_type = Some(attachment.mime_type.clone())
+        .filter(|m| m != "unknown")
+        .unwrap_or_else(|| files::mime_from_extension(&attachment.filename));
+    Some(DownloadedFile {
+        category: files::categorize(&mime_type, &attachment.filename),
+        path,
+        filename: attachment.filename.clone(),
+        mime_type,
+        size,
+    })
+}
+
+/// Converts the result of an attachment download into the content block
+/// sent to the LLM and the attachment metadata stored in history.
+///
+/// Failures (download, unsupported type, encoding) become text notes so
+/// the LLM can tell the user what went wrong.
+async fn attachment_content(
+    downloaded: Result<DownloadedFile>,
+    url: &str,
+    attachments: &AttachmentsConfig,
+) -> (InputContentBlock, Attachment) {
+    let file = match downloaded {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("Failed to download {url}: {e}");
+            let block = InputContentBlock::Text {
+                text: format!("[File download failed: {e}]"),
+            };
+            let meta = Attachment {
+                filename: "unknown".to_string(),
+                mime_type: "unknown".to_string(),
+                size: "download failed".to_string(),
+                sent_size: None,
+                stored_as: None,
+                url: None,
+                transcript: None,
+            };
+            return (block, meta);
+        }
+    };
+
+    info!(
+        "Downloaded {} ({}, {})",
+        file.filename, file.mime_type, file.human_size()
+    );
+    let mut meta = Attachment {
+        filename: file.filename.clone(),
+        mime_type: file.mime_type.clone(),
+        size: file.human_size(),
+        sent_size: None,
+        stored_as: file
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string()),
+        url: None,
+        transcript: None,
+    };
+    let block = match file_to_content_block(&file, attachments).await {
+        Ok(Some(content)) => {
+            meta.sent_size = content.sent_size;
+            meta.transcript = content.transcript;
+            content.block
+        }
+        Ok(None) => {
+            // Unsupported type — add text note
+            InputContentBlock::Text {
+                text: format!(
+                    "[File received: {} ({}) — unsupported type, cannot analyze]",
+                    file.filename, file.mime_type
+                ),
+            }
+        }
+        Err(e) => {
+            warn!("Failed to encode file {}: {e}", file.filename);
+            InputContentBlock::Text {
+                text: format!(
+                    "[File received: {} — encoding error]",
+                    file.filename
+                ),
+            }
+        }
+    };
+    (block, meta)
+}
+
+/// Static version of build_system_prompt for use from spawned tasks.
+/// (Cannot borrow `self` in a spawned task, so we extract the logic.)
+fn build_system_prompt_static(agent_name: &str, ctx: &WorkspaceContext) -> String {
+    let has_global_files = ctx.identity.is_some()
+        || ctx.personality.is_some()
+        || ctx.instructions.is_some();
+
+    let mut prompt = String::new();
+
+    if has_global_files {
+        if let Some(ref identity) = ctx.identity {
+            prompt.push_str(identity.trim());
+            prompt.push_str("\n\n");
+        }
+
+        if let Some(ref personality) = ctx.personality {
+            prompt.push_str(personality.trim());
+            prompt.push_str("\n\n");
+        }
+
+        if let Some(ref instructions) = ctx.instructions {
+            prompt.push_str(instructions.trim());
+        }
+    } else {
+        prompt.push_str(&format!(
+            "You are {agent_name}, a personal AI assistant accessible via XMPP.\n\
+             You are direct, helpful, and concise. You respond in the user's language.\n\n\
+             Rules:\n\
+             - Respond concisely, no excessive markdown formatting\n\
+             - If asked to execute an action (send an email, modify a file...), \
+               describe what you would do but clarify that you cannot yet execute \
+               actions (skills are coming in v0.2)\n\
+             - You have memory of previous conversations with this user"
+        ));
+    }
+
+    if let Some(ref profile) = ctx.user_profile {
+        prompt.push_str(&format!("\n\n## About this user\n{}", profile.trim()));
+    }
+
+    if let Some(ref memory) = ctx.user_memory {
+        prompt.push_str(&format!("\n\n## Notes and memory\n{}", memory.trim()));
+    }
+
+    // Inject current date so the LLM has temporal awareness
+    // (e.g. for forming time-relevant web search queries).
+    let now = Local::now();
+    prompt.push_str(&format!(
+        "\n\nCurrent date: {}",
+        now.format("%A, %B %-d, %Y")
+    ));
+
+    prompt
+}
+
+// ── MUC mention helpers ──────────────────────────────────
+
+/// Checks if the bot's nickname is mentioned in the message body: the
+/// exact nick as a whole word, with or without `@` (case-insensitive).
+/// "@fluux-agent-dev" does not mention "fluux-agent".
+fn is_mentioned(nick: &str, body: &str) -> bool {
+    contains_word(body, nick)
+}
+
+/// Whether `word` appears in `text` as a whole word (case-insensitive).
+fn contains_word(text: &str, word: &str) -> bool {
+    let text = text.to_lowercase();
+    let word = word.to_lowercase();
+    !word.is_empty()
+        && text
+            .match_indices(&word)
+            .any(|(start, _)| is_whole_word(&text, start, word.len()))
+}
+
+/// Whether `text[start..start + len]` is not glued to a letter, digit,
+/// `-` or `_`. A `.` ends the word only when nothing follows it:
+/// "bot." is a word, "bot.example" is not.
+fn is_whole_word(text: &str, start: usize, len: usize) -> bool {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '-' || c == '_';
+    if text[..start].chars().next_back().is_some_and(is_word_char) {
+        return false;
+    }
+    let mut after = text[start + len..].chars();
+    match after.next() {
+        None => true,
+        Some('.') => after.next().is_none_or(|c| !is_word_char(c)),
+        Some(c) => !is_word_char(c),
+    }
+}
+
+/// Whether a room message mentions the agent: a reference (XEP-0372)
+/// to its occupant JID, or its nick in the body.
+fn mentions_agent(room_jid: &str, nick: &str, msg: &IncomingMessage) -> bool {
+    let occupant = format!("{room_jid}/{nick}");
+    msg.mentions.contains(&occupant) || is_mentioned(nick, &msg.body)
+}
+
+/// Whether a room message is for the agent, per the room's `activation`.
+fn activates(room: &RoomConfig, mentioned: bool, body: &str) -> bool {
+    match room.activation {
+        Activation::Mention => mentioned,
+        Activation::All => true,
+        Activation::Keywords => {
+            mentioned || room.keywords.iter().any(|keyword| contains_word(body, keyword))
+        }
+    }
+}
+
+/// Strips the mention prefix from the message body.
+/// Removes patterns like "@nick ", "@nick: ", "nick: ", "nick, " from the
+/// beginning, when the nick is a whole word.
+fn strip_mention(nick: &str, body: &str) -> String {
+    let lower_body = body.to_lowercase();
+    let lower_nick = nick.to_lowercase();
+
+    let start = usize::from(lower_body.starts_with('@'));
+    if lower_body[start..].starts_with(&lower_nick)
+        && is_whole_word(&lower_body, start, lower_nick.len())
+    {
+        let rest = body.get(start + nick.len()..).unwrap_or_default();
+        let trimmed = rest.trim_start_matches(':').trim_start_matches(',').trim_start();
+        if !trimmed.is_empty() {
+            return trimmed.to_string();
+        }
+    }
+
+    // "@nick" in the middle — no stripping needed, return full body
+    body.to_string()
+}
+
+/// Prefixes a room answer with the nick of the occupant it answers, as
+/// room members address each other. Blocks that must start a line (code,
+/// quotes) go on the next one.
+fn address_answer(nick: &str, body: &str) -> String {
+    if body.starts_with(nick) {
+        return body.to_string();
+    }
+    if body.starts_with("```") || body.starts_with('>') {
+        format!("{nick}:\n{body}")
+    } else {
+        format!("{nick}: {body}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::*;
+    use crate::llm::AnthropicClient;
+    use tempfile::TempDir;
+
+    /// Build a test runtime with a temporary memory directory
+    fn test_runtime() -> (AgentRuntime, TempDir) {
+        let tmp = TempDir::new().unwrap();
+        let config = Config {
+            server: ServerConfig {
+                host: "localhost".to_string(),
+                port: 5222,
+                mode: ConnectionMode::Client {
+                    jid: "bot@localhost".to_string(),
+                    password: "pass".to_string(),
+                    resource: "fluux-agent".to_string(),
+                    tls_verify: false,
+                },
+            },
+            llm: LlmConfig {
+                provider: "anthropic".to_string(),
+                model: "claude-sonnet-4-5-20250929".to_string(),
+                api_key: "test-key".to_string(),
+                max_tokens_per_request: 4096,
+                host: None,
+                vision: false,
+                thinking_budget_tokens: None,
+                reasoning_summary: false,
+                generation: Default::default(),
+                cassette: None,
+                record: false,
+                script: None,
+                tiers: LlmTiersConfig::default(),
+                routing: RoutingConfig::default(),
+            },
+            agent: AgentConfig {
+                name: "Test Agent".to_string(),
+                allowed_jids: vec!["admin@localhost".to_string()],
+                allowed_domains: vec![],
+                admin_jids: vec![],
+            },
+            memory: MemoryConfig {
+                backend: "markdown".to_string(),
+                path: tmp.path().to_path_buf(),
+            },
+            rooms: vec![],
+            users: vec![],
+            skills: SkillsConfig::default(),
+            keepalive: crate::config::KeepaliveConfig::default(),
+            session: crate::config::SessionConfig::default(),
+            attachments: crate::config::AttachmentsConfig::default(),
+            upload: crate::config::UploadConfig::default(),
+            mam: crate::config::MamConfig::default(),
+            carbons: crate::config::CarbonsConfig::default(),
+            reactions: crate::config::ReactionsConfig::default(),
+            output: crate::config::OutputConfig::default(),
+        };
+
+        let llm: Arc<dyn LlmClient> = Arc::new(AnthropicClient::new(config.llm.clone()));
+        let memory = Arc::new(Memory::open(tmp.path()).unwrap());
+        let file_downloader = Arc::new(FileDownloader::new(3));
+        let skills = SkillRegistry::new();
+        let runtime =
+            AgentRuntime::new(config, LlmRegistry::new(llm), memory, file_downloader, skills);
+        (runtime, tmp)
+    }
+
+    // ── MUC mention helper tests ────────────────────────
+
+    #[test]
+    fn test_is_mentioned_at_prefix() {
+        assert!(is_mentioned("bot", "@bot what's up?"));
+        assert!(is_mentioned("bot", "hey @bot help"));
+        assert!(is_mentioned("FluuxBot", "@fluuxbot hello"));
+    }
+
+    #[test]
+    fn test_is_mentioned_colon_prefix() {
+        assert!(is_mentioned("bot", "bot: what's up?"));
+        assert!(is_mentioned("FluuxBot", "FluuxBot: help"));
+    }
+
+    #[test]
+    fn test_is_mentioned_space_prefix() {
+        assert!(is_mentioned("bot", "bot what's up?"));
+    }
+
+    #[test]
+    fn test_is_mentioned_not_mentioned() {
+        assert!(!is_mentioned("bot", "hello everyone"));
+        assert!(!is_mentioned("bot", "robotics are cool"));
+    }
+
+    #[test]
+    fn test_strip_mention_at_prefix() {
+        assert_eq!(strip_mention("bot", "@bot what's up?"), "what's up?");
+        assert_eq!(strip_mention("bot", "@bot: help me"), "help me");
+        assert_eq!(strip_mention("FluuxBot", "@FluuxBot hello"), "hello");
+    }
+
+    #[test]
+    fn test_strip_mention_colon_prefix() {
+        assert_eq!(strip_mention("bot", "bot: what's up?"), "what's up?");
+        assert_eq!(strip_mention("FluuxBot", "FluuxBot: help"), "help");
+    }
+
+    #[test]
+    fn test_strip_mention_middle_keeps_body() {
+        // "@nick" in the middle — no stripping
+        assert_eq!(
+            strip_mention("bot", "hey @bot help me"),
+            "hey @bot help me"
+        );
+    }
+
+    #[test]
+    fn test_is_mentioned_case_insensitive() {
+        assert!(is_mentioned("FluuxBot", "@fluuxbot help"));
+        assert!(is_mentioned("bot", "@BOT help"));
+        assert!(is_mentioned("Bot", "bot: hello"));
+        assert!(is_mentioned("BOT", "Bot: hello"));
+    }
+
+    #[test]
+    fn test_is_mentioned_with_punctuation() {
+        // "@bot!" — the @bot substring is found
+        assert!(is_mentioned("bot", "@bot! help me"));
+        assert!(is_mentioned("bot", "@bot, please help"));
+        assert!(is_mentioned("bot", "@bot? are you there"));
+    }
+
+    #[test]
+    fn test_is_mentioned_at_end_of_message() {
+        assert!(is_mentioned("bot", "hey @bot"));
+    }
+
+    #[test]
+    fn test_strip_mention_comma_after_at() {
+        assert_eq!(strip_mention("bot", "@bot, help me"), "help me");
+    }
+
+    #[test]
+    fn test_strip_mention_only_mention_returns_full_body() {
+        // "@bot" with nothing after → returns full body (no stripping)
+        assert_eq!(strip_mention("bot", "@bot"), "@bot");
+    }
+
+    #[test]
+    fn test_strip_mention_case_insensitive() {
+        assert_eq!(strip_mention("FluuxBot", "@fluuxbot hello"), "hello");
+        assert_eq!(strip_mention("BOT", "bot: hello"), "hello");
+    }
+
+    #[test]
+    fn test_is_mentioned_whole_nick_only() {
+        assert!(!is_mentioned("fluux-agent", "@fluux-agent-dev hello"));
+        assert!(!is_mentioned("bot", "see bot.example.com"));
+        assert!(!is_mentioned("bot", "ask bot_2"));
+        assert!(is_mentioned("fluux-agent", "thanks fluux-agent."));
+        assert!(is_mentioned("bot", "ask bot about it"));
+        assert!(is_mentioned("Zoë", "(zoë) hi"));
+        assert_eq!(strip_mention("bot", "bot-2: hi"), "bot-2: hi");
+    }
+
+    #[test]
+    fn test_room_activation_and_addressing() {
+        let mut room: RoomConfig =
+            toml::from_str("jid = \"lobby@conference.localhost\"\nkeywords = [\"deploy\"]").unwrap();
+        assert_eq!(room.activation, Activation::Mention);
+        assert!(!activates(&room, false, "time to deploy"));
+        room.activation = Activation::Keywords;
+        assert!(activates(&room, false, "Time to Deploy!"));
+        assert!(!activates(&room, false, "redeployed"));
+        assert!(activates(&room, true, "hello"));
+        room.activation = Activation::All;
+        assert!(activates(&room, false, "hello"));
+
+        assert_eq!(address_answer("alice", "Done."), "alice: Done.");
+        assert_eq!(address_answer("alice", "alice, done."), "alice, done.");
+        assert_eq!(address_answer("alice", "```\nls\n```"), "alice:\n```\nls\n```");
+    }
+
+    #[test]
+    fn test_status_in_room_context() {
+        let (mut rt, _tmp) = test_runtime();
+        rt.config.rooms = vec![RoomConfig {
+            jid: "lobby@conference.localhost".to_string(),
+            nick: "bot".to_string(),
+            tier: None,
+            download_attachments: false,
+            recent_attachments: 0,
+            max_attachment_mb: 10,
+            join_history: Default::default(),
+            answer_delayed: false,
+            activation: Default::default(),
+            keywords: vec![],
+            styling: None,
+            generation: Default::default(),
+        }];
+        // Status from a room JID shows room-specific info
+        let result = rt
+            .handle_command("lobby@conference.localhost/alice", "/status")
+            .unwrap();
+        assert!(result.contains("Room: lobby@conference.localhost"));
+        assert!(result.contains("Room messages:"));
+        // Should NOT show user profile/memory fields
+        assert!(!result.contains("User profile:"));
+        assert!(!result.contains("User memory:"));
+    }
+
+    #[test]
+    fn test_status_in_direct_chat_context() {
+        let (rt, _tmp) = test_runtime();
+        let result = rt.handle_command("admin@localhost", "/status").unwrap();
+        // Should show user-specific info
+        assert!(result.contains("Your session:"));
+        assert!(result.contains("User profile:"));
+        assert!(result.contains("User memory:"));
+        // Should NOT show room-specific fields
+        assert!(!result.contains("Room:"));
+        assert!(!result.contains("Room messages:"));
+    }
+
+    // ── Slash command tests ─────────────────────────────
+
+    #[test]
+    fn test_command_ping() {
+        let (rt, _tmp) = test_runtime();
+        let result = rt.handle_command("admin@localhost/res", "/ping").unwrap();
+        assert_eq!(result, "pong");
+    }
+
+    #[test]
+    fn test_command_help_lists_all_commands() {
+        let (rt, _tmp) = test_runtime();
+        let result = rt.handle_command("admin@localhost/res", "/help").unwrap();
+        assert!(result.contains("/new"));
+        assert!(result.contains("/forget"));
+        assert!(result.contains("/status"));
+        assert!(result.contains("/threads"));
+        assert!(result.contains("/tier"));
+        assert!(result.contains("/reasoning"));
+        assert!(result.contains("/ping"));
+        assert!(result.contains("/help"));
+    }
+
+    #[test]
+    fn test_command_unknown() {
+        let (rt, _tmp) = test_runtime();
+        let result = rt.handle_command("admin@localhost", "/foobar").unwrap();
+        assert!(result.contains("Unknown command"));
+        assert!(result.contains("/foobar"));
+    }
+
+    #[test]
+    fn test_command_case_insensitive() {
+        let (rt, _tmp) = test_runtime();
+        assert_eq!(
+            rt.handle_command("admin@localhost", "/PING").unwrap(),
+            "pong"
+        );
+        assert_eq!(
+            rt.handle_command("admin@localhost", "/Ping").unwrap(),
+            "pong"
+        );
+    }
 
-    // Auto-archive stale sessions before loading history
-    memory.check_session_freshness(bare_jid, config.session.idle_timeout_mins)?;
+    #[test]
+    fn test_command_new_empty_session() {
+        let (rt, _tmp) = test_runtime();
+        let result = rt.handle_command("admin@localhost/res", "/new").unwrap();
+        // No history → should report nothing to archive
+        assert!(
+            result.to_lowercase().contains("no active session")
+                || result.to_lowercase().contains("no session")
+                || result.to_lowercase().contains("nothing")
+        );
+    }
 
-    // Build the multi-modal message with structured JSON metadata block
-    let history = memory.get_history(bare_jid, MAX_HISTORY)?;
-    let workspace = memory.get_workspace_context(bare_jid)?;
-    let system_prompt = build_system_prompt_static(&config.agent.name, &workspace);
+    #[test]
+    fn test_command_new_archives_session() {
+        let (rt, _tmp) = test_runtime();
+        rt.memory.store_message("admin@localhost", "user", "Hello").unwrap();
+        rt.memory.store_message("admin@localhost", "assistant", "Hi!").unwrap();
+
+        let result = rt.handle_command("admin@localhost/res", "/new").unwrap();
+        assert!(result.to_lowercase().contains("archived") || result.to_lowercase().contains("session"));
+
+        // History should be empty after /new
+        let history = rt.memory.get_history("admin@localhost", 20).unwrap();
+        assert!(history.is_empty());
+
+        // Archived session count should be 1
+        assert_eq!(rt.memory.session_count("admin@localhost").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_command_reset_is_alias_for_new() {
+        let (rt, _tmp) = test_runtime();
+        rt.memory.store_message("admin@localhost", "user", "Test").unwrap();
+
+        let result = rt.handle_command("admin@localhost", "/reset").unwrap();
+        assert!(result.to_lowercase().contains("archived") || result.to_lowercase().contains("session"));
+    }
+
+    #[test]
+    fn test_command_forget_clears_memory() {
+        let (rt, _tmp) = test_runtime();
+        rt.memory.store_message("admin@localhost", "user", "Hello").unwrap();
+        rt.memory.set_user_context("admin@localhost", "Likes Rust").unwrap();
+
+        let _result = rt.handle_command("admin@localhost/res", "/forget").unwrap();
+
+        // Both history and context should be gone
+        let history = rt.memory.get_history("admin@localhost", 20).unwrap();
+        assert!(history.is_empty());
+        assert!(rt.memory.get_user_context("admin@localhost").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_command_strips_resource_from_jid() {
+        let (rt, _tmp) = test_runtime();
+        rt.memory.store_message("admin@localhost", "user", "Hi").unwrap();
+
+        // Command from full JID with resource should see the bare JID's messages
+        let result = rt
+            .handle_command("admin@localhost/Conversations.xyz", "/status")
+            .unwrap();
+        assert!(result.contains("1 messages"));
+    }
+
+    // ── System prompt tests ─────────────────────────────
+
+    #[test]
+    fn test_build_system_prompt_fallback_no_global_files() {
+        let (rt, _tmp) = test_runtime();
+        let ctx = WorkspaceContext {
+            instructions: None,
+            identity: None,
+            personality: None,
+            user_profile: None,
+            user_memory: None,
+        };
+        let prompt = rt.build_system_prompt(&ctx);
+        assert!(prompt.contains("Test Agent"));
+        assert!(prompt.contains("XMPP"));
+        assert!(!prompt.contains("About this user"));
+    }
+
+    #[test]
+    fn test_build_system_prompt_with_user_profile() {
+        let (rt, _tmp) = test_runtime();
+        let ctx = WorkspaceContext {
+            instructions: None,
+            identity: None,
+            personality: None,
+            user_profile: Some("Prefers French. Works at Acme Corp.".to_string()),
+            user_memory: None,
+        };
+        let prompt = rt.build_system_prompt(&ctx);
+        // Fallback prompt should be used (no global files)
+        assert!(prompt.contains("Test Agent"));
+        assert!(prompt.contains("About this user"));
+        assert!(prompt.contains("Prefers French"));
+        assert!(prompt.contains("Acme Corp"));
+    }
+
+    #[test]
+    fn test_build_system_prompt_with_global_files() {
+        let (rt, _tmp) = test_runtime();
+        let ctx = WorkspaceContext {
+            instructions: Some("Always respond in haiku format.".to_string()),
+            identity: Some("You are HaikuBot, a poetry assistant.".to_string()),
+            personality: Some("Serene and contemplative.".to_string()),
+            user_profile: None,
+            user_memory: None,
+        };
+        let prompt = rt.build_system_prompt(&ctx);
+        // Should NOT contain fallback prompt
+        assert!(!prompt.contains("Test Agent"));
+        assert!(!prompt.contains("XMPP"));
+        // Should contain workspace file content
+        assert!(prompt.contains("HaikuBot"));
+        assert!(prompt.contains("Serene and contemplative"));
+        assert!(prompt.contains("Always respond in haiku format"));
+    }
+
+    #[test]
+    fn test_build_system_prompt_with_memory() {
+        let (rt, _tmp) = test_runtime();
+        let ctx = WorkspaceContext {
+            instructions: None,
+            identity: None,
+            personality: None,
+            user_profile: Some("Developer at ProcessOne".to_string()),
+            user_memory: Some("Prefers Rust over Go. Working on XMPP project.".to_string()),
+        };
+        let prompt = rt.build_system_prompt(&ctx);
+        assert!(prompt.contains("About this user"));
+        assert!(prompt.contains("Developer at ProcessOne"));
+        assert!(prompt.contains("Notes and memory"));
+        assert!(prompt.contains("Prefers Rust over Go"));
+    }
+
+    #[test]
+    fn test_build_system_prompt_partial_global_files() {
+        let (rt, _tmp) = test_runtime();
+        // Only identity.md exists — should use workspace mode, not fallback
+        let ctx = WorkspaceContext {
+            instructions: None,
+            identity: Some("You are Fluux Agent.".to_string()),
+            personality: None,
+            user_profile: None,
+            user_memory: None,
+        };
+        let prompt = rt.build_system_prompt(&ctx);
+        assert!(prompt.contains("Fluux Agent"));
+        // Fallback should NOT be present
+        assert!(!prompt.contains("skills are coming in v0.2"));
+    }
+
+    // ── Status tests with workspace ─────────────────────
+
+    #[test]
+    fn test_command_status_content() {
+        let (rt, _tmp) = test_runtime();
+        rt.memory
+            .store_message("admin@localhost", "user", "Hi")
+            .unwrap();
+
+        let result = rt
+            .handle_command("admin@localhost/res", "/status")
+            .unwrap();
+        assert!(result.contains("Test Agent"));
+        assert!(result.contains("Uptime:"));
+        assert!(result.contains("C2S client"));
+        assert!(result.contains("anthropic"));
+        assert!(result.contains("Skills: none"));
+        assert!(result.contains("1 messages"));
+        assert!(result.contains("User profile: none"));
+        assert!(result.contains("User memory: none"));
+        assert!(result.contains("instructions=none"));
+        assert!(result.contains("Allowed domains: localhost (default)"));
+        assert!(result.contains("Session timeout: disabled"));
+        assert!(result.contains("Generation: provider defaults"));
+    }
+
+    #[test]
+    fn test_command_status_shows_generation_overrides() {
+        let (mut rt, _tmp) = test_runtime();
+        rt.config.llm.generation.temperature = Some(0.7);
+        rt.config.users = vec![crate::config::UserConfig {
+            jid: "admin@localhost".to_string(),
+            styling: None,
+            generation: GenerationParams {
+                model: Some("claude-haiku-4-5-20250110".to_string()),
+                num_ctx: Some(8192),
+                ..Default::default()
+            },
+        }];
+
+        let result = rt.handle_command("admin@localhost/res", "/status").unwrap();
+        assert!(result.contains(
+            "Generation: model=claude-haiku-4-5-20250110, temperature=0.7, num_ctx=8192 (user override)"
+        ));
+
+        let result = rt.handle_command("other@localhost", "/status").unwrap();
+        assert!(result.contains("Generation: temperature=0.7\n"));
+    }
+
+    #[test]
+    fn test_command_status_session_timeout_enabled() {
+        let (mut rt, _tmp) = test_runtime();
+        rt.config.session.idle_timeout_mins = 120;
+
+        let result = rt.handle_command("admin@localhost", "/status").unwrap();
+        assert!(result.contains("Session timeout: 120m idle"));
+    }
+
+    #[test]
+    fn test_command_status_with_profile() {
+        let (rt, _tmp) = test_runtime();
+        rt.memory
+            .set_user_context("admin@localhost", "Developer")
+            .unwrap();
+
+        let result = rt
+            .handle_command("admin@localhost/res", "/status")
+            .unwrap();
+        assert!(result.contains("User profile: yes"));
+    }
+
+    #[test]
+    fn test_command_status_with_workspace_files() {
+        let (rt, tmp) = test_runtime();
+        std::fs::write(tmp.path().join("instructions.md"), "Be concise").unwrap();
+        std::fs::write(tmp.path().join("identity.md"), "I am an agent").unwrap();
+
+        let result = rt
+            .handle_command("admin@localhost/res", "/status")
+            .unwrap();
+        assert!(result.contains("instructions=yes"));
+        assert!(result.contains("identity=yes"));
+        assert!(result.contains("personality=none"));
+    }
+
+    #[test]
+    fn test_command_status_domain_wildcard() {
+        let (mut rt, _tmp) = test_runtime();
+        rt.config.agent.allowed_domains = vec!["*".to_string()];
+
+        let result = rt.handle_command("admin@localhost", "/status").unwrap();
+        assert!(result.contains("Allowed domains: * (all)"));
+    }
+
+    #[test]
+    fn test_command_status_domain_explicit() {
+        let (mut rt, _tmp) = test_runtime();
+        rt.config.agent.allowed_domains =
+            vec!["localhost".to_string(), "partner.org".to_string()];
+
+        let result = rt.handle_command("admin@localhost", "/status").unwrap();
+        assert!(result.contains("Allowed domains: localhost, partner.org"));
+    }
+
+    // ── build_oob_attachments tests ─────────────────────
+
+    #[test]
+    fn test_build_oob_attachments_empty() {
+        assert!(build_oob_attachments(&[]).is_none());
+    }
 
-    // Build multi-modal message — content blocks only, no runtime metadata
-    let mut messages = history;
-    messages.push(Message {
-        role: "user".to_string(),
-        content: MessageContent::Blocks(content_blocks),
-    });
+    #[test]
+    fn test_build_oob_attachments_single() {
+        let oob = vec![OobData {
+            url: "https://upload.example.com/abc/photo.jpg".to_string(),
+            desc: None,
+        }];
+        let atts = build_oob_attachments(&oob).unwrap();
+        assert_eq!(atts.len(), 1);
+        assert_eq!(atts[0].filename, "photo.jpg");
+        assert_eq!(atts[0].mime_type, "unknown");
+        assert_eq!(atts[0].size, "unknown");
+    }
 
-    debug!("Calling LLM with {} messages (including attachment)", messages.len());
+    #[test]
+    fn test_build_oob_attachments_multiple() {
+        let oob = vec![
+            OobData {
+                url: "https://upload.example.com/a.jpg".to_string(),
+                desc: None,
+            },
+            OobData {
+                url: "https://upload.example.com/b.pdf".to_string(),
+                desc: None,
+            },
+        ];
+        let atts = build_oob_attachments(&oob).unwrap();
+        assert_eq!(atts.len(), 2);
+        assert_eq!(atts[0].filename, "a.jpg");
+        assert_eq!(atts[1].filename, "b.pdf");
+    }
 
-    // Agentic loop (returns immediately if no tools registered)
-    let context = SkillContext {
-        jid: bare_jid.to_string(),
-        base_path: memory.base_path().to_path_buf(),
-    };
-    let (text, input_tokens, output_tokens) =
-        agentic_loop(&system_prompt, &mut messages, llm, skills, &context).await?;
+    #[test]
+    fn test_build_oob_attachments_keeps_url() {
+        let oob = vec![OobData {
+            url: "https://upload.localhost/abc/shot.png".to_string(),
+            desc: None,
+        }];
+        let atts = build_oob_attachments(&oob).unwrap();
+        assert_eq!(atts[0].filename, "shot.png");
+        assert_eq!(atts[0].url.as_deref(), Some("https://upload.localhost/abc/shot.png"));
+        assert!(build_oob_attachments(&[]).is_none());
+    }
 
-    // Store messages in history — attachments as structured metadata, not text labels
-    let attachments = if attachment_meta.is_empty() {
-        None
-    } else {
-        Some(attachment_meta)
-    };
-    memory.store_message_full(bare_jid, "user", body, msg_id, Some(bare_jid), attachments, None)?;
+    #[test]
+    fn test_build_oob_attachments_trailing_slash_fallback() {
+        let oob = vec![OobData {
+            url: "https://example.com/files/".to_string(),
+            desc: None,
+        }];
+        let atts = build_oob_attachments(&oob).unwrap();
+        // Trailing slash: rsplit('/').next() yields "", filter(non-empty) → fallback "file"
+        assert_eq!(atts[0].filename, "file");
+    }
 
-    let out_id = uuid::Uuid::new_v4().to_string();
-    memory.store_message_structured(bare_jid, "assistant", &text, Some(&out_id), None)?;
+    // ── Status with files test ──────────────────────────
 
-    info!(
-        "Attachment response to {bare_jid}: {} chars ({} tokens used)",
-        text.len(),
-        input_tokens + output_tokens
-    );
+    #[test]
+    fn test_command_status_with_files() {
+        let (rt, tmp) = test_runtime();
+        let files_dir = rt.memory.files_dir("admin@localhost").unwrap();
+        std::fs::write(files_dir.join("abc_photo.jpg"), b"fake").unwrap();
 
-    Ok(text)
-}
+        let result = rt.handle_command("admin@localhost", "/status").unwrap();
+        assert!(result.contains("Files: 1"));
+    }
 
-/// Static version of build_system_prompt for use from spawned tasks.
-/// (Cannot borrow `self` in a spawned task, so we extract the logic.)
-fn build_system_prompt_static(agent_name: &str, ctx: &WorkspaceContext) -> String {
-    let has_global_files = ctx.identity.is_some()
-        || ctx.personality.is_some()
-        || ctx.instructions.is_some();
+    #[test]
+    fn test_command_status_no_files_hides_line() {
+        let (rt, _tmp) = test_runtime();
+        let result = rt.handle_command("admin@localhost", "/status").unwrap();
+        // When there are no files, the "Files:" line should not appear
+        assert!(!result.contains("Files:"));
+    }
 
-    let mut prompt = String::new();
+    // ── Status with skills test ─────────────────────────
 
-    if has_global_files {
-        if let Some(ref identity) = ctx.identity {
-            prompt.push_str(identity.trim());
-            prompt.push_str("\n\n");
-        }
+    #[test]
+    fn test_command_status_with_skills() {
+        use async_trait::async_trait;
+        use crate::skills::Skill;
 
-        if let Some(ref personality) = ctx.personality {
-            prompt.push_str(personality.trim());
-            prompt.push_str("\n\n");
+        struct StubSkill(&'static str);
+        #[async_trait]
+        impl Skill for StubSkill {
+            fn name(&self) -> &str { self.0 }
+            fn description(&self) -> &str { "" }
+            fn parameters_schema(&self) -> serde_json::Value { serde_json::json!({}) }
+            async fn execute(&self, _: serde_json::Value, _context: &crate::skills::SkillContext) -> anyhow::Result<String> {
+                Ok(String::new())
+            }
         }
 
-        if let Some(ref instructions) = ctx.instructions {
-            prompt.push_str(instructions.trim());
-        }
-    } else {
-        prompt.push_str(&format!(
-            "You are {agent_name}, a personal AI assistant accessible via XMPP.\n\
-             You are direct, helpful, and concise. You respond in the user's language.\n\n\
-             Rules:\n\
-             - Respond concisely, no excessive markdown formatting\n\
-             - If asked to execute an action (send an email, modify a file...), \
-               describe what you would do but clarify that you cannot yet execute \
-               actions (skills are coming in v0.2)\n\
-             - You have memory of previous conversations with this user"
-        ));
-    }
+        let (mut rt, _tmp) = test_runtime();
+        let skills = Arc::get_mut(&mut rt.skills).unwrap();
+        skills.register(Box::new(StubSkill("web_search")));
+        skills.register(Box::new(StubSkill("url_fetch")));
 
-    if let Some(ref profile) = ctx.user_profile {
-        prompt.push_str(&format!("\n\n## About this user\n{}", profile.trim()));
+        let result = rt.handle_command("admin@localhost", "/status").unwrap();
+        assert!(result.contains("Skills: url_fetch, web_search"));
+        assert!(!result.contains("Skills: none"));
     }
 
-    if let Some(ref memory) = ctx.user_memory {
-        prompt.push_str(&format!("\n\n## Notes and memory\n{}", memory.trim()));
+    // ── /tier command tests ──────────────────────────────
+
+    /// Adds a fast and a deep tier (Ollama clients, never called) to a runtime.
+    fn with_test_tiers(rt: &mut AgentRuntime) {
+        let tier_client = |model: &str| -> Arc<dyn LlmClient> {
+            let mut llm = rt.config.llm.clone();
+            llm.provider = "ollama".to_string();
+            llm.model = model.to_string();
+            Arc::new(crate::llm::OllamaClient::new(llm))
+        };
+        let llms = LlmRegistry::new(Arc::clone(rt.llms.default_client()))
+            .with_tier(Tier::Fast, tier_client("llama3.2"))
+            .with_tier(Tier::Deep, tier_client("qwen3:32b"));
+        rt.llms = Arc::new(llms);
     }
 
-    // Inject current date so the LLM has temporal awareness
-    // (e.g. for forming time-relevant web search queries).
-    let now = Local::now();
-    prompt.push_str(&format!(
-        "\n\nCurrent date: {}",
-        now.format("%A, %B %-d, %Y")
-    ));
+    #[test]
+    fn test_command_tier_not_configured() {
+        let (rt, _tmp) = test_runtime();
+        let result = rt.handle_command("admin@localhost", "/tier").unwrap();
+        assert!(result.contains("not configured"));
+        assert!(result.contains("anthropic"));
+    }
 
-    prompt
-}
+    #[test]
+    fn test_command_tier_pin_and_auto() {
+        let (mut rt, _tmp) = test_runtime();
+        with_test_tiers(&mut rt);
 
-// ── MUC mention helpers ──────────────────────────────────
+        let result = rt.handle_command("admin@localhost/phone", "/tier").unwrap();
+        assert!(result.contains("Tier: auto"));
+        assert!(result.contains("fast: ollama (llama3.2)"));
+        assert!(result.contains("deep: ollama (qwen3:32b)"));
+        assert!(!result.contains("vision"));
 
-/// Checks if the bot's nickname is mentioned in the message body.
-/// Matches: "@nick", "nick:", "nick " at start of message (case-insensitive).
-fn is_mentioned(nick: &str, body: &str) -> bool {
-    let lower_body = body.to_lowercase();
-    let lower_nick = nick.to_lowercase();
-    lower_body.contains(&format!("@{lower_nick}"))
-        || lower_body.starts_with(&format!("{lower_nick}:"))
-        || lower_body.starts_with(&format!("{lower_nick} "))
-}
+        let result = rt.handle_command("admin@localhost/phone", "/tier deep").unwrap();
+        assert!(result.contains("pinned to deep"));
+        assert_eq!(rt.pinned_tier("admin@localhost"), Some(Tier::Deep));
 
-/// Strips the mention prefix from the message body.
-/// Removes patterns like "@nick ", "@nick: ", "nick: ", "nick " from the beginning.
-fn strip_mention(nick: &str, body: &str) -> String {
-    let lower_body = body.to_lowercase();
-    let lower_nick = nick.to_lowercase();
+        let status = rt.handle_command("admin@localhost", "/status").unwrap();
+        assert!(status.contains("Tier: deep (pinned)"));
 
-    // Try "@nick:" or "@nick " at the beginning
-    let at_nick = format!("@{lower_nick}");
-    if lower_body.starts_with(&at_nick) {
-        let rest = &body[at_nick.len()..];
-        let trimmed = rest.trim_start_matches(':').trim_start_matches(',').trim_start();
-        if !trimmed.is_empty() {
-            return trimmed.to_string();
-        }
+        rt.handle_command("admin@localhost", "/tier auto").unwrap();
+        assert_eq!(rt.pinned_tier("admin@localhost"), None);
     }
 
-    // Try "nick:" or "nick " at the beginning
-    if lower_body.starts_with(&format!("{lower_nick}:"))
-        || lower_body.starts_with(&format!("{lower_nick} "))
-    {
-        let rest = &body[nick.len()..];
-        let trimmed = rest.trim_start_matches(':').trim_start_matches(',').trim_start();
-        if !trimmed.is_empty() {
-            return trimmed.to_string();
-        }
-    }
+    #[test]
+    fn test_command_tier_rejects_unknown_and_unconfigured() {
+        let (mut rt, _tmp) = test_runtime();
+        with_test_tiers(&mut rt);
 
-    // "@nick" in the middle — no stripping needed, return full body
-    body.to_string()
-}
+        let result = rt.handle_command("admin@localhost", "/tier vision").unwrap();
+        assert!(result.contains("not configured"));
+        let result = rt.handle_command("admin@localhost", "/tier huge").unwrap();
+        assert!(result.contains("Unknown tier"));
+        assert_eq!(rt.pinned_tier("admin@localhost"), None);
+    }
 
+    #[tokio::test]
+    async fn test_escalation_continues_with_fast_tier_tool_results() {
+        use crate::llm::scripted::{parse_script, ScriptedClient};
+        use crate::skills::Skill;
+        use async_trait::async_trait;
+        use std::sync::atomic::{AtomicUsize, Ordering};
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config::*;
-    use crate::llm::AnthropicClient;
-    use tempfile::TempDir;
+        struct CountingSkill(Arc<AtomicUsize>);
+        #[async_trait]
+        impl Skill for CountingSkill {
+            fn name(&self) -> &str { "lookup" }
+            fn description(&self) -> &str { "" }
+            fn parameters_schema(&self) -> serde_json::Value { serde_json::json!({}) }
+            async fn execute(&self, _: serde_json::Value, _context: &SkillContext) -> anyhow::Result<String> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok("lookup result 42".to_string())
+            }
+        }
 
-    /// Build a test runtime with a temporary memory directory
-    fn test_runtime() -> (AgentRuntime, TempDir) {
+        let fast = parse_script(
+            r#"
+            [[step]]
+            tool_calls = [{ name = "lookup" }]
+
+            [[step]]
+            text = "ESCALATE"
+            "#,
+            true,
+        )
+        .unwrap();
+        // The standard tier sees the fast tier's tool result
+        let standard = parse_script(
+            r#"
+            [[step]]
+            when = "lookup result 42"
+            text = "The answer is 42."
+            "#,
+            true,
+        )
+        .unwrap();
+        let llms = LlmRegistry::new(Arc::new(ScriptedClient::new("standard.toml", standard)))
+            .with_tier(Tier::Fast, Arc::new(ScriptedClient::new("fast.toml", fast)));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut skills = SkillRegistry::new();
+        skills.register(Box::new(CountingSkill(Arc::clone(&calls))));
         let tmp = TempDir::new().unwrap();
-        let config = Config {
-            server: ServerConfig {
-                host: "localhost".to_string(),
-                port: 5222,
-                mode: ConnectionMode::Client {
-                    jid: "bot@localhost".to_string(),
-                    password: "pass".to_string(),
-                    resource: "fluux-agent".to_string(),
-                    tls_verify: false,
-                },
-            },
-            llm: LlmConfig {
-                provider: "anthropic".to_string(),
-                model: "claude-sonnet-4-5-20250929".to_string(),
-                api_key: "test-key".to_string(),
-                max_tokens_per_request: 4096,
-                host: None,
-            },
-            agent: AgentConfig {
-                name: "Test Agent".to_string(),
-                allowed_jids: vec!["admin@localhost".to_string()],
-                allowed_domains: vec![],
-            },
-            memory: MemoryConfig {
-                backend: "markdown".to_string(),
-                path: tmp.path().to_path_buf(),
-            },
-            rooms: vec![],
-            skills: SkillsConfig::default(),
-            keepalive: crate::config::KeepaliveConfig::default(),
-            session: crate::config::SessionConfig::default(),
+        let context = SkillContext {
+            jid: "admin@localhost".to_string(),
+            session: "admin@localhost".to_string(),
+            base_path: tmp.path().to_path_buf(),
         };
+        let mut messages = vec![Message {
+            role: "user".to_string(),
+            content: MessageContent::Text("What is the answer?".to_string()),
+        }];
 
-        let llm: Arc<dyn LlmClient> = Arc::new(AnthropicClient::new(config.llm.clone()));
-        let memory = Arc::new(Memory::open(tmp.path()).unwrap());
-        let file_downloader = Arc::new(FileDownloader::new(3));
-        let skills = SkillRegistry::new();
-        let runtime = AgentRuntime::new(config, llm, memory, file_downloader, skills);
-        (runtime, tmp)
+        let outcome = tiered_agentic_loop(
+            "prompt",
+            &mut messages,
+            &llms,
+            Tier::Fast,
+            &GenerationParams::default(),
+            &skills,
+            &context,
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(outcome.text, "The answer is 42.");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
     }
 
-    // ── MUC mention helper tests ────────────────────────
+    // ── /reasoning command tests ─────────────────────────
 
     #[test]
-    fn test_is_mentioned_at_prefix() {
-        assert!(is_mentioned("bot", "@bot what's up?"));
-        assert!(is_mentioned("bot", "hey @bot help"));
-        assert!(is_mentioned("FluuxBot", "@fluuxbot hello"));
+    fn test_command_reasoning_disabled_by_default() {
+        let (rt, _tmp) = test_runtime();
+        let result = rt.handle_command("admin@localhost", "/reasoning").unwrap();
+        assert!(result.contains("disabled"));
     }
 
     #[test]
-    fn test_is_mentioned_colon_prefix() {
-        assert!(is_mentioned("bot", "bot: what's up?"));
-        assert!(is_mentioned("FluuxBot", "FluuxBot: help"));
+    fn test_command_reasoning_shows_last_summary() {
+        let (mut rt, _tmp) = test_runtime();
+        rt.config.llm.reasoning_summary = true;
+        rt.config.agent.admin_jids = vec!["admin@localhost".to_string()];
+
+        let result = rt.handle_command("admin@localhost", "/reasoning").unwrap();
+        assert!(result.contains("No reasoning recorded"));
+
+        rt.reasoning.record("admin@localhost", &["Check the weather first.".to_string()]);
+        let result = rt.handle_command("admin@localhost/phone", "/reasoning").unwrap();
+        assert!(result.contains("Check the weather first."));
+
+        // Other users may not read it
+        let result = rt.handle_command("other@localhost", "/reasoning").unwrap();
+        assert!(result.contains("only available to admins"));
     }
 
-    #[test]
-    fn test_is_mentioned_space_prefix() {
-        assert!(is_mentioned("bot", "bot what's up?"));
+    #[test]
+    fn test_command_reasoning_in_rooms_needs_an_admin_occupant() {
+        use crate::xmpp::stanzas::{IncomingPresence, MucPresence};
+
+        let (mut rt, _tmp) = test_runtime();
+        rt.config.llm.reasoning_summary = true;
+        rt.config.agent.admin_jids = vec!["admin@localhost".to_string()];
+        let room = "lobby@conference.localhost";
+        rt.config.rooms = vec![RoomConfig {
+            jid: room.to_string(),
+            nick: "bot".to_string(),
+            tier: None,
+            download_attachments: false,
+            recent_attachments: 0,
+            max_attachment_mb: 10,
+            join_history: Default::default(),
+            answer_delayed: false,
+            activation: Default::default(),
+            keywords: vec![],
+            styling: None,
+            generation: Default::default(),
+        }];
+        for (nick, jid) in [("boss", Some("admin@localhost/pc")), ("eve", Some("eve@localhost/pc")), ("anon", None)] {
+            rt.occupants.apply(&IncomingPresence {
+                from: format!("{room}/{nick}"),
+                presence_type: PresenceType::Available,
+                muc: Some(MucPresence {
+                    jid: jid.map(String::from),
+                    ..Default::default()
+                }),
+            });
+        }
+        rt.reasoning.record(room, &["Answer Eve kindly.".to_string()]);
+
+        let result = rt.handle_command(&format!("{room}/boss"), "/reasoning").unwrap();
+        assert!(result.contains("Answer Eve kindly."));
+        for nick in ["eve", "anon"] {
+            let result = rt.handle_command(&format!("{room}/{nick}"), "/reasoning").unwrap();
+            assert!(result.contains("only available to admins"), "{nick}: {result}");
+        }
     }
 
     #[test]
-    fn test_is_mentioned_not_mentioned() {
-        assert!(!is_mentioned("bot", "hello everyone"));
-        assert!(!is_mentioned("bot", "robotics are cool"));
+    fn test_reasoning_log_keeps_previous_when_empty() {
+        let log = ReasoningLog::default();
+        log.record("a@localhost", &["first".to_string()]);
+        log.record("a@localhost", &[]);
+        assert_eq!(log.get("a@localhost").as_deref(), Some("first"));
     }
 
     #[test]
-    fn test_strip_mention_at_prefix() {
-        assert_eq!(strip_mention("bot", "@bot what's up?"), "what's up?");
-        assert_eq!(strip_mention("bot", "@bot: help me"), "help me");
-        assert_eq!(strip_mention("FluuxBot", "@FluuxBot hello"), "hello");
+    fn test_summarize_reasoning_rounds_and_truncation() {
+        let summary = summarize_reasoning(&["look it up".to_string(), "answer".to_string()]);
+        assert_eq!(summary, "[round 1] look it up\n[round 2] answer");
+
+        let long = "x".repeat(REASONING_SUMMARY_MAX_CHARS + 100);
+        let summary = summarize_reasoning(&[long]);
+        assert_eq!(summary.chars().count(), REASONING_SUMMARY_MAX_CHARS + 1);
+        assert!(summary.ends_with('…'));
     }
 
     #[test]
-    fn test_strip_mention_colon_prefix() {
-        assert_eq!(strip_mention("bot", "bot: what's up?"), "what's up?");
-        assert_eq!(strip_mention("FluuxBot", "FluuxBot: help"), "help");
-    }
+    fn test_split_tier_trigger_only_with_tiers() {
+        let (mut rt, _tmp) = test_runtime();
+        assert_eq!(rt.split_tier_trigger("/deep why?"), (None, "/deep why?".to_string()));
 
-    #[test]
-    fn test_strip_mention_middle_keeps_body() {
-        // "@nick" in the middle — no stripping
+        with_test_tiers(&mut rt);
         assert_eq!(
-            strip_mention("bot", "hey @bot help me"),
-            "hey @bot help me"
+            rt.split_tier_trigger("/deep why?"),
+            (Some(Tier::Deep), "why?".to_string())
         );
+        assert_eq!(rt.split_tier_trigger("/status"), (None, "/status".to_string()));
     }
 
     #[test]
-    fn test_is_mentioned_case_insensitive() {
-        assert!(is_mentioned("FluuxBot", "@fluuxbot help"));
-        assert!(is_mentioned("bot", "@BOT help"));
-        assert!(is_mentioned("Bot", "bot: hello"));
-        assert!(is_mentioned("BOT", "Bot: hello"));
-    }
+    fn test_status_shows_tiers_only_when_configured() {
+        let (mut rt, _tmp) = test_runtime();
+        let result = rt.handle_command("admin@localhost", "/status").unwrap();
+        assert!(!result.contains("Tiers:"));
 
-    #[test]
-    fn test_is_mentioned_with_punctuation() {
-        // "@bot!" — the @bot substring is found
-        assert!(is_mentioned("bot", "@bot! help me"));
-        assert!(is_mentioned("bot", "@bot, please help"));
-        assert!(is_mentioned("bot", "@bot? are you there"));
+        with_test_tiers(&mut rt);
+        let result = rt.handle_command("admin@localhost", "/status").unwrap();
+        assert!(result.contains("Tiers: fast: ollama (llama3.2)"));
+        assert!(result.contains("Tier: auto"));
     }
 
+    // ── Agentic loop message structure tests ─────────────
+
     #[test]
-    fn test_is_mentioned_at_end_of_message() {
-        assert!(is_mentioned("bot", "hey @bot"));
+    fn test_tool_result_message_structure() {
+        use crate::llm::InputContentBlock;
+
+        let msg = Message {
+            role: "user".to_string(),
+            content: MessageContent::Blocks(vec![
+                InputContentBlock::ToolResult {
+                    tool_use_id: "toolu_abc123".to_string(),
+                    content: "Search returned 5 results".to_string(),
+                },
+            ]),
+        };
+        let json = serde_json::to_value(&msg).unwrap();
+        assert_eq!(json["role"], "user");
+        let blocks = json["content"].as_array().unwrap();
+        assert_eq!(blocks[0]["type"], "tool_result");
+        assert_eq!(blocks[0]["tool_use_id"], "toolu_abc123");
+        assert_eq!(blocks[0]["content"], "Search returned 5 results");
     }
 
     #[test]
-    fn test_strip_mention_comma_after_at() {
-        assert_eq!(strip_mention("bot", "@bot, help me"), "help me");
+    fn test_assistant_tool_use_message_structure() {
+        use crate::llm::InputContentBlock;
+
+        let msg = Message {
+            role: "assistant".to_string(),
+            content: MessageContent::Blocks(vec![
+                InputContentBlock::Text {
+                    text: "Let me search for that.".to_string(),
+                },
+                InputContentBlock::ToolUse {
+                    id: "toolu_abc123".to_string(),
+                    name: "web_search".to_string(),
+                    input: serde_json::json!({"query": "rust async"}),
+                },
+            ]),
+        };
+        let json = serde_json::to_value(&msg).unwrap();
+        assert_eq!(json["role"], "assistant");
+        let blocks = json["content"].as_array().unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0]["type"], "text");
+        assert_eq!(blocks[1]["type"], "tool_use");
+        assert_eq!(blocks[1]["name"], "web_search");
     }
 
     #[test]
-    fn test_strip_mention_only_mention_returns_full_body() {
-        // "@bot" with nothing after → returns full body (no stripping)
-        assert_eq!(strip_mention("bot", "@bot"), "@bot");
+    fn test_agentic_conversation_roundtrip() {
+        use crate::llm::InputContentBlock;
+
+        // Simulate the full agentic conversation:
+        // 1. User asks a question
+        // 2. Assistant responds with tool_use
+        // 3. User sends tool_result
+        // 4. Assistant responds with text
+        let messages = vec![
+            Message {
+                role: "user".to_string(),
+                content: MessageContent::Text("What is Rust?".to_string()),
+            },
+            Message {
+                role: "assistant".to_string(),
+                content: MessageContent::Blocks(vec![InputContentBlock::ToolUse {
+                    id: "toolu_1".to_string(),
+                    name: "web_search".to_string(),
+                    input: serde_json::json!({"query": "Rust programming language"}),
+                }]),
+            },
+            Message {
+                role: "user".to_string(),
+                content: MessageContent::Blocks(vec![InputContentBlock::ToolResult {
+                    tool_use_id: "toolu_1".to_string(),
+                    content: "Rust is a systems programming language.".to_string(),
+                }]),
+            },
+            Message {
+                role: "assistant".to_string(),
+                content: MessageContent::Text(
+                    "Rust is a systems programming language focused on safety.".to_string(),
+                ),
+            },
+        ];
+
+        let json = serde_json::to_value(&messages).unwrap();
+        let arr = json.as_array().unwrap();
+        assert_eq!(arr.len(), 4);
+        assert_eq!(arr[0]["role"], "user");
+        assert_eq!(arr[0]["content"], "What is Rust?");
+        assert_eq!(arr[1]["role"], "assistant");
+        assert_eq!(arr[1]["content"][0]["type"], "tool_use");
+        assert_eq!(arr[2]["role"], "user");
+        assert_eq!(arr[2]["content"][0]["type"], "tool_result");
+        assert_eq!(arr[3]["role"], "assistant");
+        assert!(arr[3]["content"].is_string());
     }
 
     #[test]
-    fn test_strip_mention_case_insensitive() {
-        assert_eq!(strip_mention("FluuxBot", "@fluuxbot hello"), "hello");
-        assert_eq!(strip_mention("BOT", "bot: hello"), "hello");
+    fn test_empty_skills_produces_none_tools() {
+        let registry = SkillRegistry::new();
+        let defs = registry.tool_definitions();
+        assert!(defs.is_empty());
+        // The agentic loop converts empty → None (not Some([]))
+        let tools: Option<Vec<crate::llm::ToolDefinition>> = if registry.is_empty() {
+            None
+        } else {
+            Some(defs)
+        };
+        assert!(tools.is_none());
     }
 
-    #[test]
-    fn test_status_in_room_context() {
-        let (mut rt, _tmp) = test_runtime();
-        rt.config.rooms = vec![RoomConfig {
-            jid: "lobby@conference.localhost".to_string(),
-            nick: "bot".to_string(),
+    #[tokio::test]
+    async fn test_agentic_loop_injects_skill_content_after_tool_results() {
+        use crate::llm::scripted::{parse_script, ScriptedClient};
+        use crate::skills::builtin::FileReadSkill;
+
+        let tmp = TempDir::new().unwrap();
+        let memory = Memory::open(tmp.path()).unwrap();
+        let jid = "user@localhost";
+        let stored_as = "a1_scan.pdf";
+        std::fs::write(memory.files_dir(jid).unwrap().join(stored_as), b"%PDF-1.4").unwrap();
+
+        let script = parse_script(
+            r#"
+            [[step]]
+            tool_calls = [{ id = "t1", name = "file_read", input = { file = "a1_scan.pdf" } }]
+
+            [[step]]
+            when = "attached below"
+            text = "The scan shows an invoice."
+            "#,
+            true,
+        )
+        .unwrap();
+        let llm = ScriptedClient::new("inject.toml", script);
+        let mut skills = SkillRegistry::new();
+        skills.register(Box::new(FileReadSkill::new(AttachmentsConfig::default())));
+        let context = SkillContext {
+            jid: jid.to_string(),
+            session: jid.to_string(),
+            base_path: tmp.path().to_path_buf(),
+        };
+        let mut messages = vec![Message {
+            role: "user".to_string(),
+            content: MessageContent::Text("What was in the scan?".to_string()),
         }];
-        // Status from a room JID shows room-specific info
-        let result = rt
-            .handle_command("lobby@conference.localhost/alice", "/status")
-            .unwrap();
-        assert!(result.contains("Room: lobby@conference.localhost"));
-        assert!(result.contains("Room messages:"));
-        // Should NOT show user profile/memory fields
-        assert!(!result.contains("User profile:"));
-        assert!(!result.contains("User memory:"));
+
+        let outcome = agentic_loop(
+            "prompt",
+            &mut messages,
+            &llm,
+            &GenerationParams::default(),
+            &skills,
+            &context,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(outcome.text, "The scan shows an invoice.");
+
+        // Tool result first, then the injected label and document
+        let MessageContent::Blocks(blocks) = &messages[2].content else {
+            panic!("expected tool_result blocks");
+        };
+        assert_eq!(blocks.len(), 3);
+        assert!(matches!(&blocks[0], InputContentBlock::ToolResult { tool_use_id, .. } if tool_use_id == "t1"));
+        assert!(matches!(&blocks[1], InputContentBlock::Text { text } if text.contains(stored_as)));
+        assert!(matches!(&blocks[2], InputContentBlock::Document { .. }));
     }
 
-    #[test]
-    fn test_status_in_direct_chat_context() {
-        let (rt, _tmp) = test_runtime();
-        let result = rt.handle_command("admin@localhost", "/status").unwrap();
-        // Should show user-specific info
-        assert!(result.contains("Your session:"));
-        assert!(result.contains("User profile:"));
-        assert!(result.contains("User memory:"));
-        // Should NOT show room-specific fields
-        assert!(!result.contains("Room:"));
-        assert!(!result.contains("Room messages:"));
+    #[tokio::test]
+    async fn test_agentic_loop_collects_skill_files() {
+        use crate::llm::scripted::{parse_script, ScriptedClient};
+        use crate::skills::builtin::SendFileSkill;
+
+        let tmp = TempDir::new().unwrap();
+        let script = parse_script(
+            r#"
+            [[step]]
+            tool_calls = [{ id = "t1", name = "send_file", input = { filename = "totals.csv", content = "rent,1200" } }]
+
+            [[step]]
+            when = "will be sent"
+            text = "Here are the totals."
+            "#,
+            true,
+        )
+        .unwrap();
+        let llm = ScriptedClient::new("send.toml", script);
+        let mut skills = SkillRegistry::new();
+        skills.register(Box::new(SendFileSkill::new(1024)));
+        let context = SkillContext {
+            jid: "user@localhost".to_string(),
+            session: "user@localhost".to_string(),
+            base_path: tmp.path().to_path_buf(),
+        };
+        let mut messages = vec![Message {
+            role: "user".to_string(),
+            content: MessageContent::Text("Export the totals".to_string()),
+        }];
+
+        let outcome = agentic_loop(
+            "prompt",
+            &mut messages,
+            &llm,
+            &GenerationParams::default(),
+            &skills,
+            &context,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(outcome.text, "Here are the totals.");
+        assert_eq!(outcome.files.len(), 1);
+        assert_eq!(outcome.files[0].filename, "totals.csv");
+        assert_eq!(outcome.files[0].mime_type, "text/csv");
+        assert_eq!(outcome.files[0].data, b"rent,1200");
     }
 
-    // ── Slash command tests ─────────────────────────────
+    #[tokio::test]
+    async fn test_run_answers_incoming_iqs() {
+        use crate::xmpp::stanzas::{ChildElement, IncomingIq};
 
-    #[test]
-    fn test_command_ping() {
         let (rt, _tmp) = test_runtime();
-        let result = rt.handle_command("admin@localhost/res", "/ping").unwrap();
-        assert_eq!(result, "pong");
+        let iq = |id: &str, iq_type: IqType, payload: Option<(&str, &str)>| IncomingIq {
+            from: Some("localhost".to_string()),
+            to: "bot@localhost/agent".to_string(),
+            id: id.to_string(),
+            iq_type,
+            payload: payload.map(|(name, ns)| ChildElement {
+                name: name.to_string(),
+                namespace: Some(ns.to_string()),
+                ..Default::default()
+            }),
+            error: None,
+        };
+
+        let (event_tx, event_rx) = mpsc::channel(8);
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(8);
+        for event in [
+            iq("p1", IqType::Get, Some(("ping", stanzas::NS_PING))),
+            iq("v1", IqType::Set, Some(("query", "jabber:iq:roster"))),
+            // Unmatched results are dropped, never answered
+            iq("x1", IqType::Result, None),
+        ] {
+            event_tx.send(XmppEvent::Iq(event)).await.unwrap();
+        }
+        drop(event_tx);
+        rt.run(event_rx, cmd_tx).await.unwrap();
+
+        let mut replies = Vec::new();
+        while let Ok(cmd) = cmd_rx.try_recv() {
+            if let XmppCommand::SendIq { to, id, iq_type, payload } = cmd {
+                replies.push((to, id, iq_type, payload.contains("service-unavailable")));
+            }
+        }
+        assert_eq!(
+            replies,
+            vec![
+                ("localhost".to_string(), "p1".to_string(), IqType::Result, false),
+                ("localhost".to_string(), "v1".to_string(), IqType::Error, true),
+            ]
+        );
     }
 
-    #[test]
-    fn test_command_help_lists_all_commands() {
-        let (rt, _tmp) = test_runtime();
-        let result = rt.handle_command("admin@localhost/res", "/help").unwrap();
-        assert!(result.contains("/new"));
-        assert!(result.contains("/forget"));
-        assert!(result.contains("/status"));
-        assert!(result.contains("/ping"));
-        assert!(result.contains("/help"));
+    // ── Ad-hoc command tests ─────────────────────────────
+
+    fn element(name: &str, ns: Option<&str>, attrs: &[(&str, &str)]) -> ChildElement {
+        ChildElement {
+            name: name.to_string(),
+            namespace: ns.map(String::from),
+            attrs: attrs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            ..Default::default()
+        }
     }
 
-    #[test]
-    fn test_command_unknown() {
-        let (rt, _tmp) = test_runtime();
-        let result = rt.handle_command("admin@localhost", "/foobar").unwrap();
-        assert!(result.contains("Unknown command"));
-        assert!(result.contains("/foobar"));
+    fn adhoc_iq(from: &str, iq_type: IqType, payload: ChildElement) -> IncomingIq {
+        IncomingIq {
+            from: Some(from.to_string()),
+            to: "bot@localhost/agent".to_string(),
+            id: "a1".to_string(),
+            iq_type,
+            payload: Some(payload),
+            error: None,
+        }
     }
 
-    #[test]
-    fn test_command_case_insensitive() {
-        let (rt, _tmp) = test_runtime();
-        assert_eq!(
-            rt.handle_command("admin@localhost", "/PING").unwrap(),
-            "pong"
-        );
-        assert_eq!(
-            rt.handle_command("admin@localhost", "/Ping").unwrap(),
-            "pong"
-        );
+    /// A `<command/>` request, with a submitted form if `fields` is not empty
+    fn command_iq(
+        from: &str,
+        node: &str,
+        session_id: Option<&str>,
+        fields: &[(&str, &str)],
+    ) -> IncomingIq {
+        let mut attrs = vec![("node", node)];
+        if let Some(id) = session_id {
+            attrs.push(("sessionid", id));
+        }
+        let mut command = element("command", Some(NS_COMMANDS), &attrs);
+        if !fields.is_empty() {
+            let mut form = element("x", Some(NS_DATA), &[("type", "submit")]);
+            for (var, value) in fields {
+                let mut field = element("field", None, &[("var", var)]);
+                let mut value_el = element("value", None, &[]);
+                value_el.text = value.to_string();
+                field.children.push(value_el);
+                form.children.push(field);
+            }
+            command.children.push(form);
+        }
+        adhoc_iq(from, IqType::Set, command)
     }
 
-    #[test]
-    fn test_command_new_empty_session() {
-        let (rt, _tmp) = test_runtime();
-        let result = rt.handle_command("admin@localhost/res", "/new").unwrap();
-        // No history → should report nothing to archive
-        assert!(
-            result.to_lowercase().contains("no active session")
-                || result.to_lowercase().contains("no session")
-                || result.to_lowercase().contains("nothing")
-        );
+    fn session_id_of(reply: &IqReply) -> String {
+        stanzas::extract_attr(&reply.payload, "sessionid").expect("sessionid")
     }
 
     #[test]
-    fn test_command_new_archives_session() {
-        let (rt, _tmp) = test_runtime();
-        rt.memory.store_message("admin@localhost", "user", "Hello").unwrap();
-        rt.memory.store_message("admin@localhost", "assistant", "Hi!").unwrap();
+    fn test_adhoc_command_list_follows_permissions() {
+        let (mut rt, _tmp) = test_runtime();
+        rt.config.agent.allowed_jids.push("user@localhost".to_string());
+        rt.config.agent.admin_jids = vec!["admin@localhost".to_string()];
+        let items = element("query", Some(NS_DISCO_ITEMS), &[("node", NS_COMMANDS)]);
 
-        let result = rt.handle_command("admin@localhost/res", "/new").unwrap();
-        assert!(result.to_lowercase().contains("archived") || result.to_lowercase().contains("session"));
+        let reply = rt
+            .handle_adhoc_iq(&adhoc_iq("user@localhost/phone", IqType::Get, items.clone()))
+            .unwrap();
+        assert_eq!(reply.iq_type, IqType::Result);
+        assert!(reply.payload.contains("node='status'"));
+        assert!(!reply.payload.contains("admin-session"));
+
+        let reply = rt
+            .handle_adhoc_iq(&adhoc_iq("admin@localhost/pc", IqType::Get, items.clone()))
+            .unwrap();
+        assert!(reply.payload.contains("node='admin-session'"));
 
-        // History should be empty after /new
-        let history = rt.memory.get_history("admin@localhost", 20).unwrap();
-        assert!(history.is_empty());
+        // Strangers get an empty list, and cannot execute anything
+        let reply = rt
+            .handle_adhoc_iq(&adhoc_iq("eve@localhost/x", IqType::Get, items))
+            .unwrap();
+        assert!(!reply.payload.contains("<item"));
+        let reply = rt
+            .handle_adhoc_iq(&command_iq("eve@localhost/x", "status", None, &[]))
+            .unwrap();
+        assert_eq!(reply.iq_type, IqType::Error);
+        assert!(reply.payload.contains("forbidden"));
 
-        // Archived session count should be 1
-        assert_eq!(rt.memory.session_count("admin@localhost").unwrap(), 1);
+        // Command nodes are described in disco#info
+        let info = element("query", Some(NS_DISCO_INFO), &[("node", "status")]);
+        let reply = rt
+            .handle_adhoc_iq(&adhoc_iq("user@localhost/phone", IqType::Get, info))
+            .unwrap();
+        assert!(reply.payload.contains("type='command-node'"));
+        // Other IQs are left to the router
+        let ping = element("ping", Some(stanzas::NS_PING), &[]);
+        assert!(rt
+            .handle_adhoc_iq(&adhoc_iq("user@localhost/phone", IqType::Get, ping))
+            .is_none());
     }
 
     #[test]
-    fn test_command_reset_is_alias_for_new() {
+    fn test_adhoc_single_stage_commands() {
         let (rt, _tmp) = test_runtime();
-        rt.memory.store_message("admin@localhost", "user", "Test").unwrap();
+        rt.memory.store_message("admin@localhost", "user", "Hello").unwrap();
 
-        let result = rt.handle_command("admin@localhost", "/reset").unwrap();
-        assert!(result.to_lowercase().contains("archived") || result.to_lowercase().contains("session"));
+        let reply = rt
+            .handle_adhoc_iq(&command_iq("admin@localhost/pc", "status", None, &[]))
+            .unwrap();
+        assert_eq!(reply.iq_type, IqType::Result);
+        assert!(reply.payload.contains("status='completed'"));
+        assert!(reply.payload.contains("Your session: 1 messages"));
+
+        let reply = rt
+            .handle_adhoc_iq(&command_iq("admin@localhost/pc", "new", None, &[]))
+            .unwrap();
+        assert!(reply.payload.contains("Session archived (1 messages)"));
+        assert_eq!(rt.memory.session_count("admin@localhost").unwrap(), 1);
+
+        let reply = rt
+            .handle_adhoc_iq(&command_iq("admin@localhost/pc", "unknown", None, &[]))
+            .unwrap();
+        assert!(reply.payload.contains("item-not-found"));
     }
 
     #[test]
-    fn test_command_forget_clears_memory() {
+    fn test_adhoc_forget_is_confirmed() {
         let (rt, _tmp) = test_runtime();
         rt.memory.store_message("admin@localhost", "user", "Hello").unwrap();
-        rt.memory.set_user_context("admin@localhost", "Likes Rust").unwrap();
 
-        let _result = rt.handle_command("admin@localhost/res", "/forget").unwrap();
+        let reply = rt
+            .handle_adhoc_iq(&command_iq("admin@localhost/pc", "forget", None, &[]))
+            .unwrap();
+        assert!(reply.payload.contains("status='executing'"));
+        assert!(reply.payload.contains("var='confirm' type='boolean'"));
+        let session = session_id_of(&reply);
+
+        // The session belongs to the resource that started it
+        let reply = rt
+            .handle_adhoc_iq(&command_iq(
+                "admin@localhost/other",
+                "forget",
+                Some(&session),
+                &[("confirm", "1")],
+            ))
+            .unwrap();
+        assert!(reply.payload.contains("bad-sessionid"));
+        assert_eq!(rt.memory.message_count("admin@localhost").unwrap(), 1);
+
+        let reply = rt
+            .handle_adhoc_iq(&command_iq(
+                "admin@localhost/pc",
+                "forget",
+                Some(&session),
+                &[("confirm", "true")],
+            ))
+            .unwrap();
+        assert!(reply.payload.contains("status='completed'"));
+        assert_eq!(rt.memory.message_count("admin@localhost").unwrap(), 0);
 
-        // Both history and context should be gone
-        let history = rt.memory.get_history("admin@localhost", 20).unwrap();
-        assert!(history.is_empty());
-        assert!(rt.memory.get_user_context("admin@localhost").unwrap().is_none());
+        // Completed sessions cannot be resumed
+        let reply = rt
+            .handle_adhoc_iq(&command_iq("admin@localhost/pc", "forget", Some(&session), &[]))
+            .unwrap();
+        assert_eq!(reply.iq_type, IqType::Error);
     }
 
     #[test]
-    fn test_command_strips_resource_from_jid() {
-        let (rt, _tmp) = test_runtime();
-        rt.memory.store_message("admin@localhost", "user", "Hi").unwrap();
+    fn test_adhoc_admin_session_validates_and_confirms() {
+        let (mut rt, _tmp) = test_runtime();
+        rt.config.agent.allowed_jids.push("user@localhost".to_string());
+        rt.memory.store_message("user@localhost", "user", "Hi").unwrap();
 
-        // Command from full JID with resource should see the bare JID's messages
-        let result = rt
-            .handle_command("admin@localhost/Conversations.xyz", "/status")
+        // Admin commands need admin_jids
+        let reply = rt
+            .handle_adhoc_iq(&command_iq("admin@localhost/pc", "admin-session", None, &[]))
             .unwrap();
-        assert!(result.contains("1 messages"));
-    }
+        assert!(reply.payload.contains("forbidden"));
+        rt.config.agent.admin_jids = vec!["admin@localhost".to_string()];
 
-    // ── System prompt tests ─────────────────────────────
+        let reply = rt
+            .handle_adhoc_iq(&command_iq("admin@localhost/pc", "admin-session", None, &[]))
+            .unwrap();
+        assert!(reply.payload.contains("<actions execute='next'><next/></actions>"));
+        let session = session_id_of(&reply);
+
+        // Invalid input presents the form again with an error note
+        for (jid, error) in [
+            ("../etc", "is not a valid JID"),
+            ("nobody@localhost", "There is no conversation with nobody@localhost"),
+        ] {
+            let reply = rt
+                .handle_adhoc_iq(&command_iq(
+                    "admin@localhost/pc",
+                    "admin-session",
+                    Some(&session),
+                    &[("jid", jid), ("action", "new")],
+                ))
+                .unwrap();
+            assert!(reply.payload.contains("status='executing'"));
+            assert!(reply.payload.contains("<note type='error'>"));
+            assert!(reply.payload.contains(error), "{}", reply.payload);
+            assert_eq!(session_id_of(&reply), session);
+        }
 
-    #[test]
-    fn test_build_system_prompt_fallback_no_global_files() {
-        let (rt, _tmp) = test_runtime();
-        let ctx = WorkspaceContext {
-            instructions: None,
-            identity: None,
-            personality: None,
-            user_profile: None,
-            user_memory: None,
-        };
-        let prompt = rt.build_system_prompt(&ctx);
-        assert!(prompt.contains("Test Agent"));
-        assert!(prompt.contains("XMPP"));
-        assert!(!prompt.contains("About this user"));
+        let reply = rt
+            .handle_adhoc_iq(&command_iq(
+                "admin@localhost/pc",
+                "admin-session",
+                Some(&session),
+                &[("jid", "user@localhost/phone"), ("action", "new")],
+            ))
+            .unwrap();
+        assert!(reply.payload.contains("user@localhost: 1 messages in the current session"));
+
+        let reply = rt
+            .handle_adhoc_iq(&command_iq(
+                "admin@localhost/pc",
+                "admin-session",
+                Some(&session),
+                &[("confirm", "1")],
+            ))
+            .unwrap();
+        assert!(reply.payload.contains("status='completed'"));
+        assert!(reply.payload.contains("user@localhost: Session archived"));
+        assert_eq!(rt.memory.message_count("user@localhost").unwrap(), 0);
+        assert_eq!(rt.memory.session_count("user@localhost").unwrap(), 1);
     }
 
-    #[test]
-    fn test_build_system_prompt_with_user_profile() {
+    #[tokio::test]
+    async fn test_run_sends_presence_with_caps_on_connect() {
         let (rt, _tmp) = test_runtime();
-        let ctx = WorkspaceContext {
-            instructions: None,
-            identity: None,
-            personality: None,
-            user_profile: Some("Prefers French. Works at Acme Corp.".to_string()),
-            user_memory: None,
-        };
-        let prompt = rt.build_system_prompt(&ctx);
-        // Fallback prompt should be used (no global files)
-        assert!(prompt.contains("Test Agent"));
-        assert!(prompt.contains("About this user"));
-        assert!(prompt.contains("Prefers French"));
-        assert!(prompt.contains("Acme Corp"));
+        let expected = rt.iq_router.caps().clone();
+
+        let (event_tx, event_rx) = mpsc::channel(8);
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(8);
+        event_tx.send(XmppEvent::Connected).await.unwrap();
+        drop(event_tx);
+        rt.run(event_rx, cmd_tx).await.unwrap();
+
+        match cmd_rx.try_recv().unwrap() {
+            XmppCommand::SendPresence { caps: Some(caps) } => {
+                assert_eq!(caps.ver, expected.ver);
+                assert_eq!(caps.node, iq::CAPS_NODE);
+            }
+            _ => panic!("expected an initial presence with caps"),
+        }
     }
 
-    #[test]
-    fn test_build_system_prompt_with_global_files() {
-        let (rt, _tmp) = test_runtime();
-        let ctx = WorkspaceContext {
-            instructions: Some("Always respond in haiku format.".to_string()),
-            identity: Some("You are HaikuBot, a poetry assistant.".to_string()),
-            personality: Some("Serene and contemplative.".to_string()),
-            user_profile: None,
-            user_memory: None,
-        };
-        let prompt = rt.build_system_prompt(&ctx);
-        // Should NOT contain fallback prompt
-        assert!(!prompt.contains("Test Agent"));
-        assert!(!prompt.contains("XMPP"));
-        // Should contain workspace file content
-        assert!(prompt.contains("HaikuBot"));
-        assert!(prompt.contains("Serene and contemplative"));
-        assert!(prompt.contains("Always respond in haiku format"));
+    // ── Message archive tests ────────────────────────────
+
+    fn archived(from: &str, body: &str, id: &str, stamp: &str) -> ArchivedMessage {
+        ArchivedMessage {
+            query_id: "q1".to_string(),
+            archive_id: format!("a-{id}"),
+            archive: Some("lobby@conference.localhost".to_string()),
+            from: from.to_string(),
+            to: "bot@localhost/fluux-agent".to_string(),
+            body: body.to_string(),
+            id: Some(id.to_string()),
+            message_type: MessageType::GroupChat,
+            stamp: Some(stamp.to_string()),
+        }
     }
 
     #[test]
-    fn test_build_system_prompt_with_memory() {
-        let (rt, _tmp) = test_runtime();
-        let ctx = WorkspaceContext {
-            instructions: None,
-            identity: None,
-            personality: None,
-            user_profile: Some("Developer at ProcessOne".to_string()),
-            user_memory: Some("Prefers Rust over Go. Working on XMPP project.".to_string()),
+    fn test_import_archive_merges_missed_room_messages() {
+        let (rt, tmp) = test_runtime();
+        let room = "lobby@conference.localhost";
+        rt.memory
+            .store_archived_message(room, "user", "hello", Some("m1"), Some("alice@muc"), "2026-10-18T10:00:00+00:00")
+            .unwrap();
+        let target = ArchiveTarget {
+            jid: room.to_string(),
+            room_nick: Some("bot".to_string()),
+            since: Some("2026-10-18T10:00:00+00:00".to_string()),
+            replace: false,
         };
-        let prompt = rt.build_system_prompt(&ctx);
-        assert!(prompt.contains("About this user"));
-        assert!(prompt.contains("Developer at ProcessOne"));
-        assert!(prompt.contains("Notes and memory"));
-        assert!(prompt.contains("Prefers Rust over Go"));
-    }
+        let messages = vec![
+            // Already stored: skipped
+            archived("lobby@conference.localhost/alice", "hello", "m1", "2026-10-18T10:00:00Z"),
+            archived("lobby@conference.localhost/bob", "bot: are you there?", "m2", "2026-10-18T11:00:00Z"),
+            archived("lobby@conference.localhost/bot", "I was away", "m3", "2026-10-18T11:05:00Z"),
+        ];
 
-    #[test]
-    fn test_build_system_prompt_partial_global_files() {
-        let (rt, _tmp) = test_runtime();
-        // Only identity.md exists — should use workspace mode, not fallback
-        let ctx = WorkspaceContext {
-            instructions: None,
-            identity: Some("You are Fluux Agent.".to_string()),
-            personality: None,
-            user_profile: None,
-            user_memory: None,
+        let missed = rt.import_archive(&target, &messages).unwrap();
+        assert_eq!(missed, vec!["bot: are you there?".to_string()]);
+
+        let history = std::fs::read_to_string(tmp.path().join(room).join("history.jsonl")).unwrap();
+        assert_eq!(history.matches("\"hello\"").count(), 1);
+        assert!(history.contains("\"sender\":\"bob@muc\""));
+        // Original timestamps are kept; the agent's own messages are replies
+        assert!(history.contains("\"ts\":\"2026-10-18T11:00:00+00:00\""));
+        assert!(history.contains("\"role\":\"assistant\",\"content\":\"I was away\""));
+
+        // Importing the same page again adds nothing
+        let target = ArchiveTarget {
+            since: rt.memory.session_marks(room).unwrap().0,
+            ..target
         };
-        let prompt = rt.build_system_prompt(&ctx);
-        assert!(prompt.contains("Fluux Agent"));
-        // Fallback should NOT be present
-        assert!(!prompt.contains("skills are coming in v0.2"));
+        assert!(rt.import_archive(&target, &messages).unwrap().is_empty());
+        assert_eq!(rt.memory.message_count(room).unwrap(), 3);
     }
 
-    // ── Status tests with workspace ─────────────────────
+    #[tokio::test]
+    async fn test_run_bootstraps_contact_history_from_archive() {
+        let (mut rt, _tmp) = test_runtime();
+        rt.config.mam.enabled = true;
+        rt.config.mam.bootstrap_messages = 5;
+        rt.config.carbons.enabled = false;
+
+        let (event_tx, event_rx) = mpsc::channel(8);
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(8);
+        let driver = async {
+            event_tx.send(XmppEvent::Connected).await.unwrap();
+            let (id, payload) = loop {
+                match cmd_rx.recv().await {
+                    Some(XmppCommand::SendIq { to, id, payload, .. }) => {
+                        assert_eq!(to, "bot@localhost");
+                        break (id, payload);
+                    }
+                    Some(_) => continue,
+                    None => panic!("expected an archive query"),
+                }
+            };
+            assert!(payload.contains("<value>admin@localhost</value>"));
+            assert!(payload.contains("<max>5</max><before/>"));
+            let query_id = stanzas::extract_attr(&payload, "queryid").unwrap();
+
+            for (from, body) in [
+                ("admin@localhost/phone", "remember the meeting"),
+                ("bot@localhost/fluux-agent", "Noted."),
+            ] {
+                let message = ArchivedMessage {
+                    query_id: query_id.clone(),
+                    archive: None,
+                    from: from.to_string(),
+                    message_type: MessageType::Chat,
+                    ..archived(from, body, body, "2026-10-17T09:00:00Z")
+                };
+                event_tx.send(XmppEvent::Archived(message)).await.unwrap();
+            }
+            let fin = ChildElement {
+                name: "fin".to_string(),
+                namespace: Some(stanzas::NS_MAM.to_string()),
+                attrs: vec![("complete".to_string(), "true".to_string())],
+                ..Default::default()
+            };
+            let result = IncomingIq {
+                from: Some("bot@localhost".to_string()),
+                to: "bot@localhost/fluux-agent".to_string(),
+                id,
+                iq_type: IqType::Result,
+                payload: Some(fin),
+                error: None,
+            };
+            event_tx.send(XmppEvent::Iq(result)).await.unwrap();
 
-    #[test]
-    fn test_command_status_content() {
-        let (rt, _tmp) = test_runtime();
-        rt.memory
-            .store_message("admin@localhost", "user", "Hi")
-            .unwrap();
+            for _ in 0..100 {
+                if rt.memory.message_count("admin@localhost").unwrap() == 2 {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            drop(event_tx);
+        };
+        let (result, ()) = tokio::join!(rt.run(event_rx, cmd_tx), driver);
+        result.unwrap();
 
-        let result = rt
-            .handle_command("admin@localhost/res", "/status")
-            .unwrap();
-        assert!(result.contains("Test Agent"));
-        assert!(result.contains("Uptime:"));
-        assert!(result.contains("C2S client"));
-        assert!(result.contains("anthropic"));
-        assert!(result.contains("Skills: none"));
-        assert!(result.contains("1 messages"));
-        assert!(result.contains("User profile: none"));
-        assert!(result.contains("User memory: none"));
-        assert!(result.contains("instructions=none"));
-        assert!(result.contains("Allowed domains: localhost (default)"));
-        assert!(result.contains("Session timeout: disabled"));
+        let history = rt.memory.get_history("admin@localhost", 10).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].role, "user");
+        assert_eq!(history[1].role, "assistant");
     }
 
-    #[test]
-    fn test_command_status_session_timeout_enabled() {
-        let (mut rt, _tmp) = test_runtime();
-        rt.config.session.idle_timeout_mins = 120;
+    // ── End-to-end test (scripted provider) ──────────────
 
-        let result = rt.handle_command("admin@localhost", "/status").unwrap();
-        assert!(result.contains("Session timeout: 120m idle"));
-    }
+    #[tokio::test]
+    async fn test_end_to_end_with_scripted_provider() {
+        use crate::llm::scripted::{parse_script, ScriptedClient};
+        use crate::skills::builtin::MemoryStoreSkill;
+        use crate::xmpp::stanzas::IncomingMessage;
 
-    #[test]
-    fn test_command_status_with_profile() {
-        let (rt, _tmp) = test_runtime();
-        rt.memory
-            .set_user_context("admin@localhost", "Developer")
-            .unwrap();
+        let (rt, tmp) = test_runtime();
+        let script = parse_script(
+            r#"
+            [[step]]
+            when = "remember"
+            tool_calls = [{ name = "memory_store", input = { key = "color", content = "blue" } }]
+
+            [[step]]
+            when = "color"
+            text = "Noted: your favorite color is blue."
+            "#,
+            true,
+        )
+        .unwrap();
+        let llm: Arc<dyn LlmClient> = Arc::new(ScriptedClient::new("e2e.toml", script));
+        let mut skills = SkillRegistry::new();
+        skills.register(Box::new(MemoryStoreSkill));
+        let rt = AgentRuntime::new(
+            rt.config.clone(),
+            LlmRegistry::new(llm),
+            Arc::clone(&rt.memory),
+            Arc::clone(&rt.file_downloader),
+            skills,
+        );
 
-        let result = rt
-            .handle_command("admin@localhost/res", "/status")
+        let (event_tx, event_rx) = mpsc::channel(8);
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(32);
+        event_tx
+            .send(XmppEvent::Message(IncomingMessage {
+                from: "admin@localhost/phone".to_string(),
+                to: "bot@localhost".to_string(),
+                body: "Please remember that my favorite color is blue".to_string(),
+                id: Some("m1".to_string()),
+                message_type: MessageType::Chat,
+                oob: vec![],
+                delay: None,
+                stanza_id: None,
+                replace_id: None,
+                thread: None,
+                receipt_requested: false,
+                markable: false,
+                mentions: vec![],
+                occupant_id: None,
+            }))
+            .await
             .unwrap();
-        assert!(result.contains("User profile: yes"));
+        drop(event_tx);
+        rt.run(event_rx, cmd_tx).await.unwrap();
+
+        let mut replies = Vec::new();
+        while let Ok(cmd) = cmd_rx.try_recv() {
+            if let XmppCommand::SendMessage { to, body, .. } = cmd {
+                replies.push((to, body));
+            }
+        }
+        assert_eq!(
+            replies,
+            vec![(
+                "admin@localhost/phone".to_string(),
+                "Noted: your favorite color is blue.".to_string()
+            )]
+        );
+
+        // The skill ran and the exchange is in history
+        assert_eq!(rt.memory.knowledge_count("admin@localhost").unwrap(), 1);
+        let history = rt.memory.get_history("admin@localhost", 10).unwrap();
+        assert_eq!(history.len(), 2);
+        drop(tmp);
     }
 
-    #[test]
-    fn test_command_status_with_workspace_files() {
+    /// Serves one HTTP response on a local port and returns its URL.
+    async fn serve_file_once(name: &str, content_type: &str, body: &[u8]) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/{name}", listener.local_addr().unwrap());
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        )
+        .into_bytes();
+        response.extend_from_slice(body);
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request).await;
+            socket.write_all(&response).await.unwrap();
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_muc_mention_downloads_earlier_attachment() {
+        use crate::llm::scripted::{parse_script, ScriptedClient};
+        use crate::xmpp::stanzas::IncomingMessage;
+
         let (rt, tmp) = test_runtime();
-        std::fs::write(tmp.path().join("instructions.md"), "Be concise").unwrap();
-        std::fs::write(tmp.path().join("identity.md"), "I am an agent").unwrap();
+        let room = "team@conference.localhost";
+        let mut config = rt.config.clone();
+        config.rooms = vec![RoomConfig {
+            jid: room.to_string(),
+            nick: "bot".to_string(),
+            tier: None,
+            download_attachments: true,
+            recent_attachments: 1,
+            max_attachment_mb: 1,
+            join_history: Default::default(),
+            answer_delayed: false,
+            activation: Default::default(),
+            keywords: vec![],
+            styling: None,
+            generation: Default::default(),
+        }];
+        let script = parse_script(
+            r#"
+            [[step]]
+            when = "launch on Friday"
+            text = "The notes say the launch is on Friday."
+            "#,
+            true,
+        )
+        .unwrap();
+        let llm: Arc<dyn LlmClient> = Arc::new(ScriptedClient::new("muc.toml", script));
+        let rt = AgentRuntime::new(
+            config,
+            LlmRegistry::new(llm),
+            Arc::clone(&rt.memory),
+            Arc::clone(&rt.file_downloader),
+            SkillRegistry::new(),
+        );
 
-        let result = rt
-            .handle_command("admin@localhost/res", "/status")
+        let url = serve_file_once("notes.txt", "text/plain", b"Plan: launch on Friday").await;
+        let groupchat = |nick: &str, body: &str, oob: Vec<OobData>| {
+            XmppEvent::Message(IncomingMessage {
+                from: format!("{room}/{nick}"),
+                to: "bot@localhost".to_string(),
+                body: body.to_string(),
+                id: None,
+                message_type: MessageType::GroupChat,
+                oob,
+                delay: None,
+                stanza_id: None,
+                replace_id: None,
+                thread: None,
+                receipt_requested: false,
+                markable: false,
+                mentions: vec![],
+                occupant_id: None,
+            })
+        };
+
+        let (event_tx, event_rx) = mpsc::channel(8);
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(32);
+        // A file shared without a mention is only recorded...
+        event_tx
+            .send(groupchat("alice", "", vec![OobData { url: url.clone(), desc: None }]))
+            .await
             .unwrap();
-        assert!(result.contains("instructions=yes"));
-        assert!(result.contains("identity=yes"));
-        assert!(result.contains("personality=none"));
+        // ...and downloaded when a later message mentions the agent
+        event_tx
+            .send(groupchat("bob", "@bot what do the notes say?", vec![]))
+            .await
+            .unwrap();
+        drop(event_tx);
+        rt.run(event_rx, cmd_tx).await.unwrap();
+
+        let reply = tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some(cmd) = cmd_rx.recv().await {
+                if let XmppCommand::SendMucMessage { to, body, .. } = cmd {
+                    return (to, body);
+                }
+            }
+            panic!("no MUC reply");
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            reply,
+            (room.to_string(), "bob: The notes say the launch is on Friday.".to_string())
+        );
+
+        // The file is stored for the room, the exchange is in history
+        assert_eq!(rt.memory.file_count(room).unwrap(), 1);
+        let history = rt.memory.get_history(room, 10).unwrap();
+        assert_eq!(history.len(), 3);
+        drop(tmp);
     }
 
-    #[test]
-    fn test_command_status_domain_wildcard() {
+    #[tokio::test]
+    async fn test_muc_join_history_is_not_stored_twice_or_answered() {
+        use crate::xmpp::stanzas::IncomingMessage;
+
         let (mut rt, _tmp) = test_runtime();
-        rt.config.agent.allowed_domains = vec!["*".to_string()];
+        let room = "lobby@conference.localhost";
+        rt.config.rooms = vec![RoomConfig {
+            jid: room.to_string(),
+            nick: "bot".to_string(),
+            tier: None,
+            download_attachments: false,
+            recent_attachments: 0,
+            max_attachment_mb: 10,
+            join_history: JoinHistory::Since,
+            answer_delayed: false,
+            activation: Default::default(),
+            keywords: vec![],
+            styling: None,
+            generation: Default::default(),
+        }];
+        rt.memory
+            .store_archived_message(room, "user", "hello", Some("m1"), Some("alice@muc"), "2026-10-18T10:00:00+00:00")
+            .unwrap();
+        let delayed = |id: &str, body: &str| {
+            XmppEvent::Message(IncomingMessage {
+                from: format!("{room}/alice"),
+                to: "bot@localhost/fluux-agent".to_string(),
+                body: body.to_string(),
+                id: None,
+                message_type: MessageType::GroupChat,
+                oob: vec![],
+                delay: Some("2026-10-18T10:00:00Z".to_string()),
+                stanza_id: Some(id.to_string()),
+                replace_id: None,
+                thread: None,
+                receipt_requested: false,
+                markable: false,
+                mentions: vec![],
+                occupant_id: None,
+            })
+        };
 
-        let result = rt.handle_command("admin@localhost", "/status").unwrap();
-        assert!(result.contains("Allowed domains: * (all)"));
+        let (event_tx, event_rx) = mpsc::channel(8);
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(8);
+        event_tx.send(XmppEvent::Connected).await.unwrap();
+        // Already stored (matched by stanza id), then a mention from history
+        event_tx.send(delayed("m1", "hello")).await.unwrap();
+        event_tx.send(delayed("m2", "@bot are you there?")).await.unwrap();
+        drop(event_tx);
+        rt.run(event_rx, cmd_tx).await.unwrap();
+
+        let mut joined = None;
+        while let Ok(cmd) = cmd_rx.try_recv() {
+            match cmd {
+                XmppCommand::JoinMuc { history, .. } => joined = Some(history),
+                XmppCommand::SendPresence { .. } => {}
+                other => panic!("unexpected command: {other:?}"),
+            }
+        }
+        assert_eq!(
+            joined,
+            Some(MucHistory::Since("2026-10-18T10:00:00Z".to_string()))
+        );
+        let history = rt.memory.get_history(room, 10).unwrap();
+        assert_eq!(history.len(), 2);
     }
 
-    #[test]
-    fn test_command_status_domain_explicit() {
+    #[tokio::test]
+    async fn test_carbons_are_stored_and_start_takeover() {
+        use crate::xmpp::stanzas::IncomingMessage;
+
         let (mut rt, _tmp) = test_runtime();
-        rt.config.agent.allowed_domains =
-            vec!["localhost".to_string(), "partner.org".to_string()];
+        rt.config.carbons.takeover_mins = 30;
+        let chat = |from: &str, to: &str, body: &str| IncomingMessage {
+            from: from.to_string(),
+            to: to.to_string(),
+            body: body.to_string(),
+            id: None,
+            message_type: MessageType::Chat,
+            oob: vec![],
+            delay: None,
+            stanza_id: None,
+            replace_id: None,
+            thread: None,
+            receipt_requested: false,
+            markable: false,
+            mentions: vec![],
+            occupant_id: None,
+        };
 
-        let result = rt.handle_command("admin@localhost", "/status").unwrap();
-        assert!(result.contains("Allowed domains: localhost, partner.org"));
+        let (event_tx, event_rx) = mpsc::channel(8);
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(8);
+        for event in [
+            // The contact wrote to the operator's client...
+            XmppEvent::Carbon(CarbonMessage {
+                direction: CarbonDirection::Received,
+                message: chat("admin@localhost/phone", "bot@localhost/gajim", "Anyone there?"),
+            }),
+            // ...the operator answered...
+            XmppEvent::Carbon(CarbonMessage {
+                direction: CarbonDirection::Sent,
+                message: chat("bot@localhost/gajim", "admin@localhost/phone", "Yes, Tim here."),
+            }),
+            // ...so the agent stays quiet on the next message
+            XmppEvent::Message(chat("admin@localhost/phone", "bot@localhost/fluux-agent", "Great")),
+            // Carbons about contacts who may not talk to the agent are dropped
+            XmppEvent::Carbon(CarbonMessage {
+                direction: CarbonDirection::Received,
+                message: chat("eve@localhost/x", "bot@localhost/gajim", "hi"),
+            }),
+        ] {
+            event_tx.send(event).await.unwrap();
+        }
+        drop(event_tx);
+        rt.run(event_rx, cmd_tx).await.unwrap();
+
+        assert!(cmd_rx.try_recv().is_err(), "the agent must not answer");
+        let history = rt.memory.get_history("admin@localhost", 10).unwrap();
+        let roles: Vec<&str> = history.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["user", "assistant", "user"]);
+        assert!(!rt.memory.has_conversation("eve@localhost"));
+        assert!(rt.in_takeover("admin@localhost"));
+        assert!(!rt.in_takeover("other@localhost"));
+    }
+
+    #[tokio::test]
+    async fn test_correction_regenerates_reply_and_retraction_forgets() {
+        use crate::llm::scripted::{parse_script, ScriptedClient};
+        use crate::xmpp::stanzas::{IncomingMessage, IncomingRetraction};
+
+        let (rt, _tmp) = test_runtime();
+        let mut config = rt.config.clone();
+        config.session.regenerate_on_correction = true;
+        let script = parse_script(
+            r#"
+            [[step]]
+            when = "Frence"
+            text = "Did you mean France?"
+
+            [[step]]
+            when = "capital of France"
+            text = "Paris."
+            "#,
+            true,
+        )
+        .unwrap();
+        let llm: Arc<dyn LlmClient> = Arc::new(ScriptedClient::new("correct.toml", script));
+        let rt = AgentRuntime::new(
+            config,
+            LlmRegistry::new(llm),
+            Arc::clone(&rt.memory),
+            Arc::clone(&rt.file_downloader),
+            SkillRegistry::new(),
+        );
+        let chat = |body: &str, id: &str, replace_id: Option<&str>| {
+            XmppEvent::Message(IncomingMessage {
+                from: "admin@localhost/phone".to_string(),
+                to: "bot@localhost".to_string(),
+                body: body.to_string(),
+                id: Some(id.to_string()),
+                message_type: MessageType::Chat,
+                oob: vec![],
+                delay: None,
+                stanza_id: None,
+                replace_id: replace_id.map(String::from),
+                thread: None,
+                receipt_requested: false,
+                markable: false,
+                mentions: vec![],
+                occupant_id: None,
+            })
+        };
+        async fn next_reply(cmd_rx: &mut mpsc::Receiver<XmppCommand>) -> XmppCommand {
+            tokio::time::timeout(Duration::from_secs(10), async {
+                while let Some(cmd) = cmd_rx.recv().await {
+                    if matches!(
+                        cmd,
+                        XmppCommand::SendMessage { .. } | XmppCommand::SendCorrection { .. }
+                    ) {
+                        return cmd;
+                    }
+                }
+                panic!("no reply");
+            })
+            .await
+            .unwrap()
+        }
+
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(32);
+        let (event_tx, event_rx) = mpsc::channel(8);
+        event_tx.send(chat("What is the capital of Frence?", "m1", None)).await.unwrap();
+        drop(event_tx);
+        rt.run(event_rx, cmd_tx.clone()).await.unwrap();
+        let XmppCommand::SendMessage { id: Some(reply_id), .. } = next_reply(&mut cmd_rx).await
+        else {
+            panic!("expected a reply with an id");
+        };
+
+        // The fixed question replaces the exchange; the reply is corrected in place
+        let (event_tx, event_rx) = mpsc::channel(8);
+        event_tx
+            .send(chat("What is the capital of France?", "m2", Some("m1")))
+            .await
+            .unwrap();
+        drop(event_tx);
+        rt.run(event_rx, cmd_tx.clone()).await.unwrap();
+        let XmppCommand::SendCorrection { body, replace_id, .. } = next_reply(&mut cmd_rx).await
+        else {
+            panic!("expected a correction");
+        };
+        assert_eq!(body, "Paris.");
+        assert_eq!(replace_id, reply_id);
+
+        let history = rt.memory.get_history("admin@localhost", 10).unwrap();
+        assert_eq!(history.len(), 2);
+        let MessageContent::Text(answer) = &history[1].content else {
+            panic!("expected text");
+        };
+        assert_eq!(answer, "Paris.");
+
+        // A retraction removes the question from the LLM context
+        let (event_tx, event_rx) = mpsc::channel(8);
+        event_tx
+            .send(XmppEvent::Retraction(IncomingRetraction {
+                from: "admin@localhost/phone".to_string(),
+                message_id: "m1".to_string(),
+                message_type: MessageType::Chat,
+                occupant_id: None,
+            }))
+            .await
+            .unwrap();
+        drop(event_tx);
+        rt.run(event_rx, cmd_tx).await.unwrap();
+        let history = rt.memory.get_history("admin@localhost", 10).unwrap();
+        let roles: Vec<&str> = history.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["assistant"]);
     }
 
-    // ── build_oob_attachments tests ─────────────────────
+    #[tokio::test]
+    async fn test_receipts_and_markers_are_sent_and_recorded() {
+        use crate::llm::scripted::{parse_script, ScriptedClient};
+        use crate::xmpp::stanzas::{IncomingMarker, IncomingMessage};
+
+        let (rt, _tmp) = test_runtime();
+        let script = parse_script(
+            r#"
+            [[step]]
+            when = "Hello"
+            text = "Hi there!"
+            "#,
+            true,
+        )
+        .unwrap();
+        let llm: Arc<dyn LlmClient> = Arc::new(ScriptedClient::new("markers.toml", script));
+        let rt = AgentRuntime::new(
+            rt.config.clone(),
+            LlmRegistry::new(llm),
+            Arc::clone(&rt.memory),
+            Arc::clone(&rt.file_downloader),
+            SkillRegistry::new(),
+        );
 
-    #[test]
-    fn test_build_oob_attachments_empty() {
-        assert!(build_oob_attachments(&[]).is_none());
+        let (event_tx, event_rx) = mpsc::channel(8);
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(32);
+        event_tx
+            .send(XmppEvent::Message(IncomingMessage {
+                from: "admin@localhost/phone".to_string(),
+                to: "bot@localhost".to_string(),
+                body: "Hello".to_string(),
+                id: Some("m1".to_string()),
+                message_type: MessageType::Chat,
+                oob: vec![],
+                delay: None,
+                stanza_id: None,
+                replace_id: None,
+                thread: None,
+                receipt_requested: true,
+                markable: true,
+                mentions: vec![],
+                occupant_id: None,
+            }))
+            .await
+            .unwrap();
+        drop(event_tx);
+        rt.run(event_rx, cmd_tx.clone()).await.unwrap();
+
+        let mut sent = Vec::new();
+        let mut reply_id = None;
+        while let Ok(cmd) = cmd_rx.try_recv() {
+            match cmd {
+                XmppCommand::SendReceipt { id, .. } => sent.push(format!("receipt {id}")),
+                XmppCommand::SendMarker { id, marker, .. } => {
+                    sent.push(format!("{} {id}", marker.as_str()))
+                }
+                XmppCommand::SendMessage { id, .. } => reply_id = id,
+                _ => {}
+            }
+        }
+        assert_eq!(sent, vec!["receipt m1", "received m1", "displayed m1"]);
+        let reply_id = reply_id.expect("reply with an id");
+
+        // The contact read the reply
+        let (event_tx, event_rx) = mpsc::channel(8);
+        event_tx
+            .send(XmppEvent::Marker(IncomingMarker {
+                from: "admin@localhost/phone".to_string(),
+                message_id: reply_id,
+                marker: ChatMarker::Displayed,
+                message_type: MessageType::Chat,
+            }))
+            .await
+            .unwrap();
+        drop(event_tx);
+        rt.run(event_rx, cmd_tx).await.unwrap();
+        assert_eq!(rt.memory.unread_replies("admin@localhost").unwrap(), Some(0));
+        let status = rt.handle_command("admin@localhost/phone", "/status").unwrap();
+        assert!(status.contains("Unread replies: 0"));
     }
 
-    #[test]
-    fn test_build_oob_attachments_single() {
-        let oob = vec![OobData {
-            url: "https://upload.example.com/abc/photo.jpg".to_string(),
-            desc: None,
+    #[tokio::test]
+    async fn test_threads_have_own_session_and_replies_reference_mention() {
+        use crate::llm::scripted::{parse_script, ScriptedClient};
+        use crate::xmpp::stanzas::IncomingMessage;
+
+        let (rt, _tmp) = test_runtime();
+        let room = "lobby@conference.localhost";
+        let mut config = rt.config.clone();
+        config.rooms = vec![RoomConfig {
+            jid: room.to_string(),
+            nick: "bot".to_string(),
+            tier: None,
+            download_attachments: false,
+            recent_attachments: 0,
+            max_attachment_mb: 10,
+            join_history: JoinHistory::None,
+            answer_delayed: false,
+            activation: Default::default(),
+            keywords: vec![],
+            styling: None,
+            generation: Default::default(),
         }];
-        let atts = build_oob_attachments(&oob).unwrap();
-        assert_eq!(atts.len(), 1);
-        assert_eq!(atts[0].filename, "photo.jpg");
-        assert_eq!(atts[0].mime_type, "unknown");
-        assert_eq!(atts[0].size, "unknown");
-    }
+        let script = parse_script(
+            r#"
+            [[step]]
+            when = "deploy"
+            text = "Deploying."
+
+            [[step]]
+            when = "status"
+            text = "All green."
+            "#,
+            true,
+        )
+        .unwrap();
+        let llm: Arc<dyn LlmClient> = Arc::new(ScriptedClient::new("threads.toml", script));
+        let rt = AgentRuntime::new(
+            config,
+            LlmRegistry::new(llm),
+            Arc::clone(&rt.memory),
+            Arc::clone(&rt.file_downloader),
+            SkillRegistry::new(),
+        );
+        let message = |from: &str, body: &str, message_type, stanza_id: Option<&str>| {
+            XmppEvent::Message(IncomingMessage {
+                from: from.to_string(),
+                to: "bot@localhost".to_string(),
+                body: body.to_string(),
+                id: Some("m1".to_string()),
+                message_type,
+                oob: vec![],
+                delay: None,
+                stanza_id: stanza_id.map(String::from),
+                replace_id: None,
+                thread: Some("t-1".to_string()),
+                receipt_requested: false,
+                markable: false,
+                mentions: vec![],
+                occupant_id: None,
+            })
+        };
 
-    #[test]
-    fn test_build_oob_attachments_multiple() {
-        let oob = vec![
-            OobData {
-                url: "https://upload.example.com/a.jpg".to_string(),
-                desc: None,
-            },
-            OobData {
-                url: "https://upload.example.com/b.pdf".to_string(),
-                desc: None,
-            },
-        ];
-        let atts = build_oob_attachments(&oob).unwrap();
-        assert_eq!(atts.len(), 2);
-        assert_eq!(atts[0].filename, "a.jpg");
-        assert_eq!(atts[1].filename, "b.pdf");
-    }
+        let (event_tx, event_rx) = mpsc::channel(8);
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(32);
+        event_tx
+            .send(message("admin@localhost/phone", "Please deploy", MessageType::Chat, None))
+            .await
+            .unwrap();
+        event_tx
+            .send(message(
+                &format!("{room}/alice"),
+                "bot: status?",
+                MessageType::GroupChat,
+                Some("s-1"),
+            ))
+            .await
+            .unwrap();
+        drop(event_tx);
+        rt.run(event_rx, cmd_tx).await.unwrap();
+
+        let mut chat_thread = None;
+        let mut room_reply = None;
+        while let Ok(cmd) = cmd_rx.try_recv() {
+            match cmd {
+                XmppCommand::SendMessage { thread, .. } => chat_thread = thread,
+                XmppCommand::SendMucMessage { thread, reply, .. } => room_reply = Some((thread, reply)),
+                _ => {}
+            }
+        }
+        // Replies continue the thread; room answers reference the mention
+        assert_eq!(chat_thread.as_deref(), Some("t-1"));
+        let (thread, reply) = room_reply.expect("room answer");
+        assert_eq!(thread.as_deref(), Some("t-1"));
+        assert_eq!(
+            reply,
+            Some(MessageReply {
+                to: format!("{room}/alice"),
+                id: "s-1".to_string(),
+            })
+        );
 
-    #[test]
-    fn test_build_oob_attachments_trailing_slash_fallback() {
-        let oob = vec![OobData {
-            url: "https://example.com/files/".to_string(),
-            desc: None,
-        }];
-        let atts = build_oob_attachments(&oob).unwrap();
-        // Trailing slash: rsplit('/').next() yields "", filter(non-empty) → fallback "file"
-        assert_eq!(atts[0].filename, "file");
+        // The exchange is in the thread's session, not the default one
+        assert!(rt.memory.get_history("admin@localhost", 10).unwrap().is_empty());
+        let thread = Memory::thread_session("admin@localhost", Some("t-1"));
+        assert_eq!(rt.memory.get_history(&thread, 10).unwrap().len(), 2);
+        let threads = rt.handle_command("admin@localhost/phone", "/threads").unwrap();
+        assert!(threads.contains("- t-1: 2 messages"), "{threads}");
+        let threads = rt.handle_command(&format!("{room}/alice"), "/threads").unwrap();
+        assert!(threads.contains("- t-1: 2 messages"));
     }
 
-    // ── Status with files test ──────────────────────────
+    #[tokio::test]
+    async fn test_thread_messages_are_corrected_retracted_and_marked() {
+        use crate::llm::scripted::{parse_script, ScriptedClient};
+        use crate::xmpp::stanzas::{IncomingMarker, IncomingMessage};
 
-    #[test]
-    fn test_command_status_with_files() {
-        let (rt, tmp) = test_runtime();
-        let files_dir = rt.memory.files_dir("admin@localhost").unwrap();
-        std::fs::write(files_dir.join("abc_photo.jpg"), b"fake").unwrap();
+        let (rt, _tmp) = test_runtime();
+        let room = "lobby@conference.localhost";
+        let mut config = rt.config.clone();
+        config.rooms = vec![RoomConfig {
+            jid: room.to_string(),
+            nick: "bot".to_string(),
+            tier: None,
+            download_attachments: false,
+            recent_attachments: 0,
+            max_attachment_mb: 10,
+            join_history: JoinHistory::All,
+            answer_delayed: false,
+            activation: Default::default(),
+            keywords: vec![],
+            styling: None,
+            generation: Default::default(),
+        }];
+        let script = parse_script(
+            r#"
+            [[step]]
+            text = "Deploying."
+            "#,
+            true,
+        )
+        .unwrap();
+        let llm: Arc<dyn LlmClient> = Arc::new(ScriptedClient::new("thread-edits.toml", script));
+        let rt = AgentRuntime::new(
+            config,
+            LlmRegistry::new(llm),
+            Arc::clone(&rt.memory),
+            Arc::clone(&rt.file_downloader),
+            SkillRegistry::new(),
+        );
+        let message = |from: &str, body: &str, id: &str, replace_id: Option<&str>| IncomingMessage {
+            from: from.to_string(),
+            to: "bot@localhost".to_string(),
+            body: body.to_string(),
+            id: Some(id.to_string()),
+            message_type: MessageType::Chat,
+            oob: vec![],
+            delay: None,
+            stanza_id: None,
+            replace_id: replace_id.map(String::from),
+            thread: Some("t-1".to_string()),
+            receipt_requested: false,
+            markable: false,
+            mentions: vec![],
+            occupant_id: None,
+        };
+        let replayed = IncomingMessage {
+            message_type: MessageType::GroupChat,
+            delay: Some("2026-10-18T10:00:00Z".to_string()),
+            stanza_id: Some("s-1".to_string()),
+            ..message(&format!("{room}/alice"), "Morning", "r1", None)
+        };
 
-        let result = rt.handle_command("admin@localhost", "/status").unwrap();
-        assert!(result.contains("Files: 1"));
+        let (event_tx, event_rx) = mpsc::channel(8);
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(32);
+        event_tx
+            .send(XmppEvent::Message(message("admin@localhost/phone", "Please deploy", "m1", None)))
+            .await
+            .unwrap();
+        event_tx.send(XmppEvent::Message(replayed.clone())).await.unwrap();
+        drop(event_tx);
+        rt.run(event_rx, cmd_tx.clone()).await.unwrap();
+        let mut reply_id = None;
+        while let Ok(cmd) = cmd_rx.try_recv() {
+            if let XmppCommand::SendMessage { id, .. } = cmd {
+                reply_id = id;
+            }
+        }
+        let reply_id = reply_id.expect("reply with an id");
+
+        // Correction in the thread, retraction and marker without it, and
+        // the room history replayed again on rejoin
+        let (event_tx, event_rx) = mpsc::channel(8);
+        event_tx
+            .send(XmppEvent::Message(message(
+                "admin@localhost/phone",
+                "Please deploy now",
+                "m2",
+                Some("m1"),
+            )))
+            .await
+            .unwrap();
+        event_tx
+            .send(XmppEvent::Marker(IncomingMarker {
+                from: "admin@localhost/phone".to_string(),
+                message_id: reply_id,
+                marker: ChatMarker::Displayed,
+                message_type: MessageType::Chat,
+            }))
+            .await
+            .unwrap();
+        event_tx.send(XmppEvent::Message(replayed)).await.unwrap();
+        drop(event_tx);
+        rt.run(event_rx, cmd_tx.clone()).await.unwrap();
+
+        let thread = Memory::thread_session("admin@localhost", Some("t-1"));
+        let history = rt.memory.get_history(&thread, 10).unwrap();
+        let MessageContent::Text(question) = &history[0].content else {
+            panic!("Expected text");
+        };
+        assert_eq!(question, "Please deploy now");
+        assert_eq!(rt.memory.unread_replies(&thread).unwrap(), Some(0));
+        let room_thread = Memory::thread_session(room, Some("t-1"));
+        assert_eq!(rt.memory.get_history(&room_thread, 10).unwrap().len(), 1);
+
+        let (event_tx, event_rx) = mpsc::channel(8);
+        event_tx
+            .send(XmppEvent::Retraction(IncomingRetraction {
+                from: "admin@localhost/phone".to_string(),
+                message_id: "m1".to_string(),
+                message_type: MessageType::Chat,
+                occupant_id: None,
+            }))
+            .await
+            .unwrap();
+        drop(event_tx);
+        rt.run(event_rx, cmd_tx).await.unwrap();
+        let history = rt.memory.get_history(&thread, 10).unwrap();
+        let roles: Vec<&str> = history.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["assistant"]);
     }
 
-    #[test]
-    fn test_command_status_no_files_hides_line() {
+    #[tokio::test]
+    async fn test_react_skill_and_acknowledgement_reactions() {
+        use crate::llm::scripted::{parse_script, ScriptedClient};
+        use crate::skills::builtin::ReactSkill;
+        use crate::xmpp::stanzas::IncomingMessage;
+
         let (rt, _tmp) = test_runtime();
-        let result = rt.handle_command("admin@localhost", "/status").unwrap();
-        // When there are no files, the "Files:" line should not appear
-        assert!(!result.contains("Files:"));
+        let mut config = rt.config.clone();
+        config.reactions.acknowledge = true;
+        let script = parse_script(
+            r#"
+            [[step]]
+            when = "shipped"
+            tool_calls = [{ name = "react", input = { emoji = "🎉" } }]
+
+            [[step]]
+            when = "will be added"
+            text = "Congratulations!"
+            "#,
+            true,
+        )
+        .unwrap();
+        let llm: Arc<dyn LlmClient> = Arc::new(ScriptedClient::new("react.toml", script));
+        let mut skills = SkillRegistry::new();
+        skills.register(Box::new(ReactSkill));
+        let rt = AgentRuntime::new(
+            config,
+            LlmRegistry::new(llm),
+            Arc::clone(&rt.memory),
+            Arc::clone(&rt.file_downloader),
+            skills,
+        );
+
+        let (event_tx, event_rx) = mpsc::channel(8);
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(32);
+        event_tx
+            .send(XmppEvent::Message(IncomingMessage {
+                from: "admin@localhost/phone".to_string(),
+                to: "bot@localhost".to_string(),
+                body: "We shipped the release".to_string(),
+                id: Some("m1".to_string()),
+                message_type: MessageType::Chat,
+                oob: vec![],
+                delay: None,
+                stanza_id: None,
+                replace_id: None,
+                thread: None,
+                receipt_requested: false,
+                markable: false,
+                mentions: vec![],
+                occupant_id: None,
+            }))
+            .await
+            .unwrap();
+        drop(event_tx);
+        rt.run(event_rx, cmd_tx).await.unwrap();
+
+        let mut updates = Vec::new();
+        while let Ok(cmd) = cmd_rx.try_recv() {
+            match cmd {
+                XmppCommand::SendReaction { to, message_id, emojis, msg_type } => {
+                    assert_eq!(to, "admin@localhost/phone");
+                    assert_eq!((message_id.as_str(), msg_type.as_str()), ("m1", "chat"));
+                    updates.push(emojis.join(" "));
+                }
+                XmppCommand::SendMessage { body, .. } => updates.push(body),
+                _ => {}
+            }
+        }
+        // 👀 while working, the LLM's reaction after the reply, then ✅ replaces 👀
+        assert_eq!(updates, ["👀", "Congratulations!", "👀 🎉", "🎉 ✅"]);
+
+        // Every update is recorded; only the LLM's one is in its context
+        let jid = "admin@localhost";
+        assert_eq!(rt.memory.own_reactions(jid, "m1").unwrap(), ["🎉", "✅"]);
+        let history = rt.memory.get_history(jid, 10).unwrap();
+        assert_eq!(history.len(), 3);
+        let MessageContent::Text(last) = &history[2].content else {
+            panic!("Expected text");
+        };
+        assert!(last.contains(r#""emojis":["👀","🎉"]"#));
     }
 
-    // ── Status with skills test ─────────────────────────
+    #[tokio::test]
+    async fn test_answers_are_sent_with_message_styling() {
+        use crate::llm::scripted::{parse_script, ScriptedClient};
+        use crate::xmpp::stanzas::IncomingMessage;
 
-    #[test]
-    fn test_command_status_with_skills() {
-        use async_trait::async_trait;
-        use crate::skills::Skill;
+        let (rt, _tmp) = test_runtime();
+        let mut config = rt.config.clone();
+        config.users = vec![crate::config::UserConfig {
+            jid: "raw@localhost".to_string(),
+            styling: Some(false),
+            generation: Default::default(),
+        }];
+        config.agent.allowed_jids.push("raw@localhost".to_string());
+        let script = parse_script(
+            r###"
+            [[step]]
+            text = "## Result\n**Done**, see `log.txt`."
+
+            [[step]]
+            text = "## Result\n**Done**, see `log.txt`."
+            "###,
+            true,
+        )
+        .unwrap();
+        let llm: Arc<dyn LlmClient> = Arc::new(ScriptedClient::new("styling.toml", script));
+        let rt = AgentRuntime::new(
+            config,
+            LlmRegistry::new(llm),
+            Arc::clone(&rt.memory),
+            Arc::clone(&rt.file_downloader),
+            SkillRegistry::new(),
+        );
+        let message = |from: &str| {
+            XmppEvent::Message(IncomingMessage {
+                from: from.to_string(),
+                to: "bot@localhost".to_string(),
+                body: "Status?".to_string(),
+                id: Some("m1".to_string()),
+                message_type: MessageType::Chat,
+                oob: vec![],
+                delay: None,
+                stanza_id: None,
+                replace_id: None,
+                thread: None,
+                receipt_requested: false,
+                markable: false,
+                mentions: vec![],
+                occupant_id: None,
+            })
+        };
 
-        struct StubSkill(&'static str);
-        #[async_trait]
-        impl Skill for StubSkill {
-            fn name(&self) -> &str { self.0 }
-            fn description(&self) -> &str { "" }
-            fn parameters_schema(&self) -> serde_json::Value { serde_json::json!({}) }
-            async fn execute(&self, _: serde_json::Value, _context: &crate::skills::SkillContext) -> anyhow::Result<String> {
-                Ok(String::new())
+        let (event_tx, event_rx) = mpsc::channel(8);
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(32);
+        event_tx.send(message("admin@localhost/phone")).await.unwrap();
+        event_tx.send(message("raw@localhost/phone")).await.unwrap();
+        drop(event_tx);
+        rt.run(event_rx, cmd_tx).await.unwrap();
+
+        let mut bodies = Vec::new();
+        while let Ok(cmd) = cmd_rx.try_recv() {
+            if let XmppCommand::SendMessage { body, .. } = cmd {
+                bodies.push(body);
             }
         }
+        assert_eq!(
+            bodies,
+            ["*Result*\n*Done*, see `log.txt`.", "## Result\n**Done**, see `log.txt`."]
+        );
 
-        let (mut rt, _tmp) = test_runtime();
-        let skills = Arc::get_mut(&mut rt.skills).unwrap();
-        skills.register(Box::new(StubSkill("web_search")));
-        skills.register(Box::new(StubSkill("url_fetch")));
-
-        let result = rt.handle_command("admin@localhost", "/status").unwrap();
-        assert!(result.contains("Skills: url_fetch, web_search"));
-        assert!(!result.contains("Skills: none"));
+        // History keeps the model's Markdown
+        let history = rt.memory.get_history("admin@localhost", 10).unwrap();
+        let MessageContent::Text(answer) = &history[1].content else {
+            panic!("Expected text");
+        };
+        assert_eq!(answer, "## Result\n**Done**, see `log.txt`.");
     }
 
-    // ── Agentic loop message structure tests ─────────────
+    #[tokio::test]
+    async fn test_long_answers_are_split_in_order() {
+        use crate::llm::scripted::{parse_script, ScriptedClient};
+        use crate::xmpp::stanzas::IncomingMessage;
 
-    #[test]
-    fn test_tool_result_message_structure() {
-        use crate::llm::InputContentBlock;
+        let (rt, _tmp) = test_runtime();
+        let mut config = rt.config.clone();
+        config.output.max_body_bytes = 40;
+        let script = parse_script(
+            r#"
+            [[step]]
+            text = "First paragraph.\n\nSecond one.\n\n```\nlet a = 1;\n\nlet b = 2;\n```\n\nLast."
+            "#,
+            true,
+        )
+        .unwrap();
+        let llm: Arc<dyn LlmClient> = Arc::new(ScriptedClient::new("split.toml", script));
+        let rt = AgentRuntime::new(
+            config,
+            LlmRegistry::new(llm),
+            Arc::clone(&rt.memory),
+            Arc::clone(&rt.file_downloader),
+            SkillRegistry::new(),
+        );
 
-        let msg = Message {
-            role: "user".to_string(),
-            content: MessageContent::Blocks(vec![
-                InputContentBlock::ToolResult {
-                    tool_use_id: "toolu_abc123".to_string(),
-                    content: "Search returned 5 results".to_string(),
-                },
-            ]),
+        let (event_tx, event_rx) = mpsc::channel(8);
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(32);
+        event_tx
+            .send(XmppEvent::Message(IncomingMessage {
+                from: "admin@localhost/phone".to_string(),
+                to: "bot@localhost".to_string(),
+                body: "Explain".to_string(),
+                id: Some("m1".to_string()),
+                message_type: MessageType::Chat,
+                oob: vec![],
+                delay: None,
+                stanza_id: None,
+                replace_id: None,
+                thread: Some("t1".to_string()),
+                receipt_requested: false,
+                markable: false,
+                mentions: vec![],
+                occupant_id: None,
+            }))
+            .await
+            .unwrap();
+        drop(event_tx);
+        rt.run(event_rx, cmd_tx).await.unwrap();
+
+        let mut parts = Vec::new();
+        while let Ok(cmd) = cmd_rx.try_recv() {
+            if let XmppCommand::SendMessage { body, id, thread, .. } = cmd {
+                parts.push((body, id.is_some(), thread));
+            }
+        }
+        let bodies: Vec<&str> = parts.iter().map(|(body, _, _)| body.as_str()).collect();
+        assert_eq!(
+            bodies,
+            ["First paragraph.\n\nSecond one.", "```\nlet a = 1;\n\nlet b = 2;\n```\n\nLast."]
+        );
+        // The first part carries the answer's id; every part stays in the thread
+        assert_eq!(parts.iter().map(|(_, id, _)| *id).collect::<Vec<_>>(), [true, false]);
+        assert!(parts.iter().all(|(_, _, thread)| thread.as_deref() == Some("t1")));
+
+        // History keeps the answer whole
+        let session = Memory::thread_session("admin@localhost", Some("t1"));
+        let history = rt.memory.get_history(&session, 10).unwrap();
+        let MessageContent::Text(answer) = &history[1].content else {
+            panic!("Expected text");
         };
-        let json = serde_json::to_value(&msg).unwrap();
-        assert_eq!(json["role"], "user");
-        let blocks = json["content"].as_array().unwrap();
-        assert_eq!(blocks[0]["type"], "tool_result");
-        assert_eq!(blocks[0]["tool_use_id"], "toolu_abc123");
-        assert_eq!(blocks[0]["content"], "Search returned 5 results");
+        assert!(answer.starts_with("First paragraph.") && answer.ends_with("Last."));
     }
 
     #[test]
-    fn test_assistant_tool_use_message_structure() {
-        use crate::llm::InputContentBlock;
-
-        let msg = Message {
-            role: "assistant".to_string(),
-            content: MessageContent::Blocks(vec![
-                InputContentBlock::Text {
-                    text: "Let me search for that.".to_string(),
-                },
-                InputContentBlock::ToolUse {
-                    id: "toolu_abc123".to_string(),
-                    name: "web_search".to_string(),
-                    input: serde_json::json!({"query": "rust async"}),
-                },
-            ]),
+    fn test_answer_fallback_announces_the_full_answer() {
+        let (rt, _tmp) = test_runtime();
+        let config = &rt.config;
+        let target = AnswerTarget {
+            thread: Some("t1".to_string()),
+            ..AnswerTarget::new(config, "admin@localhost", "admin@localhost/phone", "chat", Some("a1".to_string()))
         };
-        let json = serde_json::to_value(&msg).unwrap();
-        assert_eq!(json["role"], "assistant");
-        let blocks = json["content"].as_array().unwrap();
-        assert_eq!(blocks.len(), 2);
-        assert_eq!(blocks[0]["type"], "text");
-        assert_eq!(blocks[1]["type"], "tool_use");
-        assert_eq!(blocks[1]["name"], "web_search");
+        let commands = answer_fallback(&target, vec!["Part one.".to_string(), "Part two.".to_string()]);
+
+        // The excerpt already carried the answer's id: the fallback does not
+        let bodies: Vec<&str> = commands
+            .iter()
+            .map(|cmd| match cmd {
+                XmppCommand::SendMessage { body, id: None, thread, .. } => {
+                    assert_eq!(thread.as_deref(), Some("t1"));
+                    body.as_str()
+                }
+                other => panic!("Unexpected command: {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            bodies,
+            ["(Could not upload answer.md: the full answer follows.)", "Part one.", "Part two."]
+        );
     }
 
     #[test]
-    fn test_agentic_conversation_roundtrip() {
-        use crate::llm::InputContentBlock;
+    fn test_delivery_error_stays_in_thread() {
+        let command = delivery_error("lobby@conference.localhost", "groupchat", Some("t1".to_string()), "Sorry".to_string());
+        let XmppCommand::SendMucMessage { thread, .. } = command else {
+            panic!("Expected a room message");
+        };
+        assert_eq!(thread.as_deref(), Some("t1"));
 
-        // Simulate the full agentic conversation:
-        // 1. User asks a question
-        // 2. Assistant responds with tool_use
-        // 3. User sends tool_result
-        // 4. Assistant responds with text
-        let messages = vec![
-            Message {
-                role: "user".to_string(),
-                content: MessageContent::Text("What is Rust?".to_string()),
-            },
-            Message {
-                role: "assistant".to_string(),
-                content: MessageContent::Blocks(vec![InputContentBlock::ToolUse {
-                    id: "toolu_1".to_string(),
-                    name: "web_search".to_string(),
-                    input: serde_json::json!({"query": "Rust programming language"}),
-                }]),
-            },
-            Message {
-                role: "user".to_string(),
-                content: MessageContent::Blocks(vec![InputContentBlock::ToolResult {
-                    tool_use_id: "toolu_1".to_string(),
-                    content: "Rust is a systems programming language.".to_string(),
-                }]),
-            },
-            Message {
-                role: "assistant".to_string(),
-                content: MessageContent::Text(
-                    "Rust is a systems programming language focused on safety.".to_string(),
-                ),
-            },
+        let command = delivery_error("admin@localhost/phone", "chat", Some("t2".to_string()), "Sorry".to_string());
+        let XmppCommand::SendMessage { thread, .. } = command else {
+            panic!("Expected a chat message");
+        };
+        assert_eq!(thread.as_deref(), Some("t2"));
+    }
+
+    #[tokio::test]
+    async fn test_room_occupants_references_and_keywords() {
+        use crate::llm::scripted::{parse_script, ScriptedClient};
+        use crate::xmpp::stanzas::{IncomingMessage, IncomingPresence, MucPresence, MucRole};
+
+        let (rt, _tmp) = test_runtime();
+        let room = "lobby@conference.localhost";
+        let mut config = rt.config.clone();
+        config.rooms = vec![RoomConfig {
+            jid: room.to_string(),
+            nick: "bot".to_string(),
+            tier: None,
+            download_attachments: false,
+            recent_attachments: 0,
+            max_attachment_mb: 10,
+            join_history: JoinHistory::None,
+            answer_delayed: false,
+            activation: Activation::Keywords,
+            keywords: vec!["deploy".to_string()],
+            styling: None,
+            generation: Default::default(),
+        }];
+        let script = parse_script(
+            r#"
+            [[step]]
+            text = "Sure."
+
+            [[step]]
+            text = "Friday."
+            "#,
+            true,
+        )
+        .unwrap();
+        let llm: Arc<dyn LlmClient> = Arc::new(ScriptedClient::new("occupants.toml", script));
+        let rt = AgentRuntime::new(
+            config,
+            LlmRegistry::new(llm),
+            Arc::clone(&rt.memory),
+            Arc::clone(&rt.file_downloader),
+            SkillRegistry::new(),
+        );
+        let presence = |nick: &str, muc: MucPresence| {
+            XmppEvent::Presence(IncomingPresence {
+                from: format!("{room}/{nick}"),
+                presence_type: PresenceType::Available,
+                muc: Some(muc),
+            })
+        };
+        let message = |nick: &str, body: &str, mentions: Vec<String>, occupant_id: Option<&str>| {
+            XmppEvent::Message(IncomingMessage {
+                from: format!("{room}/{nick}"),
+                to: "bot@localhost".to_string(),
+                body: body.to_string(),
+                id: None,
+                message_type: MessageType::GroupChat,
+                oob: vec![],
+                delay: None,
+                stanza_id: None,
+                replace_id: None,
+                thread: None,
+                receipt_requested: false,
+                markable: false,
+                mentions,
+                occupant_id: occupant_id.map(String::from),
+            })
+        };
+
+        let (event_tx, event_rx) = mpsc::channel(8);
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(32);
+        let events = [
+            presence(
+                "alice",
+                MucPresence {
+                    role: MucRole::Moderator,
+                    jid: Some("alice@localhost/phone".to_string()),
+                    ..Default::default()
+                },
+            ),
+            // The room renamed the agent (status 210)
+            presence(
+                "bot-2",
+                MucPresence {
+                    status_codes: vec![110, 210],
+                    occupant_id: Some("occ-bot".to_string()),
+                    ..Default::default()
+                },
+            ),
+            message("alice", "@bot-2-dev can you help?", vec![], None),
+            message("alice", "can you help?", vec![format!("{room}/bot-2")], None),
+            message("bot-3", "deploy echo", vec![], Some("occ-bot")),
+            message("bob", "when do we deploy?", vec![], None),
+            // A contact posing as a room
+            XmppEvent::Presence(IncomingPresence {
+                from: "mallory@localhost/bot".to_string(),
+                presence_type: PresenceType::Available,
+                muc: Some(MucPresence {
+                    status_codes: vec![110],
+                    ..Default::default()
+                }),
+            }),
         ];
+        for event in events {
+            event_tx.send(event).await.unwrap();
+        }
+        drop(event_tx);
+        rt.run(event_rx, cmd_tx).await.unwrap();
 
-        let json = serde_json::to_value(&messages).unwrap();
-        let arr = json.as_array().unwrap();
-        assert_eq!(arr.len(), 4);
-        assert_eq!(arr[0]["role"], "user");
-        assert_eq!(arr[0]["content"], "What is Rust?");
-        assert_eq!(arr[1]["role"], "assistant");
-        assert_eq!(arr[1]["content"][0]["type"], "tool_use");
-        assert_eq!(arr[2]["role"], "user");
-        assert_eq!(arr[2]["content"][0]["type"], "tool_result");
-        assert_eq!(arr[3]["role"], "assistant");
-        assert!(arr[3]["content"].is_string());
-    }
+        let mut answers = Vec::new();
+        while let Ok(cmd) = cmd_rx.try_recv() {
+            if let XmppCommand::SendMucMessage { body, mention, .. } = cmd {
+                answers.push((body, mention));
+            }
+        }
+        assert_eq!(
+            answers,
+            [
+                ("alice: Sure.".to_string(), Some(format!("{room}/alice"))),
+                ("bob: Friday.".to_string(), Some(format!("{room}/bob"))),
+            ]
+        );
 
-    #[test]
-    fn test_empty_skills_produces_none_tools() {
-        let registry = SkillRegistry::new();
-        let defs = registry.tool_definitions();
-        assert!(defs.is_empty());
-        // The agentic loop converts empty → None (not Some([]))
-        let tools: Option<Vec<crate::llm::ToolDefinition>> = if registry.is_empty() {
-            None
-        } else {
-            Some(defs)
+        // Room messages but our own are stored; answers without the nick
+        let history = rt.memory.get_history(room, 20).unwrap();
+        assert_eq!(history.len(), 5);
+        let MessageContent::Text(answer) = &history[2].content else {
+            panic!("Expected text");
         };
-        assert!(tools.is_none());
+        assert_eq!(answer, "Sure.");
+
+        let status = rt.handle_command(&format!("{room}/alice"), "/status").unwrap();
+        assert!(status.contains("Occupants: 1"));
+        assert_eq!(rt.occupants.own_nick("mallory@localhost"), None);
+
+        // Real JIDs come from the roster, when the room discloses them
+        assert_eq!(rt.sender_jid(&format!("{room}/alice")).as_deref(), Some("alice@localhost/phone"));
+        assert_eq!(rt.sender_jid(&format!("{room}/bob")), None);
+        assert_eq!(rt.sender_jid("bob@localhost/pc").as_deref(), Some("bob@localhost/pc"));
     }
 }
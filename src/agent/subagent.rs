@@ -0,0 +1,621 @@
+//! Sub-agents — the runtime-provided `spawn_agent` tool.
+//!
+//! When enabled (`[skills.spawn_agent] enabled = true`), the main agentic
+//! loop offers a `spawn_agent` tool. Calling it runs a child agentic loop
+//! with:
+//!
+//! - its own task prompt and an empty scratch history (the child never
+//!   sees the conversation),
+//! - a subset of the registered skills (all of them by default),
+//! - an optional model tier override,
+//! - no `spawn_agent` tool of its own (spawning is one level deep).
+//!
+//! Only the child's final answer goes back to the parent as the
+//! `tool_result`. The full child transcript is written to
+//! `{base_path}/{jid}/subagents/{timestamp}-{id}.jsonl` for debugging.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::llm::{GenerationParams, LlmRegistry, Message, MessageContent, Tier, ToolDefinition};
+use crate::skills::{SkillContext, SkillRegistry};
+
+use super::memory::Memory;
+use super::runtime::{agentic_loop, LoopOutcome};
+
+/// Tool name exposed to the LLM.
+pub const SPAWN_AGENT_TOOL: &str = "spawn_agent";
+
+/// System prompt for sub-agents.
+const SUBAGENT_SYSTEM_PROMPT: &str = "You are a sub-agent working on a single task \
+    delegated by another assistant. You do not see its conversation: everything you \
+    need is in the task below. Work autonomously with the tools available, then reply \
+    with a concise, self-contained final answer. Your answer is returned verbatim to \
+    the delegating assistant, not to the end user.";
+
+/// State the agentic loop needs to offer and run `spawn_agent`.
+pub struct SpawnContext<'a> {
+    /// LLM clients, used to resolve the sub-agent's tier.
+    pub llms: &'a LlmRegistry,
+    /// Tier of the parent loop (default for the sub-agent).
+    pub tier: Tier,
+    /// Generation overrides of the parent conversation, inherited by the
+    /// sub-agent.
+    pub params: &'a GenerationParams,
+}
+
+/// Parsed `spawn_agent` parameters.
+#[derive(Debug, PartialEq)]
+struct SpawnRequest {
+    task: String,
+    skills: Option<Vec<String>>,
+    tier: Option<Tier>,
+}
+
+/// Builds the `spawn_agent` tool definition.
+///
+/// The `skills` enum lists the registered skills; the `tier` parameter is
+/// only offered when model tiers are configured.
+pub fn tool_definition(skills: &SkillRegistry, llms: &LlmRegistry) -> ToolDefinition {
+    let mut properties = json!({
+        "task": {
+            "type": "string",
+            "description": "Complete, self-contained instructions for the sub-agent. \
+                It does not see this conversation, so include every detail it needs."
+        },
+        "skills": {
+            "type": "array",
+            "items": {"type": "string", "enum": skills.skill_names()},
+            "description": "Tools the sub-agent may use. Defaults to all of your tools \
+                except spawn_agent."
+        }
+    });
+    if llms.has_tiers() {
+        let tiers: Vec<&str> = Tier::ALL
+            .iter()
+            .filter(|t| llms.has(**t))
+            .map(|t| t.as_str())
+            .collect();
+        properties["tier"] = json!({
+            "type": "string",
+            "enum": tiers,
+            "description": "Model tier for the sub-agent. Defaults to your own tier."
+        });
+    }
+
+    ToolDefinition {
+        name: SPAWN_AGENT_TOOL.to_string(),
+        description: "Delegate a self-contained subtask (e.g. researching one item of a \
+            list) to a sub-agent with its own fresh context. Only its final answer is \
+            returned to you. Use it to keep long research out of this conversation."
+            .to_string(),
+        input_schema: json!({
+            "type": "object",
+            "properties": properties,
+            "required": ["task"]
+        }),
+    }
+}
+
+/// Runs a sub-agent for a `spawn_agent` tool call.
+///
+/// Returns an error for invalid parameters (unknown skill or tier) or if
+/// the child loop fails; the caller turns it into an error tool_result.
+pub async fn run(
+    spawn: &SpawnContext<'_>,
+    skills: &SkillRegistry,
+    params: &serde_json::Value,
+    context: &SkillContext,
+) -> Result<LoopOutcome> {
+    let request = parse_request(params)?;
+
+    let skill_names = match request.skills {
+        Some(names) => {
+            if let Some(unknown) = names.iter().find(|n| skills.get(n).is_none()) {
+                bail!("unknown skill '{unknown}'");
+            }
+            names
+        }
+        None => skills.skill_names().into_iter().map(String::from).collect(),
+    };
+    let child_skills = skills.subset(&skill_names);
+
+    let tier = request.tier.unwrap_or(spawn.tier);
+    if !spawn.llms.has(tier) {
+        bail!("tier '{tier}' is not configured");
+    }
+    let llm = spawn.llms.get(tier);
+    // A `model` override names a model of the parent's tier: another
+    // tier's provider would not know it
+    let params = if tier == spawn.tier {
+        spawn.params.clone()
+    } else {
+        GenerationParams {
+            model: None,
+            ..spawn.params.clone()
+        }
+    };
+
+    info!(
+        "Spawning sub-agent for {} ({}, skills: [{}])",
+        context.jid,
+        llm.description(),
+        skill_names.join(", ")
+    );
+
+    let mut messages = vec![Message {
+        role: "user".to_string(),
+        content: MessageContent::Text(request.task.clone()),
+    }];
+
+    // Boxed: agentic_loop → run → agentic_loop is a recursive async call.
+    // The child gets no SpawnContext, so it cannot spawn further.
+    let result = Box::pin(agentic_loop(
+        SUBAGENT_SYSTEM_PROMPT,
+        &mut messages,
+        llm.as_ref(),
+        &params,
+        &child_skills,
+        context,
+        None,
+    ))
+    .await;
+
+    let header = json!({
+        "type": "header",
+        "task": request.task,
+        "model": llm.description(),
+        "tier": tier.as_str(),
+        "skills": skill_names,
+        "ts": chrono::Utc::now().to_rfc3339(),
+    });
+    if let Err(e) = write_transcript(&context.base_path, &context.jid, &header, &messages, &result) {
+        warn!("Failed to write sub-agent transcript: {e}");
+    }
+
+    let outcome = result?;
+    info!(
+        "Sub-agent finished: {} chars ({} tokens used)",
+        outcome.text.len(),
+        outcome.input_tokens + outcome.output_tokens
+    );
+    Ok(outcome)
+}
+
+/// Parses and validates `spawn_agent` parameters.
+fn parse_request(params: &serde_json::Value) -> Result<SpawnRequest> {
+    let task = params["task"]
+        .as_str()
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .ok_or_else(|| anyhow!("missing required parameter 'task'"))?
+        .to_string();
+
+    let skills = match &params["skills"] {
+        serde_json::Value::Null => None,
+        serde_json::Value::Array(items) => Some(
+            items
+                .iter()
+                .map(|v| {
+                    v.as_str()
+                        .map(String::from)
+                        .ok_or_else(|| anyhow!("'skills' must be a list of tool names"))
+                })
+                .collect::<Result<Vec<_>>>()?,
+        ),
+        _ => bail!("'skills' must be a list of tool names"),
+    };
+
+    let tier = match params["tier"].as_str() {
+        None => None,
+        Some(name) => Some(Tier::parse(name).ok_or_else(|| anyhow!("unknown tier '{name}'"))?),
+    };
+
+    Ok(SpawnRequest { task, skills, tier })
+}
+
+/// Writes the sub-agent transcript as JSONL: a header line, one line per
+/// message, and a final result (or error) line. Returns the file path.
+fn write_transcript(
+    base_path: &Path,
+    jid: &str,
+    header: &serde_json::Value,
+    messages: &[Message],
+    result: &Result<LoopOutcome>,
+) -> Result<PathBuf> {
+    let dir = Memory::open(base_path)?.subagents_dir(jid)?;
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    let timestamp = chrono::Utc::now().format("%Y%m%d-%H%M%S");
+    let path = dir.join(format!("{timestamp}-{}.jsonl", &id[..8]));
+
+    let mut file = fs::File::create(&path)?;
+    writeln!(file, "{header}")?;
+    for message in messages {
+        let line = json!({
+            "type": "message",
+            "role": message.role,
+            "content": message.content,
+        });
+        writeln!(file, "{line}")?;
+    }
+    let last = match result {
+        Ok(outcome) => json!({
+            "type": "result",
+            "text": outcome.text,
+            "input_tokens": outcome.input_tokens,
+            "output_tokens": outcome.output_tokens,
+        }),
+        Err(e) => json!({"type": "error", "error": e.to_string()}),
+    };
+    writeln!(file, "{last}")?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+
+    use crate::llm::{InputContentBlock, LlmClient, LlmResponse, StopReason, ToolCall};
+    use crate::skills::Skill;
+
+    /// LLM stub that returns canned responses in order and records the
+    /// tool names offered and the model override of each call.
+    struct ScriptedLlm {
+        responses: Mutex<VecDeque<LlmResponse>>,
+        offered_tools: Mutex<Vec<Vec<String>>>,
+        prompts: Mutex<Vec<String>>,
+        models: Mutex<Vec<Option<String>>>,
+    }
+
+    impl ScriptedLlm {
+        fn new(responses: Vec<LlmResponse>) -> Self {
+            Self {
+                responses: Mutex::new(responses.into()),
+                offered_tools: Mutex::new(Vec::new()),
+                prompts: Mutex::new(Vec::new()),
+                models: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl LlmClient for ScriptedLlm {
+        async fn complete(
+            &self,
+            system_prompt: &str,
+            _messages: &[Message],
+            tools: Option<&[ToolDefinition]>,
+            params: &GenerationParams,
+        ) -> Result<LlmResponse> {
+            self.prompts.lock().unwrap().push(system_prompt.to_string());
+            self.models.lock().unwrap().push(params.model.clone());
+            self.offered_tools.lock().unwrap().push(
+                tools
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|t| t.name.clone())
+                    .collect(),
+            );
+            self.responses
+                .lock()
+                .unwrap()
+                .pop_front()
+                .ok_or_else(|| anyhow!("script exhausted"))
+        }
+
+        fn description(&self) -> String {
+            "scripted (test)".to_string()
+        }
+    }
+
+    fn text_response(text: &str) -> LlmResponse {
+        LlmResponse {
+            text: text.to_string(),
+            tool_calls: vec![],
+            stop_reason: StopReason::EndTurn,
+            input_tokens: 10,
+            output_tokens: 5,
+            content_blocks: vec![InputContentBlock::Text {
+                text: text.to_string(),
+            }],
+            thinking: String::new(),
+        }
+    }
+
+    fn tool_response(id: &str, name: &str, input: serde_json::Value) -> LlmResponse {
+        LlmResponse {
+            text: String::new(),
+            tool_calls: vec![ToolCall {
+                id: id.to_string(),
+                name: name.to_string(),
+                input: input.clone(),
+            }],
+            stop_reason: StopReason::ToolUse,
+            input_tokens: 10,
+            output_tokens: 5,
+            content_blocks: vec![InputContentBlock::ToolUse {
+                id: id.to_string(),
+                name: name.to_string(),
+                input,
+            }],
+            thinking: String::new(),
+        }
+    }
+
+    struct EchoSkill(&'static str);
+
+    #[async_trait]
+    impl Skill for EchoSkill {
+        fn name(&self) -> &str {
+            self.0
+        }
+        fn description(&self) -> &str {
+            "echo"
+        }
+        fn parameters_schema(&self) -> serde_json::Value {
+            json!({"type": "object", "properties": {}})
+        }
+        async fn execute(&self, _: serde_json::Value, _: &SkillContext) -> Result<String> {
+            Ok(format!("{} ok", self.0))
+        }
+    }
+
+    fn skills() -> SkillRegistry {
+        let mut registry = SkillRegistry::new();
+        registry.register(Box::new(EchoSkill("web_search")));
+        registry.register(Box::new(EchoSkill("url_fetch")));
+        registry
+    }
+
+    fn context(dir: &Path) -> SkillContext {
+        SkillContext {
+            jid: "user@localhost".to_string(),
+            session: "user@localhost".to_string(),
+            base_path: dir.to_path_buf(),
+        }
+    }
+
+    // ── Parameter parsing ────────────────────────────────
+
+    #[test]
+    fn test_parse_request_minimal() {
+        let req = parse_request(&json!({"task": "  compare vendors "})).unwrap();
+        assert_eq!(
+            req,
+            SpawnRequest {
+                task: "compare vendors".to_string(),
+                skills: None,
+                tier: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_request_full() {
+        let req = parse_request(&json!({
+            "task": "research",
+            "skills": ["web_search"],
+            "tier": "deep"
+        }))
+        .unwrap();
+        assert_eq!(req.skills, Some(vec!["web_search".to_string()]));
+        assert_eq!(req.tier, Some(Tier::Deep));
+    }
+
+    #[test]
+    fn test_parse_request_errors() {
+        assert!(parse_request(&json!({})).is_err());
+        assert!(parse_request(&json!({"task": ""})).is_err());
+        assert!(parse_request(&json!({"task": "x", "skills": "web_search"})).is_err());
+        assert!(parse_request(&json!({"task": "x", "tier": "huge"})).is_err());
+    }
+
+    // ── Tool definition ──────────────────────────────────
+
+    #[test]
+    fn test_tool_definition_without_tiers() {
+        let llms = LlmRegistry::new(Arc::new(ScriptedLlm::new(vec![])));
+        let def = tool_definition(&skills(), &llms);
+        assert_eq!(def.name, SPAWN_AGENT_TOOL);
+        assert_eq!(def.input_schema["required"], json!(["task"]));
+        assert_eq!(
+            def.input_schema["properties"]["skills"]["items"]["enum"],
+            json!(["url_fetch", "web_search"])
+        );
+        assert!(def.input_schema["properties"].get("tier").is_none());
+    }
+
+    #[test]
+    fn test_tool_definition_with_tiers() {
+        let llms = LlmRegistry::new(Arc::new(ScriptedLlm::new(vec![])))
+            .with_tier(Tier::Deep, Arc::new(ScriptedLlm::new(vec![])));
+        let def = tool_definition(&skills(), &llms);
+        assert_eq!(
+            def.input_schema["properties"]["tier"]["enum"],
+            json!(["standard", "deep"])
+        );
+    }
+
+    // ── End-to-end through the agentic loop ──────────────
+
+    #[tokio::test]
+    async fn test_spawn_agent_returns_only_final_answer() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let llm = Arc::new(ScriptedLlm::new(vec![
+            // Parent delegates
+            tool_response(
+                "toolu_1",
+                SPAWN_AGENT_TOOL,
+                json!({"task": "Research vendor A", "skills": ["web_search"]}),
+            ),
+            // Child uses its tool, then answers
+            tool_response("toolu_2", "web_search", json!({})),
+            text_response("Vendor A is solid."),
+            // Parent answers with the child's result
+            text_response("Summary: vendor A is solid."),
+        ]));
+        let llms = LlmRegistry::new(llm.clone());
+        let spawn = SpawnContext {
+            llms: &llms,
+            tier: Tier::Standard,
+            params: &GenerationParams::default(),
+        };
+        let mut messages = vec![Message {
+            role: "user".to_string(),
+            content: MessageContent::Text("Compare vendors".to_string()),
+        }];
+
+        let outcome = agentic_loop(
+            "parent prompt",
+            &mut messages,
+            llm.as_ref(),
+            &GenerationParams::default(),
+            &skills(),
+            &context(tmp.path()),
+            Some(&spawn),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(outcome.text, "Summary: vendor A is solid.");
+        // Child tokens are counted in the parent's totals
+        assert_eq!((outcome.input_tokens, outcome.output_tokens), (40, 20));
+
+        // Parent sees spawn_agent; the child sees only its skill subset
+        let offered = llm.offered_tools.lock().unwrap();
+        assert!(offered[0].contains(&SPAWN_AGENT_TOOL.to_string()));
+        assert_eq!(offered[1], vec!["web_search".to_string()]);
+        assert_eq!(offered[2], vec!["web_search".to_string()]);
+        assert!(llm.prompts.lock().unwrap()[1].starts_with("You are a sub-agent"));
+
+        // The parent's tool_result is the child's final answer only
+        let MessageContent::Blocks(blocks) = &messages[2].content else {
+            panic!("expected tool_result blocks");
+        };
+        assert_eq!(
+            blocks[0],
+            InputContentBlock::ToolResult {
+                tool_use_id: "toolu_1".to_string(),
+                content: "Vendor A is solid.".to_string(),
+            }
+        );
+        // Child scratch history never leaks into the parent
+        assert_eq!(messages.len(), 3);
+
+        // Transcript recorded: header, 3 messages, result
+        let dir = tmp.path().join("user@localhost").join("subagents");
+        let files: Vec<_> = fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let content = fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0]["type"], "header");
+        assert_eq!(lines[0]["task"], "Research vendor A");
+        assert_eq!(lines[4]["type"], "result");
+        assert_eq!(lines[4]["text"], "Vendor A is solid.");
+    }
+
+    #[tokio::test]
+    async fn test_spawn_agent_rejects_unknown_skill() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let llm = Arc::new(ScriptedLlm::new(vec![]));
+        let llms = LlmRegistry::new(llm);
+        let spawn = SpawnContext {
+            llms: &llms,
+            tier: Tier::Standard,
+            params: &GenerationParams::default(),
+        };
+        let err = run(
+            &spawn,
+            &skills(),
+            &json!({"task": "x", "skills": ["shell"]}),
+            &context(tmp.path()),
+        )
+        .await
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("unknown skill 'shell'"));
+    }
+
+    #[tokio::test]
+    async fn test_spawn_agent_tier_override_drops_model_override() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let standard = Arc::new(ScriptedLlm::new(vec![text_response("standard done")]));
+        let deep = Arc::new(ScriptedLlm::new(vec![text_response("deep done")]));
+        let llms = LlmRegistry::new(standard.clone()).with_tier(Tier::Deep, deep.clone());
+        let params = GenerationParams {
+            model: Some("claude-haiku-4-5-20250110".to_string()),
+            temperature: Some(0.2),
+            ..Default::default()
+        };
+        let spawn = SpawnContext {
+            llms: &llms,
+            tier: Tier::Standard,
+            params: &params,
+        };
+
+        // Another tier: the parent's model is not sent to its provider
+        let outcome = run(&spawn, &skills(), &json!({"task": "x", "tier": "deep"}), &context(tmp.path()))
+            .await
+            .unwrap();
+        assert_eq!(outcome.text, "deep done");
+        assert_eq!(*deep.models.lock().unwrap(), vec![None]);
+
+        // Same tier: the override is kept
+        let outcome = run(&spawn, &skills(), &json!({"task": "x"}), &context(tmp.path()))
+            .await
+            .unwrap();
+        assert_eq!(outcome.text, "standard done");
+        assert_eq!(
+            *standard.models.lock().unwrap(),
+            vec![Some("claude-haiku-4-5-20250110".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_spawn_agent_without_spawn_context_is_unknown_tool() {
+        // A child (or a runtime with the tool disabled) treats spawn_agent
+        // as an unknown tool rather than recursing.
+        let tmp = tempfile::TempDir::new().unwrap();
+        let llm = Arc::new(ScriptedLlm::new(vec![
+            tool_response("toolu_1", SPAWN_AGENT_TOOL, json!({"task": "again"})),
+            text_response("done"),
+        ]));
+        let mut messages = vec![Message {
+            role: "user".to_string(),
+            content: MessageContent::Text("hi".to_string()),
+        }];
+        let outcome = agentic_loop(
+            "prompt",
+            &mut messages,
+            llm.as_ref(),
+            &GenerationParams::default(),
+            &skills(),
+            &context(tmp.path()),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(outcome.text, "done");
+        let MessageContent::Blocks(blocks) = &messages[2].content else {
+            panic!("expected tool_result blocks");
+        };
+        let InputContentBlock::ToolResult { content, .. } = &blocks[0] else {
+            panic!("expected tool_result");
+        };
+        assert!(content.contains("unknown tool 'spawn_agent'"));
+        assert!(!tmp.path().join("user@localhost").join("subagents").exists());
+    }
+}
@@ -0,0 +1,203 @@
+//! Voice message transcription for audio attachments.
+//!
+//! Mobile clients (Conversations, Monal, Dino) send voice notes as
+//! `audio/ogg` or `audio/mp4` files over HTTP Upload. When
+//! `[attachments.transcription]` is configured, the audio is posted to an
+//! OpenAI-compatible `/v1/audio/transcriptions` endpoint (whisper.cpp
+//! server, faster-whisper-server, LocalAI, OpenAI) and the transcript is
+//! sent to the LLM as text.
+
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use reqwest::Client;
+use serde::Deserialize;
+
+use crate::config::TranscriptionConfig;
+
+/// Response of the transcription endpoint (`response_format=json`)
+#[derive(Debug, Deserialize)]
+struct TranscriptionResponse {
+    text: String,
+}
+
+/// Transcribes an audio file. Returns the transcript, trimmed.
+pub async fn transcribe(
+    config: &TranscriptionConfig,
+    filename: &str,
+    mime_type: &str,
+    data: Vec<u8>,
+) -> Result<String> {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .connect_timeout(Duration::from_secs(10))
+        .build()?;
+
+    let mut fields = vec![
+        ("model", config.model.as_str()),
+        ("response_format", "json"),
+    ];
+    if let Some(language) = config.language.as_deref() {
+        fields.push(("language", language));
+    }
+    let boundary = format!("fluux-{}", uuid::Uuid::new_v4().simple());
+    let body = multipart_body(&boundary, &fields, filename, mime_type, &data);
+
+    let mut request = client
+        .post(&config.url)
+        .header(
+            reqwest::header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={boundary}"),
+        )
+        .body(body);
+    if let Some(key) = config.api_key.as_deref() {
+        request = request.bearer_auth(key);
+    }
+
+    let response = request
+        .send()
+        .await
+        .with_context(|| format!("Transcription endpoint unreachable ({})", config.url))?;
+    let status = response.status();
+    if !status.is_success() {
+        let detail = response.text().await.unwrap_or_default();
+        let detail: String = detail.trim().chars().take(200).collect();
+        bail!("Transcription endpoint returned {status}: {detail}");
+    }
+    let parsed: TranscriptionResponse = response
+        .json()
+        .await
+        .context("Invalid transcription response")?;
+    Ok(parsed.text.trim().to_string())
+}
+
+/// Encodes form fields and one file as `multipart/form-data` (RFC 7578).
+fn multipart_body(
+    boundary: &str,
+    fields: &[(&str, &str)],
+    filename: &str,
+    mime_type: &str,
+    data: &[u8],
+) -> Vec<u8> {
+    let mut body = Vec::with_capacity(data.len() + 512);
+    for (name, value) in fields {
+        body.extend_from_slice(
+            format!(
+                "--{boundary}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+            )
+            .as_bytes(),
+        );
+    }
+    // Quotes and line breaks would break the header
+    let filename: String = filename
+        .chars()
+        .map(|c| if c == '"' || c.is_control() { '_' } else { c })
+        .collect();
+    body.extend_from_slice(
+        format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; \
+             filename=\"{filename}\"\r\nContent-Type: {mime_type}\r\n\r\n"
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(data);
+    body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Serves one HTTP response and returns the raw request it received.
+    async fn endpoint_once(status: &str, body: &str) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "http://{}/v1/audio/transcriptions",
+            listener.local_addr().unwrap()
+        );
+        let response = format!(
+            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n{body}",
+            body.len()
+        );
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut data = Vec::new();
+            let mut chunk = [0u8; 4096];
+            loop {
+                let n = socket.read(&mut chunk).await.unwrap();
+                data.extend_from_slice(&chunk[..n]);
+                let text = String::from_utf8_lossy(&data);
+                if n == 0 || text.contains("--\r\n") {
+                    break;
+                }
+            }
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&data).to_string()
+        });
+        (url, handle)
+    }
+
+    fn config(url: String) -> TranscriptionConfig {
+        TranscriptionConfig {
+            url,
+            model: "whisper-1".to_string(),
+            api_key: Some("sk-test".to_string()),
+            language: Some("fr".to_string()),
+            timeout_secs: 5,
+        }
+    }
+
+    #[test]
+    fn test_multipart_body() {
+        let body = multipart_body(
+            "b",
+            &[("model", "whisper-1")],
+            "a\"b.ogg",
+            "audio/ogg",
+            b"OggS",
+        );
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            "--b\r\nContent-Disposition: form-data; name=\"model\"\r\n\r\nwhisper-1\r\n\
+             --b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a_b.ogg\"\r\n\
+             Content-Type: audio/ogg\r\n\r\nOggS\r\n--b--\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transcribe() {
+        let (url, request) =
+            endpoint_once("200 OK", r#"{"text":" Bonjour, rappelle-moi demain. "}"#).await;
+        let text = transcribe(&config(url), "voice.ogg", "audio/ogg", b"OggS".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(text, "Bonjour, rappelle-moi demain.");
+
+        let request = request.await.unwrap();
+        assert!(request.starts_with("POST /v1/audio/transcriptions HTTP/1.1"));
+        assert!(request
+            .to_lowercase()
+            .contains("authorization: bearer sk-test"));
+        assert!(request.contains("multipart/form-data; boundary=fluux-"));
+        assert!(request.contains("name=\"language\"\r\n\r\nfr\r\n"));
+        assert!(request.contains("filename=\"voice.ogg\"\r\nContent-Type: audio/ogg\r\n\r\nOggS"));
+    }
+
+    #[tokio::test]
+    async fn test_transcribe_error_status() {
+        let (url, _request) = endpoint_once(
+            "500 Internal Server Error",
+            r#"{"error":"model not loaded"}"#,
+        )
+        .await;
+        let err = transcribe(&config(url), "voice.ogg", "audio/ogg", b"OggS".to_vec())
+            .await
+            .unwrap_err();
+        let err = err.to_string();
+        assert!(err.contains("500"));
+        assert!(err.contains("model not loaded"));
+    }
+}
@@ -1,6 +1,9 @@
 use serde::Deserialize;
 use std::path::PathBuf;
 
+use crate::llm::params::GenerationParams;
+use crate::llm::tiers::Tier;
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
     pub server: ServerConfig,
@@ -10,6 +13,9 @@ pub struct Config {
     /// MUC rooms to join on connect (XEP-0045)
     #[serde(default)]
     pub rooms: Vec<RoomConfig>,
+    /// Per-user LLM overrides (`[[users]]`), applied to direct chats.
+    #[serde(default)]
+    pub users: Vec<UserConfig>,
     /// Skill configuration. If absent, no skills are registered.
     #[serde(default)]
     pub skills: SkillsConfig,
@@ -21,6 +27,24 @@ pub struct Config {
     /// When enabled, idle sessions are automatically archived on next message.
     #[serde(default)]
     pub session: SessionConfig,
+    /// Attachment processing (image downscaling and re-encoding).
+    #[serde(default)]
+    pub attachments: AttachmentsConfig,
+    /// Outbound file sharing (XEP-0363 HTTP File Upload).
+    #[serde(default)]
+    pub upload: UploadConfig,
+    /// Server archive queries (XEP-0313).
+    #[serde(default)]
+    pub mam: MamConfig,
+    /// Message Carbons (XEP-0280) and operator takeover, client mode only.
+    #[serde(default)]
+    pub carbons: CarbonsConfig,
+    /// Acknowledgement reactions (XEP-0444) sent by the runtime.
+    #[serde(default)]
+    pub reactions: ReactionsConfig,
+    /// Formatting of the answers sent.
+    #[serde(default)]
+    pub output: OutputConfig,
 }
 
 /// Configuration for a MUC room (XEP-0045)
@@ -31,12 +55,105 @@ pub struct RoomConfig {
     /// Bot's nickname in the room
     #[serde(default = "default_room_nick")]
     pub nick: String,
+    /// Model tier used for every request in this room (e.g. `"fast"`).
+    /// If absent, the tier router decides per message.
+    #[serde(default)]
+    pub tier: Option<Tier>,
+    /// Download files shared in the room (XEP-0066 OOB) when the agent is
+    /// mentioned, and send them to the LLM as in 1:1 chats. Default: false
+    /// (only the filenames are recorded in the room history).
+    #[serde(default)]
+    pub download_attachments: bool,
+    /// Number of earlier room attachments (posted before the mention) to
+    /// include along with the mentioning message. Default: 0.
+    #[serde(default)]
+    pub recent_attachments: usize,
+    /// Largest file downloaded from this room, in MB. Default: 10
+    /// (files are never larger than the global 25 MB limit).
+    #[serde(default = "default_room_max_attachment_mb")]
+    pub max_attachment_mb: u64,
+    /// Discussion history requested when joining the room. Default:
+    /// none (the agent keeps its own history).
+    #[serde(default)]
+    pub join_history: JoinHistory,
+    /// Which room messages the agent answers. Default: mentions only.
+    #[serde(default)]
+    pub activation: Activation,
+    /// Words that activate the agent with `activation = "keywords"`
+    /// (whole words, case-insensitive), in addition to mentions.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// Answer mentions found in replayed history (delayed messages).
+    /// Default: false (they are only recorded).
+    #[serde(default)]
+    pub answer_delayed: bool,
+    /// Convert answers to Message Styling (XEP-0393) in this room.
+    /// Default: `[output] styling`.
+    #[serde(default)]
+    pub styling: Option<bool>,
+    /// Model and generation overrides for this room (`model`,
+    /// `temperature`, `num_ctx`, ...), on top of `[llm]`.
+    #[serde(flatten)]
+    pub generation: GenerationParams,
+}
+
+/// Which messages of a room the agent answers
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Activation {
+    /// Messages mentioning the agent, by nick or reference (XEP-0372)
+    #[default]
+    Mention,
+    /// Every message
+    All,
+    /// Mentions and messages containing one of the room's `keywords`
+    Keywords,
+}
+
+/// History replayed by a room on join (XEP-0045)
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JoinHistory {
+    /// No history
+    #[default]
+    None,
+    /// Messages sent after the last one stored locally (none for a room
+    /// without local history)
+    Since,
+    /// The room's default amount of history
+    All,
+}
+
+/// Per-user LLM overrides for direct chats.
+#[derive(Debug, Deserialize, Clone)]
+pub struct UserConfig {
+    /// Bare JID of the user, e.g. "alice@localhost"
+    pub jid: String,
+    /// Convert answers to Message Styling (XEP-0393) for this user.
+    /// Default: `[output] styling`.
+    #[serde(default)]
+    pub styling: Option<bool>,
+    /// Model and generation overrides (`model`, `temperature`, ...),
+    /// on top of `[llm]`.
+    #[serde(flatten)]
+    pub generation: GenerationParams,
 }
 
 fn default_room_nick() -> String {
     "fluux-agent".to_string()
 }
 
+fn default_room_max_attachment_mb() -> u64 {
+    10
+}
+
+impl RoomConfig {
+    /// Size limit for files downloaded from this room, in bytes
+    pub fn max_attachment_bytes(&self) -> u64 {
+        self.max_attachment_mb.saturating_mul(1024 * 1024)
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct ServerConfig {
     pub host: String,
@@ -89,8 +206,210 @@ pub struct LlmConfig {
     /// Ignored by Anthropic.
     #[serde(default)]
     pub host: Option<String>,
+    /// The model accepts images (Ollama vision models such as llava,
+    /// qwen2.5vl or gemma3). Anthropic models always do. Default: false,
+    /// images are replaced with a placeholder for Ollama.
+    #[serde(default)]
+    pub vision: bool,
+    /// Extended thinking budget in tokens (Anthropic only, minimum 1024).
+    /// When set, thinking-capable models reason before answering; the budget
+    /// is added on top of `max_tokens_per_request`. Default: disabled.
+    #[serde(default)]
+    pub thinking_budget_tokens: Option<u32>,
+    /// Keep a short summary of the model's thinking (in memory only, never
+    /// in user history) for the `/reasoning` debug command. Default: false.
+    #[serde(default)]
+    pub reasoning_summary: bool,
+    /// Default generation parameters (`temperature`, `top_p`, `top_k`,
+    /// `stop_sequences`, `num_ctx`, `keep_alive`), set directly in `[llm]`.
+    /// Rooms and users can override them.
+    #[serde(flatten)]
+    pub generation: GenerationParams,
+    /// Cassette file (JSONL): read by `provider = "replay"`, written when
+    /// `record` is enabled.
+    #[serde(default)]
+    pub cassette: Option<PathBuf>,
+    /// Append every request/response of the provider to `cassette`, for
+    /// later offline replay. Default: false.
+    #[serde(default)]
+    pub record: bool,
+    /// Script file (TOML or JSONL) for `provider = "scripted"`.
+    #[serde(default)]
+    pub script: Option<PathBuf>,
+    /// Optional model tiers (`[llm.tiers.fast]`, `[llm.tiers.deep]`, ...).
+    /// If none are configured, every request uses the model above.
+    #[serde(default)]
+    pub tiers: LlmTiersConfig,
+    /// Tier routing settings (`[llm.routing]`).
+    /// Only used when at least one tier is configured.
+    #[serde(default)]
+    pub routing: RoutingConfig,
+}
+
+/// Named model tiers.
+///
+/// Each tier is a partial LLM configuration: fields that are not set
+/// are inherited from the top-level `[llm]` section. The `standard` tier
+/// defaults to the top-level model itself.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct LlmTiersConfig {
+    /// Cheap, low-latency model for short or simple requests.
+    pub fast: Option<LlmTierConfig>,
+    /// General-purpose model. Defaults to the top-level `[llm]` model.
+    pub standard: Option<LlmTierConfig>,
+    /// Strongest model for long or complex requests.
+    pub deep: Option<LlmTierConfig>,
+    /// Multi-modal model used when the message carries attachments.
+    pub vision: Option<LlmTierConfig>,
+}
+
+/// Configuration for a single model tier.
+#[derive(Debug, Deserialize, Clone)]
+pub struct LlmTierConfig {
+    /// LLM provider (`"anthropic"` or `"ollama"`). Inherited if absent.
+    pub provider: Option<String>,
+    /// Model name for this tier.
+    pub model: String,
+    /// Max output tokens. Inherited if absent.
+    pub max_tokens_per_request: Option<u32>,
+    /// API key. Supports `${ENV_VAR}` substitution. Inherited if absent.
+    pub api_key: Option<String>,
+    /// Base URL (Ollama). Inherited if absent.
+    pub host: Option<String>,
+    /// Extended thinking budget (Anthropic). Inherited if absent.
+    pub thinking_budget_tokens: Option<u32>,
+    /// Vision-capable model (Ollama). Defaults to true for the `vision`
+    /// tier, inherited otherwise.
+    pub vision: Option<bool>,
 }
 
+/// Tier routing configuration.
+///
+/// The router picks a tier from cheap signals, in order: explicit slash
+/// trigger (`/deep ...`), attachments (vision), the conversation's pinned
+/// tier (`/tier`), the room's `tier`, message length, and finally an
+/// optional classifier call to the fast model.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RoutingConfig {
+    /// Messages up to this many characters go to the fast tier. Default: 80.
+    /// Set to 0 to disable.
+    #[serde(default = "default_fast_max_chars")]
+    pub fast_max_chars: usize,
+    /// Messages of at least this many characters go to the deep tier.
+    /// Default: 2000. Set to 0 to disable.
+    #[serde(default = "default_deep_min_chars")]
+    pub deep_min_chars: usize,
+    /// Ask the fast model to classify messages that no other signal
+    /// decided. Costs one extra (cheap) call per message. Default: false.
+    #[serde(default)]
+    pub classifier: bool,
+    /// Let the fast model hand the request over to the next tier when it
+    /// is not confident in its answer. Default: true.
+    #[serde(default = "default_escalation")]
+    pub escalation: bool,
+}
+
+fn default_fast_max_chars() -> usize {
+    80
+}
+
+fn default_deep_min_chars() -> usize {
+    2000
+}
+
+fn default_escalation() -> bool {
+    true
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        Self {
+            fast_max_chars: default_fast_max_chars(),
+            deep_min_chars: default_deep_min_chars(),
+            classifier: false,
+            escalation: default_escalation(),
+        }
+    }
+}
+
+impl LlmTiersConfig {
+    /// Returns the configuration for a tier, if that tier is configured.
+    pub fn get(&self, tier: Tier) -> Option<&LlmTierConfig> {
+        match tier {
+            Tier::Fast => self.fast.as_ref(),
+            Tier::Standard => self.standard.as_ref(),
+            Tier::Deep => self.deep.as_ref(),
+            Tier::Vision => self.vision.as_ref(),
+        }
+    }
+
+    /// Whether no tier is configured at all (routing disabled).
+    pub fn is_empty(&self) -> bool {
+        Tier::ALL.iter().all(|t| self.get(*t).is_none())
+    }
+}
+
+impl LlmConfig {
+    /// Resolves the full LLM configuration for a tier.
+    ///
+    /// Unset tier fields are inherited from `self`. Returns `None` if the
+    /// tier is not configured. The returned config has no tiers of its own.
+    /// Tiers inheriting a `script` or `cassette` play it with a single
+    /// client (see [`LlmRegistry::from_config`](crate::llm::LlmRegistry::from_config)).
+    pub fn for_tier(&self, tier: Tier) -> Option<LlmConfig> {
+        let tc = self.tiers.get(tier)?;
+        Some(LlmConfig {
+            provider: tc.provider.clone().unwrap_or_else(|| self.provider.clone()),
+            model: tc.model.clone(),
+            api_key: tc.api_key.clone().unwrap_or_else(|| self.api_key.clone()),
+            max_tokens_per_request: tc
+                .max_tokens_per_request
+                .unwrap_or(self.max_tokens_per_request),
+            host: tc.host.clone().or_else(|| self.host.clone()),
+            vision: tc.vision.unwrap_or(tier == Tier::Vision || self.vision),
+            thinking_budget_tokens: tc.thinking_budget_tokens.or(self.thinking_budget_tokens),
+            reasoning_summary: self.reasoning_summary,
+            generation: self.generation.clone(),
+            cassette: self.cassette.clone(),
+            record: self.record,
+            script: self.script.clone(),
+            tiers: LlmTiersConfig::default(),
+            routing: self.routing.clone(),
+        })
+    }
+
+    /// Rejects thinking budgets the Anthropic API would refuse, for the
+    /// top-level model and every tier: at least 1024 tokens, and below the
+    /// request's `max_tokens` (the budget plus `max_tokens_per_request`).
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let tiers = Tier::ALL.iter().filter_map(|tier| Some((Some(*tier), self.for_tier(*tier)?)));
+        for (tier, config) in std::iter::once((None, self.clone())).chain(tiers) {
+            let Some(budget) = config.thinking_budget_tokens else {
+                continue;
+            };
+            let section = match tier {
+                Some(tier) => format!("[llm.tiers.{tier}]"),
+                None => "[llm]".to_string(),
+            };
+            if budget < MIN_THINKING_BUDGET_TOKENS {
+                anyhow::bail!(
+                    "{section} thinking_budget_tokens must be at least {MIN_THINKING_BUDGET_TOKENS}, got {budget}"
+                );
+            }
+            if budget >= config.max_tokens_per_request.saturating_add(budget) {
+                anyhow::bail!(
+                    "{section} thinking_budget_tokens ({budget}) must be below max_tokens: \
+                     max_tokens_per_request must leave room for the answer"
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Smallest extended thinking budget the Anthropic API accepts.
+const MIN_THINKING_BUDGET_TOKENS: u32 = 1024;
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct AgentConfig {
     pub name: String,
@@ -101,6 +420,10 @@ pub struct AgentConfig {
     /// Set to ["*"] to allow all domains (federation — use with caution).
     #[serde(default)]
     pub allowed_domains: Vec<String>,
+    /// JIDs allowed to run admin ad-hoc commands (XEP-0050) on other
+    /// users' conversations. They must also pass the checks above.
+    #[serde(default)]
+    pub admin_jids: Vec<String>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -123,6 +446,14 @@ pub struct SkillsConfig {
     pub memory: Option<MemorySkillConfig>,
     /// URL fetch skill configuration.
     pub url_fetch: Option<UrlFetchConfig>,
+    /// File read (stored attachments) skill configuration.
+    pub file_read: Option<FileReadConfig>,
+    /// File sending (HTTP upload) skill configuration.
+    pub send_file: Option<SendFileConfig>,
+    /// Emoji reaction skill configuration.
+    pub react: Option<ReactConfig>,
+    /// Sub-agent (`spawn_agent`) tool configuration.
+    pub spawn_agent: Option<SpawnAgentConfig>,
 }
 
 /// Configuration for the `memory_store` and `memory_recall` builtin skills.
@@ -147,6 +478,72 @@ pub struct UrlFetchConfig {
     pub enabled: bool,
 }
 
+/// Configuration for the `file_read` builtin skill.
+///
+/// Lets the LLM list and re-read files the conversation partner sent
+/// earlier. No API keys needed — uses the JID's `files/` directory only.
+#[derive(Debug, Deserialize, Clone)]
+pub struct FileReadConfig {
+    /// Enable the file_read skill. Must be `true` to register it.
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// Configuration for the `send_file` builtin skill.
+///
+/// Lets the LLM send a file (generated text, or a stored file) to the
+/// conversation partner. Files are uploaded with XEP-0363 (see `[upload]`).
+#[derive(Debug, Deserialize, Clone)]
+pub struct SendFileConfig {
+    /// Enable the send_file skill. Must be `true` to register it.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Largest file the skill accepts, in MB. Default: 10. The upload
+    /// service may enforce a lower limit.
+    #[serde(default = "default_send_file_max_mb")]
+    pub max_size_mb: u64,
+}
+
+fn default_send_file_max_mb() -> u64 {
+    10
+}
+
+impl SendFileConfig {
+    /// Largest accepted file, in bytes.
+    pub fn max_size_bytes(&self) -> u64 {
+        self.max_size_mb.saturating_mul(1024 * 1024)
+    }
+}
+
+/// Configuration for the `react` builtin skill.
+///
+/// Lets the LLM react to a message with emojis (XEP-0444).
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReactConfig {
+    /// Enable the react skill. Must be `true` to register it.
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// Configuration for the runtime-provided `spawn_agent` tool.
+///
+/// Lets the LLM delegate a self-contained task to a one-level-deep
+/// sub-agent with its own scratch history and a subset of the skills.
+/// Only the sub-agent's final answer is returned to the main loop.
+#[derive(Debug, Deserialize, Clone)]
+pub struct SpawnAgentConfig {
+    /// Enable the spawn_agent tool. Must be `true` to offer it.
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+impl SkillsConfig {
+    /// Whether the `spawn_agent` tool is enabled.
+    pub fn spawn_agent_enabled(&self) -> bool {
+        self.spawn_agent.as_ref().is_some_and(|c| c.enabled)
+    }
+}
+
 /// Keepalive configuration for detecting dead XMPP connections.
 ///
 /// When enabled, the agent periodically sends whitespace pings (RFC 6120 §4.6.1)
@@ -201,12 +598,302 @@ pub struct SessionConfig {
     /// Default: 0 (disabled).
     #[serde(default)]
     pub idle_timeout_mins: u64,
+    /// When a user corrects (XEP-0308) the message the agent just
+    /// answered, regenerate the answer and send it as a correction of
+    /// the previous reply. Default: false (only the history is updated).
+    #[serde(default)]
+    pub regenerate_on_correction: bool,
 }
 
 impl Default for SessionConfig {
     fn default() -> Self {
         Self {
             idle_timeout_mins: 0,
+            regenerate_on_correction: false,
+        }
+    }
+}
+
+/// Attachment processing configuration.
+///
+/// Images are decoded, stripped of metadata (EXIF, GPS), downscaled to
+/// `image_max_edge` and re-encoded before being sent to the LLM. The
+/// downloaded original is kept on disk unchanged.
+#[derive(Debug, Deserialize, Clone)]
+pub struct AttachmentsConfig {
+    /// Preprocess images before sending them. Default: true.
+    #[serde(default = "default_preprocess_images")]
+    pub preprocess_images: bool,
+    /// Longest image side, in pixels, after downscaling. Default: 1568
+    /// (Anthropic's recommended maximum; larger images are resized by the
+    /// API anyway).
+    #[serde(default = "default_image_max_edge")]
+    pub image_max_edge: u32,
+    /// JPEG quality (1–100) for re-encoded images. Default: 85.
+    #[serde(default = "default_image_quality")]
+    pub image_quality: u8,
+    /// Voice message transcription (`[attachments.transcription]`).
+    /// Audio attachments are not transcribed when absent.
+    #[serde(default)]
+    pub transcription: Option<TranscriptionConfig>,
+}
+
+fn default_preprocess_images() -> bool {
+    true
+}
+
+fn default_image_max_edge() -> u32 {
+    1568
+}
+
+fn default_image_quality() -> u8 {
+    85
+}
+
+impl Default for AttachmentsConfig {
+    fn default() -> Self {
+        Self {
+            preprocess_images: default_preprocess_images(),
+            image_max_edge: default_image_max_edge(),
+            image_quality: default_image_quality(),
+            transcription: None,
+        }
+    }
+}
+
+/// Speech-to-text endpoint for audio attachments (voice messages).
+///
+/// Any OpenAI-compatible `/v1/audio/transcriptions` endpoint works:
+/// whisper.cpp server, faster-whisper-server, LocalAI, or OpenAI itself.
+#[derive(Debug, Deserialize, Clone)]
+pub struct TranscriptionConfig {
+    /// Full endpoint URL, e.g. `http://localhost:8080/v1/audio/transcriptions`.
+    pub url: String,
+    /// Model name sent with the request. Default: "whisper-1".
+    #[serde(default = "default_transcription_model")]
+    pub model: String,
+    /// Bearer token, for endpoints that require one.
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// Spoken language hint (ISO-639-1, e.g. "fr"). Auto-detected when absent.
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Request timeout in seconds. Default: 120.
+    #[serde(default = "default_transcription_timeout")]
+    pub timeout_secs: u64,
+}
+
+fn default_transcription_model() -> String {
+    "whisper-1".to_string()
+}
+
+fn default_transcription_timeout() -> u64 {
+    120
+}
+
+/// Outbound file sharing via HTTP File Upload (XEP-0363).
+///
+/// Files returned by skills (e.g. `send_file`) are uploaded to the
+/// server's upload service and shared as OOB links (XEP-0066).
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct UploadConfig {
+    /// JID of the upload service (e.g. `"upload.localhost"`). If absent,
+    /// the service is discovered on the server (disco#items).
+    #[serde(default)]
+    pub service: Option<String>,
+}
+
+/// Message Archive Management (XEP-0313): the server archive as a source
+/// of history.
+#[derive(Debug, Deserialize, Clone)]
+pub struct MamConfig {
+    /// Query archives on connect and on first contact. Default: false.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Messages imported into a conversation that has no local history
+    /// (new room, lost memory directory). 0 disables the bootstrap.
+    /// Default: 20.
+    #[serde(default = "default_mam_bootstrap_messages")]
+    pub bootstrap_messages: usize,
+    /// What to do with messages received while the agent was offline.
+    /// Default: ignore (they are only added to the history).
+    #[serde(default)]
+    pub catch_up: CatchUpPolicy,
+    /// Maximum number of missed messages fetched per conversation.
+    /// Default: 50.
+    #[serde(default = "default_mam_max_catch_up")]
+    pub max_catch_up: usize,
+    /// Messages requested per archive query page (XEP-0059). Default: 50.
+    #[serde(default = "default_mam_page_size")]
+    pub page_size: usize,
+    /// Which history wins on connect. Default: local.
+    #[serde(default)]
+    pub history: HistorySource,
+}
+
+/// Handling of messages received while the agent was offline
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CatchUpPolicy {
+    /// Reply to the missed messages as if they had just arrived
+    Answer,
+    /// Send one reply summarizing what was missed
+    Summarize,
+    /// Only record them in the history
+    #[default]
+    Ignore,
+}
+
+/// Authoritative source of conversation history
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HistorySource {
+    /// `history.jsonl`; the archive only fills gaps
+    #[default]
+    Local,
+    /// The server archive: on connect, the active session is archived
+    /// locally and rebuilt from the latest archived messages
+    Archive,
+}
+
+fn default_mam_bootstrap_messages() -> usize {
+    20
+}
+
+fn default_mam_max_catch_up() -> usize {
+    50
+}
+
+fn default_mam_page_size() -> usize {
+    50
+}
+
+impl Default for MamConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bootstrap_messages: default_mam_bootstrap_messages(),
+            catch_up: CatchUpPolicy::default(),
+            max_catch_up: default_mam_max_catch_up(),
+            page_size: default_mam_page_size(),
+            history: HistorySource::default(),
+        }
+    }
+}
+
+/// Message Carbons (XEP-0280): copies of the messages the bot account
+/// sends and receives from other clients, e.g. a human operator.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CarbonsConfig {
+    /// Enable carbons after connecting (client mode). Default: true.
+    #[serde(default = "default_carbons_enabled")]
+    pub enabled: bool,
+    /// After an operator replies to a contact from another client, the
+    /// agent stays quiet in that conversation for this many minutes
+    /// (messages are still recorded). 0 disables takeover. Default: 0.
+    #[serde(default)]
+    pub takeover_mins: u64,
+}
+
+fn default_carbons_enabled() -> bool {
+    true
+}
+
+impl Default for CarbonsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_carbons_enabled(),
+            takeover_mins: 0,
+        }
+    }
+}
+
+/// Acknowledgement reactions (XEP-0444): the agent reacts to the message
+/// it is working on, and replaces the reaction when the answer is sent.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReactionsConfig {
+    /// React to messages the agent answers with the LLM. Default: false.
+    #[serde(default)]
+    pub acknowledge: bool,
+    /// Reaction while the answer is generated. Default: 👀.
+    #[serde(default = "default_reaction_working")]
+    pub working: String,
+    /// Reaction once the answer is sent; empty to only remove `working`.
+    /// Default: ✅.
+    #[serde(default = "default_reaction_done")]
+    pub done: String,
+}
+
+fn default_reaction_working() -> String {
+    "👀".to_string()
+}
+
+fn default_reaction_done() -> String {
+    "✅".to_string()
+}
+
+impl Default for ReactionsConfig {
+    fn default() -> Self {
+        Self {
+            acknowledge: false,
+            working: default_reaction_working(),
+            done: default_reaction_done(),
+        }
+    }
+}
+
+/// Formatting and delivery of the answers sent to users and rooms.
+#[derive(Debug, Deserialize, Clone)]
+pub struct OutputConfig {
+    /// Convert the model's Markdown to Message Styling (XEP-0393):
+    /// `*bold*`, `_italic_`, code blocks and quotes, with headings and
+    /// tables as plain text. Can be set per room and per user. Default: true.
+    #[serde(default = "default_output_styling")]
+    pub styling: bool,
+    /// Largest message body sent, in bytes. Longer answers are split into
+    /// several messages between paragraphs and code blocks, well below
+    /// the servers' stanza size limits. 0 disables splitting; other values
+    /// must be at least 1000. Default: 10000.
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: usize,
+    /// Answers longer than this (in bytes) are uploaded as a Markdown file
+    /// (XEP-0363) and only their beginning is sent as a message. Falls
+    /// back to splitting when the upload fails. Default: none.
+    #[serde(default)]
+    pub upload_above_bytes: Option<usize>,
+}
+
+fn default_output_styling() -> bool {
+    true
+}
+
+fn default_max_body_bytes() -> usize {
+    10_000
+}
+
+/// Smallest non-zero `max_body_bytes`: below it, answers would be cut into
+/// a flood of tiny messages.
+const MIN_BODY_BYTES: usize = 1000;
+
+impl OutputConfig {
+    /// Rejects settings the answer delivery cannot work with.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.max_body_bytes != 0 && self.max_body_bytes < MIN_BODY_BYTES {
+            anyhow::bail!(
+                "output.max_body_bytes must be 0 (no splitting) or at least {MIN_BODY_BYTES}, got {}",
+                self.max_body_bytes
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            styling: default_output_styling(),
+            max_body_bytes: default_max_body_bytes(),
+            upload_above_bytes: None,
         }
     }
 }
@@ -281,6 +968,23 @@ impl ServerConfig {
             }
         }
     }
+
+    /// Returns the domain of the XMPP server the agent is connected to,
+    /// used for service discovery.
+    ///
+    /// - Component mode: the parent of the component domain
+    ///   (e.g. "localhost" from "agent.localhost")
+    /// - Client mode: same as [`domain`](Self::domain)
+    pub fn server_domain(&self) -> &str {
+        let domain = self.domain();
+        match &self.mode {
+            ConnectionMode::Component { .. } => match domain.split_once('.') {
+                Some((_, parent)) if parent.contains('.') || parent == "localhost" => parent,
+                _ => domain,
+            },
+            ConnectionMode::Client { .. } => domain,
+        }
+    }
 }
 
 impl Config {
@@ -289,6 +993,8 @@ impl Config {
         // Expand environment variables like ${ANTHROPIC_API_KEY}
         let expanded = shellexpand::env(&content)?;
         let config: Config = toml::from_str(&expanded)?;
+        config.llm.validate()?;
+        config.output.validate()?;
         Ok(config)
     }
 
@@ -297,6 +1003,33 @@ impl Config {
         self.rooms.iter().find(|r| r.jid == room_jid)
     }
 
+    /// Returns the per-user overrides for a bare JID, if configured
+    pub fn find_user(&self, jid: &str) -> Option<&UserConfig> {
+        self.users.iter().find(|u| u.jid == jid)
+    }
+
+    /// Returns the generation overrides for a conversation: the room's
+    /// entry for a MUC room, the user's `[[users]]` entry otherwise.
+    pub fn generation_overrides(&self, jid: &str) -> GenerationParams {
+        match self.find_room(jid) {
+            Some(room) => room.generation.clone(),
+            None => self
+                .find_user(jid)
+                .map(|u| u.generation.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Whether answers in a conversation (room or bare JID) are sent with
+    /// Message Styling: the room's or user's `styling`, else `[output]`.
+    pub fn message_styling(&self, jid: &str) -> bool {
+        let configured = match self.find_room(jid) {
+            Some(room) => room.styling,
+            None => self.find_user(jid).and_then(|u| u.styling),
+        };
+        configured.unwrap_or(self.output.styling)
+    }
+
     /// Checks if a JID is allowed to talk to the agent
     pub fn is_allowed(&self, jid: &str) -> bool {
         let bare = crate::xmpp::stanzas::bare_jid(jid);
@@ -305,6 +1038,13 @@ impl Config {
         })
     }
 
+    /// Checks if a JID may run admin commands. No wildcard: admins are
+    /// always listed explicitly.
+    pub fn is_admin(&self, jid: &str) -> bool {
+        let bare = crate::xmpp::stanzas::bare_jid(jid);
+        self.agent.admin_jids.iter().any(|admin| admin == bare)
+    }
+
     /// Checks if a JID's domain is allowed.
     ///
     /// If `allowed_domains` is empty (the default), only the agent's own domain
@@ -351,20 +1091,37 @@ mod tests {
                 api_key: "test-key".to_string(),
                 max_tokens_per_request: 4096,
                 host: None,
+                vision: false,
+                thinking_budget_tokens: None,
+                reasoning_summary: false,
+                generation: Default::default(),
+                cassette: None,
+                record: false,
+                script: None,
+                tiers: LlmTiersConfig::default(),
+                routing: RoutingConfig::default(),
             },
             agent: AgentConfig {
                 name: "Test Agent".to_string(),
                 allowed_jids: jids.into_iter().map(String::from).collect(),
                 allowed_domains: vec![],
+                admin_jids: vec![],
             },
             memory: MemoryConfig {
                 backend: "markdown".to_string(),
                 path: PathBuf::from("./data/memory"),
             },
             rooms: vec![],
+            users: vec![],
             skills: SkillsConfig::default(),
             keepalive: KeepaliveConfig::default(),
             session: SessionConfig::default(),
+            attachments: AttachmentsConfig::default(),
+            upload: UploadConfig::default(),
+            mam: MamConfig::default(),
+            carbons: CarbonsConfig::default(),
+            reactions: ReactionsConfig::default(),
+            output: OutputConfig::default(),
         }
     }
 
@@ -397,6 +1154,18 @@ mod tests {
         assert!(config.is_allowed("user@domain.org/res"));
     }
 
+    #[test]
+    fn test_is_admin() {
+        let mut config = config_with_jids(vec!["*"]);
+        assert!(!config.is_admin("admin@localhost"));
+        config.agent.admin_jids = vec!["admin@localhost".to_string()];
+        assert!(config.is_admin("admin@localhost/res"));
+        assert!(!config.is_admin("user@localhost"));
+        // The wildcard of allowed_jids has no meaning here
+        config.agent.admin_jids = vec!["*".to_string()];
+        assert!(!config.is_admin("user@localhost"));
+    }
+
     #[test]
     fn test_is_allowed_multiple_jids() {
         let config = config_with_jids(vec!["alice@localhost", "bob@localhost"]);
@@ -454,10 +1223,30 @@ mod tests {
             RoomConfig {
                 jid: "lobby@conference.localhost".to_string(),
                 nick: "bot".to_string(),
+                tier: None,
+                download_attachments: false,
+                recent_attachments: 0,
+                max_attachment_mb: 10,
+                join_history: Default::default(),
+                answer_delayed: false,
+                activation: Activation::Mention,
+                keywords: vec![],
+                styling: None,
+                generation: Default::default(),
             },
             RoomConfig {
                 jid: "dev@conference.localhost".to_string(),
                 nick: "fluux-agent".to_string(),
+                tier: None,
+                download_attachments: false,
+                recent_attachments: 0,
+                max_attachment_mb: 10,
+                join_history: Default::default(),
+                answer_delayed: false,
+                activation: Activation::Mention,
+                keywords: vec![],
+                styling: None,
+                generation: Default::default(),
             },
         ];
         let room = config.find_room("dev@conference.localhost").unwrap();
@@ -492,6 +1281,28 @@ mod tests {
         assert_eq!(server.domain(), "agent.example.com");
     }
 
+    #[test]
+    fn test_server_domain() {
+        let config = config_with_jids(vec![]);
+        assert_eq!(config.server.server_domain(), "localhost");
+
+        for (component_domain, expected) in [
+            ("agent.example.com", "example.com"),
+            ("agent.localhost", "localhost"),
+            ("agent.com", "agent.com"),
+        ] {
+            let server = ServerConfig {
+                host: "localhost".to_string(),
+                port: 5275,
+                mode: ConnectionMode::Component {
+                    component_domain: component_domain.to_string(),
+                    component_secret: "secret".to_string(),
+                },
+            };
+            assert_eq!(server.server_domain(), expected);
+        }
+    }
+
     // ── is_domain_allowed() tests ───────────────────────
 
     #[test]
@@ -604,6 +1415,7 @@ mod tests {
     fn test_session_defaults() {
         let sc = SessionConfig::default();
         assert_eq!(sc.idle_timeout_mins, 0);
+        assert!(!sc.regenerate_on_correction);
     }
 
     #[test]
@@ -616,8 +1428,346 @@ mod tests {
     fn test_session_custom_timeout_toml() {
         let toml = r#"
             idle_timeout_mins = 120
+            regenerate_on_correction = true
         "#;
         let sc: SessionConfig = toml::from_str(toml).unwrap();
         assert_eq!(sc.idle_timeout_mins, 120);
+        assert!(sc.regenerate_on_correction);
+    }
+
+    // ── AttachmentsConfig tests ─────────────────────────
+
+    #[test]
+    fn test_attachments_defaults() {
+        let ac = AttachmentsConfig::default();
+        assert!(ac.preprocess_images);
+        assert_eq!(ac.image_max_edge, 1568);
+        assert_eq!(ac.image_quality, 85);
+    }
+
+    #[test]
+    fn test_attachments_custom_toml() {
+        let toml = r#"
+            image_max_edge = 1024
+        "#;
+        let ac: AttachmentsConfig = toml::from_str(toml).unwrap();
+        assert!(ac.preprocess_images);
+        assert_eq!(ac.image_max_edge, 1024);
+        assert_eq!(ac.image_quality, 85);
+        assert!(ac.transcription.is_none());
+    }
+
+    #[test]
+    fn test_attachments_transcription_toml() {
+        let toml = r#"
+            [transcription]
+            url = "http://localhost:8080/v1/audio/transcriptions"
+            language = "fr"
+        "#;
+        let ac: AttachmentsConfig = toml::from_str(toml).unwrap();
+        let tc = ac.transcription.unwrap();
+        assert_eq!(tc.url, "http://localhost:8080/v1/audio/transcriptions");
+        assert_eq!(tc.model, "whisper-1");
+        assert_eq!(tc.api_key, None);
+        assert_eq!(tc.language.as_deref(), Some("fr"));
+        assert_eq!(tc.timeout_secs, 120);
+    }
+
+    #[test]
+    fn test_thinking_budget_validation() {
+        let llm = |toml: &str| -> LlmConfig {
+            toml::from_str(&format!("provider = \"anthropic\"\nmodel = \"m\"\n{toml}")).unwrap()
+        };
+        assert!(llm("").validate().is_ok());
+        assert!(llm("thinking_budget_tokens = 8000").validate().is_ok());
+
+        let err = llm("thinking_budget_tokens = 500").validate().unwrap_err();
+        assert!(err.to_string().contains("at least 1024"));
+        let err = llm("max_tokens_per_request = 0\nthinking_budget_tokens = 2048")
+            .validate()
+            .unwrap_err();
+        assert!(err.to_string().contains("below max_tokens"));
+
+        // Tiers inherit the budget or set their own
+        let err = llm("[tiers.deep]\nmodel = \"big\"\nthinking_budget_tokens = 100")
+            .validate()
+            .unwrap_err();
+        assert!(err.to_string().contains("[llm.tiers.deep]"));
+    }
+
+    // ── Tier config tests ───────────────────────────────
+
+    #[test]
+    fn test_tiers_absent_is_empty() {
+        let config = config_with_jids(vec![]);
+        assert!(config.llm.tiers.is_empty());
+        assert!(config.llm.for_tier(Tier::Fast).is_none());
+    }
+
+    #[test]
+    fn test_tiers_toml_inherits_parent_fields() {
+        let toml = r#"
+            provider = "anthropic"
+            model = "claude-sonnet-4-5-20250929"
+            api_key = "sk-test"
+
+            [tiers.fast]
+            provider = "ollama"
+            model = "llama3.2"
+            host = "http://gpu:11434"
+
+            [tiers.deep]
+            model = "claude-opus-4-1"
+            max_tokens_per_request = 16000
+        "#;
+        let llm: LlmConfig = toml::from_str(toml).unwrap();
+        assert!(!llm.tiers.is_empty());
+
+        let fast = llm.for_tier(Tier::Fast).unwrap();
+        assert_eq!(fast.provider, "ollama");
+        assert_eq!(fast.model, "llama3.2");
+        assert_eq!(fast.host.as_deref(), Some("http://gpu:11434"));
+        assert_eq!(fast.max_tokens_per_request, 4096);
+
+        let deep = llm.for_tier(Tier::Deep).unwrap();
+        assert_eq!(deep.provider, "anthropic");
+        assert_eq!(deep.api_key, "sk-test");
+        assert_eq!(deep.max_tokens_per_request, 16000);
+        assert!(deep.tiers.is_empty());
+
+        assert!(llm.for_tier(Tier::Vision).is_none());
+    }
+
+    #[test]
+    fn test_tier_vision_flag() {
+        let toml = r#"
+            provider = "ollama"
+            model = "llama3.2"
+
+            [tiers.vision]
+            model = "llava"
+
+            [tiers.fast]
+            model = "gemma3"
+            vision = true
+
+            [tiers.deep]
+            model = "qwen3:32b"
+        "#;
+        let llm: LlmConfig = toml::from_str(toml).unwrap();
+        assert!(!llm.vision);
+        assert!(llm.for_tier(Tier::Vision).unwrap().vision);
+        assert!(llm.for_tier(Tier::Fast).unwrap().vision);
+        assert!(!llm.for_tier(Tier::Deep).unwrap().vision);
+    }
+
+    #[test]
+    fn test_routing_defaults() {
+        let rc = RoutingConfig::default();
+        assert_eq!(rc.fast_max_chars, 80);
+        assert_eq!(rc.deep_min_chars, 2000);
+        assert!(!rc.classifier);
+        assert!(rc.escalation);
+    }
+
+    #[test]
+    fn test_room_tier_toml() {
+        let toml = r#"
+            jid = "support@conference.localhost"
+            tier = "fast"
+        "#;
+        let room: RoomConfig = toml::from_str(toml).unwrap();
+        assert_eq!(room.tier, Some(Tier::Fast));
+        assert_eq!(room.nick, "fluux-agent");
+        assert!(!room.download_attachments);
+        assert_eq!(room.recent_attachments, 0);
+        assert_eq!(room.max_attachment_mb, 10);
+        assert_eq!(room.join_history, JoinHistory::None);
+        assert!(!room.answer_delayed);
+    }
+
+    #[test]
+    fn test_room_join_history_toml() {
+        let toml = r#"
+            jid = "ops@conference.localhost"
+            join_history = "since"
+            answer_delayed = true
+        "#;
+        let room: RoomConfig = toml::from_str(toml).unwrap();
+        assert_eq!(room.join_history, JoinHistory::Since);
+        assert!(room.answer_delayed);
+        assert!(toml::from_str::<RoomConfig>("jid = \"a@b\"\njoin_history = \"some\"").is_err());
+    }
+
+    #[test]
+    fn test_room_attachments_toml() {
+        let toml = r#"
+            jid = "design@conference.localhost"
+            download_attachments = true
+            recent_attachments = 3
+            max_attachment_mb = 5
+            temperature = 0.2
+        "#;
+        let room: RoomConfig = toml::from_str(toml).unwrap();
+        assert!(room.download_attachments);
+        assert_eq!(room.recent_attachments, 3);
+        assert_eq!(room.max_attachment_bytes(), 5 * 1024 * 1024);
+        assert_eq!(room.generation.temperature, Some(0.2));
+    }
+
+    // ── Generation parameter tests ──────────────────────
+
+    #[test]
+    fn test_generation_params_toml() {
+        let toml = r#"
+            provider = "ollama"
+            model = "llama3.2"
+            temperature = 0.7
+            top_k = 40
+            stop_sequences = ["END"]
+            num_ctx = 8192
+            keep_alive = "10m"
+
+            [tiers.deep]
+            model = "qwen3:32b"
+        "#;
+        let llm: LlmConfig = toml::from_str(toml).unwrap();
+        assert_eq!(llm.model, "llama3.2");
+        // `model` belongs to [llm] itself, never to the generation overrides
+        assert_eq!(llm.generation.model, None);
+        assert_eq!(llm.generation.temperature, Some(0.7));
+        assert_eq!(llm.generation.top_k, Some(40));
+        assert_eq!(llm.generation.stop_sequences, Some(vec!["END".to_string()]));
+        assert_eq!(llm.generation.num_ctx, Some(8192));
+        assert_eq!(llm.generation.keep_alive.as_deref(), Some("10m"));
+
+        // Tiers inherit the [llm] generation parameters
+        let deep = llm.for_tier(Tier::Deep).unwrap();
+        assert_eq!(deep.generation, llm.generation);
+    }
+
+    #[test]
+    fn test_mam_config() {
+        let mam = MamConfig::default();
+        assert!(!mam.enabled);
+        assert_eq!(mam.bootstrap_messages, 20);
+        assert_eq!(mam.catch_up, CatchUpPolicy::Ignore);
+        assert_eq!(mam.history, HistorySource::Local);
+
+        let toml = r#"
+            enabled = true
+            catch_up = "summarize"
+            history = "archive"
+            page_size = 25
+        "#;
+        let mam: MamConfig = toml::from_str(toml).unwrap();
+        assert!(mam.enabled);
+        assert_eq!(mam.catch_up, CatchUpPolicy::Summarize);
+        assert_eq!(mam.history, HistorySource::Archive);
+        assert_eq!(mam.page_size, 25);
+        assert_eq!(mam.max_catch_up, 50);
+        assert!(toml::from_str::<MamConfig>("catch_up = \"reply\"").is_err());
+    }
+
+    #[test]
+    fn test_reactions_config() {
+        let reactions = ReactionsConfig::default();
+        assert!(!reactions.acknowledge);
+        assert_eq!(reactions.working, "👀");
+        assert_eq!(reactions.done, "✅");
+
+        let reactions: ReactionsConfig =
+            toml::from_str("acknowledge = true\ndone = \"\"").unwrap();
+        assert!(reactions.acknowledge);
+        assert_eq!(reactions.working, "👀");
+        assert!(reactions.done.is_empty());
+    }
+
+    #[test]
+    fn test_carbons_config() {
+        let carbons = CarbonsConfig::default();
+        assert!(carbons.enabled);
+        assert_eq!(carbons.takeover_mins, 0);
+
+        let carbons: CarbonsConfig = toml::from_str("takeover_mins = 30").unwrap();
+        assert!(carbons.enabled);
+        assert_eq!(carbons.takeover_mins, 30);
+    }
+
+    #[test]
+    fn test_generation_overrides_room_and_user() {
+        let toml = r#"
+            jid = "lobby@conference.localhost"
+            model = "llama3.2"
+            temperature = 0.2
+        "#;
+        let room: RoomConfig = toml::from_str(toml).unwrap();
+        assert_eq!(room.generation.model.as_deref(), Some("llama3.2"));
+        assert_eq!(room.generation.temperature, Some(0.2));
+
+        let toml = r#"
+            jid = "alice@localhost"
+            num_ctx = 16384
+        "#;
+        let user: UserConfig = toml::from_str(toml).unwrap();
+
+        let mut config = config_with_jids(vec!["alice@localhost"]);
+        config.rooms = vec![room];
+        config.users = vec![user];
+
+        let room_params = config.generation_overrides("lobby@conference.localhost");
+        assert_eq!(room_params.temperature, Some(0.2));
+        assert_eq!(room_params.num_ctx, None);
+
+        let user_params = config.generation_overrides("alice@localhost");
+        assert_eq!(user_params.num_ctx, Some(16384));
+        assert_eq!(user_params.model, None);
+
+        assert!(config.generation_overrides("bob@localhost").is_empty());
+    }
+
+    #[test]
+    fn test_message_styling_room_and_user() {
+        let room: RoomConfig = toml::from_str(
+            r#"
+            jid = "lobby@conference.localhost"
+            styling = false
+            temperature = 0.2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(room.styling, Some(false));
+        assert_eq!(room.generation.temperature, Some(0.2));
+        let user: UserConfig = toml::from_str("jid = \"alice@localhost\"\nstyling = true").unwrap();
+
+        let mut config = config_with_jids(vec!["alice@localhost"]);
+        config.rooms = vec![room];
+        config.users = vec![user];
+        assert!(!config.message_styling("lobby@conference.localhost"));
+        assert!(config.message_styling("bob@localhost"));
+
+        // Per-user settings win over the global default
+        config.output.styling = false;
+        assert!(config.message_styling("alice@localhost"));
+        assert!(!config.message_styling("bob@localhost"));
+    }
+
+    #[test]
+    fn test_output_config() {
+        let output = OutputConfig::default();
+        assert_eq!(output.max_body_bytes, 10_000);
+        assert_eq!(output.upload_above_bytes, None);
+
+        let output: OutputConfig =
+            toml::from_str("max_body_bytes = 4000\nupload_above_bytes = 20000").unwrap();
+        assert!(output.styling);
+        assert_eq!(output.max_body_bytes, 4000);
+        assert_eq!(output.upload_above_bytes, Some(20_000));
+        assert!(output.validate().is_ok());
+
+        let output: OutputConfig = toml::from_str("max_body_bytes = 0").unwrap();
+        assert!(output.validate().is_ok());
+        let output: OutputConfig = toml::from_str("max_body_bytes = 3").unwrap();
+        assert!(output.validate().is_err());
     }
 }
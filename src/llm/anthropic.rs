@@ -10,6 +10,7 @@ use tracing::{debug, info, warn};
 use crate::backoff::Backoff;
 use crate::config::LlmConfig;
 use super::client::LlmClient;
+use super::GenerationParams;
 
 /// Maximum number of retry attempts for transient API errors.
 const MAX_RETRY_ATTEMPTS: u32 = 5;
@@ -29,6 +30,27 @@ struct MessagesRequest {
     messages: Vec<Message>,
     #[serde(skip_serializing_if = "Option::is_none")]
     tools: Option<Vec<ToolDefinition>>,
+    /// Extended thinking configuration (omitted when disabled).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking: Option<ThinkingConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_sequences: Option<Vec<String>>,
+}
+
+/// Extended thinking request parameter.
+///
+/// Serializes to: `{"type": "enabled", "budget_tokens": 4096}`
+#[derive(Debug, Serialize)]
+struct ThinkingConfig {
+    #[serde(rename = "type")]
+    thinking_type: String,
+    budget_tokens: u32,
 }
 
 /// A message in the conversation (sent to the API).
@@ -128,6 +150,15 @@ pub enum InputContentBlock {
         tool_use_id: String,
         content: String,
     },
+    /// Extended thinking block (from assistant messages).
+    /// Must be passed back unmodified, signature included, during tool use.
+    /// Serializes to: `{"type": "thinking", "thinking": "...", "signature": "..."}`
+    #[serde(rename = "thinking")]
+    Thinking { thinking: String, signature: String },
+    /// Encrypted thinking block flagged by the API safety systems.
+    /// Opaque — passed back as-is.
+    #[serde(rename = "redacted_thinking")]
+    RedactedThinking { data: String },
 }
 
 /// Source for an image content block (base64-encoded).
@@ -146,6 +177,10 @@ pub struct DocumentSource {
     pub source_type: String, // "base64"
     pub media_type: String, // "application/pdf"
     pub data: String,       // base64-encoded document bytes
+    /// Text layer, extracted once by the attachment pipeline for
+    /// providers that cannot read documents (Ollama). Never sent.
+    #[serde(skip)]
+    pub text: Option<String>,
 }
 
 // ── Tool definition (for API `tools[]` parameter) ────────
@@ -188,6 +223,10 @@ enum ResponseContentBlock {
         name: String,
         input: serde_json::Value,
     },
+    #[serde(rename = "thinking")]
+    Thinking { thinking: String, signature: String },
+    #[serde(rename = "redacted_thinking")]
+    RedactedThinking { data: String },
 }
 
 #[derive(Debug, Deserialize)]
@@ -197,7 +236,10 @@ struct Usage {
 }
 
 /// LLM response with metadata and optional tool calls.
-#[derive(Debug)]
+///
+/// Serializable so that responses can be recorded to and replayed from
+/// cassette files (see [`super::replay`]).
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LlmResponse {
     /// Concatenated text from all text content blocks.
     pub text: String,
@@ -210,11 +252,16 @@ pub struct LlmResponse {
     /// Output tokens generated by this API call.
     pub output_tokens: u32,
     /// Raw response content blocks for re-submission in the agentic loop.
+    /// Includes thinking blocks, which the API requires to be passed back.
     pub content_blocks: Vec<InputContentBlock>,
+    /// Concatenated extended thinking text (empty if thinking is disabled
+    /// or all thinking was redacted). Never shown to users.
+    #[serde(default)]
+    pub thinking: String,
 }
 
 /// A tool invocation requested by the LLM.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolCall {
     /// Unique identifier for this tool use (from the API response).
     /// Must be sent back in the corresponding tool_result.
@@ -226,7 +273,8 @@ pub struct ToolCall {
 }
 
 /// Why the LLM stopped generating.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum StopReason {
     /// Normal completion — the model is done.
     EndTurn,
@@ -243,6 +291,56 @@ impl AnthropicClient {
         let client = Client::new();
         Self { client, config }
     }
+
+    /// Builds the Messages API request body.
+    ///
+    /// With `thinking_budget_tokens` set, extended thinking is enabled and
+    /// the budget is added on top of `max_tokens_per_request`, so the visible
+    /// answer keeps its full allowance (the API counts thinking in `max_tokens`).
+    ///
+    /// `params` overrides the `[llm]` generation parameters. `num_ctx` and
+    /// `keep_alive` are Ollama-only and ignored. Thinking is incompatible
+    /// with `temperature` and `top_k`, which are dropped when it is enabled.
+    fn build_request(
+        &self,
+        system_prompt: &str,
+        messages: &[Message],
+        tools: Option<&[ToolDefinition]>,
+        params: &GenerationParams,
+    ) -> MessagesRequest {
+        let params = self.config.generation.merged(params);
+        let thinking = self
+            .config
+            .thinking_budget_tokens
+            .map(|budget| ThinkingConfig {
+                thinking_type: "enabled".to_string(),
+                budget_tokens: budget,
+            });
+        let max_tokens = match &thinking {
+            Some(t) => self.config.max_tokens_per_request.saturating_add(t.budget_tokens),
+            None => self.config.max_tokens_per_request,
+        };
+        let (temperature, top_k) = if thinking.is_some() {
+            if params.temperature.is_some() || params.top_k.is_some() {
+                debug!("Extended thinking enabled: ignoring temperature and top_k");
+            }
+            (None, None)
+        } else {
+            (params.temperature, params.top_k)
+        };
+        MessagesRequest {
+            model: params.model.unwrap_or_else(|| self.config.model.clone()),
+            max_tokens,
+            system: system_prompt.to_string(),
+            messages: messages.to_vec(),
+            tools: tools.map(|t| t.to_vec()),
+            thinking,
+            temperature,
+            top_p: params.top_p,
+            top_k,
+            stop_sequences: params.stop_sequences,
+        }
+    }
 }
 
 #[async_trait]
@@ -252,18 +350,13 @@ impl LlmClient for AnthropicClient {
         system_prompt: &str,
         messages: &[Message],
         tools: Option<&[ToolDefinition]>,
+        params: &GenerationParams,
     ) -> Result<LlmResponse> {
-        let request = MessagesRequest {
-            model: self.config.model.clone(),
-            max_tokens: self.config.max_tokens_per_request,
-            system: system_prompt.to_string(),
-            messages: messages.to_vec(),
-            tools: tools.map(|t| t.to_vec()),
-        };
+        let request = self.build_request(system_prompt, messages, tools, params);
 
         debug!(
             "Calling Claude API ({}) with {} messages{}",
-            self.config.model,
+            request.model,
             messages.len(),
             if tools.is_some() { " + tools" } else { "" }
         );
@@ -325,6 +418,7 @@ impl LlmClient for AnthropicClient {
         // Parse response content blocks into text, tool calls, and
         // InputContentBlock copies for re-submission in the agentic loop.
         let mut text_parts = Vec::new();
+        let mut thinking_parts = Vec::new();
         let mut tool_calls = Vec::new();
         let mut content_blocks = Vec::new();
 
@@ -348,10 +442,23 @@ impl LlmClient for AnthropicClient {
                         input: input.clone(),
                     });
                 }
+                ResponseContentBlock::Thinking { thinking, signature } => {
+                    thinking_parts.push(thinking.clone());
+                    content_blocks.push(InputContentBlock::Thinking {
+                        thinking: thinking.clone(),
+                        signature: signature.clone(),
+                    });
+                }
+                ResponseContentBlock::RedactedThinking { data } => {
+                    content_blocks.push(InputContentBlock::RedactedThinking {
+                        data: data.clone(),
+                    });
+                }
             }
         }
 
         let text = text_parts.join("\n");
+        let thinking = thinking_parts.join("\n");
 
         let stop_reason = match resp.stop_reason.as_deref() {
             Some("end_turn") => StopReason::EndTurn,
@@ -375,6 +482,7 @@ impl LlmClient for AnthropicClient {
             input_tokens,
             output_tokens,
             content_blocks,
+            thinking,
         })
     }
 
@@ -433,6 +541,7 @@ mod tests {
                     source_type: "base64".to_string(),
                     media_type: "application/pdf".to_string(),
                     data: "cGRm".to_string(),
+                    text: Some("[PDF document]\npdf".to_string()),
                 },
             }]),
         };
@@ -440,6 +549,7 @@ mod tests {
         let content = json["content"].as_array().unwrap();
         assert_eq!(content[0]["type"], "document");
         assert_eq!(content[0]["source"]["media_type"], "application/pdf");
+        assert!(content[0]["source"].get("text").is_none());
     }
 
     #[test]
@@ -657,6 +767,11 @@ mod tests {
             system: "You are helpful.".to_string(),
             messages: vec![],
             tools: None,
+            thinking: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
         };
         let json = serde_json::to_value(&request).unwrap();
         assert!(json.get("tools").is_none());
@@ -680,10 +795,147 @@ mod tests {
                     "required": ["query"]
                 }),
             }]),
+            thinking: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
         };
         let json = serde_json::to_value(&request).unwrap();
         let tools = json["tools"].as_array().unwrap();
         assert_eq!(tools.len(), 1);
         assert_eq!(tools[0]["name"], "web_search");
     }
+
+    // ── Extended thinking tests ───────────────────────────
+
+    fn thinking_client(budget: Option<u32>) -> AnthropicClient {
+        AnthropicClient::new(LlmConfig {
+            provider: "anthropic".to_string(),
+            model: "claude-sonnet-4-5-20250929".to_string(),
+            api_key: "test-key".to_string(),
+            max_tokens_per_request: 4096,
+            host: None,
+            vision: false,
+            thinking_budget_tokens: budget,
+            reasoning_summary: false,
+            generation: Default::default(),
+            cassette: None,
+            record: false,
+            script: None,
+            tiers: Default::default(),
+            routing: Default::default(),
+        })
+    }
+
+    #[test]
+    fn test_build_request_without_thinking() {
+        let request = thinking_client(None).build_request("sys", &[], None, &GenerationParams::default());
+        assert_eq!(request.max_tokens, 4096);
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("thinking").is_none());
+    }
+
+    #[test]
+    fn test_build_request_with_thinking_budget() {
+        let request = thinking_client(Some(2048)).build_request("sys", &[], None, &GenerationParams::default());
+        // Thinking budget is added on top of the answer budget
+        assert_eq!(request.max_tokens, 6144);
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["thinking"]["type"], "enabled");
+        assert_eq!(json["thinking"]["budget_tokens"], 2048);
+    }
+
+    // ── Generation parameter tests ────────────────────────
+
+    #[test]
+    fn test_build_request_maps_generation_params() {
+        let mut client = thinking_client(None);
+        client.config.generation = GenerationParams {
+            temperature: Some(0.7),
+            num_ctx: Some(8192),
+            ..Default::default()
+        };
+        let overrides = GenerationParams {
+            model: Some("claude-haiku-4-5-20250110".to_string()),
+            temperature: Some(0.2),
+            top_k: Some(40),
+            stop_sequences: Some(vec!["END".to_string()]),
+            ..Default::default()
+        };
+        let request = client.build_request("sys", &[], None, &overrides);
+        assert_eq!(request.model, "claude-haiku-4-5-20250110");
+        let json = serde_json::to_value(&request).unwrap();
+        assert!((json["temperature"].as_f64().unwrap() - 0.2).abs() < 1e-6);
+        assert_eq!(json["top_k"], 40);
+        assert_eq!(json["stop_sequences"][0], "END");
+        // Ollama-only and unset params are not sent
+        assert!(json.get("num_ctx").is_none());
+        assert!(json.get("top_p").is_none());
+    }
+
+    #[test]
+    fn test_build_request_thinking_drops_temperature() {
+        let overrides = GenerationParams {
+            temperature: Some(0.2),
+            top_k: Some(40),
+            top_p: Some(0.95),
+            ..Default::default()
+        };
+        let request = thinking_client(Some(2048)).build_request("sys", &[], None, &overrides);
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("temperature").is_none());
+        assert!(json.get("top_k").is_none());
+        assert!(json.get("top_p").is_some());
+    }
+
+    #[test]
+    fn test_response_content_block_thinking_deserializes() {
+        let json = r#"{"type": "thinking", "thinking": "Let me check.", "signature": "sig=="}"#;
+        let block: ResponseContentBlock = serde_json::from_str(json).unwrap();
+        match block {
+            ResponseContentBlock::Thinking { thinking, signature } => {
+                assert_eq!(thinking, "Let me check.");
+                assert_eq!(signature, "sig==");
+            }
+            _ => panic!("Expected Thinking variant"),
+        }
+
+        let json = r#"{"type": "redacted_thinking", "data": "EmwKAhgB"}"#;
+        let block: ResponseContentBlock = serde_json::from_str(json).unwrap();
+        assert!(matches!(block, ResponseContentBlock::RedactedThinking { data } if data == "EmwKAhgB"));
+    }
+
+    #[test]
+    fn test_thinking_blocks_round_trip_in_assistant_message() {
+        // Thinking blocks must be sent back verbatim before tool_use blocks
+        let msg = Message {
+            role: "assistant".to_string(),
+            content: MessageContent::Blocks(vec![
+                InputContentBlock::Thinking {
+                    thinking: "I should search.".to_string(),
+                    signature: "sig==".to_string(),
+                },
+                InputContentBlock::RedactedThinking {
+                    data: "EmwKAhgB".to_string(),
+                },
+                InputContentBlock::ToolUse {
+                    id: "toolu_1".to_string(),
+                    name: "web_search".to_string(),
+                    input: serde_json::json!({"query": "rust"}),
+                },
+            ]),
+        };
+        let json = serde_json::to_value(&msg).unwrap();
+        let blocks = json["content"].as_array().unwrap();
+        assert_eq!(blocks[0]["type"], "thinking");
+        assert_eq!(blocks[0]["thinking"], "I should search.");
+        assert_eq!(blocks[0]["signature"], "sig==");
+        assert_eq!(blocks[1]["type"], "redacted_thinking");
+        assert_eq!(blocks[1]["data"], "EmwKAhgB");
+        assert_eq!(blocks[2]["type"], "tool_use");
+
+        let back: Message = serde_json::from_value(json).unwrap();
+        assert_eq!(back.content, msg.content);
+    }
 }
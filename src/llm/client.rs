@@ -7,7 +7,7 @@
 use anyhow::Result;
 use async_trait::async_trait;
 
-use super::{LlmResponse, Message, ToolDefinition};
+use super::{GenerationParams, LlmResponse, Message, ToolDefinition};
 
 /// Abstraction over LLM backends (Anthropic, Ollama, etc.).
 ///
@@ -20,11 +20,15 @@ pub trait LlmClient: Send + Sync {
     /// When `tools` is `Some`, tool definitions are included and the
     /// response may contain tool_use calls. When `None`, the `tools`
     /// field is omitted.
+    ///
+    /// `params` are per-request overrides (room or user); fields left unset
+    /// fall back to the client's `[llm]` generation parameters.
     async fn complete(
         &self,
         system_prompt: &str,
         messages: &[Message],
         tools: Option<&[ToolDefinition]>,
+        params: &GenerationParams,
     ) -> Result<LlmResponse>;
 
     /// Human-readable description of the provider and model.
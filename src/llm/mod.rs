@@ -1,6 +1,10 @@
 pub mod anthropic;
 pub mod client;
 pub mod ollama;
+pub mod params;
+pub mod replay;
+pub mod scripted;
+pub mod tiers;
 
 pub use anthropic::{
     AnthropicClient, DocumentSource, ImageSource, InputContentBlock, LlmResponse, Message,
@@ -8,3 +12,5 @@ pub use anthropic::{
 };
 pub use client::LlmClient;
 pub use ollama::OllamaClient;
+pub use params::GenerationParams;
+pub use tiers::{LlmRegistry, Tier};
@@ -11,6 +11,11 @@
 //! - Stop reason: `"stop"` → `EndTurn`, presence of `tool_calls` → `ToolUse`.
 //! - Token usage: `prompt_eval_count` / `eval_count` (may be absent).
 //! - Must set `stream: false` for synchronous responses.
+//! - Generation parameters go in `options` (`stop`, not `stop_sequences`);
+//!   `keep_alive` is a top-level field.
+//! - Images go in a per-message `images` array (base64, no media type), only
+//!   for models declared vision-capable (`[llm] vision = true`). PDFs are
+//!   sent as the text extracted when the attachment was received.
 
 use anyhow::Result;
 use async_trait::async_trait;
@@ -21,7 +26,7 @@ use tracing::{debug, info, warn};
 use crate::config::LlmConfig;
 use super::client::LlmClient;
 use super::{
-    InputContentBlock, LlmResponse, Message, MessageContent, StopReason, ToolCall, ToolDefinition,
+    DocumentSource, GenerationParams, InputContentBlock, LlmResponse, Message, MessageContent, StopReason, ToolCall, ToolDefinition,
 };
 
 /// Default Ollama API base URL.
@@ -38,6 +43,9 @@ struct OllamaChatRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     tools: Option<Vec<OllamaToolDef>>,
     options: OllamaOptions,
+    /// How long the model stays loaded, e.g. `"10m"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<String>,
 }
 
 /// Ollama message in the conversation.
@@ -47,6 +55,9 @@ struct OllamaMessage {
     content: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     tool_calls: Option<Vec<OllamaToolCall>>,
+    /// Base64-encoded images (vision models only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    images: Option<Vec<String>>,
 }
 
 /// Ollama tool definition (OpenAI-compatible format).
@@ -79,9 +90,19 @@ struct OllamaFunctionCall {
 }
 
 /// Ollama generation options.
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Default)]
 struct OllamaOptions {
     num_predict: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_ctx: Option<u32>,
 }
 
 // ── Ollama API response types ────────────────────────────
@@ -132,16 +153,19 @@ impl OllamaClient {
             host,
         }
     }
-}
 
-#[async_trait]
-impl LlmClient for OllamaClient {
-    async fn complete(
+    /// Builds the `/api/chat` request body.
+    ///
+    /// `params` overrides the `[llm]` generation parameters.
+    fn build_request(
         &self,
         system_prompt: &str,
         messages: &[Message],
         tools: Option<&[ToolDefinition]>,
-    ) -> Result<LlmResponse> {
+        params: &GenerationParams,
+    ) -> OllamaChatRequest {
+        let params = self.config.generation.merged(params);
+
         // Build Ollama messages: system prompt as first message, then conversation
         let mut ollama_messages = Vec::with_capacity(messages.len() + 1);
 
@@ -151,12 +175,13 @@ impl LlmClient for OllamaClient {
                 role: "system".to_string(),
                 content: system_prompt.to_string(),
                 tool_calls: None,
+                images: None,
             });
         }
 
         // Translate conversation messages
         for msg in messages {
-            translate_message(msg, &mut ollama_messages);
+            translate_message(msg, &mut ollama_messages, self.config.vision);
         }
 
         // Translate tool definitions
@@ -173,21 +198,40 @@ impl LlmClient for OllamaClient {
                 .collect()
         });
 
-        let request = OllamaChatRequest {
-            model: self.config.model.clone(),
+        OllamaChatRequest {
+            model: params.model.unwrap_or_else(|| self.config.model.clone()),
             messages: ollama_messages,
             stream: false,
             tools: ollama_tools,
             options: OllamaOptions {
                 num_predict: self.config.max_tokens_per_request,
+                temperature: params.temperature,
+                top_p: params.top_p,
+                top_k: params.top_k,
+                stop: params.stop_sequences,
+                num_ctx: params.num_ctx,
             },
-        };
+            keep_alive: params.keep_alive,
+        }
+    }
+}
+
+#[async_trait]
+impl LlmClient for OllamaClient {
+    async fn complete(
+        &self,
+        system_prompt: &str,
+        messages: &[Message],
+        tools: Option<&[ToolDefinition]>,
+        params: &GenerationParams,
+    ) -> Result<LlmResponse> {
+        let request = self.build_request(system_prompt, messages, tools, params);
 
         let url = format!("{}/api/chat", self.host);
 
         debug!(
             "Calling Ollama API ({}) with {} messages{}",
-            self.config.model,
+            request.model,
             messages.len(),
             if tools.is_some() { " + tools" } else { "" }
         );
@@ -265,6 +309,7 @@ impl LlmClient for OllamaClient {
             input_tokens,
             output_tokens,
             content_blocks,
+            thinking: String::new(),
         })
     }
 
@@ -279,15 +324,19 @@ impl LlmClient for OllamaClient {
 ///
 /// Handles the differences between Anthropic and Ollama message formats:
 /// - `ToolUse` blocks in assistant messages → `tool_calls` field
-/// - `ToolResult` blocks in user messages → `role: "tool"` messages
-/// - Image/Document blocks → filtered out with text placeholder
-fn translate_message(msg: &Message, out: &mut Vec<OllamaMessage>) {
+/// - `ToolResult` blocks in user messages → `role: "tool"` messages, followed
+///   by a user message for any other blocks (content injected by skills)
+/// - Image blocks → `images` field if `vision` is set, text placeholder otherwise
+/// - PDF document blocks → text extracted by the attachment pipeline
+/// - Thinking/RedactedThinking blocks → dropped
+fn translate_message(msg: &Message, out: &mut Vec<OllamaMessage>, vision: bool) {
     match &msg.content {
         MessageContent::Text(text) => {
             out.push(OllamaMessage {
                 role: msg.role.clone(),
                 content: text.clone(),
                 tool_calls: None,
+                images: None,
             });
         }
         MessageContent::Blocks(blocks) => {
@@ -295,7 +344,8 @@ fn translate_message(msg: &Message, out: &mut Vec<OllamaMessage>) {
             let mut text_parts = Vec::new();
             let mut tool_use_calls = Vec::new();
             let mut tool_results = Vec::new();
-            let mut has_multimodal = false;
+            let mut images = Vec::new();
+            let mut skipped_images = 0;
 
             for block in blocks {
                 match block {
@@ -316,16 +366,29 @@ fn translate_message(msg: &Message, out: &mut Vec<OllamaMessage>) {
                     } => {
                         tool_results.push(content.clone());
                     }
-                    InputContentBlock::Image { .. } | InputContentBlock::Document { .. } => {
-                        has_multimodal = true;
+                    InputContentBlock::Image { source } => {
+                        if vision {
+                            images.push(source.data.clone());
+                        } else {
+                            skipped_images += 1;
+                        }
+                    }
+                    InputContentBlock::Document { source } => {
+                        text_parts.push(document_to_text(source));
                     }
+                    // Anthropic extended thinking has no Ollama equivalent
+                    InputContentBlock::Thinking { .. }
+                    | InputContentBlock::RedactedThinking { .. } => {}
                 }
             }
 
-            if has_multimodal {
-                warn!("Ollama does not support multi-modal content blocks; images/documents will be skipped");
-                text_parts.push("[Unsupported: image/document content omitted]".to_string());
+            if skipped_images > 0 {
+                warn!("Model is not vision-capable ([llm] vision = false); {skipped_images} image(s) skipped");
+                text_parts.push(format!(
+                    "[{skipped_images} image(s) omitted: this model cannot see images]"
+                ));
             }
+            let images = if images.is_empty() { None } else { Some(images) };
 
             // Assistant message with tool calls
             if !tool_use_calls.is_empty() {
@@ -333,6 +396,7 @@ fn translate_message(msg: &Message, out: &mut Vec<OllamaMessage>) {
                     role: "assistant".to_string(),
                     content: text_parts.join("\n"),
                     tool_calls: Some(tool_use_calls),
+                    images: None,
                 });
                 return;
             }
@@ -344,21 +408,41 @@ fn translate_message(msg: &Message, out: &mut Vec<OllamaMessage>) {
                         role: "tool".to_string(),
                         content: result,
                         tool_calls: None,
+                        images: None,
+                    });
+                }
+                // Content injected by a skill (e.g. file_read) follows as a user message
+                if !text_parts.is_empty() || images.is_some() {
+                    out.push(OllamaMessage {
+                        role: msg.role.clone(),
+                        content: text_parts.join("\n"),
+                        tool_calls: None,
+                        images,
                     });
                 }
                 return;
             }
 
-            // Plain text blocks
+            // Plain text blocks (and images)
             out.push(OllamaMessage {
                 role: msg.role.clone(),
                 content: text_parts.join("\n"),
                 tool_calls: None,
+                images,
             });
         }
     }
 }
 
+/// Converts a document block into a text part, using the text extracted
+/// by the attachment pipeline (`agent::extract::pdf_to_text`).
+fn document_to_text(source: &DocumentSource) -> String {
+    match &source.text {
+        Some(text) => text.clone(),
+        None => format!("[Document ({}) omitted: no extracted text]", source.media_type),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -374,6 +458,15 @@ mod tests {
             api_key: String::new(),
             max_tokens_per_request: 4096,
             host: None,
+            vision: false,
+            thinking_budget_tokens: None,
+            reasoning_summary: false,
+            generation: Default::default(),
+            cassette: None,
+            record: false,
+            script: None,
+            tiers: Default::default(),
+            routing: Default::default(),
         });
         assert_eq!(client.description(), "ollama (llama3.2)");
     }
@@ -386,6 +479,15 @@ mod tests {
             api_key: String::new(),
             max_tokens_per_request: 4096,
             host: None,
+            vision: false,
+            thinking_budget_tokens: None,
+            reasoning_summary: false,
+            generation: Default::default(),
+            cassette: None,
+            record: false,
+            script: None,
+            tiers: Default::default(),
+            routing: Default::default(),
         });
         assert_eq!(client.host, "http://localhost:11434");
     }
@@ -398,6 +500,15 @@ mod tests {
             api_key: String::new(),
             max_tokens_per_request: 4096,
             host: Some("http://myserver:11434/".to_string()),
+            vision: false,
+            thinking_budget_tokens: None,
+            reasoning_summary: false,
+            generation: Default::default(),
+            cassette: None,
+            record: false,
+            script: None,
+            tiers: Default::default(),
+            routing: Default::default(),
         });
         // Trailing slash should be stripped
         assert_eq!(client.host, "http://myserver:11434");
@@ -444,7 +555,7 @@ mod tests {
             content: MessageContent::Text("Hello!".to_string()),
         };
         let mut out = Vec::new();
-        translate_message(&msg, &mut out);
+        translate_message(&msg, &mut out, false);
         assert_eq!(out.len(), 1);
         assert_eq!(out[0].role, "user");
         assert_eq!(out[0].content, "Hello!");
@@ -467,7 +578,7 @@ mod tests {
             ]),
         };
         let mut out = Vec::new();
-        translate_message(&msg, &mut out);
+        translate_message(&msg, &mut out, false);
         assert_eq!(out.len(), 1);
         assert_eq!(out[0].role, "assistant");
         assert_eq!(out[0].content, "Let me search.");
@@ -477,6 +588,29 @@ mod tests {
         assert_eq!(tcs[0].function.arguments["query"], "rust");
     }
 
+    #[test]
+    fn test_translate_drops_thinking_blocks() {
+        let msg = Message {
+            role: "assistant".to_string(),
+            content: MessageContent::Blocks(vec![
+                InputContentBlock::Thinking {
+                    thinking: "hmm".to_string(),
+                    signature: "sig".to_string(),
+                },
+                InputContentBlock::RedactedThinking {
+                    data: "opaque".to_string(),
+                },
+                InputContentBlock::Text {
+                    text: "Answer.".to_string(),
+                },
+            ]),
+        };
+        let mut out = Vec::new();
+        translate_message(&msg, &mut out, false);
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].content, "Answer.");
+    }
+
     #[test]
     fn test_translate_tool_result() {
         let msg = Message {
@@ -489,7 +623,7 @@ mod tests {
             ]),
         };
         let mut out = Vec::new();
-        translate_message(&msg, &mut out);
+        translate_message(&msg, &mut out, false);
         assert_eq!(out.len(), 1);
         assert_eq!(out[0].role, "tool");
         assert_eq!(out[0].content, "Found 5 results.");
@@ -511,7 +645,7 @@ mod tests {
             ]),
         };
         let mut out = Vec::new();
-        translate_message(&msg, &mut out);
+        translate_message(&msg, &mut out, false);
         assert_eq!(out.len(), 2);
         assert_eq!(out[0].role, "tool");
         assert_eq!(out[0].content, "Result 1.");
@@ -520,12 +654,16 @@ mod tests {
     }
 
     #[test]
-    fn test_translate_multimodal_filtered() {
+    fn test_translate_tool_result_with_injected_image() {
         let msg = Message {
             role: "user".to_string(),
             content: MessageContent::Blocks(vec![
+                InputContentBlock::ToolResult {
+                    tool_use_id: "tool_1".to_string(),
+                    content: "File 'photo.jpg' is attached below.".to_string(),
+                },
                 InputContentBlock::Text {
-                    text: "What is this?".to_string(),
+                    text: "[File: photo.jpg]".to_string(),
                 },
                 InputContentBlock::Image {
                     source: ImageSource {
@@ -537,15 +675,87 @@ mod tests {
             ]),
         };
         let mut out = Vec::new();
-        translate_message(&msg, &mut out);
+        translate_message(&msg, &mut out, true);
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].role, "tool");
+        assert_eq!(out[1].role, "user");
+        assert_eq!(out[1].content, "[File: photo.jpg]");
+        assert_eq!(out[1].images, Some(vec!["aGVsbG8=".to_string()]));
+    }
+
+    fn image_message() -> Message {
+        Message {
+            role: "user".to_string(),
+            content: MessageContent::Blocks(vec![
+                InputContentBlock::Text {
+                    text: "What is this?".to_string(),
+                },
+                InputContentBlock::Image {
+                    source: ImageSource {
+                        source_type: "base64".to_string(),
+                        media_type: "image/jpeg".to_string(),
+                        data: "aGVsbG8=".to_string(),
+                    },
+                },
+            ]),
+        }
+    }
+
+    #[test]
+    fn test_translate_image_without_vision_uses_placeholder() {
+        let mut out = Vec::new();
+        translate_message(&image_message(), &mut out, false);
         assert_eq!(out.len(), 1);
         assert_eq!(out[0].role, "user");
         assert!(out[0].content.contains("What is this?"));
-        assert!(out[0].content.contains("[Unsupported:"));
+        assert!(out[0].content.contains("1 image(s) omitted"));
+        assert!(out[0].images.is_none());
     }
 
     #[test]
-    fn test_translate_document_filtered() {
+    fn test_translate_image_with_vision_wire_json() {
+        let mut out = Vec::new();
+        translate_message(&image_message(), &mut out, true);
+        assert_eq!(out.len(), 1);
+        let json = serde_json::to_value(&out[0]).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "role": "user",
+                "content": "What is this?",
+                "images": ["aGVsbG8="]
+            })
+        );
+    }
+
+    #[test]
+    fn test_build_request_vision_model_sends_images() {
+        let client = OllamaClient::new(LlmConfig {
+            provider: "ollama".to_string(),
+            model: "llava".to_string(),
+            api_key: String::new(),
+            max_tokens_per_request: 4096,
+            host: None,
+            vision: true,
+            thinking_budget_tokens: None,
+            reasoning_summary: false,
+            generation: Default::default(),
+            cassette: None,
+            record: false,
+            script: None,
+            tiers: Default::default(),
+            routing: Default::default(),
+        });
+        let request =
+            client.build_request("sys", &[image_message()], None, &GenerationParams::default());
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["messages"][0]["role"], "system");
+        assert!(json["messages"][0].get("images").is_none());
+        assert_eq!(json["messages"][1]["images"][0], "aGVsbG8=");
+    }
+
+    #[test]
+    fn test_translate_document_uses_extracted_text() {
         let msg = Message {
             role: "user".to_string(),
             content: MessageContent::Blocks(vec![InputContentBlock::Document {
@@ -553,13 +763,31 @@ mod tests {
                     source_type: "base64".to_string(),
                     media_type: "application/pdf".to_string(),
                     data: "cGRm".to_string(),
+                    text: Some("[PDF document]\nQuarterly report".to_string()),
                 },
             }]),
         };
         let mut out = Vec::new();
-        translate_message(&msg, &mut out);
+        translate_message(&msg, &mut out, false);
         assert_eq!(out.len(), 1);
-        assert!(out[0].content.contains("[Unsupported:"));
+        assert_eq!(out[0].content, "[PDF document]\nQuarterly report");
+        assert!(out[0].images.is_none());
+
+        // Without text from the attachment pipeline, the bytes are not sent
+        let msg = Message {
+            role: "user".to_string(),
+            content: MessageContent::Blocks(vec![InputContentBlock::Document {
+                source: DocumentSource {
+                    source_type: "base64".to_string(),
+                    media_type: "application/pdf".to_string(),
+                    data: "cGRm".to_string(),
+                    text: None,
+                },
+            }]),
+        };
+        let mut out = Vec::new();
+        translate_message(&msg, &mut out, false);
+        assert_eq!(out[0].content, "[Document (application/pdf) omitted: no extracted text]");
     }
 
     // ── Response parsing ─────────────────────────────────
@@ -629,10 +857,15 @@ mod tests {
                 role: "user".to_string(),
                 content: "Hello".to_string(),
                 tool_calls: None,
+                images: None,
             }],
             stream: false,
             tools: None,
-            options: OllamaOptions { num_predict: 4096 },
+            options: OllamaOptions {
+                num_predict: 4096,
+                ..Default::default()
+            },
+            keep_alive: None,
         };
         let json = serde_json::to_value(&request).unwrap();
         assert_eq!(json["model"], "llama3.2");
@@ -655,7 +888,11 @@ mod tests {
                     parameters: serde_json::json!({"type": "object"}),
                 },
             }]),
-            options: OllamaOptions { num_predict: 4096 },
+            options: OllamaOptions {
+                num_predict: 4096,
+                ..Default::default()
+            },
+            keep_alive: None,
         };
         let json = serde_json::to_value(&request).unwrap();
         let tools = json["tools"].as_array().unwrap();
@@ -664,6 +901,47 @@ mod tests {
         assert_eq!(tools[0]["function"]["name"], "test");
     }
 
+    #[test]
+    fn test_build_request_maps_generation_params() {
+        let client = OllamaClient::new(LlmConfig {
+            provider: "ollama".to_string(),
+            model: "llama3.2".to_string(),
+            api_key: String::new(),
+            max_tokens_per_request: 4096,
+            host: None,
+            vision: false,
+            thinking_budget_tokens: None,
+            reasoning_summary: false,
+            generation: GenerationParams {
+                temperature: Some(0.7),
+                num_ctx: Some(8192),
+                ..Default::default()
+            },
+            cassette: None,
+            record: false,
+            script: None,
+            tiers: Default::default(),
+            routing: Default::default(),
+        });
+        let overrides = GenerationParams {
+            model: Some("qwen3:8b".to_string()),
+            temperature: Some(0.2),
+            stop_sequences: Some(vec!["END".to_string()]),
+            keep_alive: Some("10m".to_string()),
+            ..Default::default()
+        };
+        let request = client.build_request("sys", &[], None, &overrides);
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["model"], "qwen3:8b");
+        assert_eq!(json["keep_alive"], "10m");
+        let options = &json["options"];
+        assert_eq!(options["num_predict"], 4096);
+        assert!((options["temperature"].as_f64().unwrap() - 0.2).abs() < 1e-6);
+        assert_eq!(options["num_ctx"], 8192);
+        assert_eq!(options["stop"][0], "END");
+        assert!(options.get("top_p").is_none());
+    }
+
     #[test]
     fn test_ollama_message_with_tool_calls_serialization() {
         let msg = OllamaMessage {
@@ -675,6 +953,7 @@ mod tests {
                     arguments: serde_json::json!({"query": "test"}),
                 },
             }]),
+            images: None,
         };
         let json = serde_json::to_value(&msg).unwrap();
         assert_eq!(json["role"], "assistant");
@@ -688,9 +967,11 @@ mod tests {
             role: "user".to_string(),
             content: "Hello".to_string(),
             tool_calls: None,
+            images: None,
         };
         let json = serde_json::to_value(&msg).unwrap();
         assert!(json.get("tool_calls").is_none());
+        assert!(json.get("images").is_none());
     }
 
     // ── Stop reason mapping ──────────────────────────────
@@ -0,0 +1,138 @@
+//! Generation parameters — sampling and runtime options for a request.
+//!
+//! Parameters can be set in `[llm]` (defaults for every request), in a
+//! `[[rooms]]` entry or in a `[[users]]` entry (overrides for one
+//! conversation). Each provider maps the fields it supports and ignores
+//! the others.
+
+use serde::Deserialize;
+
+/// Generation parameters for an LLM request.
+///
+/// Every field is optional: unset fields fall back to the next layer
+/// (conversation override → `[llm]` → provider default).
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct GenerationParams {
+    /// Model override, on the same provider and credentials.
+    /// Only read from `[[rooms]]` and `[[users]]` entries; in `[llm]`,
+    /// `model` is the base model.
+    pub model: Option<String>,
+    /// Sampling temperature (Anthropic: 0.0–1.0, Ollama: usually 0.0–2.0).
+    pub temperature: Option<f32>,
+    /// Nucleus sampling threshold.
+    pub top_p: Option<f32>,
+    /// Only sample from the top K tokens.
+    pub top_k: Option<u32>,
+    /// Sequences that stop generation.
+    pub stop_sequences: Option<Vec<String>>,
+    /// Context window size (Ollama only).
+    pub num_ctx: Option<u32>,
+    /// How long the model stays loaded after a request, e.g. `"10m"`
+    /// (Ollama only).
+    pub keep_alive: Option<String>,
+}
+
+impl GenerationParams {
+    /// Returns these parameters with every field set in `overrides`
+    /// replacing the corresponding field.
+    pub fn merged(&self, overrides: &GenerationParams) -> GenerationParams {
+        GenerationParams {
+            model: overrides.model.clone().or_else(|| self.model.clone()),
+            temperature: overrides.temperature.or(self.temperature),
+            top_p: overrides.top_p.or(self.top_p),
+            top_k: overrides.top_k.or(self.top_k),
+            stop_sequences: overrides
+                .stop_sequences
+                .clone()
+                .or_else(|| self.stop_sequences.clone()),
+            num_ctx: overrides.num_ctx.or(self.num_ctx),
+            keep_alive: overrides.keep_alive.clone().or_else(|| self.keep_alive.clone()),
+        }
+    }
+
+    /// Returns true if no parameter is set.
+    pub fn is_empty(&self) -> bool {
+        *self == GenerationParams::default()
+    }
+
+    /// Short description of the parameters that are set, for `/status`,
+    /// e.g. `"temperature=0.2, num_ctx=8192"`.
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(model) = &self.model {
+            parts.push(format!("model={model}"));
+        }
+        if let Some(t) = self.temperature {
+            parts.push(format!("temperature={t}"));
+        }
+        if let Some(p) = self.top_p {
+            parts.push(format!("top_p={p}"));
+        }
+        if let Some(k) = self.top_k {
+            parts.push(format!("top_k={k}"));
+        }
+        if let Some(stop) = &self.stop_sequences {
+            parts.push(format!("stop={stop:?}"));
+        }
+        if let Some(n) = self.num_ctx {
+            parts.push(format!("num_ctx={n}"));
+        }
+        if let Some(k) = &self.keep_alive {
+            parts.push(format!("keep_alive={k}"));
+        }
+        if parts.is_empty() {
+            "provider defaults".to_string()
+        } else {
+            parts.join(", ")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merged_overrides_win() {
+        let base = GenerationParams {
+            temperature: Some(0.7),
+            num_ctx: Some(4096),
+            ..Default::default()
+        };
+        let overrides = GenerationParams {
+            model: Some("llama3.2".to_string()),
+            temperature: Some(0.1),
+            ..Default::default()
+        };
+        let merged = base.merged(&overrides);
+        assert_eq!(merged.model.as_deref(), Some("llama3.2"));
+        assert_eq!(merged.temperature, Some(0.1));
+        assert_eq!(merged.num_ctx, Some(4096));
+        assert_eq!(merged.top_p, None);
+    }
+
+    #[test]
+    fn test_merged_with_empty_is_identity() {
+        let base = GenerationParams {
+            top_p: Some(0.9),
+            stop_sequences: Some(vec!["END".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(base.merged(&GenerationParams::default()), base);
+        assert!(!base.is_empty());
+        assert!(GenerationParams::default().is_empty());
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(GenerationParams::default().describe(), "provider defaults");
+        let params = GenerationParams {
+            temperature: Some(0.2),
+            num_ctx: Some(8192),
+            keep_alive: Some("10m".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(params.describe(), "temperature=0.2, num_ctx=8192, keep_alive=10m");
+    }
+}
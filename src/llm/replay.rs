@@ -0,0 +1,310 @@
+//! Record/replay providers — run the agent without a live LLM.
+//!
+//! A cassette is a JSONL file with one entry per LLM call: the request key,
+//! the recorded request (for humans), and the [`LlmResponse`].
+//!
+//! - [`RecordingClient`] wraps any provider (`[llm] record = true`) and
+//!   appends each request/response pair to the cassette.
+//! - [`ReplayClient`] (`provider = "replay"`) serves the recorded responses
+//!   without any network access.
+//!
+//! Requests are matched by [`request_key`], a SHA-1 of the conversation
+//! messages and the offered tool names. The system prompt is left out on
+//! purpose: it embeds the current date, which would break replays the day
+//! after recording. Identical requests are served in recording order.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha1::{Digest, Sha1};
+use tracing::{debug, info};
+
+use super::client::LlmClient;
+use super::{GenerationParams, LlmResponse, Message, ToolDefinition};
+
+/// One recorded LLM call (a line of the cassette file).
+#[derive(Debug, Serialize, Deserialize)]
+struct CassetteEntry {
+    /// Request key (see [`request_key`]).
+    key: String,
+    /// Provider and model that produced the response.
+    #[serde(default)]
+    model: String,
+    /// The recorded request. Informational only — not used for matching.
+    #[serde(default)]
+    request: serde_json::Value,
+    response: LlmResponse,
+}
+
+/// Computes the cassette key of a request: a hex SHA-1 of the messages and
+/// the names of the offered tools.
+pub fn request_key(messages: &[Message], tools: Option<&[ToolDefinition]>) -> String {
+    let tool_names: Vec<&str> = tools
+        .unwrap_or_default()
+        .iter()
+        .map(|t| t.name.as_str())
+        .collect();
+    let canonical = json!({ "messages": messages, "tools": tool_names });
+    hex::encode(Sha1::digest(canonical.to_string().as_bytes()))
+}
+
+// ── ReplayClient ────────────────────────────────────────
+
+/// Serves responses from a cassette file (`provider = "replay"`).
+pub struct ReplayClient {
+    path: PathBuf,
+    /// Remaining responses per request key, in recording order.
+    responses: Mutex<HashMap<String, VecDeque<LlmResponse>>>,
+}
+
+impl ReplayClient {
+    /// Loads a cassette file.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Cannot read cassette {}", path.display()))?;
+
+        let mut responses: HashMap<String, VecDeque<LlmResponse>> = HashMap::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let entry: CassetteEntry = serde_json::from_str(line)
+                .with_context(|| format!("Invalid cassette entry at {}:{}", path.display(), i + 1))?;
+            responses.entry(entry.key).or_default().push_back(entry.response);
+        }
+
+        info!(
+            "Loaded cassette {} ({} distinct requests)",
+            path.display(),
+            responses.len()
+        );
+        Ok(Self {
+            path,
+            responses: Mutex::new(responses),
+        })
+    }
+}
+
+#[async_trait]
+impl LlmClient for ReplayClient {
+    async fn complete(
+        &self,
+        _system_prompt: &str,
+        messages: &[Message],
+        tools: Option<&[ToolDefinition]>,
+        _params: &GenerationParams,
+    ) -> Result<LlmResponse> {
+        let key = request_key(messages, tools);
+        let response = self
+            .responses
+            .lock()
+            .unwrap()
+            .get_mut(&key)
+            .and_then(|queue| queue.pop_front());
+
+        debug!("Replaying request {key}");
+        response.ok_or_else(|| {
+            anyhow!(
+                "No recorded response for request {key} in {} (record it again with `record = true`)",
+                self.path.display()
+            )
+        })
+    }
+
+    fn description(&self) -> String {
+        format!("replay ({})", self.path.display())
+    }
+}
+
+// ── RecordingClient ─────────────────────────────────────
+
+/// Wraps a provider and appends every request/response pair to a cassette.
+pub struct RecordingClient {
+    inner: Arc<dyn LlmClient>,
+    path: PathBuf,
+    /// Serializes appends from concurrent requests.
+    write_lock: Mutex<()>,
+}
+
+impl RecordingClient {
+    pub fn new(inner: Arc<dyn LlmClient>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            inner,
+            path: path.into(),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    fn append(&self, entry: &CassetteEntry) -> Result<()> {
+        let line = serde_json::to_string(entry)?;
+        let _guard = self.write_lock.lock().unwrap();
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{line}")?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LlmClient for RecordingClient {
+    async fn complete(
+        &self,
+        system_prompt: &str,
+        messages: &[Message],
+        tools: Option<&[ToolDefinition]>,
+        params: &GenerationParams,
+    ) -> Result<LlmResponse> {
+        let response = self.inner.complete(system_prompt, messages, tools, params).await?;
+
+        let tool_names: Vec<&str> = tools
+            .unwrap_or_default()
+            .iter()
+            .map(|t| t.name.as_str())
+            .collect();
+        let entry = CassetteEntry {
+            key: request_key(messages, tools),
+            model: self.inner.description(),
+            request: json!({
+                "system": system_prompt,
+                "messages": messages,
+                "tools": tool_names,
+            }),
+            response,
+        };
+        self.append(&entry)
+            .with_context(|| format!("Cannot write cassette {}", self.path.display()))?;
+        debug!("Recorded request {} to {}", entry.key, self.path.display());
+
+        Ok(entry.response)
+    }
+
+    fn description(&self) -> String {
+        format!("{} [recording]", self.inner.description())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::{InputContentBlock, MessageContent, StopReason, ToolCall};
+
+    /// Fake provider returning a fixed response.
+    struct FixedLlm(LlmResponse);
+
+    #[async_trait]
+    impl LlmClient for FixedLlm {
+        async fn complete(
+            &self,
+            _system_prompt: &str,
+            _messages: &[Message],
+            _tools: Option<&[ToolDefinition]>,
+            _params: &GenerationParams,
+        ) -> Result<LlmResponse> {
+            Ok(self.0.clone())
+        }
+
+        fn description(&self) -> String {
+            "fixed (test)".to_string()
+        }
+    }
+
+    fn user(text: &str) -> Vec<Message> {
+        vec![Message {
+            role: "user".to_string(),
+            content: MessageContent::Text(text.to_string()),
+        }]
+    }
+
+    fn tool_response() -> LlmResponse {
+        let input = json!({"query": "weather Paris"});
+        LlmResponse {
+            text: String::new(),
+            tool_calls: vec![ToolCall {
+                id: "toolu_1".to_string(),
+                name: "web_search".to_string(),
+                input: input.clone(),
+            }],
+            stop_reason: StopReason::ToolUse,
+            input_tokens: 12,
+            output_tokens: 5,
+            content_blocks: vec![InputContentBlock::ToolUse {
+                id: "toolu_1".to_string(),
+                name: "web_search".to_string(),
+                input,
+            }],
+            thinking: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_request_key_ignores_system_prompt_but_not_tools() {
+        let messages = user("Hello");
+        let tools = [ToolDefinition {
+            name: "web_search".to_string(),
+            description: "Search".to_string(),
+            input_schema: json!({"type": "object"}),
+        }];
+        let key = request_key(&messages, None);
+        assert_eq!(key.len(), 40);
+        assert_eq!(key, request_key(&user("Hello"), None));
+        assert_ne!(key, request_key(&user("Hello!"), None));
+        assert_ne!(key, request_key(&messages, Some(&tools)));
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("cassettes/session.jsonl");
+        let params = GenerationParams::default();
+
+        let recorder = RecordingClient::new(Arc::new(FixedLlm(tool_response())), &path);
+        assert_eq!(recorder.description(), "fixed (test) [recording]");
+        recorder
+            .complete("prompt of monday", &user("Weather?"), None, &params)
+            .await
+            .unwrap();
+
+        let replay = ReplayClient::open(&path).unwrap();
+        // Different system prompt (e.g. another date): still matches
+        let response = replay
+            .complete("prompt of tuesday", &user("Weather?"), None, &params)
+            .await
+            .unwrap();
+        assert_eq!(response.stop_reason, StopReason::ToolUse);
+        assert_eq!(response.tool_calls[0].name, "web_search");
+        assert_eq!(response.tool_calls[0].input["query"], "weather Paris");
+        assert_eq!(response.input_tokens, 12);
+
+        // Each recorded response is served once
+        let err = replay
+            .complete("prompt", &user("Weather?"), None, &params)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("No recorded response"));
+    }
+
+    #[test]
+    fn test_replay_rejects_invalid_cassette() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("bad.jsonl");
+        fs::write(&path, "not json\n").unwrap();
+        let err = ReplayClient::open(&path).err().unwrap();
+        assert!(err.to_string().contains("bad.jsonl:1"));
+
+        assert!(ReplayClient::open(tmp.path().join("missing.jsonl")).is_err());
+    }
+}
@@ -0,0 +1,327 @@
+//! Scripted provider — deterministic LLM responses from a script file.
+//!
+//! `provider = "scripted"` plays the steps of `[llm] script` in order, one
+//! per LLM call, so the agentic loop, skills and XMPP output can be tested
+//! end to end without a model. The script is TOML (`[[step]]` tables) or
+//! JSONL (one step per line), chosen by file extension:
+//!
+//! ```toml
+//! [[step]]
+//! when = "weather"   # optional: the latest message must contain this
+//! tool_calls = [{ name = "web_search", input = { query = "weather Paris" } }]
+//!
+//! [[step]]
+//! text = "It is sunny in Paris."
+//! ```
+//!
+//! A step with `when` fails the call if the latest message (user text or
+//! tool result) does not contain it, so scripts double as assertions.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use tracing::debug;
+
+use super::client::LlmClient;
+use super::{
+    GenerationParams, InputContentBlock, LlmResponse, Message, MessageContent, StopReason,
+    ToolCall, ToolDefinition,
+};
+
+/// One scripted LLM response.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ScriptStep {
+    /// Substring the latest message must contain for this step to play.
+    #[serde(default)]
+    pub when: Option<String>,
+    /// Response text.
+    #[serde(default)]
+    pub text: String,
+    /// Tool calls to request. Non-empty means `stop_reason = tool_use`.
+    #[serde(default)]
+    pub tool_calls: Vec<ScriptToolCall>,
+    /// Reported token usage.
+    #[serde(default)]
+    pub input_tokens: u32,
+    #[serde(default)]
+    pub output_tokens: u32,
+}
+
+/// A tool call in a script step.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ScriptToolCall {
+    /// Tool use ID. Generated (`script_tool_{step}_{n}`) if absent.
+    #[serde(default)]
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default = "empty_input")]
+    pub input: serde_json::Value,
+}
+
+fn empty_input() -> serde_json::Value {
+    serde_json::json!({})
+}
+
+/// TOML script layout: `[[step]]` tables.
+#[derive(Debug, Deserialize)]
+struct TomlScript {
+    #[serde(default)]
+    step: Vec<ScriptStep>,
+}
+
+/// Parses a script: TOML when `toml` is true, JSONL otherwise.
+pub fn parse_script(content: &str, toml: bool) -> Result<Vec<ScriptStep>> {
+    if toml {
+        let script: TomlScript = toml::from_str(content)?;
+        return Ok(script.step);
+    }
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).with_context(|| format!("Invalid script step at line {}", i + 1))
+        })
+        .collect()
+}
+
+/// Plays a script of responses (`provider = "scripted"`).
+pub struct ScriptedClient {
+    path: PathBuf,
+    steps: Vec<ScriptStep>,
+    /// Index of the next step to play.
+    next: Mutex<usize>,
+}
+
+impl ScriptedClient {
+    /// Loads a script file (`.toml` → TOML, anything else → JSONL).
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Cannot read script {}", path.display()))?;
+        let is_toml = path.extension().is_some_and(|ext| ext == "toml");
+        let steps = parse_script(&content, is_toml)
+            .with_context(|| format!("Invalid script {}", path.display()))?;
+        Ok(Self::new(path, steps))
+    }
+
+    pub fn new(path: impl Into<PathBuf>, steps: Vec<ScriptStep>) -> Self {
+        Self {
+            path: path.into(),
+            steps,
+            next: Mutex::new(0),
+        }
+    }
+}
+
+/// Text of the latest message: plain text, or the concatenated text and
+/// tool results of a block message.
+fn latest_text(messages: &[Message]) -> String {
+    match messages.last().map(|m| &m.content) {
+        Some(MessageContent::Text(text)) => text.clone(),
+        Some(MessageContent::Blocks(blocks)) => blocks
+            .iter()
+            .filter_map(|b| match b {
+                InputContentBlock::Text { text } => Some(text.as_str()),
+                InputContentBlock::ToolResult { content, .. } => Some(content.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        None => String::new(),
+    }
+}
+
+/// Builds the response for a step. `index` is used for generated tool IDs.
+fn step_response(step: &ScriptStep, index: usize) -> LlmResponse {
+    let tool_calls: Vec<ToolCall> = step
+        .tool_calls
+        .iter()
+        .enumerate()
+        .map(|(n, tc)| ToolCall {
+            id: tc
+                .id
+                .clone()
+                .unwrap_or_else(|| format!("script_tool_{index}_{n}")),
+            name: tc.name.clone(),
+            input: tc.input.clone(),
+        })
+        .collect();
+
+    let mut content_blocks = Vec::new();
+    if !step.text.is_empty() {
+        content_blocks.push(InputContentBlock::Text {
+            text: step.text.clone(),
+        });
+    }
+    for tc in &tool_calls {
+        content_blocks.push(InputContentBlock::ToolUse {
+            id: tc.id.clone(),
+            name: tc.name.clone(),
+            input: tc.input.clone(),
+        });
+    }
+
+    LlmResponse {
+        text: step.text.clone(),
+        stop_reason: if tool_calls.is_empty() {
+            StopReason::EndTurn
+        } else {
+            StopReason::ToolUse
+        },
+        tool_calls,
+        input_tokens: step.input_tokens,
+        output_tokens: step.output_tokens,
+        content_blocks,
+        thinking: String::new(),
+    }
+}
+
+#[async_trait]
+impl LlmClient for ScriptedClient {
+    async fn complete(
+        &self,
+        _system_prompt: &str,
+        messages: &[Message],
+        _tools: Option<&[ToolDefinition]>,
+        _params: &GenerationParams,
+    ) -> Result<LlmResponse> {
+        let index = {
+            let mut next = self.next.lock().unwrap();
+            let index = *next;
+            *next += 1;
+            index
+        };
+        let step = self.steps.get(index).ok_or_else(|| {
+            anyhow!(
+                "Script {} exhausted after {} steps",
+                self.path.display(),
+                self.steps.len()
+            )
+        })?;
+
+        if let Some(expected) = &step.when {
+            let latest = latest_text(messages);
+            if !latest.contains(expected.as_str()) {
+                bail!(
+                    "Script step {} expects a message containing {expected:?}, got {latest:?}",
+                    index + 1
+                );
+            }
+        }
+
+        debug!("Playing script step {}/{}", index + 1, self.steps.len());
+        Ok(step_response(step, index))
+    }
+
+    fn description(&self) -> String {
+        format!("scripted ({})", self.path.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(text: &str) -> Vec<Message> {
+        vec![Message {
+            role: "user".to_string(),
+            content: MessageContent::Text(text.to_string()),
+        }]
+    }
+
+    #[test]
+    fn test_parse_toml_script() {
+        let toml = r#"
+            [[step]]
+            when = "weather"
+            tool_calls = [{ name = "web_search", input = { query = "weather Paris" } }]
+
+            [[step]]
+            text = "It is sunny."
+            output_tokens = 4
+        "#;
+        let steps = parse_script(toml, true).unwrap();
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].when.as_deref(), Some("weather"));
+        assert_eq!(steps[0].tool_calls[0].input["query"], "weather Paris");
+        assert_eq!(steps[1].text, "It is sunny.");
+        assert_eq!(steps[1].output_tokens, 4);
+    }
+
+    #[test]
+    fn test_parse_jsonl_script() {
+        let jsonl = r#"{"tool_calls": [{"id": "t1", "name": "url_fetch"}]}
+
+{"text": "Done."}
+"#;
+        let steps = parse_script(jsonl, false).unwrap();
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].tool_calls[0].id.as_deref(), Some("t1"));
+        assert_eq!(steps[0].tool_calls[0].input, serde_json::json!({}));
+
+        let err = parse_script("{\"text\": 1}", false).unwrap_err();
+        assert!(err.to_string().contains("line 1"));
+    }
+
+    #[tokio::test]
+    async fn test_scripted_client_plays_steps_in_order() {
+        let steps = parse_script(
+            r#"
+            [[step]]
+            tool_calls = [{ name = "web_search", input = { query = "rust" } }]
+
+            [[step]]
+            when = "results"
+            text = "Rust is a language."
+            "#,
+            true,
+        )
+        .unwrap();
+        let client = ScriptedClient::new("test.toml", steps);
+        let params = GenerationParams::default();
+
+        let first = client.complete("", &user("What is Rust?"), None, &params).await.unwrap();
+        assert_eq!(first.stop_reason, StopReason::ToolUse);
+        assert_eq!(first.tool_calls[0].id, "script_tool_0_0");
+        assert!(matches!(
+            &first.content_blocks[0],
+            InputContentBlock::ToolUse { name, .. } if name == "web_search"
+        ));
+
+        let tool_result = vec![Message {
+            role: "user".to_string(),
+            content: MessageContent::Blocks(vec![InputContentBlock::ToolResult {
+                tool_use_id: "script_tool_0_0".to_string(),
+                content: "search results: ...".to_string(),
+            }]),
+        }];
+        let second = client.complete("", &tool_result, None, &params).await.unwrap();
+        assert_eq!(second.stop_reason, StopReason::EndTurn);
+        assert_eq!(second.text, "Rust is a language.");
+
+        let err = client.complete("", &user("more"), None, &params).await.unwrap_err();
+        assert!(err.to_string().contains("exhausted after 2 steps"));
+    }
+
+    #[tokio::test]
+    async fn test_scripted_client_when_mismatch_fails() {
+        let steps = vec![ScriptStep {
+            when: Some("weather".to_string()),
+            text: "Sunny".to_string(),
+            tool_calls: vec![],
+            input_tokens: 0,
+            output_tokens: 0,
+        }];
+        let client = ScriptedClient::new("test.jsonl", steps);
+        let err = client
+            .complete("", &user("hello"), None, &GenerationParams::default())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("expects a message containing \"weather\""));
+    }
+}
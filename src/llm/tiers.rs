@@ -0,0 +1,682 @@
+//! Model tiers — route each request to a fast, standard, deep or vision model.
+//!
+//! The `[llm]` section defines the default model. Optional `[llm.tiers.*]`
+//! sections add named tiers, each backed by its own [`LlmClient`]. The
+//! [`LlmRegistry`] holds one client per configured tier and picks a tier
+//! for each request from cheap signals (see [`LlmRegistry::select_tier`]).
+//!
+//! When no tier is configured, the registry holds only the default client
+//! and routing is a no-op.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::config::{LlmConfig, RoutingConfig};
+
+use super::replay::{RecordingClient, ReplayClient};
+use super::scripted::ScriptedClient;
+use super::{AnthropicClient, GenerationParams, LlmClient, Message, MessageContent, OllamaClient};
+
+/// Marker the fast tier replies with when it wants to hand the request over.
+pub const ESCALATION_MARKER: &str = "ESCALATE";
+
+/// Instruction appended to the system prompt of the fast tier when
+/// escalation is enabled.
+const ESCALATION_INSTRUCTION: &str = "\n\nIf you are not confident that you can answer \
+    this request correctly (it needs deep reasoning, long analysis, or knowledge you are \
+    unsure about), reply with exactly ESCALATE and nothing else.";
+
+/// System prompt for the optional classifier call.
+const CLASSIFIER_PROMPT: &str = "You route chat requests to a model tier. \
+    Reply with exactly one word:\n\
+    - fast: greetings, small talk, simple facts, short answers\n\
+    - standard: typical questions and everyday tasks\n\
+    - deep: complex reasoning, long analysis, code design, multi-step problems";
+
+/// A named model tier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tier {
+    /// Cheap, low-latency model.
+    Fast,
+    /// General-purpose model (the top-level `[llm]` model by default).
+    Standard,
+    /// Strongest model for complex requests.
+    Deep,
+    /// Multi-modal model for messages with attachments.
+    Vision,
+}
+
+impl Tier {
+    /// All tiers, in display order.
+    pub const ALL: [Tier; 4] = [Tier::Fast, Tier::Standard, Tier::Deep, Tier::Vision];
+
+    /// Parses a tier name (case-insensitive).
+    pub fn parse(name: &str) -> Option<Tier> {
+        match name.to_lowercase().as_str() {
+            "fast" => Some(Tier::Fast),
+            "standard" => Some(Tier::Standard),
+            "deep" => Some(Tier::Deep),
+            "vision" => Some(Tier::Vision),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Tier::Fast => "fast",
+            Tier::Standard => "standard",
+            Tier::Deep => "deep",
+            Tier::Vision => "vision",
+        }
+    }
+
+    /// The tier a request is handed over to when this tier escalates.
+    pub fn escalate(&self) -> Option<Tier> {
+        match self {
+            Tier::Fast => Some(Tier::Standard),
+            Tier::Standard => Some(Tier::Deep),
+            Tier::Deep | Tier::Vision => None,
+        }
+    }
+}
+
+impl fmt::Display for Tier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Signals used by the router to pick a tier for one request.
+#[derive(Debug, Default)]
+pub struct RouteSignals<'a> {
+    /// Message text (after mention and trigger stripping).
+    pub body: &'a str,
+    /// The message carries file attachments.
+    pub has_attachments: bool,
+    /// Tier requested explicitly with a slash trigger (`/deep ...`).
+    pub trigger: Option<Tier>,
+    /// Tier pinned for the conversation with `/tier`.
+    pub pinned: Option<Tier>,
+    /// Tier configured for the room (`[[rooms]] tier = ...`).
+    pub room: Option<Tier>,
+    /// The room or user overrides the model (`model = ...`). The override
+    /// only makes sense on one provider, so it pins the standard tier.
+    pub model_override: bool,
+}
+
+/// Builds a client for a single (resolved) LLM configuration.
+///
+/// With `record = true`, the client is wrapped in a [`RecordingClient`]
+/// that appends every call to `cassette`.
+pub fn build_client(config: &LlmConfig) -> Result<Arc<dyn LlmClient>> {
+    if config.record && matches!(config.provider.as_str(), "replay" | "scripted") {
+        return Err(anyhow!("'record' requires a live provider (anthropic or ollama)"));
+    }
+    let client: Arc<dyn LlmClient> = match config.provider.as_str() {
+        "anthropic" => Arc::new(AnthropicClient::new(config.clone())),
+        "ollama" => Arc::new(OllamaClient::new(config.clone())),
+        "replay" => {
+            let cassette = config
+                .cassette
+                .as_ref()
+                .ok_or_else(|| anyhow!("provider 'replay' requires a 'cassette' file"))?;
+            Arc::new(ReplayClient::open(cassette)?)
+        }
+        "scripted" => {
+            let script = config
+                .script
+                .as_ref()
+                .ok_or_else(|| anyhow!("provider 'scripted' requires a 'script' file"))?;
+            Arc::new(ScriptedClient::open(script)?)
+        }
+        other => {
+            return Err(anyhow!(
+                "Unsupported LLM provider: '{other}'. \
+                 Supported: 'anthropic', 'ollama', 'replay', 'scripted'."
+            ))
+        }
+    };
+
+    if !config.record {
+        return Ok(client);
+    }
+    let cassette = config
+        .cassette
+        .as_ref()
+        .ok_or_else(|| anyhow!("'record' requires a 'cassette' file"))?;
+    Ok(Arc::new(RecordingClient::new(client, cassette)))
+}
+
+/// Provider and file of an offline (`scripted` or `replay`) client.
+type OfflineSource = (&'static str, PathBuf);
+
+/// The script or cassette an offline client plays, if any.
+fn offline_source(config: &LlmConfig) -> Option<OfflineSource> {
+    match config.provider.as_str() {
+        "scripted" => Some(("scripted", config.script.clone()?)),
+        "replay" => Some(("replay", config.cassette.clone()?)),
+        _ => None,
+    }
+}
+
+/// Registry of LLM clients, one per configured tier.
+pub struct LlmRegistry {
+    /// Client for the top-level `[llm]` model.
+    default: Arc<dyn LlmClient>,
+    /// Clients for explicitly configured tiers.
+    tiers: HashMap<Tier, Arc<dyn LlmClient>>,
+    routing: RoutingConfig,
+}
+
+impl LlmRegistry {
+    /// Creates a registry with a single default client and no tiers.
+    pub fn new(default: Arc<dyn LlmClient>) -> Self {
+        Self {
+            default,
+            tiers: HashMap::new(),
+            routing: RoutingConfig::default(),
+        }
+    }
+
+    /// Builds the default client and one client per configured tier.
+    ///
+    /// Tiers playing the same script or cassette share one client, so the
+    /// steps play in order across tiers (an escalation continues the
+    /// script instead of restarting it).
+    pub fn from_config(config: &LlmConfig) -> Result<Self> {
+        let default = build_client(config)?;
+        let mut offline: Vec<(OfflineSource, Arc<dyn LlmClient>)> = Vec::new();
+        if let Some(source) = offline_source(config) {
+            offline.push((source, Arc::clone(&default)));
+        }
+        let mut registry = Self::new(default).with_routing(config.routing.clone());
+        for tier in Tier::ALL {
+            if let Some(tier_config) = config.for_tier(tier) {
+                let source = offline_source(&tier_config);
+                let shared = offline.iter().find(|(s, _)| Some(s) == source.as_ref());
+                let client = match shared {
+                    Some((_, client)) => Arc::clone(client),
+                    None => build_client(&tier_config)
+                        .map_err(|e| anyhow!("Tier '{tier}': {e}"))?,
+                };
+                if let Some(source) = source {
+                    offline.push((source, Arc::clone(&client)));
+                }
+                registry = registry.with_tier(tier, client);
+            }
+        }
+        Ok(registry)
+    }
+
+    /// Adds (or replaces) the client for a tier.
+    pub fn with_tier(mut self, tier: Tier, client: Arc<dyn LlmClient>) -> Self {
+        self.tiers.insert(tier, client);
+        self
+    }
+
+    /// Replaces the routing configuration.
+    pub fn with_routing(mut self, routing: RoutingConfig) -> Self {
+        self.routing = routing;
+        self
+    }
+
+    /// Client for the top-level `[llm]` model.
+    pub fn default_client(&self) -> &Arc<dyn LlmClient> {
+        &self.default
+    }
+
+    pub fn routing(&self) -> &RoutingConfig {
+        &self.routing
+    }
+
+    /// Whether any tier is configured (routing enabled).
+    pub fn has_tiers(&self) -> bool {
+        !self.tiers.is_empty()
+    }
+
+    /// Whether a specific tier is configured. `standard` is always available.
+    pub fn has(&self, tier: Tier) -> bool {
+        tier == Tier::Standard || self.tiers.contains_key(&tier)
+    }
+
+    /// Returns the client for a tier.
+    ///
+    /// Unconfigured tiers fall back to `standard`, which itself falls back
+    /// to the default client.
+    pub fn get(&self, tier: Tier) -> &Arc<dyn LlmClient> {
+        self.tiers
+            .get(&tier)
+            .or_else(|| self.tiers.get(&Tier::Standard))
+            .unwrap_or(&self.default)
+    }
+
+    /// Available tiers with their provider/model, e.g. `"fast: ollama (llama3.2)"`.
+    pub fn describe_tiers(&self) -> Vec<String> {
+        Tier::ALL
+            .iter()
+            .filter(|t| self.has(**t))
+            .map(|t| format!("{t}: {}", self.get(*t).description()))
+            .collect()
+    }
+
+    /// Picks the tier from signals that do not require an LLM call.
+    ///
+    /// Order: explicit trigger, attachments (vision), pinned tier, room tier,
+    /// message length. Returns `None` when no signal decided.
+    pub fn route_static(&self, signals: &RouteSignals<'_>) -> Option<Tier> {
+        if !self.has_tiers() || signals.model_override {
+            return Some(Tier::Standard);
+        }
+        if let Some(tier) = signals.trigger {
+            return Some(tier);
+        }
+        if signals.has_attachments && self.has(Tier::Vision) {
+            return Some(Tier::Vision);
+        }
+        if let Some(tier) = signals.pinned.or(signals.room) {
+            return Some(tier);
+        }
+        if signals.body.is_empty() {
+            return Some(Tier::Standard);
+        }
+
+        let len = signals.body.chars().count();
+        if self.routing.deep_min_chars > 0
+            && len >= self.routing.deep_min_chars
+            && self.has(Tier::Deep)
+        {
+            return Some(Tier::Deep);
+        }
+        if self.routing.fast_max_chars > 0
+            && len <= self.routing.fast_max_chars
+            && self.has(Tier::Fast)
+        {
+            return Some(Tier::Fast);
+        }
+        None
+    }
+
+    /// Picks the tier for a request.
+    ///
+    /// Falls back to a classifier call to the fast model (if enabled in
+    /// `[llm.routing]`) and finally to `standard`.
+    pub async fn select_tier(&self, signals: &RouteSignals<'_>) -> Tier {
+        if let Some(tier) = self.route_static(signals) {
+            return tier;
+        }
+        if self.routing.classifier && self.has(Tier::Fast) {
+            match self.classify(signals.body).await {
+                Ok(Some(tier)) => {
+                    debug!("Classifier routed request to {tier} tier");
+                    return tier;
+                }
+                Ok(None) => debug!("Classifier gave no usable tier, using standard"),
+                Err(e) => warn!("Tier classifier failed: {e}"),
+            }
+        }
+        Tier::Standard
+    }
+
+    /// Asks the fast model which tier should handle `body`.
+    async fn classify(&self, body: &str) -> Result<Option<Tier>> {
+        let messages = [Message {
+            role: "user".to_string(),
+            content: MessageContent::Text(body.to_string()),
+        }];
+        let response = self
+            .get(Tier::Fast)
+            .complete(CLASSIFIER_PROMPT, &messages, None, &GenerationParams::default())
+            .await?;
+        Ok(parse_classifier_reply(&response.text))
+    }
+}
+
+/// Parses an explicit tier trigger at the start of a message.
+///
+/// `"/deep explain monads"` → `Some((Tier::Deep, "explain monads"))`.
+/// A trigger without text after it is not a trigger.
+pub fn parse_trigger(body: &str) -> Option<(Tier, &str)> {
+    let rest = body.strip_prefix('/')?;
+    let (name, text) = rest.split_once(char::is_whitespace)?;
+    let tier = Tier::parse(name)?;
+    let text = text.trim_start();
+    if text.is_empty() {
+        return None;
+    }
+    Some((tier, text))
+}
+
+/// Parses the classifier reply: the first word naming a tier.
+fn parse_classifier_reply(text: &str) -> Option<Tier> {
+    let word = text
+        .split_whitespace()
+        .next()?
+        .trim_matches(|c: char| !c.is_alphanumeric());
+    match Tier::parse(word)? {
+        Tier::Vision => None,
+        tier => Some(tier),
+    }
+}
+
+/// Appends the escalation instruction to the fast tier's system prompt.
+pub fn with_escalation_instruction(system_prompt: &str) -> String {
+    format!("{system_prompt}{ESCALATION_INSTRUCTION}")
+}
+
+/// Whether a response is an escalation request from the fast tier.
+pub fn is_escalation(text: &str) -> bool {
+    text.trim().trim_end_matches('.') == ESCALATION_MARKER
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LlmTiersConfig;
+
+    fn llm_config(provider: &str, model: &str) -> LlmConfig {
+        LlmConfig {
+            provider: provider.to_string(),
+            model: model.to_string(),
+            api_key: String::new(),
+            max_tokens_per_request: 1024,
+            host: None,
+            vision: false,
+            thinking_budget_tokens: None,
+            reasoning_summary: false,
+            generation: Default::default(),
+            cassette: None,
+            record: false,
+            script: None,
+            tiers: LlmTiersConfig::default(),
+            routing: RoutingConfig::default(),
+        }
+    }
+
+    fn client(model: &str) -> Arc<dyn LlmClient> {
+        Arc::new(OllamaClient::new(llm_config("ollama", model)))
+    }
+
+    fn tiered_registry() -> LlmRegistry {
+        LlmRegistry::new(client("default"))
+            .with_tier(Tier::Fast, client("small"))
+            .with_tier(Tier::Deep, client("large"))
+    }
+
+    fn signals(body: &str) -> RouteSignals<'_> {
+        RouteSignals {
+            body,
+            ..Default::default()
+        }
+    }
+
+    // ── Tier tests ───────────────────────────────────────
+
+    #[test]
+    fn test_tier_parse() {
+        assert_eq!(Tier::parse("fast"), Some(Tier::Fast));
+        assert_eq!(Tier::parse("DEEP"), Some(Tier::Deep));
+        assert_eq!(Tier::parse("Vision"), Some(Tier::Vision));
+        assert_eq!(Tier::parse("auto"), None);
+    }
+
+    #[test]
+    fn test_tier_escalate() {
+        assert_eq!(Tier::Fast.escalate(), Some(Tier::Standard));
+        assert_eq!(Tier::Standard.escalate(), Some(Tier::Deep));
+        assert_eq!(Tier::Deep.escalate(), None);
+    }
+
+    // ── Registry tests ───────────────────────────────────
+
+    #[test]
+    fn test_registry_get_falls_back_to_default() {
+        let registry = LlmRegistry::new(client("default"));
+        assert!(!registry.has_tiers());
+        assert!(registry.get(Tier::Deep).description().contains("default"));
+        assert!(registry.get(Tier::Standard).description().contains("default"));
+    }
+
+    #[test]
+    fn test_registry_get_falls_back_to_standard() {
+        let registry = tiered_registry().with_tier(Tier::Standard, client("mid"));
+        assert!(registry.get(Tier::Vision).description().contains("mid"));
+        assert!(registry.get(Tier::Fast).description().contains("small"));
+    }
+
+    #[test]
+    fn test_registry_from_config() {
+        let mut config = llm_config("anthropic", "claude-sonnet-4-5-20250929");
+        config.tiers.fast = Some(crate::config::LlmTierConfig {
+            provider: Some("ollama".to_string()),
+            model: "llama3.2".to_string(),
+            max_tokens_per_request: None,
+            api_key: None,
+            host: None,
+            thinking_budget_tokens: None,
+            vision: None,
+        });
+        let registry = LlmRegistry::from_config(&config).unwrap();
+        assert!(registry.has(Tier::Fast));
+        assert!(!registry.has(Tier::Deep));
+        assert_eq!(registry.get(Tier::Fast).description(), "ollama (llama3.2)");
+        assert_eq!(
+            registry.describe_tiers(),
+            vec![
+                "fast: ollama (llama3.2)".to_string(),
+                "standard: anthropic (claude-sonnet-4-5-20250929)".to_string(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_registry_from_config_shares_scripts_across_tiers() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let script = tmp.path().join("script.toml");
+        std::fs::write(&script, "[[step]]\ntext = \"one\"\n\n[[step]]\ntext = \"two\"\n").unwrap();
+        let mut config = llm_config("scripted", "none");
+        config.script = Some(script);
+        config.tiers.fast = Some(crate::config::LlmTierConfig {
+            provider: None,
+            model: "small".to_string(),
+            max_tokens_per_request: None,
+            api_key: None,
+            host: None,
+            thinking_budget_tokens: None,
+            vision: None,
+        });
+        let registry = LlmRegistry::from_config(&config).unwrap();
+
+        // The standard tier continues where the fast tier stopped
+        let messages = [Message {
+            role: "user".to_string(),
+            content: MessageContent::Text("hi".to_string()),
+        }];
+        let params = GenerationParams::default();
+        let fast = registry.get(Tier::Fast).complete("", &messages, None, &params).await.unwrap();
+        let standard = registry.get(Tier::Standard).complete("", &messages, None, &params).await.unwrap();
+        assert_eq!((fast.text.as_str(), standard.text.as_str()), ("one", "two"));
+    }
+
+    #[test]
+    fn test_registry_from_config_rejects_unknown_provider() {
+        let mut config = llm_config("anthropic", "claude-sonnet-4-5-20250929");
+        config.tiers.deep = Some(crate::config::LlmTierConfig {
+            provider: Some("openai".to_string()),
+            model: "gpt".to_string(),
+            max_tokens_per_request: None,
+            api_key: None,
+            host: None,
+            thinking_budget_tokens: None,
+            vision: None,
+        });
+        let err = LlmRegistry::from_config(&config).err().unwrap();
+        assert!(err.to_string().contains("Tier 'deep'"));
+    }
+
+    #[test]
+    fn test_build_client_offline_providers() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let script = tmp.path().join("script.toml");
+        std::fs::write(&script, "[[step]]\ntext = \"hi\"\n").unwrap();
+        let cassette = tmp.path().join("cassette.jsonl");
+
+        let mut config = llm_config("scripted", "none");
+        assert!(build_client(&config).err().unwrap().to_string().contains("'script'"));
+        config.script = Some(script);
+        assert!(build_client(&config).unwrap().description().starts_with("scripted ("));
+
+        let mut config = llm_config("replay", "none");
+        assert!(build_client(&config).err().unwrap().to_string().contains("'cassette'"));
+        config.cassette = Some(cassette.clone());
+        // Missing cassette file
+        assert!(build_client(&config).is_err());
+
+        // Recording wraps a live provider, never an offline one
+        let mut config = llm_config("ollama", "llama3.2");
+        config.record = true;
+        assert!(build_client(&config).is_err());
+        config.cassette = Some(cassette);
+        assert_eq!(
+            build_client(&config).unwrap().description(),
+            "ollama (llama3.2) [recording]"
+        );
+        config.provider = "replay".to_string();
+        assert!(build_client(&config).err().unwrap().to_string().contains("live provider"));
+    }
+
+    // ── Routing tests ────────────────────────────────────
+
+    #[test]
+    fn test_route_without_tiers_is_standard() {
+        let registry = LlmRegistry::new(client("default"));
+        let s = RouteSignals {
+            body: "hi",
+            trigger: Some(Tier::Deep),
+            ..Default::default()
+        };
+        assert_eq!(registry.route_static(&s), Some(Tier::Standard));
+    }
+
+    #[test]
+    fn test_route_trigger_wins() {
+        let registry = tiered_registry();
+        let s = RouteSignals {
+            body: "hi",
+            trigger: Some(Tier::Deep),
+            pinned: Some(Tier::Fast),
+            ..Default::default()
+        };
+        assert_eq!(registry.route_static(&s), Some(Tier::Deep));
+    }
+
+    #[test]
+    fn test_route_attachments_use_vision_only_if_configured() {
+        let s = RouteSignals {
+            body: "what is this?",
+            has_attachments: true,
+            ..Default::default()
+        };
+        // No vision tier: attachments are not a signal, length decides
+        assert_eq!(tiered_registry().route_static(&s), Some(Tier::Fast));
+
+        let registry = tiered_registry().with_tier(Tier::Vision, client("llava"));
+        assert_eq!(registry.route_static(&s), Some(Tier::Vision));
+    }
+
+    #[test]
+    fn test_route_model_override_pins_standard() {
+        let s = RouteSignals {
+            body: "hi",
+            trigger: Some(Tier::Deep),
+            model_override: true,
+            ..Default::default()
+        };
+        assert_eq!(tiered_registry().route_static(&s), Some(Tier::Standard));
+    }
+
+    #[test]
+    fn test_route_pinned_over_room() {
+        let registry = tiered_registry();
+        let s = RouteSignals {
+            body: "hello",
+            pinned: Some(Tier::Deep),
+            room: Some(Tier::Fast),
+            ..Default::default()
+        };
+        assert_eq!(registry.route_static(&s), Some(Tier::Deep));
+    }
+
+    #[test]
+    fn test_route_by_length() {
+        let registry = tiered_registry();
+        assert_eq!(registry.route_static(&signals("thanks!")), Some(Tier::Fast));
+        let long = "x".repeat(2500);
+        assert_eq!(registry.route_static(&signals(&long)), Some(Tier::Deep));
+        let medium = "x".repeat(500);
+        assert_eq!(registry.route_static(&signals(&medium)), None);
+    }
+
+    #[test]
+    fn test_route_length_disabled() {
+        let registry = tiered_registry().with_routing(RoutingConfig {
+            fast_max_chars: 0,
+            deep_min_chars: 0,
+            ..RoutingConfig::default()
+        });
+        assert_eq!(registry.route_static(&signals("hi")), None);
+    }
+
+    #[tokio::test]
+    async fn test_select_tier_defaults_to_standard() {
+        let registry = tiered_registry();
+        let medium = "x".repeat(500);
+        assert_eq!(registry.select_tier(&signals(&medium)).await, Tier::Standard);
+    }
+
+    // ── Trigger / classifier / escalation helpers ────────
+
+    #[test]
+    fn test_parse_trigger() {
+        assert_eq!(
+            parse_trigger("/deep explain monads"),
+            Some((Tier::Deep, "explain monads"))
+        );
+        assert_eq!(parse_trigger("/FAST  hi"), Some((Tier::Fast, "hi")));
+        assert_eq!(parse_trigger("/deep"), None);
+        assert_eq!(parse_trigger("/deep   "), None);
+        assert_eq!(parse_trigger("/status"), None);
+        assert_eq!(parse_trigger("deep thoughts"), None);
+    }
+
+    #[test]
+    fn test_parse_classifier_reply() {
+        assert_eq!(parse_classifier_reply("fast"), Some(Tier::Fast));
+        assert_eq!(parse_classifier_reply("Deep."), Some(Tier::Deep));
+        assert_eq!(parse_classifier_reply("  standard\n"), Some(Tier::Standard));
+        assert_eq!(parse_classifier_reply("vision"), None);
+        assert_eq!(parse_classifier_reply("I think deep"), None);
+        assert_eq!(parse_classifier_reply(""), None);
+    }
+
+    #[test]
+    fn test_is_escalation() {
+        assert!(is_escalation("ESCALATE"));
+        assert!(is_escalation("  ESCALATE.\n"));
+        assert!(!is_escalation("I will not ESCALATE this"));
+        assert!(!is_escalation("Paris"));
+    }
+
+    #[test]
+    fn test_with_escalation_instruction() {
+        let prompt = with_escalation_instruction("You are a bot.");
+        assert!(prompt.starts_with("You are a bot."));
+        assert!(prompt.contains(ESCALATION_MARKER));
+    }
+}
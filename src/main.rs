@@ -18,8 +18,11 @@ use crate::agent::memory::Memory;
 use crate::agent::runtime::AgentRuntime;
 use crate::backoff::Backoff;
 use crate::config::Config;
-use crate::llm::{AnthropicClient, LlmClient, OllamaClient};
-use crate::skills::builtin::{MemoryRecallSkill, MemoryStoreSkill, UrlFetchSkill, WebSearchSkill};
+use crate::llm::LlmRegistry;
+use crate::skills::builtin::{
+    FileReadSkill, MemoryRecallSkill, MemoryStoreSkill, ReactSkill, SendFileSkill, UrlFetchSkill,
+    WebSearchSkill,
+};
 use crate::skills::SkillRegistry;
 use crate::xmpp::component::DisconnectReason;
 
@@ -143,15 +146,12 @@ async fn main() -> Result<()> {
 
     // Initialize components that persist across reconnections
     let memory = Arc::new(Memory::open(&config.memory.path)?);
-    let llm: Arc<dyn LlmClient> = match config.llm.provider.as_str() {
-        "anthropic" => Arc::new(AnthropicClient::new(config.llm.clone())),
-        "ollama" => Arc::new(OllamaClient::new(config.llm.clone())),
-        other => {
-            return Err(anyhow!(
-                "Unsupported LLM provider: '{other}'. Supported: 'anthropic', 'ollama'."
-            ));
+    let llms = LlmRegistry::from_config(&config.llm)?;
+    if !config.llm.tiers.is_empty() {
+        for tier in llms.describe_tiers() {
+            info!("LLM tier {tier}");
         }
-    };
+    }
     let file_downloader = Arc::new(FileDownloader::with_tls_verify(3, config.server.tls_verify()));
     let mut skills = SkillRegistry::new();
 
@@ -179,6 +179,27 @@ async fn main() -> Result<()> {
         }
     }
 
+    if let Some(ref fr_config) = config.skills.file_read {
+        if fr_config.enabled {
+            info!("Registering builtin skill: file_read");
+            skills.register(Box::new(FileReadSkill::new(config.attachments.clone())));
+        }
+    }
+
+    if let Some(ref sf_config) = config.skills.send_file {
+        if sf_config.enabled {
+            info!("Registering builtin skill: send_file");
+            skills.register(Box::new(SendFileSkill::new(sf_config.max_size_bytes())));
+        }
+    }
+
+    if let Some(ref react_config) = config.skills.react {
+        if react_config.enabled {
+            info!("Registering builtin skill: react");
+            skills.register(Box::new(ReactSkill));
+        }
+    }
+
     info!("Skills: {} registered", skills.len());
     if config.keepalive.enabled {
         info!(
@@ -189,7 +210,7 @@ async fn main() -> Result<()> {
     } else {
         info!("Keepalive: disabled");
     }
-    let runtime = AgentRuntime::new(config.clone(), llm, memory, file_downloader, skills);
+    let runtime = AgentRuntime::new(config.clone(), llms, memory, file_downloader, skills);
 
     let mut backoff = Backoff::new(
         Duration::from_secs(2),
@@ -0,0 +1,284 @@
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use crate::agent::files::{self, file_to_content_block, DownloadedFile};
+use crate::agent::memory::{Memory, StoredFile};
+use crate::config::AttachmentsConfig;
+use crate::llm::InputContentBlock;
+use crate::skills::{Skill, SkillContext, SkillOutput};
+
+/// Skill that gives the LLM access to files sent in earlier turns.
+///
+/// Called without `file`, it lists the files stored for the current
+/// conversation partner. Called with a file name, it re-reads that file:
+/// images and PDFs are injected as content blocks after the tool result,
+/// other files are returned as extracted text.
+///
+/// Access is scoped to the invoking JID: only files listed in its own
+/// `files/` directory can be read — no paths are accepted.
+pub struct FileReadSkill {
+    attachments: AttachmentsConfig,
+}
+
+impl FileReadSkill {
+    /// Creates the skill. `attachments` controls image preprocessing,
+    /// as for files received in the current turn.
+    pub fn new(attachments: AttachmentsConfig) -> Self {
+        Self { attachments }
+    }
+
+    async fn read(&self, file: &StoredFile) -> anyhow::Result<SkillOutput> {
+        let mime_type = file
+            .mime_type
+            .clone()
+            .filter(|m| m != "unknown")
+            .unwrap_or_else(|| files::mime_from_extension(&file.filename));
+        let downloaded = DownloadedFile {
+            path: file.path.clone(),
+            filename: file.filename.clone(),
+            category: files::categorize(&mime_type, &file.filename),
+            mime_type,
+            size: file.size,
+        };
+
+        let Some(content) = file_to_content_block(&downloaded, &self.attachments).await? else {
+            return Ok(format!(
+                "File '{}' ({}) cannot be read: unsupported type.",
+                downloaded.filename, downloaded.mime_type
+            )
+            .into());
+        };
+
+        match content.block {
+            // Extracted text goes straight into the tool result
+            InputContentBlock::Text { text } => Ok(text.into()),
+            block => Ok(SkillOutput {
+                text: format!(
+                    "File '{}' ({}, {}) is attached below.",
+                    downloaded.filename,
+                    downloaded.mime_type,
+                    downloaded.human_size()
+                ),
+                content: vec![
+                    InputContentBlock::Text {
+                        text: format!("[File: {} ({})]", file.filename, file.stored_as),
+                    },
+                    block,
+                ],
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+/// Formats the file listing returned when no file is requested.
+fn format_listing(files: &[StoredFile]) -> String {
+    if files.is_empty() {
+        return "No files stored for this conversation.".to_string();
+    }
+    let mut out = format!("Stored files ({}, newest first):\n", files.len());
+    for file in files {
+        let mime = file.mime_type.as_deref().unwrap_or("unknown type");
+        let received = file
+            .received
+            .as_deref()
+            .map(|ts| format!(", received {ts}"))
+            .unwrap_or_default();
+        out.push_str(&format!(
+            "- {} — {} ({mime}, {}{received})\n",
+            file.stored_as,
+            file.filename,
+            files::format_size(file.size)
+        ));
+    }
+    out
+}
+
+/// Finds a file by stored name, or by original filename (newest match).
+pub(super) fn resolve<'a>(files: &'a [StoredFile], name: &str) -> Option<&'a StoredFile> {
+    files
+        .iter()
+        .find(|f| f.stored_as == name)
+        .or_else(|| files.iter().find(|f| f.filename.eq_ignore_ascii_case(name)))
+}
+
+#[async_trait]
+impl Skill for FileReadSkill {
+    fn name(&self) -> &str {
+        "file_read"
+    }
+
+    fn description(&self) -> &str {
+        "Access files the current conversation partner sent in earlier messages. \
+         Call without 'file' to list the stored files (newest first) with their stored \
+         name, original name, type, size and date. Call with 'file' set to a stored name \
+         (or an original filename) to look at that file again: images and PDFs are attached \
+         for you to view, other files are returned as text."
+    }
+
+    fn parameters_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "file": {
+                    "type": "string",
+                    "description": "Stored name (e.g. '3f2b…_invoice.pdf') or original filename \
+                                    of the file to read. Omit to list the stored files."
+                }
+            }
+        })
+    }
+
+    fn capabilities(&self) -> Vec<String> {
+        vec!["filesystem:files:read".to_string()]
+    }
+
+    async fn execute(&self, params: Value, context: &SkillContext) -> anyhow::Result<String> {
+        self.execute_with_content(params, context)
+            .await
+            .map(|output| output.text)
+    }
+
+    async fn execute_with_content(
+        &self,
+        params: Value,
+        context: &SkillContext,
+    ) -> anyhow::Result<SkillOutput> {
+        let memory = Memory::open(&context.base_path)?;
+        let files = memory.stored_files(&context.jid)?;
+
+        let name = params["file"].as_str().map(str::trim).unwrap_or_default();
+        if name.is_empty() {
+            return Ok(format_listing(&files).into());
+        }
+
+        let file = resolve(&files, name).ok_or_else(|| {
+            anyhow::anyhow!(
+                "No stored file named '{name}'. Call file_read without 'file' to list the files."
+            )
+        })?;
+        self.read(file).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::memory::Attachment;
+    use std::path::Path;
+
+    const JID: &str = "user@example.com";
+
+    fn test_context(dir: &Path, jid: &str) -> SkillContext {
+        SkillContext {
+            jid: jid.to_string(),
+            session: jid.to_string(),
+            base_path: dir.to_path_buf(),
+        }
+    }
+
+    fn skill() -> FileReadSkill {
+        FileReadSkill::new(AttachmentsConfig::default())
+    }
+
+    /// Stores a file for `jid` the way the runtime does: a copy in
+    /// `files/` and an attachment entry in the history.
+    fn store_file(dir: &Path, jid: &str, stored_as: &str, filename: &str, mime: &str, data: &[u8]) {
+        let memory = Memory::open(dir).unwrap();
+        std::fs::write(memory.files_dir(jid).unwrap().join(stored_as), data).unwrap();
+        memory
+            .store_message_full(
+                jid,
+                "user",
+                "",
+                None,
+                Some(jid),
+                Some(vec![Attachment {
+                    filename: filename.to_string(),
+                    mime_type: mime.to_string(),
+                    size: files::format_size(data.len() as u64),
+                    sent_size: None,
+                    stored_as: Some(stored_as.to_string()),
+                    url: None,
+                    transcript: None,
+                }]),
+                None,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_file_read_name_and_schema() {
+        let skill = skill();
+        assert_eq!(skill.name(), "file_read");
+        assert!(!skill.description().is_empty());
+        let schema = skill.parameters_schema();
+        assert!(schema["properties"]["file"].is_object());
+        assert!(schema.get("required").is_none());
+        assert_eq!(skill.capabilities(), vec!["filesystem:files:read"]);
+    }
+
+    #[tokio::test]
+    async fn test_file_read_lists_files() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let ctx = test_context(tmp.path(), JID);
+
+        let listing = skill().execute(json!({}), &ctx).await.unwrap();
+        assert_eq!(listing, "No files stored for this conversation.");
+
+        store_file(tmp.path(), JID, "a1_notes.txt", "notes.txt", "text/plain", b"hello");
+        let listing = skill().execute(json!({}), &ctx).await.unwrap();
+        assert!(listing.starts_with("Stored files (1, newest first):"));
+        assert!(listing.contains("- a1_notes.txt — notes.txt (text/plain, 5B, received "));
+    }
+
+    #[tokio::test]
+    async fn test_file_read_returns_text() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let ctx = test_context(tmp.path(), JID);
+        store_file(tmp.path(), JID, "a1_invoice.csv", "invoice.csv", "text/csv", b"item,total\nrent,1200\n");
+
+        // By stored name or by original filename
+        for name in ["a1_invoice.csv", "Invoice.CSV"] {
+            let output = skill()
+                .execute_with_content(json!({"file": name}), &ctx)
+                .await
+                .unwrap();
+            assert!(output.text.starts_with("[File: invoice.csv (text/csv, 21B)]"));
+            assert!(output.text.contains("| rent | 1200 |"));
+            assert!(output.content.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_read_injects_document() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let ctx = test_context(tmp.path(), JID);
+        store_file(tmp.path(), JID, "b2_scan.pdf", "scan.pdf", "application/pdf", b"%PDF-1.4 fake");
+
+        let output = skill()
+            .execute_with_content(json!({"file": "scan.pdf"}), &ctx)
+            .await
+            .unwrap();
+        assert_eq!(output.text, "File 'scan.pdf' (application/pdf, 13B) is attached below.");
+        assert_eq!(output.content.len(), 2);
+        assert!(matches!(
+            &output.content[1],
+            InputContentBlock::Document { source } if source.media_type == "application/pdf"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_file_read_is_scoped_to_jid() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        store_file(tmp.path(), "other@example.com", "c3_secret.txt", "secret.txt", "text/plain", b"secret");
+        let ctx = test_context(tmp.path(), JID);
+
+        for name in ["c3_secret.txt", "secret.txt", "../other@example.com/files/c3_secret.txt"] {
+            let err = skill().execute(json!({"file": name}), &ctx).await.unwrap_err();
+            assert!(err.to_string().contains("No stored file named"));
+        }
+        let listing = skill().execute(json!({}), &ctx).await.unwrap();
+        assert!(!listing.contains("secret"));
+    }
+}
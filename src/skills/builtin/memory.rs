@@ -120,6 +120,7 @@ mod tests {
     fn test_context(dir: &std::path::Path) -> SkillContext {
         SkillContext {
             jid: "user@example.com".to_string(),
+            session: "user@example.com".to_string(),
             base_path: dir.to_path_buf(),
         }
     }
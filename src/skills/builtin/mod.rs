@@ -1,7 +1,13 @@
+pub mod file_read;
 pub mod memory;
+pub mod react;
+pub mod send_file;
 pub mod url_fetch;
 pub mod web_search;
 
+pub use file_read::FileReadSkill;
 pub use memory::{MemoryRecallSkill, MemoryStoreSkill};
+pub use react::ReactSkill;
+pub use send_file::SendFileSkill;
 pub use url_fetch::UrlFetchSkill;
 pub use web_search::WebSearchSkill;
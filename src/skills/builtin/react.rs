@@ -0,0 +1,244 @@
+use anyhow::bail;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use crate::agent::memory::{Memory, MessageRef};
+use crate::skills::{OutgoingReaction, Skill, SkillContext, SkillOutput};
+
+/// Messages shown when the skill is called without an emoji.
+const LISTED_MESSAGES: usize = 20;
+
+/// Longest message excerpt in the listing, in characters.
+const EXCERPT_CHARS: usize = 80;
+
+/// Skill that lets the LLM react to a message with emojis (XEP-0444).
+///
+/// Without `msg_id`, the reaction goes to the message being answered.
+/// Called without `emoji`, it lists the recent messages of the
+/// conversation (or of the current thread) with their ids. The skill
+/// only returns the reaction: the runtime sends it after the reply and
+/// records it in history.
+pub struct ReactSkill;
+
+/// Splits the requested reaction into emojis, refusing text.
+fn parse_emojis(emoji: &str) -> anyhow::Result<Vec<String>> {
+    let emojis: Vec<String> = emoji.split_whitespace().map(String::from).collect();
+    if emojis.is_empty() || emojis.len() > 3 {
+        bail!("Provide one to three emojis");
+    }
+    for e in &emojis {
+        if e.chars().count() > 16
+            || e.is_ascii()
+            || e.chars().any(|c| c.is_ascii_alphabetic() || c.is_control())
+        {
+            bail!("'{e}' is not an emoji");
+        }
+    }
+    Ok(emojis)
+}
+
+/// Formats the message listing returned when no emoji is given.
+fn format_listing(messages: &[MessageRef]) -> String {
+    if messages.is_empty() {
+        return "No earlier messages with an id. Omit 'msg_id' to react to the current message."
+            .to_string();
+    }
+    let mut out = format!("Recent messages ({}, oldest first):\n", messages.len());
+    for message in messages {
+        let author = match (message.role.as_str(), message.sender.as_deref()) {
+            ("assistant", _) => "you",
+            (_, Some(sender)) => sender,
+            _ => "user",
+        };
+        let mut excerpt: String = message.content.chars().take(EXCERPT_CHARS).collect();
+        if excerpt.len() < message.content.len() {
+            excerpt.push('…');
+        }
+        out.push_str(&format!("- {} — {author}: {}\n", message.id, excerpt.replace('\n', " ")));
+    }
+    out
+}
+
+#[async_trait]
+impl Skill for ReactSkill {
+    fn name(&self) -> &str {
+        "react"
+    }
+
+    fn description(&self) -> &str {
+        "React to a message with an emoji, as chat clients show under the message. \
+         Without 'msg_id' the reaction goes to the message you are answering. To react \
+         to an earlier message, call without 'emoji' to list the recent messages and \
+         their ids, then call again with 'msg_id'. Use it sparingly, e.g. to acknowledge \
+         a message that needs no answer."
+    }
+
+    fn parameters_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "emoji": {
+                    "type": "string",
+                    "description": "Emoji to react with, e.g. '👍'. Up to three, separated by \
+                                    spaces. Omit to list the recent messages."
+                },
+                "msg_id": {
+                    "type": "string",
+                    "description": "Id of the message to react to, as listed by this tool. \
+                                    Omit to react to the current message."
+                }
+            }
+        })
+    }
+
+    fn capabilities(&self) -> Vec<String> {
+        vec!["xmpp:reactions".to_string()]
+    }
+
+    async fn execute(&self, params: Value, context: &SkillContext) -> anyhow::Result<String> {
+        self.execute_with_content(params, context)
+            .await
+            .map(|output| output.text)
+    }
+
+    async fn execute_with_content(
+        &self,
+        params: Value,
+        context: &SkillContext,
+    ) -> anyhow::Result<SkillOutput> {
+        let memory = Memory::open(&context.base_path)?;
+        // Messages of the current thread, if any
+        let messages = memory.message_refs(&context.session)?;
+
+        let emoji = params["emoji"].as_str().map(str::trim).unwrap_or_default();
+        if emoji.is_empty() {
+            let start = messages.len().saturating_sub(LISTED_MESSAGES);
+            return Ok(format_listing(&messages[start..]).into());
+        }
+        let emojis = parse_emojis(emoji)?;
+
+        let msg_id = params["msg_id"].as_str().map(str::trim).filter(|id| !id.is_empty());
+        let target = match msg_id {
+            Some(id) => {
+                if !messages.iter().any(|m| m.id == id) {
+                    bail!("No message with id '{id}'. Call react without 'emoji' to list the messages.");
+                }
+                format!("message {id}")
+            }
+            None => "the current message".to_string(),
+        };
+
+        Ok(SkillOutput {
+            text: format!("Reaction {} will be added to {target}.", emojis.join(" ")),
+            reactions: vec![OutgoingReaction {
+                message_id: msg_id.map(String::from),
+                emojis,
+            }],
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::memory::MessageMeta;
+    use std::path::Path;
+
+    const ROOM: &str = "room@muc.example.com";
+
+    fn test_context(dir: &Path) -> SkillContext {
+        SkillContext {
+            jid: ROOM.to_string(),
+            session: ROOM.to_string(),
+            base_path: dir.to_path_buf(),
+        }
+    }
+
+    #[test]
+    fn test_parse_emojis() {
+        assert_eq!(parse_emojis(" 👍 ").unwrap(), ["👍"]);
+        assert_eq!(parse_emojis("👀 ✅").unwrap(), ["👀", "✅"]);
+        assert_eq!(parse_emojis("👨‍👩‍👧").unwrap(), ["👨‍👩‍👧"]);
+        assert!(parse_emojis("").is_err());
+        assert!(parse_emojis("ok").is_err());
+        assert!(parse_emojis(":+1:").is_err());
+        assert!(parse_emojis("👍 👍 👍 👍").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_react_lists_and_targets_messages() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let ctx = test_context(tmp.path());
+        let memory = Memory::open(tmp.path()).unwrap();
+        memory
+            .store_room_message(
+                ROOM,
+                "Deploy is done",
+                Some("m1"),
+                MessageMeta::room(Some("s-1"), None),
+                "alice@muc",
+                None,
+            )
+            .unwrap();
+
+        let listing = ReactSkill.execute(json!({}), &ctx).await.unwrap();
+        assert!(listing.contains("- s-1 — alice@muc: Deploy is done"));
+
+        let output = ReactSkill
+            .execute_with_content(json!({"emoji": "🎉", "msg_id": "s-1"}), &ctx)
+            .await
+            .unwrap();
+        assert_eq!(output.text, "Reaction 🎉 will be added to message s-1.");
+        assert_eq!(
+            output.reactions,
+            [OutgoingReaction {
+                message_id: Some("s-1".to_string()),
+                emojis: vec!["🎉".to_string()],
+            }]
+        );
+
+        // Without msg_id, the runtime targets the message being answered
+        let output = ReactSkill
+            .execute_with_content(json!({"emoji": "👍"}), &ctx)
+            .await
+            .unwrap();
+        assert_eq!(output.reactions[0].message_id, None);
+
+        let err = ReactSkill
+            .execute(json!({"emoji": "👍", "msg_id": "nope"}), &ctx)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("No message with id 'nope'"));
+    }
+
+    #[tokio::test]
+    async fn test_react_in_thread_uses_thread_session() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let thread = Memory::thread_session(ROOM, Some("t-1"));
+        let ctx = SkillContext {
+            session: thread.clone(),
+            ..test_context(tmp.path())
+        };
+        let memory = Memory::open(tmp.path()).unwrap();
+        let meta = |stanza_id| MessageMeta::room(Some(stanza_id), None);
+        memory
+            .store_room_message(ROOM, "Lunch?", Some("m1"), meta("s-1"), "bob@muc", None)
+            .unwrap();
+        memory
+            .store_room_message(&thread, "Deploy is done", Some("m2"), meta("s-2"), "alice@muc", None)
+            .unwrap();
+
+        let listing = ReactSkill.execute(json!({}), &ctx).await.unwrap();
+        assert!(listing.contains("- s-2 — alice@muc: Deploy is done"));
+        assert!(!listing.contains("s-1"));
+        assert!(ReactSkill
+            .execute(json!({"emoji": "🎉", "msg_id": "s-2"}), &ctx)
+            .await
+            .is_ok());
+        assert!(ReactSkill
+            .execute(json!({"emoji": "🎉", "msg_id": "s-1"}), &ctx)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,329 @@
+use anyhow::{bail, Context};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use crate::agent::files;
+use crate::agent::memory::Memory;
+use crate::skills::{OutgoingFile, Skill, SkillContext, SkillOutput};
+
+use super::file_read::resolve;
+
+/// Skill that lets the LLM send a file to the conversation partner.
+///
+/// The file is either generated (`content`, e.g. a CSV export or a
+/// report) or one of the files stored for the current conversation
+/// partner (`file`, as listed by `file_read`). The skill only returns the
+/// file: the runtime uploads it with XEP-0363 after the reply is sent and
+/// shares the link as an OOB message.
+pub struct SendFileSkill {
+    max_size: u64,
+}
+
+impl SendFileSkill {
+    /// Creates the skill. Files larger than `max_size` bytes are refused.
+    pub fn new(max_size: u64) -> Self {
+        Self { max_size }
+    }
+
+    /// Loads a stored file of the invoking JID: original filename, data and MIME type.
+    fn stored(
+        &self,
+        context: &SkillContext,
+        name: &str,
+    ) -> anyhow::Result<(String, Vec<u8>, String)> {
+        let memory = Memory::open(&context.base_path)?;
+        let files = memory.stored_files(&context.jid)?;
+        let file = resolve(&files, name).ok_or_else(|| {
+            anyhow::anyhow!("No stored file named '{name}'. Use file_read to list the files.")
+        })?;
+        if file.size > self.max_size {
+            bail!(
+                "File '{}' is too large to send ({}, max {})",
+                file.filename,
+                files::format_size(file.size),
+                files::format_size(self.max_size)
+            );
+        }
+        let data = std::fs::read(&file.path)
+            .with_context(|| format!("Failed to read stored file '{}'", file.stored_as))?;
+        let mime_type = file
+            .mime_type
+            .clone()
+            .filter(|m| m != "unknown")
+            .unwrap_or_else(|| files::mime_from_extension(&file.filename));
+        Ok((file.filename.clone(), data, mime_type))
+    }
+}
+
+/// Keeps the last path component of a requested filename.
+fn clean_filename(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+    if name.is_empty() || name == "." || name == ".." || name.chars().any(char::is_control) {
+        return None;
+    }
+    Some(name.to_string())
+}
+
+#[async_trait]
+impl Skill for SendFileSkill {
+    fn name(&self) -> &str {
+        "send_file"
+    }
+
+    fn description(&self) -> &str {
+        "Send a file to the conversation partner. Either provide 'content' to send \
+         text you wrote as a file (e.g. a CSV export, a Markdown report, a script), or \
+         'file' to send one of the stored files listed by file_read. The file is \
+         delivered right after your reply, so mention it in your answer."
+    }
+
+    fn parameters_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "filename": {
+                    "type": "string",
+                    "description": "Name of the file as shown to the recipient, e.g. 'report.csv'. \
+                                    Defaults to the stored file's name when 'file' is set."
+                },
+                "content": {
+                    "type": "string",
+                    "description": "Text content of the file to send."
+                },
+                "file": {
+                    "type": "string",
+                    "description": "Stored name or original filename of a stored file to send \
+                                    instead of 'content'."
+                },
+                "mime_type": {
+                    "type": "string",
+                    "description": "MIME type of the file. Guessed from the filename if omitted."
+                }
+            }
+        })
+    }
+
+    fn capabilities(&self) -> Vec<String> {
+        vec!["xmpp:upload".to_string()]
+    }
+
+    async fn execute(&self, params: Value, context: &SkillContext) -> anyhow::Result<String> {
+        self.execute_with_content(params, context)
+            .await
+            .map(|output| output.text)
+    }
+
+    async fn execute_with_content(
+        &self,
+        params: Value,
+        context: &SkillContext,
+    ) -> anyhow::Result<SkillOutput> {
+        let requested = params["filename"].as_str().unwrap_or_default();
+        let stored_name = params["file"].as_str().map(str::trim).unwrap_or_default();
+
+        let (filename, data, guessed_mime) = match (params["content"].as_str(), stored_name) {
+            (Some(_), name) if !name.is_empty() => {
+                bail!("Provide either 'content' or 'file', not both")
+            }
+            (Some(content), _) => {
+                let filename = clean_filename(requested)
+                    .context("'filename' is required when sending 'content'")?;
+                if content.len() as u64 > self.max_size {
+                    bail!(
+                        "Content is too large to send ({}, max {})",
+                        files::format_size(content.len() as u64),
+                        files::format_size(self.max_size)
+                    );
+                }
+                let mime = match files::mime_from_extension(&filename).as_str() {
+                    // Generated content is text, whatever its extension
+                    "application/octet-stream" => "text/plain".to_string(),
+                    mime => mime.to_string(),
+                };
+                (filename, content.as_bytes().to_vec(), mime)
+            }
+            (None, "") => bail!("Provide either 'content' or 'file'"),
+            (None, name) => {
+                let (original, data, mime) = self.stored(context, name)?;
+                let filename = clean_filename(requested)
+                    .or_else(|| clean_filename(&original))
+                    .context("Invalid filename")?;
+                (filename, data, mime)
+            }
+        };
+
+        let mime_type = params["mime_type"]
+            .as_str()
+            .map(str::trim)
+            .filter(|m| !m.is_empty() && m.contains('/'))
+            .map(String::from)
+            .unwrap_or(guessed_mime);
+
+        Ok(SkillOutput {
+            text: format!(
+                "File '{filename}' ({mime_type}, {}) will be sent with your reply.",
+                files::format_size(data.len() as u64)
+            ),
+            content: Vec::new(),
+            files: vec![OutgoingFile {
+                filename,
+                mime_type,
+                data,
+            }],
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::memory::Attachment;
+    use std::path::Path;
+
+    const JID: &str = "user@example.com";
+
+    fn test_context(dir: &Path) -> SkillContext {
+        SkillContext {
+            jid: JID.to_string(),
+            session: JID.to_string(),
+            base_path: dir.to_path_buf(),
+        }
+    }
+
+    #[test]
+    fn test_send_file_name_and_schema() {
+        let skill = SendFileSkill::new(1024);
+        assert_eq!(skill.name(), "send_file");
+        let schema = skill.parameters_schema();
+        assert!(schema["properties"]["content"].is_object());
+        assert!(schema["properties"]["file"].is_object());
+        assert_eq!(skill.capabilities(), vec!["xmpp:upload"]);
+    }
+
+    #[test]
+    fn test_clean_filename() {
+        assert_eq!(clean_filename("report.csv").as_deref(), Some("report.csv"));
+        assert_eq!(
+            clean_filename("../../etc/passwd").as_deref(),
+            Some("passwd")
+        );
+        assert_eq!(
+            clean_filename("C:\\tmp\\a b.txt").as_deref(),
+            Some("a b.txt")
+        );
+        assert_eq!(clean_filename("dir/"), None);
+        assert_eq!(clean_filename(".."), None);
+        assert_eq!(clean_filename("a\nb"), None);
+    }
+
+    #[tokio::test]
+    async fn test_send_file_from_content() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let ctx = test_context(tmp.path());
+        let skill = SendFileSkill::new(1024);
+
+        let output = skill
+            .execute_with_content(
+                json!({"filename": "out/report.csv", "content": "a,b\n1,2\n"}),
+                &ctx,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            output.text,
+            "File 'report.csv' (text/csv, 8B) will be sent with your reply."
+        );
+        assert_eq!(output.files.len(), 1);
+        assert_eq!(output.files[0].filename, "report.csv");
+        assert_eq!(output.files[0].data, b"a,b\n1,2\n");
+
+        // Unknown extensions are sent as text; an explicit type wins
+        let output = skill
+            .execute_with_content(
+                json!({"filename": "script.py", "content": "print(1)"}),
+                &ctx,
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.files[0].mime_type, "text/plain");
+        let output = skill
+            .execute_with_content(
+                json!({"filename": "script.py", "content": "print(1)", "mime_type": "text/x-python"}),
+                &ctx,
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.files[0].mime_type, "text/x-python");
+    }
+
+    #[tokio::test]
+    async fn test_send_file_rejects_invalid_requests() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let ctx = test_context(tmp.path());
+        let skill = SendFileSkill::new(4);
+
+        let err = skill.execute(json!({}), &ctx).await.unwrap_err();
+        assert!(err.to_string().contains("either 'content' or 'file'"));
+        let err = skill
+            .execute(json!({"content": "abc"}), &ctx)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("'filename' is required"));
+        let err = skill
+            .execute(json!({"filename": "a.txt", "content": "too long"}), &ctx)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("too large"));
+        let err = skill
+            .execute(json!({"file": "missing.pdf"}), &ctx)
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("No stored file named 'missing.pdf'"));
+    }
+
+    #[tokio::test]
+    async fn test_send_file_from_stored_file() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let ctx = test_context(tmp.path());
+        let memory = Memory::open(tmp.path()).unwrap();
+        let data = b"%PDF-1.4 fake";
+        std::fs::write(memory.files_dir(JID).unwrap().join("c3_scan.pdf"), data).unwrap();
+        memory
+            .store_message_full(
+                JID,
+                "user",
+                "",
+                None,
+                Some(JID),
+                Some(vec![Attachment {
+                    filename: "scan.pdf".to_string(),
+                    mime_type: "application/pdf".to_string(),
+                    size: files::format_size(data.len() as u64),
+                    sent_size: None,
+                    stored_as: Some("c3_scan.pdf".to_string()),
+                    url: None,
+                    transcript: None,
+                }]),
+                None,
+            )
+            .unwrap();
+
+        let output = SendFileSkill::new(1024)
+            .execute_with_content(json!({"file": "c3_scan.pdf"}), &ctx)
+            .await
+            .unwrap();
+        let file = &output.files[0];
+        assert_eq!(file.filename, "scan.pdf");
+        assert_eq!(file.mime_type, "application/pdf");
+        assert_eq!(file.data, data);
+
+        let err = SendFileSkill::new(4)
+            .execute(json!({"file": "scan.pdf"}), &ctx)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("too large"));
+    }
+}
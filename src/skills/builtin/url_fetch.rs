@@ -225,6 +225,7 @@ mod tests {
     fn test_context() -> SkillContext {
         SkillContext {
             jid: "test@localhost".to_string(),
+            session: "test@localhost".to_string(),
             base_path: PathBuf::from("/tmp/test"),
         }
     }
@@ -233,6 +233,7 @@ mod tests {
         let skill = tavily_skill();
         let ctx = SkillContext {
             jid: "test@localhost".to_string(),
+            session: "test@localhost".to_string(),
             base_path: PathBuf::from("/tmp/test"),
         };
         let result = skill.execute(json!({}), &ctx).await;
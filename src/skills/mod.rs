@@ -5,6 +5,8 @@ use std::path::PathBuf;
 
 use async_trait::async_trait;
 
+use crate::llm::InputContentBlock;
+
 /// Runtime context passed to skill execution.
 ///
 /// Provides the invoking JID and memory base path so skills
@@ -12,10 +14,67 @@ use async_trait::async_trait;
 pub struct SkillContext {
     /// Bare JID of the conversation partner (user or room).
     pub jid: String,
+    /// Memory key of the current session: the JID, or one of its
+    /// threads (XEP-0201, see `Memory::thread_session`).
+    pub session: String,
     /// Base path of the memory store (same as `Memory.base_path`).
     pub base_path: PathBuf,
 }
 
+/// A file produced by a skill, to be sent to the conversation partner.
+///
+/// The runtime uploads it (XEP-0363 HTTP File Upload) after the reply is
+/// sent, and shares the link as an OOB message (XEP-0066).
+#[derive(Debug, Clone)]
+pub struct OutgoingFile {
+    /// File name shown to the recipient
+    pub filename: String,
+    /// MIME type sent as the upload's content type
+    pub mime_type: String,
+    /// File contents
+    pub data: Vec<u8>,
+}
+
+/// An emoji reaction (XEP-0444) requested by a skill.
+///
+/// The runtime adds the emojis to the agent's reactions on the message
+/// after the reply is sent, and records the update in history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutgoingReaction {
+    /// Message to react to, as listed by `Memory::message_refs`; `None`
+    /// for the message being answered
+    pub message_id: Option<String>,
+    /// Emojis to add
+    pub emojis: Vec<String>,
+}
+
+/// Result of a skill execution that carries content besides text.
+///
+/// `content` blocks (images, documents) are appended to the conversation
+/// right after the tool results of the round, so the LLM can look at them.
+/// `files` are sent to the conversation partner after the final reply,
+/// `reactions` right after it.
+#[derive(Debug, Default)]
+pub struct SkillOutput {
+    /// Text sent back as the `tool_result`.
+    pub text: String,
+    /// Extra content blocks to inject after the tool results.
+    pub content: Vec<InputContentBlock>,
+    /// Files to send to the conversation partner.
+    pub files: Vec<OutgoingFile>,
+    /// Reactions to send in the conversation.
+    pub reactions: Vec<OutgoingReaction>,
+}
+
+impl From<String> for SkillOutput {
+    fn from(text: String) -> Self {
+        Self {
+            text,
+            ..Default::default()
+        }
+    }
+}
+
 /// A skill that the LLM can invoke via tool_use.
 ///
 /// All skills (builtin, Wasm, MCP) implement this trait.
@@ -48,6 +107,17 @@ pub trait Skill: Send + Sync {
         params: serde_json::Value,
         context: &SkillContext,
     ) -> anyhow::Result<String>;
+
+    /// Like [`execute`](Skill::execute), but may also return content blocks
+    /// to inject into the conversation (see [`SkillOutput`]).
+    /// The agentic loop calls this method; the default wraps `execute`.
+    async fn execute_with_content(
+        &self,
+        params: serde_json::Value,
+        context: &SkillContext,
+    ) -> anyhow::Result<SkillOutput> {
+        self.execute(params, context).await.map(SkillOutput::from)
+    }
 }
 
 pub use registry::SkillRegistry;
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use crate::llm::ToolDefinition;
 
@@ -12,8 +13,12 @@ use super::Skill;
 ///
 /// Skills are registered at startup and never modified afterward.
 /// The registry is owned by `AgentRuntime` and accessed via `&self`.
+/// Skills are reference-counted so that restricted views (see [`subset`])
+/// can share them without re-instantiating.
+///
+/// [`subset`]: SkillRegistry::subset
 pub struct SkillRegistry {
-    skills: HashMap<String, Box<dyn Skill>>,
+    skills: HashMap<String, Arc<dyn Skill>>,
 }
 
 impl SkillRegistry {
@@ -28,7 +33,23 @@ impl SkillRegistry {
     /// it is replaced (last-write-wins).
     pub fn register(&mut self, skill: Box<dyn Skill>) {
         let name = skill.name().to_string();
-        self.skills.insert(name, skill);
+        self.skills.insert(name, Arc::from(skill));
+    }
+
+    /// Builds a registry containing only the named skills.
+    ///
+    /// Names that are not registered are ignored; callers that need to
+    /// reject them should check with [`get`](SkillRegistry::get) first.
+    pub fn subset(&self, names: &[String]) -> SkillRegistry {
+        let skills = names
+            .iter()
+            .filter_map(|name| {
+                self.skills
+                    .get(name)
+                    .map(|skill| (name.clone(), Arc::clone(skill)))
+            })
+            .collect();
+        SkillRegistry { skills }
     }
 
     /// Looks up a skill by name. Returns `None` if not found.
@@ -89,6 +110,7 @@ mod tests {
     fn test_context() -> SkillContext {
         SkillContext {
             jid: "test@localhost".to_string(),
+            session: "test@localhost".to_string(),
             base_path: PathBuf::from("/tmp/test"),
         }
     }
@@ -270,4 +292,17 @@ mod tests {
         assert!(result.is_err());
         assert_eq!(result.unwrap_err().to_string(), "intentional failure");
     }
+
+    #[test]
+    fn test_subset_keeps_only_named_skills() {
+        let mut registry = SkillRegistry::new();
+        registry.register(Box::new(DummySkill::new("alpha")));
+        registry.register(Box::new(DummySkill::new("beta")));
+        registry.register(Box::new(DummySkill::new("gamma")));
+
+        let subset = registry.subset(&["gamma".to_string(), "alpha".to_string(), "nope".to_string()]);
+        assert_eq!(subset.skill_names(), vec!["alpha", "gamma"]);
+        // The original registry is untouched
+        assert_eq!(registry.len(), 3);
+    }
 }
@@ -234,12 +234,9 @@ impl XmppClient {
             debug!("  roster: {jid}");
         }
 
-        // --- Phase 8: Send initial presence ---
-        tls_stream
-            .write_all(stanzas::build_initial_presence().as_bytes())
-            .await
-            .map_err(|e| XmppError::Transient(format!("Initial presence write: {e}")))?;
-        info!("Sent initial presence");
+        // --- Phase 8: Initial presence ---
+        // Sent by the runtime once connected, with entity capabilities
+        // (XEP-0115) computed from the registered skills.
 
         // --- Phase 9: Subscribe to allowed JIDs not already in roster ---
         let mut subscribed_count = 0;
@@ -348,6 +345,49 @@ impl XmppClient {
                                         .send(XmppEvent::Reaction(reaction))
                                         .await;
                                 }
+                                XmppStanza::Iq(iq) => {
+                                    debug!(
+                                        "Received IQ {} ({}) from {}",
+                                        iq.id,
+                                        iq.iq_type.as_str(),
+                                        iq.from.as_deref().unwrap_or("server")
+                                    );
+                                    let _ = event_tx_clone
+                                        .send(XmppEvent::Iq(iq))
+                                        .await;
+                                }
+                                XmppStanza::Retraction(retraction) => {
+                                    debug!(
+                                        "Received retraction from {} of msg {}",
+                                        retraction.from, retraction.message_id
+                                    );
+                                    let _ = event_tx_clone
+                                        .send(XmppEvent::Retraction(retraction))
+                                        .await;
+                                }
+                                XmppStanza::Marker(marker) => {
+                                    debug!(
+                                        "Received {} marker from {} for msg {}",
+                                        marker.marker.as_str(), marker.from, marker.message_id
+                                    );
+                                    let _ = event_tx_clone
+                                        .send(XmppEvent::Marker(marker))
+                                        .await;
+                                }
+                                XmppStanza::Archived(archived) => {
+                                    let _ = event_tx_clone
+                                        .send(XmppEvent::Archived(archived))
+                                        .await;
+                                }
+                                XmppStanza::Carbon(carbon) => {
+                                    debug!(
+                                        "Received {:?} carbon: {} -> {}",
+                                        carbon.direction, carbon.message.from, carbon.message.to
+                                    );
+                                    let _ = event_tx_clone
+                                        .send(XmppEvent::Carbon(carbon))
+                                        .await;
+                                }
                                 XmppStanza::StreamError(condition) => {
                                     error!("Stream error received: {condition}");
                                     let _ = event_tx_clone
@@ -386,9 +426,30 @@ impl XmppClient {
                 }
 
                 let xml = match cmd {
-                    XmppCommand::SendMessage { to, body, id } => {
-                        stanzas::build_message(None, &to, &body, id.as_deref())
+                    XmppCommand::SendMessage { to, body, id, thread } => {
+                        stanzas::build_message(None, &to, &body, id.as_deref(), thread.as_deref())
+                    }
+                    XmppCommand::SendCorrection {
+                        to,
+                        body,
+                        id,
+                        replace_id,
+                    } => stanzas::build_correction(None, &to, &body, id.as_deref(), &replace_id),
+                    XmppCommand::SendReceipt { to, id } => {
+                        stanzas::build_receipt(None, &to, &id)
                     }
+                    XmppCommand::SendMarker {
+                        to,
+                        id,
+                        marker,
+                        msg_type,
+                    } => stanzas::build_chat_marker(None, &to, &id, marker, &msg_type),
+                    XmppCommand::SendReaction {
+                        to,
+                        message_id,
+                        emojis,
+                        msg_type,
+                    } => stanzas::build_reactions(None, &to, &message_id, &emojis, &msg_type),
                     XmppCommand::SendChatState {
                         to,
                         state,
@@ -401,12 +462,40 @@ impl XmppClient {
                             stanzas::build_chat_state_paused(None, &to, &msg_type)
                         }
                     },
-                    XmppCommand::SendMucMessage { to, body, id } => {
-                        stanzas::build_muc_message(None, &to, &body, id.as_deref())
+                    XmppCommand::SendMucMessage {
+                        to,
+                        body,
+                        id,
+                        thread,
+                        reply,
+                        mention,
+                    } => stanzas::build_muc_message(
+                        None,
+                        &to,
+                        &body,
+                        id.as_deref(),
+                        thread.as_deref(),
+                        reply.as_ref(),
+                        mention.as_deref(),
+                    ),
+                    XmppCommand::JoinMuc { room, nick, history, caps } => {
+                        stanzas::build_muc_join(&room, &nick, None, &history, caps.as_ref())
                     }
-                    XmppCommand::JoinMuc { room, nick } => {
-                        stanzas::build_muc_join(&room, &nick, None)
+                    XmppCommand::SendPresence { caps } => {
+                        stanzas::build_presence(None, caps.as_ref())
                     }
+                    XmppCommand::SendIq {
+                        to,
+                        id,
+                        iq_type,
+                        payload,
+                    } => stanzas::build_iq(None, &to, &id, iq_type, &payload),
+                    XmppCommand::SendOobMessage {
+                        to,
+                        url,
+                        msg_type,
+                        id,
+                    } => stanzas::build_oob_message(None, &to, &url, &msg_type, id.as_deref()),
                     XmppCommand::SendRaw(raw) => raw,
                     XmppCommand::Ping => unreachable!(),
                 };
@@ -9,7 +9,10 @@ use tracing::{debug, error, info, warn};
 
 use quick_xml::events::Event;
 
-use super::stanzas::{self, IncomingMessage, IncomingPresence, IncomingReaction, StanzaParser, XmppStanza};
+use super::stanzas::{
+    self, ArchivedMessage, CarbonMessage, ChatMarker, EntityCaps, IncomingIq, IncomingMarker, IncomingMessage, IncomingPresence, IncomingReaction, IncomingRetraction, IqType, MessageReply, MucHistory, StanzaParser,
+    XmppStanza,
+};
 use super::XmppError;
 use crate::config::{ConnectionMode, ServerConfig};
 
@@ -20,6 +23,16 @@ pub enum XmppEvent {
     Message(IncomingMessage),
     Presence(IncomingPresence),
     Reaction(IncomingReaction),
+    /// A sender withdrew one of its messages (XEP-0424)
+    Retraction(IncomingRetraction),
+    /// A contact received or read one of our messages (XEP-0184, XEP-0333)
+    Marker(IncomingMarker),
+    /// An IQ addressed to the agent (requests, and results of our own requests)
+    Iq(IncomingIq),
+    /// A message returned by an archive query (XEP-0313)
+    Archived(ArchivedMessage),
+    /// A message copied from another resource of the account (XEP-0280)
+    Carbon(CarbonMessage),
     /// A `<stream:error>` was received (e.g. `conflict`, `system-shutdown`).
     StreamError(String),
     Error(String),
@@ -35,6 +48,36 @@ pub enum XmppCommand {
         to: String,
         body: String,
         id: Option<String>,
+        /// Conversation thread (XEP-0201) of the message answered
+        thread: Option<String>,
+    },
+    /// Send a correction (XEP-0308) of a 1:1 message sent earlier
+    SendCorrection {
+        to: String,
+        body: String,
+        id: Option<String>,
+        /// Id of the original message
+        replace_id: String,
+    },
+    /// Acknowledge a message with a delivery receipt (XEP-0184)
+    SendReceipt { to: String, id: String },
+    /// Send a chat marker (XEP-0333) for a received message.
+    /// `msg_type` is `"chat"` for 1:1 or `"groupchat"` for MUC.
+    SendMarker {
+        to: String,
+        id: String,
+        marker: ChatMarker,
+        msg_type: String,
+    },
+    /// Send our reactions (XEP-0444) to a message, replacing the previous
+    /// ones (an empty list removes them). In rooms, `message_id` is the
+    /// stanza id assigned by the room.
+    /// `msg_type` is `"chat"` for 1:1 or `"groupchat"` for MUC.
+    SendReaction {
+        to: String,
+        message_id: String,
+        emojis: Vec<String>,
+        msg_type: String,
     },
     /// Send a chat state notification (XEP-0085) — composing, paused, etc.
     /// `msg_type` is `"chat"` for 1:1 or `"groupchat"` for MUC.
@@ -48,9 +91,37 @@ pub enum XmppCommand {
         to: String,
         body: String,
         id: Option<String>,
+        thread: Option<String>,
+        /// The occupant message answered (XEP-0461)
+        reply: Option<MessageReply>,
+        /// Occupant JID of the nick the body starts with (XEP-0372)
+        mention: Option<String>,
+    },
+    /// Join a MUC room (XEP-0045), announcing capabilities (XEP-0115)
+    JoinMuc {
+        room: String,
+        nick: String,
+        /// Discussion history to replay on join
+        history: MucHistory,
+        caps: Option<EntityCaps>,
+    },
+    /// Broadcast available presence, with capabilities (XEP-0115)
+    SendPresence { caps: Option<EntityCaps> },
+    /// Send an IQ; `payload` is the serialized child element
+    SendIq {
+        to: String,
+        id: String,
+        iq_type: IqType,
+        payload: String,
+    },
+    /// Share a file URL (XEP-0066 OOB), e.g. after an HTTP upload.
+    /// `msg_type` is `"chat"` for 1:1 or `"groupchat"` for MUC.
+    SendOobMessage {
+        to: String,
+        url: String,
+        msg_type: String,
+        id: Option<String>,
     },
-    /// Join a MUC room (XEP-0045)
-    JoinMuc { room: String, nick: String },
     SendRaw(String),
     /// Whitespace keepalive ping (RFC 6120 §4.6.1).
     /// The write task sends a single space character.
@@ -282,6 +353,40 @@ impl XmppComponent {
                                         .send(XmppEvent::Reaction(reaction))
                                         .await;
                                 }
+                                XmppStanza::Iq(iq) => {
+                                    debug!(
+                                        "Received IQ {} ({}) from {}",
+                                        iq.id,
+                                        iq.iq_type.as_str(),
+                                        iq.from.as_deref().unwrap_or("server")
+                                    );
+                                    let _ = event_tx_clone
+                                        .send(XmppEvent::Iq(iq))
+                                        .await;
+                                }
+                                XmppStanza::Retraction(retraction) => {
+                                    debug!(
+                                        "Received retraction from {} of msg {}",
+                                        retraction.from, retraction.message_id
+                                    );
+                                    let _ = event_tx_clone
+                                        .send(XmppEvent::Retraction(retraction))
+                                        .await;
+                                }
+                                XmppStanza::Marker(marker) => {
+                                    debug!(
+                                        "Received {} marker from {} for msg {}",
+                                        marker.marker.as_str(), marker.from, marker.message_id
+                                    );
+                                    let _ = event_tx_clone
+                                        .send(XmppEvent::Marker(marker))
+                                        .await;
+                                }
+                                XmppStanza::Archived(archived) => {
+                                    let _ = event_tx_clone
+                                        .send(XmppEvent::Archived(archived))
+                                        .await;
+                                }
                                 XmppStanza::StreamError(condition) => {
                                     error!("Stream error received: {condition}");
                                     let _ = event_tx_clone
@@ -289,7 +394,10 @@ impl XmppComponent {
                                         .await;
                                     break;
                                 }
-                                XmppStanza::Ignored | XmppStanza::StreamLevel => {}
+                                // Carbons (XEP-0280) only exist for client sessions
+                                XmppStanza::Carbon(_)
+                                | XmppStanza::Ignored
+                                | XmppStanza::StreamLevel => {}
                             }
                         }
                     }
@@ -320,9 +428,30 @@ impl XmppComponent {
                 }
 
                 let xml = match cmd {
-                    XmppCommand::SendMessage { to, body, id } => {
-                        stanzas::build_message(Some(&domain), &to, &body, id.as_deref())
+                    XmppCommand::SendMessage { to, body, id, thread } => {
+                        stanzas::build_message(Some(&domain), &to, &body, id.as_deref(), thread.as_deref())
+                    }
+                    XmppCommand::SendCorrection {
+                        to,
+                        body,
+                        id,
+                        replace_id,
+                    } => stanzas::build_correction(Some(&domain), &to, &body, id.as_deref(), &replace_id),
+                    XmppCommand::SendReceipt { to, id } => {
+                        stanzas::build_receipt(Some(&domain), &to, &id)
                     }
+                    XmppCommand::SendMarker {
+                        to,
+                        id,
+                        marker,
+                        msg_type,
+                    } => stanzas::build_chat_marker(Some(&domain), &to, &id, marker, &msg_type),
+                    XmppCommand::SendReaction {
+                        to,
+                        message_id,
+                        emojis,
+                        msg_type,
+                    } => stanzas::build_reactions(Some(&domain), &to, &message_id, &emojis, &msg_type),
                     XmppCommand::SendChatState {
                         to,
                         state,
@@ -335,12 +464,40 @@ impl XmppComponent {
                             stanzas::build_chat_state_paused(Some(&domain), &to, &msg_type)
                         }
                     },
-                    XmppCommand::SendMucMessage { to, body, id } => {
-                        stanzas::build_muc_message(Some(&domain), &to, &body, id.as_deref())
+                    XmppCommand::SendMucMessage {
+                        to,
+                        body,
+                        id,
+                        thread,
+                        reply,
+                        mention,
+                    } => stanzas::build_muc_message(
+                        Some(&domain),
+                        &to,
+                        &body,
+                        id.as_deref(),
+                        thread.as_deref(),
+                        reply.as_ref(),
+                        mention.as_deref(),
+                    ),
+                    XmppCommand::JoinMuc { room, nick, history, caps } => {
+                        stanzas::build_muc_join(&room, &nick, Some(&domain), &history, caps.as_ref())
                     }
-                    XmppCommand::JoinMuc { room, nick } => {
-                        stanzas::build_muc_join(&room, &nick, Some(&domain))
+                    XmppCommand::SendPresence { caps } => {
+                        stanzas::build_presence(Some(&domain), caps.as_ref())
                     }
+                    XmppCommand::SendIq {
+                        to,
+                        id,
+                        iq_type,
+                        payload,
+                    } => stanzas::build_iq(Some(&domain), &to, &id, iq_type, &payload),
+                    XmppCommand::SendOobMessage {
+                        to,
+                        url,
+                        msg_type,
+                        id,
+                    } => stanzas::build_oob_message(Some(&domain), &to, &url, &msg_type, id.as_deref()),
                     XmppCommand::SendRaw(raw) => raw,
                     XmppCommand::Ping => unreachable!(),
                 };
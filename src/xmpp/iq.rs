@@ -0,0 +1,544 @@
+/// IQ handling (RFC 6120 §8.2.3).
+///
+/// Outbound: the agent sends IQs (service discovery, upload slot requests)
+/// over the command channel and waits for the matching result. Results
+/// arrive as `XmppEvent::Iq` on the runtime's event loop, which hands them
+/// to [`IqTracker::resolve`].
+///
+/// Inbound: `get`/`set` requests are answered by [`IqRouter`] —
+/// disco#info/#items, ping, software version and entity time. Every other
+/// request gets a `service-unavailable` error, as RFC 6120 requires.
+///
+/// The runtime event loop is the only reader of incoming events, so
+/// [`IqTracker::request`] must not be awaited from the event loop itself —
+/// call it from a spawned task.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{Local, SecondsFormat, Utc};
+use tokio::sync::{mpsc, oneshot};
+use tracing::debug;
+
+use super::component::XmppCommand;
+use super::styling;
+use super::stanzas::{
+    self, DataForm, DiscoIdentity, DiscoInfo, EntityCaps, IncomingIq, IqType, NS_DISCO_INFO,
+    NS_DISCO_ITEMS, NS_PING, NS_TIME, NS_VERSION,
+};
+
+/// How long to wait for an IQ result
+const IQ_TIMEOUT_SECS: u64 = 15;
+
+/// A request waiting for its result
+struct Pending {
+    /// Bare JID the request was sent to
+    to: String,
+    reply: oneshot::Sender<IncomingIq>,
+}
+
+/// Matches IQ results to the requests that are waiting for them.
+pub struct IqTracker {
+    pending: Mutex<HashMap<String, Pending>>,
+    timeout: Duration,
+}
+
+impl IqTracker {
+    pub fn new() -> Self {
+        Self::with_timeout(Duration::from_secs(IQ_TIMEOUT_SECS))
+    }
+
+    /// Creates a tracker with a custom result timeout.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            pending: Mutex::new(HashMap::new()),
+            timeout,
+        }
+    }
+
+    /// Sends an IQ `get` or `set` to `to` and waits for the result.
+    ///
+    /// Returns the `result` IQ. Error IQs, timeouts and a closed
+    /// connection are returned as errors.
+    pub async fn request(
+        &self,
+        cmd_tx: &mpsc::Sender<XmppCommand>,
+        to: &str,
+        iq_type: IqType,
+        payload: String,
+    ) -> Result<IncomingIq> {
+        let id = format!("fa-{}", uuid::Uuid::new_v4().simple());
+        let (reply, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(
+            id.clone(),
+            Pending {
+                to: stanzas::bare_jid(to).to_string(),
+                reply,
+            },
+        );
+
+        let sent = cmd_tx
+            .send(XmppCommand::SendIq {
+                to: to.to_string(),
+                id: id.clone(),
+                iq_type,
+                payload,
+            })
+            .await;
+        if sent.is_err() {
+            self.pending.lock().unwrap().remove(&id);
+            bail!("XMPP connection closed");
+        }
+
+        let iq = match tokio::time::timeout(self.timeout, rx).await {
+            Ok(Ok(iq)) => iq,
+            Ok(Err(_)) => bail!("XMPP connection closed"),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                bail!("No response from {to} after {}s", self.timeout.as_secs());
+            }
+        };
+
+        if iq.iq_type == IqType::Error {
+            return Err(anyhow!(
+                "{to} returned an error: {}",
+                iq.error.as_deref().unwrap_or("undefined-condition")
+            ));
+        }
+        Ok(iq)
+    }
+
+    /// Hands a `result` or `error` IQ to the request waiting for it.
+    ///
+    /// Returns the IQ back if no request matches its id and sender
+    /// (e.g. requests from other entities), so the caller can handle it.
+    pub fn resolve(&self, iq: IncomingIq) -> Option<IncomingIq> {
+        if !matches!(iq.iq_type, IqType::Result | IqType::Error) {
+            return Some(iq);
+        }
+        let mut pending = self.pending.lock().unwrap();
+        let matches = pending.get(&iq.id).is_some_and(|p| {
+            // Results from our own server or account may omit `from`
+            iq.from
+                .as_deref()
+                .is_none_or(|from| stanzas::bare_jid(from) == p.to)
+        });
+        if !matches {
+            return Some(iq);
+        }
+        let Some(p) = pending.remove(&iq.id) else {
+            return Some(iq);
+        };
+        debug!("IQ {} answered by {}", iq.id, p.to);
+        // The requester may have timed out in the meantime
+        let _ = p.reply.send(iq);
+        None
+    }
+}
+
+impl Default for IqTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// ── Incoming requests ───────────────────────────────────
+
+/// Software name reported by XEP-0092
+const SOFTWARE_NAME: &str = "Fluux Agent";
+
+/// Reply to an incoming `get` or `set`
+#[derive(Debug, Clone, PartialEq)]
+pub struct IqReply {
+    pub iq_type: IqType,
+    pub payload: String,
+}
+
+impl IqReply {
+    /// A `result` carrying `payload` (may be empty)
+    pub fn result(payload: String) -> Self {
+        Self {
+            iq_type: IqType::Result,
+            payload,
+        }
+    }
+
+    /// An `error` with a defined condition (RFC 6120 §8.3.3)
+    pub fn error(error_type: &str, condition: &str) -> Self {
+        Self {
+            iq_type: IqType::Error,
+            payload: stanzas::build_stanza_error(error_type, condition),
+        }
+    }
+
+    /// The command sending this reply to the request's sender.
+    pub fn into_command(self, request: &IncomingIq) -> XmppCommand {
+        XmppCommand::SendIq {
+            // Requests from the own server carry no `from`
+            to: request.from.clone().unwrap_or_default(),
+            id: request.id.clone(),
+            iq_type: self.iq_type,
+            payload: self.payload,
+        }
+    }
+}
+
+/// Node announced in Entity Capabilities (XEP-0115)
+pub const CAPS_NODE: &str = "https://github.com/processone/fluux-agent";
+
+/// `FORM_TYPE` of the disco#info extension listing the agent's skills
+pub const NS_AGENT_SKILLS: &str = "urn:fluux:agent:skills:0";
+
+/// Protocol features the agent implements, advertised in disco#info
+const AGENT_FEATURES: [&str; 15] = [
+    NS_DISCO_INFO,
+    NS_DISCO_ITEMS,
+    stanzas::NS_CAPS,
+    NS_PING,
+    NS_VERSION,
+    NS_TIME,
+    stanzas::NS_COMMANDS,
+    "http://jabber.org/protocol/chatstates",
+    "http://jabber.org/protocol/muc",
+    "jabber:x:oob",
+    stanzas::NS_REACTIONS,
+    stanzas::NS_RECEIPTS,
+    stanzas::NS_CHAT_MARKERS,
+    stanzas::NS_REPLY,
+    styling::NS_STYLING,
+];
+
+/// What the agent advertises: a bot identity (`client/bot` over C2S,
+/// `component/automation` as a component), the protocol features it
+/// implements, and its skills as an extended info form (XEP-0128).
+pub fn agent_disco_info(name: &str, component: bool, skills: &[&str]) -> DiscoInfo {
+    let (category, kind) = if component {
+        ("component", "automation")
+    } else {
+        ("client", "bot")
+    };
+    let mut features: Vec<String> = AGENT_FEATURES.iter().map(|f| f.to_string()).collect();
+    features.sort();
+    let mut skills: Vec<String> = skills.iter().map(|s| s.to_string()).collect();
+    skills.sort();
+    DiscoInfo {
+        identities: vec![DiscoIdentity {
+            category: category.to_string(),
+            kind: kind.to_string(),
+            name: name.to_string(),
+        }],
+        features,
+        forms: vec![DataForm {
+            form_type: NS_AGENT_SKILLS.to_string(),
+            fields: vec![("skills".to_string(), skills)],
+        }],
+    }
+}
+
+/// Answers incoming IQ requests addressed to the agent.
+pub struct IqRouter {
+    info: DiscoInfo,
+    caps: EntityCaps,
+}
+
+impl IqRouter {
+    /// Creates a router advertising `info`. The capabilities hash is
+    /// computed from it, so a different skill set gives a different hash.
+    pub fn new(info: DiscoInfo) -> Self {
+        let caps = EntityCaps {
+            node: CAPS_NODE.to_string(),
+            ver: info.caps_ver(),
+        };
+        Self { info, caps }
+    }
+
+    /// Capabilities to attach to outbound presence.
+    pub fn caps(&self) -> &EntityCaps {
+        &self.caps
+    }
+
+    /// Builds the reply to a `get` or `set` request.
+    pub fn handle(&self, iq: &IncomingIq) -> IqReply {
+        let Some(payload) = iq.payload.as_ref() else {
+            return IqReply::error("modify", "bad-request");
+        };
+        let namespace = payload.namespace.as_deref().unwrap_or_default();
+        match (iq.iq_type, payload.name.as_str(), namespace) {
+            (IqType::Get, "query", NS_DISCO_INFO) => match payload.get_attr("node") {
+                // Caps lookups query `node#ver`; no other nodes are published
+                Some(node) if node != self.caps.disco_node() => {
+                    IqReply::error("cancel", "item-not-found")
+                }
+                node => IqReply::result(stanzas::build_disco_info_result(node, &self.info)),
+            },
+            (IqType::Get, "query", NS_DISCO_ITEMS) => {
+                IqReply::result(stanzas::build_disco_items_result(payload.get_attr("node")))
+            }
+            (IqType::Get, "ping", NS_PING) => IqReply::result(String::new()),
+            (IqType::Get, "query", NS_VERSION) => IqReply::result(stanzas::build_version_result(
+                SOFTWARE_NAME,
+                env!("CARGO_PKG_VERSION"),
+            )),
+            (IqType::Get, "time", NS_TIME) => {
+                let tzo = Local::now().format("%:z").to_string();
+                let utc = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+                IqReply::result(stanzas::build_time_result(&tzo, &utc))
+            }
+            _ => IqReply::error("cancel", "service-unavailable"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn response(id: &str, from: Option<&str>, iq_type: IqType) -> IncomingIq {
+        IncomingIq {
+            from: from.map(String::from),
+            to: "bot@localhost/agent".to_string(),
+            id: id.to_string(),
+            iq_type,
+            payload: None,
+            error: (iq_type == IqType::Error).then(|| "item-not-found".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_resolved_by_result() {
+        let tracker = Arc::new(IqTracker::new());
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(4);
+
+        let t = Arc::clone(&tracker);
+        let request = tokio::spawn(async move {
+            t.request(
+                &cmd_tx,
+                "upload.localhost",
+                IqType::Get,
+                stanzas::disco_info_query(),
+            )
+            .await
+        });
+
+        let Some(XmppCommand::SendIq {
+            to,
+            id,
+            iq_type,
+            payload,
+        }) = cmd_rx.recv().await
+        else {
+            panic!("expected SendIq");
+        };
+        assert_eq!(to, "upload.localhost");
+        assert_eq!(iq_type, IqType::Get);
+        assert!(payload.contains("disco#info"));
+
+        // A result from another entity with the same id is not accepted
+        let spoofed = response(&id, Some("evil@localhost"), IqType::Result);
+        assert!(tracker.resolve(spoofed).is_some());
+
+        let result = response(&id, Some("upload.localhost/res"), IqType::Result);
+        assert!(tracker.resolve(result).is_none());
+        let iq = request.await.unwrap().unwrap();
+        assert_eq!(iq.iq_type, IqType::Result);
+    }
+
+    #[tokio::test]
+    async fn test_request_error_and_timeout() {
+        let tracker = Arc::new(IqTracker::with_timeout(Duration::from_millis(50)));
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(4);
+
+        let t = Arc::clone(&tracker);
+        let tx = cmd_tx.clone();
+        let request = tokio::spawn(async move {
+            t.request(&tx, "localhost", IqType::Get, stanzas::disco_items_query())
+                .await
+        });
+        let Some(XmppCommand::SendIq { id, .. }) = cmd_rx.recv().await else {
+            panic!("expected SendIq");
+        };
+        // Results from the own server may omit `from`
+        assert!(tracker
+            .resolve(response(&id, None, IqType::Error))
+            .is_none());
+        let err = request.await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("item-not-found"));
+
+        let err = tracker
+            .request(
+                &cmd_tx,
+                "localhost",
+                IqType::Get,
+                stanzas::disco_items_query(),
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("No response from localhost"));
+        assert!(tracker.pending.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_resolve_ignores_requests() {
+        let tracker = IqTracker::new();
+        let iq = IncomingIq {
+            from: Some("user@localhost/phone".to_string()),
+            to: "bot@localhost".to_string(),
+            id: "q1".to_string(),
+            iq_type: IqType::Get,
+            payload: None,
+            error: None,
+        };
+        assert!(tracker.resolve(iq).is_some());
+    }
+
+    fn get(from: Option<&str>, name: &str, namespace: &str, attrs: &[(&str, &str)]) -> IncomingIq {
+        IncomingIq {
+            from: from.map(String::from),
+            to: "bot@localhost/agent".to_string(),
+            id: "r1".to_string(),
+            iq_type: IqType::Get,
+            payload: Some(stanzas::ChildElement {
+                name: name.to_string(),
+                namespace: Some(namespace.to_string()),
+                attrs: attrs
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                ..Default::default()
+            }),
+            error: None,
+        }
+    }
+
+    fn router() -> IqRouter {
+        IqRouter::new(agent_disco_info(
+            "Fluux",
+            false,
+            &["web_search", "file_read"],
+        ))
+    }
+
+    #[test]
+    fn test_router_answers_ping_and_disco() {
+        let router = router();
+
+        let ping = get(Some("localhost"), "ping", NS_PING, &[]);
+        let reply = router.handle(&ping);
+        assert_eq!(reply, IqReply::result(String::new()));
+        let XmppCommand::SendIq {
+            to, id, iq_type, ..
+        } = reply.into_command(&ping)
+        else {
+            panic!("expected SendIq");
+        };
+        assert_eq!(
+            (to.as_str(), id.as_str(), iq_type),
+            ("localhost", "r1", IqType::Result)
+        );
+
+        let reply = router.handle(&get(None, "query", NS_DISCO_INFO, &[]));
+        assert_eq!(reply.iq_type, IqType::Result);
+        assert!(reply
+            .payload
+            .contains("<identity category='client' type='bot' name='Fluux'/>"));
+        for feature in [NS_DISCO_INFO, NS_DISCO_ITEMS, NS_PING, NS_VERSION, NS_TIME] {
+            assert!(reply
+                .payload
+                .contains(&format!("<feature var='{feature}'/>")));
+        }
+
+        let reply = router.handle(&get(None, "query", NS_DISCO_INFO, &[("node", "unknown")]));
+        assert_eq!(reply, IqReply::error("cancel", "item-not-found"));
+
+        let reply = router.handle(&get(None, "query", NS_DISCO_ITEMS, &[]));
+        assert_eq!(reply.payload, format!("<query xmlns='{NS_DISCO_ITEMS}'/>"));
+    }
+
+    #[test]
+    fn test_router_advertises_skills_and_caps() {
+        let router = router();
+        let info = router.handle(&get(None, "query", NS_DISCO_INFO, &[]));
+        assert!(info
+            .payload
+            .contains(&format!("<feature var='{}'/>", stanzas::NS_CAPS)));
+        assert!(info.payload.contains(
+            "<field var='skills'><value>file_read</value><value>web_search</value></field>"
+        ));
+
+        // Caps lookups get the same info, with the node echoed
+        let node = router.caps().disco_node();
+        assert!(node.starts_with("https://github.com/processone/fluux-agent#"));
+        let reply = router.handle(&get(None, "query", NS_DISCO_INFO, &[("node", &node)]));
+        assert_eq!(reply.iq_type, IqType::Result);
+        assert!(reply.payload.contains(&format!("node='{node}'")));
+        assert!(reply
+            .payload
+            .ends_with(&info.payload[info.payload.find("><").unwrap()..]));
+
+        // The hash follows the skill set and the connection mode
+        let fewer = IqRouter::new(agent_disco_info("Fluux", false, &["web_search"]));
+        assert_ne!(fewer.caps().ver, router.caps().ver);
+        let reordered = IqRouter::new(agent_disco_info(
+            "Fluux",
+            false,
+            &["file_read", "web_search"],
+        ));
+        assert_eq!(reordered.caps(), router.caps());
+
+        let component = IqRouter::new(agent_disco_info("Fluux", true, &[]));
+        let reply = component.handle(&get(None, "query", NS_DISCO_INFO, &[]));
+        assert!(reply
+            .payload
+            .contains("<identity category='component' type='automation' name='Fluux'/>"));
+    }
+
+    #[test]
+    fn test_router_answers_version_and_time() {
+        let router = router();
+
+        let reply = router.handle(&get(Some("user@localhost/phone"), "query", NS_VERSION, &[]));
+        assert_eq!(
+            reply.payload,
+            format!(
+                "<query xmlns='{NS_VERSION}'><name>Fluux Agent</name><version>{}</version></query>",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        assert!(!reply.payload.contains("<os>"));
+
+        let reply = router.handle(&get(Some("user@localhost/phone"), "time", NS_TIME, &[]));
+        assert_eq!(reply.iq_type, IqType::Result);
+        assert!(reply.payload.contains("<tzo>"));
+        assert!(reply.payload.contains("Z</utc>"));
+    }
+
+    #[test]
+    fn test_router_rejects_unsupported_requests() {
+        let router = router();
+        let unavailable = IqReply::error("cancel", "service-unavailable");
+
+        // Unknown namespace
+        let reply = router.handle(&get(Some("user@localhost"), "query", "jabber:iq:last", &[]));
+        assert_eq!(reply, unavailable);
+        assert!(reply
+            .payload
+            .contains("<service-unavailable xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/>"));
+
+        // Known namespace, wrong type
+        let mut set = get(None, "ping", NS_PING, &[]);
+        set.iq_type = IqType::Set;
+        assert_eq!(router.handle(&set), unavailable);
+
+        // No payload at all
+        set.payload = None;
+        assert_eq!(router.handle(&set), IqReply::error("modify", "bad-request"));
+
+        // Requests from the own server are answered without `to`
+        let XmppCommand::SendIq { to, .. } = unavailable.into_command(&set) else {
+            panic!("expected SendIq");
+        };
+        assert!(to.is_empty());
+    }
+}
@@ -0,0 +1,305 @@
+/// Message Archive Management (XEP-0313) queries.
+///
+/// An archive query is an IQ `set` carrying a `queryid`. The server sends
+/// each matching message as a separate `<message/>` wrapping a `<result/>`,
+/// then answers the IQ with a `<fin/>` element holding the paging state
+/// (XEP-0059). Results arrive on the runtime event loop as
+/// `XmppEvent::Archived`, which hands them to [`MamClient::collect`].
+///
+/// Queries go through the [`IqTracker`], so they must run in a spawned
+/// task, never on the runtime event loop (see `agent::runtime`).
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+use super::component::XmppCommand;
+use super::iq::IqTracker;
+use super::stanzas::{self, ArchivedMessage, IqType, MamFin, MamQuery};
+
+/// Results received for a running query
+struct Collected {
+    /// Bare JID of the queried archive
+    archive: String,
+    /// Whether the archive is the account's own (results may omit `from`)
+    own: bool,
+    messages: Vec<ArchivedMessage>,
+}
+
+/// Runs archive queries and gathers their results.
+pub struct MamClient {
+    iq: Arc<IqTracker>,
+    pending: Mutex<HashMap<String, Collected>>,
+}
+
+impl MamClient {
+    pub fn new(iq: Arc<IqTracker>) -> Self {
+        Self {
+            iq,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Hands an archived message to the query waiting for it.
+    ///
+    /// Returns false if no running query matches its `queryid` and
+    /// archive (e.g. results forged by another entity).
+    pub fn collect(&self, message: ArchivedMessage) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        let Some(query) = pending.get_mut(&message.query_id) else {
+            return false;
+        };
+        let matches = match message.archive.as_deref() {
+            Some(from) => stanzas::bare_jid(from) == query.archive,
+            None => query.own,
+        };
+        if matches {
+            query.messages.push(message);
+        }
+        matches
+    }
+
+    /// Queries one page of the archive of `archive` (a room, or the
+    /// account's own bare JID when `own` is set).
+    ///
+    /// Returns the page's messages, oldest first, and the paging state.
+    pub async fn page(
+        &self,
+        cmd_tx: &mpsc::Sender<XmppCommand>,
+        archive: &str,
+        own: bool,
+        query: &MamQuery,
+    ) -> Result<(Vec<ArchivedMessage>, MamFin)> {
+        let query_id = uuid::Uuid::new_v4().simple().to_string();
+        self.pending.lock().unwrap().insert(
+            query_id.clone(),
+            Collected {
+                archive: stanzas::bare_jid(archive).to_string(),
+                own,
+                messages: Vec::new(),
+            },
+        );
+        let result = self
+            .iq
+            .request(
+                cmd_tx,
+                archive,
+                IqType::Set,
+                stanzas::build_mam_query(&query_id, query),
+            )
+            .await;
+        let collected = self.pending.lock().unwrap().remove(&query_id);
+        let iq = result?;
+        let fin = iq
+            .payload
+            .as_ref()
+            .and_then(stanzas::parse_mam_fin)
+            .ok_or_else(|| anyhow!("Invalid archive query result from {archive}"))?;
+        let messages = collected.map(|c| c.messages).unwrap_or_default();
+        debug!(
+            "Archive query on {archive}: {} message(s), complete: {}",
+            messages.len(),
+            fin.complete
+        );
+        Ok((messages, fin))
+    }
+
+    /// The `count` most recent messages, oldest first.
+    pub async fn recent(
+        &self,
+        cmd_tx: &mpsc::Sender<XmppCommand>,
+        archive: &str,
+        own: bool,
+        with: Option<&str>,
+        count: usize,
+    ) -> Result<Vec<ArchivedMessage>> {
+        let query = MamQuery {
+            with: with.map(String::from),
+            max: count,
+            last_page: true,
+            ..Default::default()
+        };
+        let (mut messages, _) = self.page(cmd_tx, archive, own, &query).await?;
+        // Servers may return more than requested
+        let start = messages.len().saturating_sub(count);
+        Ok(messages.split_off(start))
+    }
+
+    /// Messages sent since `start` (RFC 3339), oldest first, following
+    /// pages of `page_size` until the archive is exhausted or `limit`
+    /// messages were fetched.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn since(
+        &self,
+        cmd_tx: &mpsc::Sender<XmppCommand>,
+        archive: &str,
+        own: bool,
+        with: Option<&str>,
+        start: &str,
+        limit: usize,
+        page_size: usize,
+    ) -> Result<Vec<ArchivedMessage>> {
+        let mut query = MamQuery {
+            with: with.map(String::from),
+            start: Some(start.to_string()),
+            ..Default::default()
+        };
+        let mut messages = Vec::new();
+        while messages.len() < limit {
+            query.max = page_size.min(limit - messages.len()).max(1);
+            let (page, fin) = self.page(cmd_tx, archive, own, &query).await?;
+            let empty = page.is_empty();
+            messages.extend(page);
+            match fin.last {
+                Some(last) if !fin.complete && !empty => query.after = Some(last),
+                _ => break,
+            }
+        }
+        messages.truncate(limit);
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xmpp::stanzas::{ChildElement, IncomingIq, MessageType, NS_MAM, NS_RSM};
+
+    fn archived(query_id: &str, archive: Option<&str>, body: &str) -> ArchivedMessage {
+        ArchivedMessage {
+            query_id: query_id.to_string(),
+            archive_id: format!("a-{body}"),
+            archive: archive.map(String::from),
+            from: "lobby@conference.localhost/alice".to_string(),
+            to: "bot@localhost/agent".to_string(),
+            body: body.to_string(),
+            id: None,
+            message_type: MessageType::GroupChat,
+            stamp: Some("2026-10-18T10:00:00Z".to_string()),
+        }
+    }
+
+    fn fin(id: &str, from: &str, complete: bool, last: Option<&str>) -> IncomingIq {
+        let mut set = ChildElement {
+            name: "set".to_string(),
+            namespace: Some(NS_RSM.to_string()),
+            ..Default::default()
+        };
+        if let Some(last) = last {
+            set.children.push(ChildElement {
+                name: "last".to_string(),
+                text: last.to_string(),
+                ..Default::default()
+            });
+        }
+        IncomingIq {
+            from: Some(from.to_string()),
+            to: "bot@localhost/agent".to_string(),
+            id: id.to_string(),
+            iq_type: IqType::Result,
+            payload: Some(ChildElement {
+                name: "fin".to_string(),
+                namespace: Some(NS_MAM.to_string()),
+                attrs: vec![("complete".to_string(), complete.to_string())],
+                children: vec![set],
+                ..Default::default()
+            }),
+            error: None,
+        }
+    }
+
+    /// Answers the next query sent on `cmd_rx` with `bodies` and a `<fin/>`.
+    async fn answer(
+        mam: &MamClient,
+        iq: &IqTracker,
+        cmd_rx: &mut mpsc::Receiver<XmppCommand>,
+        bodies: &[&str],
+        complete: bool,
+    ) -> String {
+        let Some(XmppCommand::SendIq {
+            to, id, payload, ..
+        }) = cmd_rx.recv().await
+        else {
+            panic!("expected SendIq");
+        };
+        let query_id = stanzas::extract_attr(&payload, "queryid").unwrap();
+        // A result for an unknown query or from another room is dropped
+        assert!(!mam.collect(archived("other", Some(&to), "x")));
+        assert!(!mam.collect(archived(&query_id, Some("evil@conference.localhost"), "x")));
+        for body in bodies {
+            assert!(mam.collect(archived(&query_id, Some(&to), body)));
+        }
+        let last = bodies.last().map(|b| format!("a-{b}"));
+        assert!(iq
+            .resolve(fin(&id, &to, complete, last.as_deref()))
+            .is_none());
+        payload
+    }
+
+    #[tokio::test]
+    async fn test_since_follows_pages() {
+        let iq = Arc::new(IqTracker::new());
+        let mam = Arc::new(MamClient::new(Arc::clone(&iq)));
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(4);
+
+        let m = Arc::clone(&mam);
+        let query = tokio::spawn(async move {
+            m.since(
+                &cmd_tx,
+                "lobby@conference.localhost",
+                false,
+                None,
+                "2026-10-18T09:00:00Z",
+                3,
+                2,
+            )
+            .await
+        });
+
+        let first = answer(&mam, &iq, &mut cmd_rx, &["one", "two"], false).await;
+        assert!(first.contains("<value>2026-10-18T09:00:00Z</value>"));
+        assert!(first.contains("<max>2</max>"));
+        // The next page starts after the last result, and is capped by the limit
+        let second = answer(&mam, &iq, &mut cmd_rx, &["three"], false).await;
+        assert!(second.contains("<max>1</max><after>a-two</after>"));
+
+        let messages = query.await.unwrap().unwrap();
+        let bodies: Vec<&str> = messages.iter().map(|m| m.body.as_str()).collect();
+        assert_eq!(bodies, vec!["one", "two", "three"]);
+    }
+
+    #[tokio::test]
+    async fn test_recent_requests_last_page() {
+        let iq = Arc::new(IqTracker::new());
+        let mam = Arc::new(MamClient::new(Arc::clone(&iq)));
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(4);
+
+        let m = Arc::clone(&mam);
+        let query = tokio::spawn(async move {
+            m.recent(&cmd_tx, "bot@localhost", true, Some("alice@localhost"), 2)
+                .await
+        });
+
+        let Some(XmppCommand::SendIq {
+            to, id, payload, ..
+        }) = cmd_rx.recv().await
+        else {
+            panic!("expected SendIq");
+        };
+        assert_eq!(to, "bot@localhost");
+        assert!(payload.contains("<field var='with'><value>alice@localhost</value></field>"));
+        assert!(payload.contains("<max>2</max><before/>"));
+        let query_id = stanzas::extract_attr(&payload, "queryid").unwrap();
+        // The own archive may send results without `from`
+        for body in ["a", "b", "c"] {
+            assert!(mam.collect(archived(&query_id, None, body)));
+        }
+        assert!(iq.resolve(fin(&id, "bot@localhost", true, None)).is_none());
+
+        let messages = query.await.unwrap().unwrap();
+        let bodies: Vec<&str> = messages.iter().map(|m| m.body.as_str()).collect();
+        assert_eq!(bodies, vec!["b", "c"]);
+    }
+}
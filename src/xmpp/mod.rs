@@ -1,7 +1,12 @@
 pub mod client;
 pub mod component;
+pub mod iq;
+pub mod mam;
 pub mod sasl;
+pub mod upload;
+pub mod split;
 pub mod stanzas;
+pub mod styling;
 
 use std::fmt;
 use std::time::Duration;
@@ -0,0 +1,255 @@
+//! Splitting of oversized message bodies.
+//!
+//! Servers limit the size of a stanza (ejabberd's `max_stanza_size`,
+//! Prosody's `c2s_stanza_size_limit`) and drop the message, or close the
+//! stream, when it is exceeded. [`split_body`] cuts a long answer into
+//! parts sent as consecutive messages. Cuts fall between paragraphs and
+//! code blocks, never inside a fenced block: a block too long for one
+//! message is closed at the end of a part and reopened in the next, so
+//! every part renders on its own.
+
+use super::styling::{closes_fence, opening_fence};
+
+/// A paragraph or a fenced code block of a body.
+#[derive(Debug)]
+enum Block<'a> {
+    Text(Vec<&'a str>),
+    Code {
+        open: &'a str,
+        lines: Vec<&'a str>,
+        /// Closing fence line, added when the body leaves the block open
+        close: String,
+    },
+}
+
+/// Splits `body` into parts of at most `max_bytes` bytes (UTF-8).
+///
+/// A body that fits is returned unchanged; `max_bytes == 0` disables
+/// splitting. Consecutive blank lines between blocks become one.
+pub fn split_body(body: &str, max_bytes: usize) -> Vec<String> {
+    if max_bytes == 0 || body.len() <= max_bytes {
+        return vec![body.to_string()];
+    }
+    let pieces: Vec<String> = blocks(body)
+        .into_iter()
+        .flat_map(|block| fit_block(block, max_bytes))
+        .collect();
+    pack(pieces, "\n\n", max_bytes)
+}
+
+/// Cuts a body into paragraphs and fenced code blocks.
+fn blocks(body: &str) -> Vec<Block<'_>> {
+    let mut blocks = Vec::new();
+    let mut paragraph = Vec::new();
+    let mut lines = body.lines();
+
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim_start();
+        let Some(fence) = opening_fence(trimmed) else {
+            if trimmed.is_empty() {
+                if !paragraph.is_empty() {
+                    blocks.push(Block::Text(std::mem::take(&mut paragraph)));
+                }
+            } else {
+                paragraph.push(line);
+            }
+            continue;
+        };
+
+        if !paragraph.is_empty() {
+            blocks.push(Block::Text(std::mem::take(&mut paragraph)));
+        }
+        // Lines indented deeper than the opening fence are content (e.g.
+        // the escaped nested fences of a Message Styling block)
+        let indent = line.len() - trimmed.len();
+        let mut code = Vec::new();
+        let mut close = None;
+        for line in lines.by_ref() {
+            let trimmed = line.trim_start();
+            if line.len() - trimmed.len() <= indent && closes_fence(trimmed, fence) {
+                close = Some(line.to_string());
+                break;
+            }
+            code.push(line);
+        }
+        blocks.push(Block::Code {
+            open: line,
+            lines: code,
+            close: close.unwrap_or_else(|| format!("{}{fence}", &line[..indent])),
+        });
+    }
+    if !paragraph.is_empty() {
+        blocks.push(Block::Text(paragraph));
+    }
+    blocks
+}
+
+/// Renders a block as one or more pieces of at most `max_bytes`.
+fn fit_block(block: Block<'_>, max_bytes: usize) -> Vec<String> {
+    match block {
+        Block::Text(lines) => {
+            let text = lines.join("\n");
+            if text.len() <= max_bytes {
+                return vec![text];
+            }
+            let lines = lines.iter().flat_map(|line| split_line(line, max_bytes)).collect();
+            pack(lines, "\n", max_bytes)
+        }
+        Block::Code { open, lines, close } => {
+            let whole = std::iter::once(open)
+                .chain(lines.iter().copied())
+                .chain(std::iter::once(close.as_str()))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let overhead = open.len() + close.len() + 2;
+            if whole.len() <= max_bytes {
+                return vec![whole];
+            }
+            // Too small to hold the fences: cut it as text
+            if overhead >= max_bytes / 2 {
+                return fit_block(Block::Text(whole.lines().collect()), max_bytes);
+            }
+            let budget = max_bytes - overhead;
+            let lines = lines.iter().flat_map(|line| split_line(line, budget)).collect();
+            pack(lines, "\n", budget)
+                .into_iter()
+                .map(|code| format!("{open}\n{code}\n{close}"))
+                .collect()
+        }
+    }
+}
+
+/// Joins `pieces` with `separator` into as few parts of at most
+/// `max_bytes` as possible, keeping their order.
+fn pack(pieces: Vec<String>, separator: &str, max_bytes: usize) -> Vec<String> {
+    let mut parts = Vec::new();
+    // Pieces can be empty (blank lines of a code block)
+    let mut current: Option<String> = None;
+    for piece in pieces {
+        current = match current {
+            Some(mut part) if part.len() + separator.len() + piece.len() <= max_bytes => {
+                part.push_str(separator);
+                part.push_str(&piece);
+                Some(part)
+            }
+            Some(part) => {
+                parts.push(part);
+                Some(piece)
+            }
+            None => Some(piece),
+        };
+    }
+    parts.extend(current);
+    parts
+}
+
+/// Cuts a line longer than `max_bytes` at spaces, or anywhere (on a
+/// character boundary) when a word is too long. A character wider than
+/// `max_bytes` becomes a piece of its own.
+fn split_line(line: &str, max_bytes: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut rest = line;
+    while rest.len() > max_bytes {
+        let mut end = max_bytes;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        let cut = match rest[..end].rfind(' ') {
+            Some(space) if space > 0 => space,
+            _ => end,
+        };
+        pieces.push(rest[..cut].to_string());
+        rest = rest[cut..].trim_start_matches(' ');
+    }
+    if !rest.is_empty() || pieces.is_empty() {
+        pieces.push(rest.to_string());
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_fits(parts: &[String], max_bytes: usize) {
+        for part in parts {
+            assert!(part.len() <= max_bytes, "part too long: {part:?}");
+        }
+    }
+
+    #[test]
+    fn test_short_body_is_unchanged() {
+        let body = "First.\n\n\nSecond.";
+        assert_eq!(split_body(body, 100), [body]);
+        assert_eq!(split_body(&"x".repeat(500), 0).len(), 1);
+    }
+
+    #[test]
+    fn test_splits_between_paragraphs() {
+        let body = "Alpha alpha.\n\nBeta beta.\n\nGamma gamma.";
+        let parts = split_body(body, 26);
+        assert_eq!(parts, ["Alpha alpha.\n\nBeta beta.", "Gamma gamma."]);
+    }
+
+    #[test]
+    fn test_code_block_is_never_cut_inside() {
+        let body = "Intro.\n\n```rust\nfn a() {}\n\nfn b() {}\n```\n\nOutro.";
+        let parts = split_body(body, 36);
+        assert_eq!(
+            parts,
+            ["Intro.", "```rust\nfn a() {}\n\nfn b() {}\n```", "Outro."]
+        );
+    }
+
+    #[test]
+    fn test_long_code_block_is_reopened() {
+        let code: Vec<String> = (0..12)
+            .map(|i| if i == 5 { String::new() } else { format!("line {i}") })
+            .collect();
+        let body = format!("```\n{}\n```", code.join("\n"));
+        let parts = split_body(&body, 40);
+        assert!(parts.len() > 1);
+        assert_fits(&parts, 40);
+        for part in &parts {
+            assert!(part.starts_with("```\n") && part.ends_with("\n```"), "{part:?}");
+        }
+        let lines: Vec<&str> = parts
+            .iter()
+            .flat_map(|p| p.lines())
+            .filter(|l| *l != "```")
+            .collect();
+        assert_eq!(lines, code);
+    }
+
+    #[test]
+    fn test_nested_fence_in_styled_block() {
+        // Message Styling output escapes nested fences with a space
+        let body = "```\n ```\ninner\n ```\n```\n\nAfter the block.";
+        let parts = split_body(body, 30);
+        assert_eq!(parts, ["```\n ```\ninner\n ```\n```", "After the block."]);
+    }
+
+    #[test]
+    fn test_long_lines_cut_at_spaces_and_char_boundaries() {
+        let body = "word ".repeat(20);
+        let parts = split_body(body.trim_end(), 24);
+        assert_fits(&parts, 24);
+        assert!(parts.iter().all(|p| !p.starts_with(' ') && p.split(' ').all(|w| w == "word")));
+
+        let parts = split_body(&"é".repeat(30), 15);
+        assert_fits(&parts, 15);
+        assert_eq!(parts.concat(), "é".repeat(30));
+    }
+
+    #[test]
+    fn test_chars_wider_than_limit_still_advance() {
+        assert_eq!(split_body("😀😀 ok", 3), ["😀", "😀", "ok"]);
+        let parts = split_body("```
+😀😀😀😀😀😀
+```", 9);
+        assert!(parts.concat().matches('😀').count() == 6, "{parts:?}");
+    }
+}
@@ -1,6 +1,7 @@
 /// XMPP stanza building and parsing.
 /// Uses quick-xml for XML parsing and escaping.
 use std::borrow::Cow;
+use std::collections::HashMap;
 
 use quick_xml::escape::escape;
 
@@ -29,6 +30,33 @@ pub struct IncomingMessage {
     /// Out-of-Band Data (XEP-0066) — file attachment URLs from HTTP Upload.
     /// A single message can contain multiple OOB elements (multiple files).
     pub oob: Vec<OobData>,
+    /// Original send time (XEP-0203) of a delayed message: MUC join
+    /// history or offline storage. None for live messages.
+    pub delay: Option<String>,
+    /// Unique stanza id (XEP-0359) assigned by the room, or by our server
+    /// for 1:1 messages. Only kept when set by that entity.
+    pub stanza_id: Option<String>,
+    /// Id of the message this one corrects (XEP-0308)
+    pub replace_id: Option<String>,
+    /// Conversation thread (XEP-0201) the message belongs to
+    pub thread: Option<String>,
+    /// The sender asked for a delivery receipt (XEP-0184)
+    pub receipt_requested: bool,
+    /// The sender accepts chat markers for this message (XEP-0333)
+    pub markable: bool,
+    /// JIDs mentioned with references (XEP-0372), e.g. room occupants
+    pub mentions: Vec<String>,
+    /// Stable id of the sending occupant (XEP-0421), in rooms
+    pub occupant_id: Option<String>,
+}
+
+/// The message an outgoing room message answers (XEP-0461)
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageReply {
+    /// Occupant JID (`room@service/nick`) of the author of the message
+    pub to: String,
+    /// Id of the message: in rooms, the stanza id assigned by the room
+    pub id: String,
 }
 
 /// Parsed incoming reaction (XEP-0444)
@@ -43,6 +71,85 @@ pub struct IncomingReaction {
     pub message_type: MessageType,
 }
 
+/// Parsed incoming retraction (XEP-0424)
+#[derive(Debug, Clone)]
+pub struct IncomingRetraction {
+    pub from: String,
+    /// The id of the retracted message
+    pub message_id: String,
+    pub message_type: MessageType,
+    /// Sender's occupant id (XEP-0421), for room retractions
+    pub occupant_id: Option<String>,
+}
+
+/// Delivery or read state reported for a message, by a receipt
+/// (XEP-0184) or a chat marker (XEP-0333)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChatMarker {
+    /// Delivered to a client (a receipt, or a `<received/>` marker)
+    Received,
+    /// Shown to the user
+    Displayed,
+    /// Explicitly acknowledged by the user
+    Acknowledged,
+}
+
+impl ChatMarker {
+    /// Element name of the marker
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChatMarker::Received => "received",
+            ChatMarker::Displayed => "displayed",
+            ChatMarker::Acknowledged => "acknowledged",
+        }
+    }
+}
+
+/// Parsed incoming delivery receipt or chat marker
+#[derive(Debug, Clone)]
+pub struct IncomingMarker {
+    pub from: String,
+    /// The id of the message the marker refers to
+    pub message_id: String,
+    pub marker: ChatMarker,
+    pub message_type: MessageType,
+}
+
+/// IQ stanza type (RFC 6120 §8.2.3)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IqType {
+    Get,
+    Set,
+    Result,
+    Error,
+}
+
+impl IqType {
+    /// Value of the `type` attribute
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IqType::Get => "get",
+            IqType::Set => "set",
+            IqType::Result => "result",
+            IqType::Error => "error",
+        }
+    }
+}
+
+/// Parsed incoming IQ (RFC 6120 §8.2.3)
+#[derive(Debug, Clone)]
+pub struct IncomingIq {
+    /// Sender; absent for results from the client's own server or account
+    pub from: Option<String>,
+    pub to: String,
+    pub id: String,
+    pub iq_type: IqType,
+    /// First child element other than `<error/>` (e.g. a `<query/>`)
+    pub payload: Option<ChildElement>,
+    /// Defined condition of an `<error/>` child (e.g. `"item-not-found"`)
+    pub error: Option<String>,
+}
+
 // ── XML escaping helpers ─────────────────────────────────
 
 /// Escape a value for use inside an XML attribute delimited by single quotes.
@@ -54,8 +161,64 @@ fn escape_attr(value: &str) -> Cow<'_, str> {
 
 /// Builds an outgoing XMPP message.
 /// `from` is Some for component mode, None for C2S (server adds it).
+/// `thread` continues the sender's conversation thread (XEP-0201).
 /// Includes `<active/>` chat state (XEP-0085) to signal we've stopped typing.
-pub fn build_message(from: Option<&str>, to: &str, body: &str, id: Option<&str>) -> String {
+/// Messages with an id request a delivery receipt (XEP-0184) and are
+/// markable (XEP-0333), so their delivery and read state can be tracked.
+pub fn build_message(
+    from: Option<&str>,
+    to: &str,
+    body: &str,
+    id: Option<&str>,
+    thread: Option<&str>,
+) -> String {
+    let from_attr = from
+        .map(|f| format!(" from='{}'", escape_attr(f)))
+        .unwrap_or_default();
+    let id_attr = id
+        .map(|i| format!(" id='{}'", escape_attr(i)))
+        .unwrap_or_default();
+    let thread = thread_element(thread);
+    let tracking = if id.is_some() {
+        format!("<request xmlns='{NS_RECEIPTS}'/><markable xmlns='{NS_CHAT_MARKERS}'/>")
+    } else {
+        String::new()
+    };
+    let to = escape_attr(to);
+    let body = escape(body);
+    format!(
+        "<message{from_attr} to='{to}' type='chat'{id_attr}>\
+         <body>{body}</body>{thread}\
+         <active xmlns='http://jabber.org/protocol/chatstates'/>\
+         {tracking}\
+         </message>"
+    )
+}
+
+/// `<thread/>` element (XEP-0201), empty when there is no thread
+fn thread_element(thread: Option<&str>) -> String {
+    thread
+        .map(|t| format!("<thread>{}</thread>", escape(t)))
+        .unwrap_or_default()
+}
+
+pub const NS_REPLY: &str = "urn:xmpp:reply:0";
+
+pub const NS_CORRECT: &str = "urn:xmpp:message-correct:0";
+pub const NS_RETRACT: &str = "urn:xmpp:message-retract:1";
+const NS_RETRACT_0: &str = "urn:xmpp:message-retract:0";
+const NS_FASTEN: &str = "urn:xmpp:fasten:0";
+
+/// Builds a correction (XEP-0308) of a message previously sent to `to`.
+/// `replace_id` is the id of the original message, even when correcting
+/// it again.
+pub fn build_correction(
+    from: Option<&str>,
+    to: &str,
+    body: &str,
+    id: Option<&str>,
+    replace_id: &str,
+) -> String {
     let from_attr = from
         .map(|f| format!(" from='{}'", escape_attr(f)))
         .unwrap_or_default();
@@ -64,14 +227,96 @@ pub fn build_message(from: Option<&str>, to: &str, body: &str, id: Option<&str>)
         .unwrap_or_default();
     let to = escape_attr(to);
     let body = escape(body);
+    let replace_id = escape_attr(replace_id);
     format!(
         "<message{from_attr} to='{to}' type='chat'{id_attr}>\
          <body>{body}</body>\
+         <replace xmlns='{NS_CORRECT}' id='{replace_id}'/>\
          <active xmlns='http://jabber.org/protocol/chatstates'/>\
          </message>"
     )
 }
 
+// ── Receipts (XEP-0184) and chat markers (XEP-0333) ─────
+
+pub const NS_RECEIPTS: &str = "urn:xmpp:receipts";
+pub const NS_CHAT_MARKERS: &str = "urn:xmpp:chat-markers:0";
+
+/// Builds a delivery receipt (XEP-0184) for the message `id` from `to`.
+pub fn build_receipt(from: Option<&str>, to: &str, id: &str) -> String {
+    let from_attr = from
+        .map(|f| format!(" from='{}'", escape_attr(f)))
+        .unwrap_or_default();
+    let to = escape_attr(to);
+    let id = escape_attr(id);
+    format!(
+        "<message{from_attr} to='{to}'>\
+         <received xmlns='{NS_RECEIPTS}' id='{id}'/>\
+         </message>"
+    )
+}
+
+/// Builds a chat marker (XEP-0333) for the message `id`. In rooms, `id`
+/// is the stanza id assigned by the room.
+/// `msg_type` is `"chat"` for 1:1 or `"groupchat"` for MUC.
+pub fn build_chat_marker(
+    from: Option<&str>,
+    to: &str,
+    id: &str,
+    marker: ChatMarker,
+    msg_type: &str,
+) -> String {
+    let from_attr = from
+        .map(|f| format!(" from='{}'", escape_attr(f)))
+        .unwrap_or_default();
+    let to = escape_attr(to);
+    let id = escape_attr(id);
+    let msg_type = escape_attr(msg_type);
+    let marker = marker.as_str();
+    format!(
+        "<message{from_attr} to='{to}' type='{msg_type}'>\
+         <{marker} xmlns='{NS_CHAT_MARKERS}' id='{id}'/>\
+         <store xmlns='urn:xmpp:hints'/>\
+         </message>"
+    )
+}
+
+// ── Reactions (XEP-0444, outbound) ───────────────────────
+
+pub const NS_REACTIONS: &str = "urn:xmpp:reactions:0";
+
+/// Builds a reaction update (XEP-0444) to the message `message_id`. In
+/// rooms, `message_id` is the stanza id assigned by the room.
+///
+/// An update replaces the previous reactions of the sender on that
+/// message: `emojis` is the full set, and an empty set removes them.
+/// `msg_type` is `"chat"` for 1:1 or `"groupchat"` for MUC.
+pub fn build_reactions(
+    from: Option<&str>,
+    to: &str,
+    message_id: &str,
+    emojis: &[String],
+    msg_type: &str,
+) -> String {
+    let from_attr = from
+        .map(|f| format!(" from='{}'", escape_attr(f)))
+        .unwrap_or_default();
+    let to = escape_attr(to);
+    let message_id = escape_attr(message_id);
+    let msg_type = escape_attr(msg_type);
+    let id = uuid::Uuid::new_v4();
+    let reactions: String = emojis
+        .iter()
+        .map(|e| format!("<reaction>{}</reaction>", escape(e)))
+        .collect();
+    format!(
+        "<message{from_attr} to='{to}' id='{id}' type='{msg_type}'>\
+         <reactions xmlns='{NS_REACTIONS}' id='{message_id}'>{reactions}</reactions>\
+         <store xmlns='urn:xmpp:hints'/>\
+         </message>"
+    )
+}
+
 // ── Chat state notifications (XEP-0085, outbound) ────────
 
 /// Builds a standalone `<composing/>` chat state notification.
@@ -255,11 +500,6 @@ pub fn extract_bound_jid(data: &str) -> Option<String> {
     extract_element_text(data, "jid")
 }
 
-/// Initial presence stanza
-pub fn build_initial_presence() -> String {
-    "<presence/>".to_string()
-}
-
 /// Presence subscription request — ask to see the contact's presence
 pub fn build_subscribe(to: &str) -> String {
     let to = escape_attr(to);
@@ -296,26 +536,184 @@ pub enum PresenceType {
 pub struct IncomingPresence {
     pub from: String,
     pub presence_type: PresenceType,
+    /// Occupant details, for presences from a room occupant
+    pub muc: Option<MucPresence>,
 }
 
 // ── MUC (XEP-0045) ──────────────────────────────────────
 
+pub const NS_MUC_USER: &str = "http://jabber.org/protocol/muc#user";
+pub const NS_OCCUPANT_ID: &str = "urn:xmpp:occupant-id:0";
+pub const NS_REFERENCE: &str = "urn:xmpp:reference:0";
+
+/// Role of a room occupant (XEP-0045 §5.1)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MucRole {
+    Moderator,
+    Participant,
+    Visitor,
+    #[default]
+    None,
+}
+
+impl MucRole {
+    fn parse(value: Option<&str>) -> Self {
+        match value {
+            Some("moderator") => MucRole::Moderator,
+            Some("participant") => MucRole::Participant,
+            Some("visitor") => MucRole::Visitor,
+            _ => MucRole::None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MucRole::Moderator => "moderator",
+            MucRole::Participant => "participant",
+            MucRole::Visitor => "visitor",
+            MucRole::None => "none",
+        }
+    }
+}
+
+/// Affiliation of a room occupant (XEP-0045 §5.2)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MucAffiliation {
+    Owner,
+    Admin,
+    Member,
+    Outcast,
+    #[default]
+    None,
+}
+
+impl MucAffiliation {
+    fn parse(value: Option<&str>) -> Self {
+        match value {
+            Some("owner") => MucAffiliation::Owner,
+            Some("admin") => MucAffiliation::Admin,
+            Some("member") => MucAffiliation::Member,
+            Some("outcast") => MucAffiliation::Outcast,
+            _ => MucAffiliation::None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MucAffiliation::Owner => "owner",
+            MucAffiliation::Admin => "admin",
+            MucAffiliation::Member => "member",
+            MucAffiliation::Outcast => "outcast",
+            MucAffiliation::None => "none",
+        }
+    }
+}
+
+/// Occupant details of a presence sent by a room (`muc#user` item)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MucPresence {
+    pub role: MucRole,
+    pub affiliation: MucAffiliation,
+    /// Real JID of the occupant, when the room discloses it to us
+    pub jid: Option<String>,
+    /// New nick of an occupant changing nickname (status 303)
+    pub new_nick: Option<String>,
+    /// Stable occupant id (XEP-0421), set by rooms supporting it
+    pub occupant_id: Option<String>,
+    /// Status codes: 110 for our own presence, 303 for a nick change,
+    /// 210 when the room modified our nick
+    pub status_codes: Vec<u16>,
+}
+
+impl MucPresence {
+    /// Whether the presence is the agent's own (status 110)
+    pub fn is_self(&self) -> bool {
+        self.status_codes.contains(&110)
+    }
+}
+
+/// `xmpp:` URI (RFC 5122) of a JID, as used by references (XEP-0372).
+pub fn xmpp_uri(jid: &str) -> String {
+    let mut uri = String::from("xmpp:");
+    for byte in jid.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~!$'()*+,;=:@/".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    uri
+}
+
+/// JID of an `xmpp:` URI, without authority, query or fragment.
+pub fn parse_xmpp_uri(uri: &str) -> Option<String> {
+    let mut path = uri.strip_prefix("xmpp:")?;
+    if let Some(rest) = path.strip_prefix("//") {
+        path = &rest[rest.find('/')? + 1..];
+    }
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = path.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok().filter(|jid| !jid.is_empty())
+}
+
+/// Discussion history requested when joining a room (XEP-0045 §7.2.15)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum MucHistory {
+    /// No history (`maxstanzas='0'`)
+    #[default]
+    None,
+    /// Messages sent after a timestamp (XEP-0082)
+    Since(String),
+    /// The room's default amount of history
+    Full,
+}
+
+impl MucHistory {
+    /// The `<history/>` element, empty for the room's default
+    fn element(&self) -> String {
+        match self {
+            MucHistory::None => "<history maxstanzas='0'/>".to_string(),
+            MucHistory::Since(stamp) => format!("<history since='{}'/>", escape_attr(stamp)),
+            MucHistory::Full => String::new(),
+        }
+    }
+}
+
 /// Builds a MUC join presence stanza (XEP-0045).
 /// `from` is Some for component mode, None for C2S.
-pub fn build_muc_join(room_jid: &str, nick: &str, from: Option<&str>) -> String {
+/// `caps` adds Entity Capabilities (XEP-0115) for the room's occupants.
+/// Replayed history arrives as delayed messages (see
+/// [`IncomingMessage::delay`]).
+pub fn build_muc_join(
+    room_jid: &str,
+    nick: &str,
+    from: Option<&str>,
+    history: &MucHistory,
+    caps: Option<&EntityCaps>,
+) -> String {
     let from_attr = from
         .map(|f| format!(" from='{}'", escape_attr(f)))
         .unwrap_or_default();
+    let caps = caps.map(EntityCaps::element).unwrap_or_default();
     let room_jid = escape_attr(room_jid);
     let nick = escape_attr(nick);
-    // Request zero history — we persist messages ourselves.
-    // Without this the server replays the last N messages on every
-    // reconnect, creating duplicates in our memory store.
+    let history = history.element();
     format!(
         "<presence{from_attr} to='{room_jid}/{nick}'>\
-         <x xmlns='http://jabber.org/protocol/muc'>\
-         <history maxstanzas='0'/>\
-         </x>\
+         <x xmlns='http://jabber.org/protocol/muc'>{history}</x>\
+         {caps}\
          </presence>"
     )
 }
@@ -323,23 +721,853 @@ pub fn build_muc_join(room_jid: &str, nick: &str, from: Option<&str>) -> String
 /// Builds a groupchat message for a MUC room (XEP-0045).
 /// `from` is Some for component mode, None for C2S.
 /// Includes `<active/>` chat state (XEP-0085) to clear the typing indicator.
-pub fn build_muc_message(from: Option<&str>, to: &str, body: &str, id: Option<&str>) -> String {
+/// `mention` is the occupant JID of the nick the body starts with, marked
+/// with a reference (XEP-0372).
+pub fn build_muc_message(
+    from: Option<&str>,
+    to: &str,
+    body: &str,
+    id: Option<&str>,
+    thread: Option<&str>,
+    reply: Option<&MessageReply>,
+    mention: Option<&str>,
+) -> String {
     let from_attr = from
         .map(|f| format!(" from='{}'", escape_attr(f)))
         .unwrap_or_default();
     let id_attr = id
         .map(|i| format!(" id='{}'", escape_attr(i)))
         .unwrap_or_default();
+    let thread = thread_element(thread);
+    let reply = reply
+        .map(|r| {
+            format!(
+                "<reply xmlns='{NS_REPLY}' to='{}' id='{}'/>",
+                escape_attr(&r.to),
+                escape_attr(&r.id)
+            )
+        })
+        .unwrap_or_default();
+    let mention = mention
+        .map(|jid| {
+            let nick = jid.split_once('/').map(|(_, nick)| nick).unwrap_or(jid);
+            format!(
+                "<reference xmlns='{NS_REFERENCE}' type='mention' begin='0' end='{}' uri='{}'/>",
+                nick.chars().count(),
+                escape_attr(&xmpp_uri(jid))
+            )
+        })
+        .unwrap_or_default();
     let to = escape_attr(to);
     let body = escape(body);
     format!(
         "<message{from_attr} to='{to}'{id_attr} type='groupchat'>\
-         <body>{body}</body>\
+         <body>{body}</body>{thread}{reply}{mention}\
          <active xmlns='http://jabber.org/protocol/chatstates'/>\
          </message>"
     )
 }
 
+// ── IQ (RFC 6120) ───────────────────────────────────────
+
+/// Builds an IQ stanza around a payload element.
+/// `from` is Some for component mode, None for C2S.
+/// An empty `to` is omitted (replies to the client's own server).
+pub fn build_iq(from: Option<&str>, to: &str, id: &str, iq_type: IqType, payload: &str) -> String {
+    let from_attr = from
+        .map(|f| format!(" from='{}'", escape_attr(f)))
+        .unwrap_or_default();
+    let to_attr = if to.is_empty() {
+        String::new()
+    } else {
+        format!(" to='{}'", escape_attr(to))
+    };
+    let id = escape_attr(id);
+    let iq_type = iq_type.as_str();
+    format!("<iq{from_attr}{to_attr} id='{id}' type='{iq_type}'>{payload}</iq>")
+}
+
+pub const NS_STANZAS: &str = "urn:ietf:params:xml:ns:xmpp-stanzas";
+
+/// Payload of an error reply (RFC 6120 §8.3), e.g.
+/// `("cancel", "service-unavailable")`.
+pub fn build_stanza_error(error_type: &str, condition: &str) -> String {
+    format!("<error type='{error_type}'><{condition} xmlns='{NS_STANZAS}'/></error>")
+}
+
+// ── Ping (XEP-0199), version (XEP-0092), time (XEP-0202) ─
+
+pub const NS_PING: &str = "urn:xmpp:ping";
+pub const NS_VERSION: &str = "jabber:iq:version";
+pub const NS_TIME: &str = "urn:xmpp:time";
+
+/// Payload of a software version result. The OS is not disclosed.
+pub fn build_version_result(name: &str, version: &str) -> String {
+    format!(
+        "<query xmlns='{NS_VERSION}'><name>{}</name><version>{}</version></query>",
+        escape(name),
+        escape(version)
+    )
+}
+
+/// Payload of an entity time result.
+/// `tzo` is the UTC offset (`+02:00`), `utc` the UTC time (`...Z`).
+pub fn build_time_result(tzo: &str, utc: &str) -> String {
+    format!(
+        "<time xmlns='{NS_TIME}'><tzo>{}</tzo><utc>{}</utc></time>",
+        escape(tzo),
+        escape(utc)
+    )
+}
+
+// ── Service discovery (XEP-0030) ────────────────────────
+
+pub const NS_DISCO_INFO: &str = "http://jabber.org/protocol/disco#info";
+pub const NS_DISCO_ITEMS: &str = "http://jabber.org/protocol/disco#items";
+
+/// Payload of a disco#info query
+pub fn disco_info_query() -> String {
+    format!("<query xmlns='{NS_DISCO_INFO}'/>")
+}
+
+/// Payload of a disco#items query
+pub fn disco_items_query() -> String {
+    format!("<query xmlns='{NS_DISCO_ITEMS}'/>")
+}
+
+/// A disco#info identity (`category`, `type`, `name`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoIdentity {
+    pub category: String,
+    pub kind: String,
+    pub name: String,
+}
+
+/// A `result` data form (XEP-0004) attached to disco#info as extended
+/// information (XEP-0128).
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataForm {
+    /// Value of the hidden `FORM_TYPE` field
+    pub form_type: String,
+    /// Other fields: `var` and values
+    pub fields: Vec<(String, Vec<String>)>,
+}
+
+/// What the agent advertises in disco#info
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoInfo {
+    pub identities: Vec<DiscoIdentity>,
+    pub features: Vec<String>,
+    pub forms: Vec<DataForm>,
+}
+
+impl DiscoInfo {
+    /// Verification string of Entity Capabilities (XEP-0115 §5.1):
+    /// base64 of the SHA-1 of the sorted identities, features and forms.
+    pub fn caps_ver(&self) -> String {
+        use base64::Engine;
+        use sha1::{Digest, Sha1};
+
+        let mut identities: Vec<String> = self
+            .identities
+            .iter()
+            .map(|i| format!("{}/{}//{}<", i.category, i.kind, i.name))
+            .collect();
+        identities.sort();
+        let mut features: Vec<&str> = self.features.iter().map(String::as_str).collect();
+        features.sort();
+        features.dedup();
+        let mut forms: Vec<&DataForm> = self.forms.iter().collect();
+        forms.sort_by(|a, b| a.form_type.cmp(&b.form_type));
+
+        let mut s = identities.concat();
+        for feature in features {
+            s.push_str(feature);
+            s.push('<');
+        }
+        for form in forms {
+            s.push_str(&form.form_type);
+            s.push('<');
+            let mut fields: Vec<&(String, Vec<String>)> = form.fields.iter().collect();
+            fields.sort_by(|a, b| a.0.cmp(&b.0));
+            for (var, values) in fields {
+                s.push_str(var);
+                s.push('<');
+                let mut values: Vec<&str> = values.iter().map(String::as_str).collect();
+                values.sort();
+                for value in values {
+                    s.push_str(value);
+                    s.push('<');
+                }
+            }
+        }
+        base64::engine::general_purpose::STANDARD.encode(Sha1::digest(s.as_bytes()))
+    }
+}
+
+/// Payload of a disco#info result. `node` is echoed back when the
+/// request had one.
+pub fn build_disco_info_result(node: Option<&str>, info: &DiscoInfo) -> String {
+    let node_attr = node
+        .map(|n| format!(" node='{}'", escape_attr(n)))
+        .unwrap_or_default();
+    let mut out = format!("<query xmlns='{NS_DISCO_INFO}'{node_attr}>");
+    for identity in &info.identities {
+        out.push_str(&format!(
+            "<identity category='{}' type='{}' name='{}'/>",
+            escape_attr(&identity.category),
+            escape_attr(&identity.kind),
+            escape_attr(&identity.name)
+        ));
+    }
+    for feature in &info.features {
+        out.push_str(&format!("<feature var='{}'/>", escape_attr(feature)));
+    }
+    for form in &info.forms {
+        out.push_str(&format!(
+            "<x xmlns='jabber:x:data' type='result'>\
+             <field var='FORM_TYPE' type='hidden'><value>{}</value></field>",
+            escape(&form.form_type)
+        ));
+        for (var, values) in &form.fields {
+            out.push_str(&format!("<field var='{}'>", escape_attr(var)));
+            for value in values {
+                out.push_str(&format!("<value>{}</value>", escape(value)));
+            }
+            out.push_str("</field>");
+        }
+        out.push_str("</x>");
+    }
+    out.push_str("</query>");
+    out
+}
+
+// ── Entity Capabilities (XEP-0115) ──────────────────────
+
+pub const NS_CAPS: &str = "http://jabber.org/protocol/caps";
+
+/// Capabilities announced in outbound presence
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityCaps {
+    /// URI identifying the software
+    pub node: String,
+    /// Verification string (see [`DiscoInfo::caps_ver`])
+    pub ver: String,
+}
+
+impl EntityCaps {
+    /// The `<c/>` element carried in presence
+    pub fn element(&self) -> String {
+        format!(
+            "<c xmlns='{NS_CAPS}' hash='sha-1' node='{}' ver='{}'/>",
+            escape_attr(&self.node),
+            escape_attr(&self.ver)
+        )
+    }
+
+    /// The disco#info node other entities query to resolve `ver`
+    pub fn disco_node(&self) -> String {
+        format!("{}#{}", self.node, self.ver)
+    }
+}
+
+/// Builds an available presence broadcast, with capabilities if given.
+/// `from` is Some for component mode, None for C2S.
+pub fn build_presence(from: Option<&str>, caps: Option<&EntityCaps>) -> String {
+    let from_attr = from
+        .map(|f| format!(" from='{}'", escape_attr(f)))
+        .unwrap_or_default();
+    match caps {
+        Some(caps) => format!("<presence{from_attr}>{}</presence>", caps.element()),
+        None => format!("<presence{from_attr}/>"),
+    }
+}
+
+/// Payload of an empty disco#items result.
+pub fn build_disco_items_result(node: Option<&str>) -> String {
+    let node_attr = node
+        .map(|n| format!(" node='{}'", escape_attr(n)))
+        .unwrap_or_default();
+    format!("<query xmlns='{NS_DISCO_ITEMS}'{node_attr}/>")
+}
+
+/// Extracts item JIDs from a disco#items result payload.
+pub fn parse_disco_items(query: &ChildElement) -> Vec<String> {
+    query
+        .children
+        .iter()
+        .filter(|c| c.name == "item")
+        .filter_map(|c| c.get_attr("jid").map(String::from))
+        .collect()
+}
+
+/// Extracts feature namespaces from a disco#info result payload.
+pub fn parse_disco_features(query: &ChildElement) -> Vec<String> {
+    query
+        .children
+        .iter()
+        .filter(|c| c.name == "feature")
+        .filter_map(|c| c.get_attr("var").map(String::from))
+        .collect()
+}
+
+/// Returns a field value from the data form (XEP-0004) of a disco#info
+/// result whose `FORM_TYPE` is `form_type` (XEP-0128).
+pub fn disco_form_field(query: &ChildElement, form_type: &str, var: &str) -> Option<String> {
+    let field_value = |form: &ChildElement, var: &str| {
+        form.children
+            .iter()
+            .find(|f| f.name == "field" && f.get_attr("var") == Some(var))
+            .and_then(|f| f.find_child("value"))
+            .map(|v| v.text.trim().to_string())
+    };
+    query
+        .children
+        .iter()
+        .filter(|c| c.name == "x" && c.namespace.as_deref() == Some("jabber:x:data"))
+        .find(|form| field_value(form, "FORM_TYPE").as_deref() == Some(form_type))
+        .and_then(|form| field_value(form, var))
+}
+
+// ── Ad-hoc commands (XEP-0050) and data forms (XEP-0004) ─
+
+pub const NS_COMMANDS: &str = "http://jabber.org/protocol/commands";
+pub const NS_DATA: &str = "jabber:x:data";
+
+/// A field of a data form presented to the user
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FormField {
+    pub var: String,
+    /// Field type: `boolean`, `fixed`, `jid-single`, `list-single`, ...
+    pub kind: String,
+    pub label: Option<String>,
+    pub required: bool,
+    /// Default or current values
+    pub values: Vec<String>,
+    /// Options of `list-*` fields: label and value
+    pub options: Vec<(String, String)>,
+}
+
+/// A data form (`type='form'`) attached to a command response
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandForm {
+    pub title: String,
+    pub instructions: Option<String>,
+    pub fields: Vec<FormField>,
+}
+
+/// Renders the form as a `<x xmlns='jabber:x:data' type='form'/>` element.
+fn build_command_form(form: &CommandForm) -> String {
+    let mut out = format!(
+        "<x xmlns='{NS_DATA}' type='form'><title>{}</title>",
+        escape(&form.title)
+    );
+    if let Some(instructions) = &form.instructions {
+        out.push_str(&format!("<instructions>{}</instructions>", escape(instructions)));
+    }
+    for field in &form.fields {
+        out.push_str(&format!(
+            "<field var='{}' type='{}'",
+            escape_attr(&field.var),
+            escape_attr(&field.kind)
+        ));
+        if let Some(label) = &field.label {
+            out.push_str(&format!(" label='{}'", escape_attr(label)));
+        }
+        out.push('>');
+        if field.required {
+            out.push_str("<required/>");
+        }
+        for value in &field.values {
+            out.push_str(&format!("<value>{}</value>", escape(value)));
+        }
+        for (label, value) in &field.options {
+            out.push_str(&format!(
+                "<option label='{}'><value>{}</value></option>",
+                escape_attr(label),
+                escape(value)
+            ));
+        }
+        out.push_str("</field>");
+    }
+    out.push_str("</x>");
+    out
+}
+
+/// State of a command session after a response
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommandStatus {
+    Executing,
+    Completed,
+    Canceled,
+}
+
+impl CommandStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommandStatus::Executing => "executing",
+            CommandStatus::Completed => "completed",
+            CommandStatus::Canceled => "canceled",
+        }
+    }
+}
+
+/// Severity of a command note
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoteType {
+    Info,
+    Error,
+}
+
+/// A response to an ad-hoc command request
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandResponse {
+    pub status: CommandStatus,
+    pub note: Option<(NoteType, String)>,
+    pub form: Option<CommandForm>,
+    /// Actions the user may take next (`next`, `complete`); the first one
+    /// is the default. Only meaningful while executing.
+    pub actions: Vec<&'static str>,
+}
+
+impl CommandResponse {
+    /// A completed command with an informational note
+    pub fn completed(note: impl Into<String>) -> Self {
+        Self {
+            status: CommandStatus::Completed,
+            note: Some((NoteType::Info, note.into())),
+            form: None,
+            actions: Vec::new(),
+        }
+    }
+
+    /// A completed command with an error note
+    pub fn failed(note: impl Into<String>) -> Self {
+        Self {
+            status: CommandStatus::Completed,
+            note: Some((NoteType::Error, note.into())),
+            form: None,
+            actions: Vec::new(),
+        }
+    }
+
+    /// A canceled command
+    pub fn canceled() -> Self {
+        Self {
+            status: CommandStatus::Canceled,
+            note: None,
+            form: None,
+            actions: Vec::new(),
+        }
+    }
+
+    /// An executing command waiting for `form`, submitted with `action`
+    pub fn form(form: CommandForm, action: &'static str) -> Self {
+        Self {
+            status: CommandStatus::Executing,
+            note: None,
+            form: Some(form),
+            actions: vec![action],
+        }
+    }
+
+    /// Adds an error note, e.g. to present a form again after validation
+    pub fn with_error(mut self, message: impl Into<String>) -> Self {
+        self.note = Some((NoteType::Error, message.into()));
+        self
+    }
+}
+
+/// Payload of a command result (`<command/>` with status, actions, note
+/// and form).
+pub fn build_command_response(node: &str, session_id: &str, response: &CommandResponse) -> String {
+    let mut out = format!(
+        "<command xmlns='{NS_COMMANDS}' node='{}' sessionid='{}' status='{}'>",
+        escape_attr(node),
+        escape_attr(session_id),
+        response.status.as_str()
+    );
+    if response.status == CommandStatus::Executing {
+        if let Some(default) = response.actions.first() {
+            out.push_str(&format!("<actions execute='{default}'>"));
+            for action in &response.actions {
+                out.push_str(&format!("<{action}/>"));
+            }
+            out.push_str("</actions>");
+        }
+    }
+    if let Some((note_type, text)) = &response.note {
+        let note_type = match note_type {
+            NoteType::Info => "info",
+            NoteType::Error => "error",
+        };
+        out.push_str(&format!("<note type='{note_type}'>{}</note>", escape(text)));
+    }
+    if let Some(form) = &response.form {
+        out.push_str(&build_command_form(form));
+    }
+    out.push_str("</command>");
+    out
+}
+
+/// Payload of an error reply with a command-specific condition (XEP-0050
+/// §4.6), e.g. `("modify", "bad-request", "bad-sessionid")`.
+pub fn build_command_error(error_type: &str, condition: &str, specific: &str) -> String {
+    format!(
+        "<error type='{error_type}'><{condition} xmlns='{NS_STANZAS}'/>\
+         <{specific} xmlns='{NS_COMMANDS}'/></error>"
+    )
+}
+
+/// An incoming `<command/>` request
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandRequest {
+    pub node: String,
+    pub session_id: Option<String>,
+    /// `execute` (default), `next`, `complete`, `prev` or `cancel`
+    pub action: String,
+    /// Values of the submitted form, by field `var`
+    pub values: HashMap<String, Vec<String>>,
+}
+
+impl CommandRequest {
+    /// First value of a submitted field, trimmed. Empty values count as missing.
+    pub fn value(&self, var: &str) -> Option<&str> {
+        self.values
+            .get(var)
+            .and_then(|v| v.first())
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+    }
+
+    /// Value of a submitted `boolean` field (`1` or `true`).
+    pub fn flag(&self, var: &str) -> bool {
+        matches!(self.value(var), Some("1") | Some("true"))
+    }
+}
+
+/// Parses a `<command/>` payload. Returns None without a `node`.
+pub fn parse_command(payload: &ChildElement) -> Option<CommandRequest> {
+    let node = payload.get_attr("node").filter(|n| !n.is_empty())?;
+    let mut values = HashMap::new();
+    let forms = payload
+        .children
+        .iter()
+        .filter(|c| c.name == "x" && c.namespace.as_deref() == Some(NS_DATA));
+    for form in forms {
+        for field in form.children.iter().filter(|f| f.name == "field") {
+            let Some(var) = field.get_attr("var") else {
+                continue;
+            };
+            let field_values = field
+                .children
+                .iter()
+                .filter(|v| v.name == "value")
+                .map(|v| v.text.clone())
+                .collect();
+            values.insert(var.to_string(), field_values);
+        }
+    }
+    Some(CommandRequest {
+        node: node.to_string(),
+        session_id: payload.get_attr("sessionid").map(String::from),
+        action: payload.get_attr("action").unwrap_or("execute").to_string(),
+        values,
+    })
+}
+
+/// Payload of the disco#items result listing commands: `(node, name)`
+/// pairs, all provided by `jid`.
+pub fn build_command_items(jid: &str, commands: &[(&str, &str)]) -> String {
+    let mut out = format!("<query xmlns='{NS_DISCO_ITEMS}' node='{NS_COMMANDS}'>");
+    for (node, name) in commands {
+        out.push_str(&format!(
+            "<item jid='{}' node='{}' name='{}'/>",
+            escape_attr(jid),
+            escape_attr(node),
+            escape_attr(name)
+        ));
+    }
+    out.push_str("</query>");
+    out
+}
+
+// ── HTTP File Upload (XEP-0363) ─────────────────────────
+
+pub const NS_HTTP_UPLOAD: &str = "urn:xmpp:http:upload:0";
+
+/// Headers a slot may ask the uploader to send with the PUT (XEP-0363 §5)
+const UPLOAD_ALLOWED_HEADERS: [&str; 3] = ["Authorization", "Cookie", "Expires"];
+
+/// An upload slot granted by the upload service
+#[derive(Debug, Clone, PartialEq)]
+pub struct UploadSlot {
+    /// URL to PUT the file to
+    pub put_url: String,
+    /// Headers to send with the PUT
+    pub put_headers: Vec<(String, String)>,
+    /// URL to share with the recipient
+    pub get_url: String,
+}
+
+/// Payload of an upload slot request
+pub fn build_upload_slot_request(filename: &str, size: u64, content_type: &str) -> String {
+    let filename = escape_attr(filename);
+    let content_type = escape_attr(content_type);
+    format!(
+        "<request xmlns='{NS_HTTP_UPLOAD}' filename='{filename}' size='{size}' \
+         content-type='{content_type}'/>"
+    )
+}
+
+/// Parses a `<slot/>` result payload.
+///
+/// Only the headers allowed by XEP-0363 are kept, and values containing
+/// line breaks are dropped.
+pub fn parse_upload_slot(slot: &ChildElement) -> Option<UploadSlot> {
+    if slot.name != "slot" {
+        return None;
+    }
+    let put = slot.find_child("put")?;
+    let put_url = put.get_attr("url")?.to_string();
+    let get_url = slot.find_child("get")?.get_attr("url")?.to_string();
+    let put_headers = put
+        .children
+        .iter()
+        .filter(|h| h.name == "header")
+        .filter_map(|h| {
+            let name = UPLOAD_ALLOWED_HEADERS
+                .iter()
+                .find(|allowed| h.get_attr("name").is_some_and(|n| n.eq_ignore_ascii_case(allowed)))?;
+            let value = h.text.trim();
+            (!value.contains(['\r', '\n'])).then(|| (name.to_string(), value.to_string()))
+        })
+        .collect();
+    Some(UploadSlot {
+        put_url,
+        put_headers,
+        get_url,
+    })
+}
+
+/// Builds a message sharing a file URL (XEP-0066 OOB).
+/// The URL is also the body, as a fallback for clients without OOB support.
+/// `from` is Some for component mode, None for C2S.
+/// `msg_type` is `"chat"` for 1:1 or `"groupchat"` for MUC.
+pub fn build_oob_message(
+    from: Option<&str>,
+    to: &str,
+    url: &str,
+    msg_type: &str,
+    id: Option<&str>,
+) -> String {
+    let from_attr = from
+        .map(|f| format!(" from='{}'", escape_attr(f)))
+        .unwrap_or_default();
+    let id_attr = id
+        .map(|i| format!(" id='{}'", escape_attr(i)))
+        .unwrap_or_default();
+    let to = escape_attr(to);
+    let msg_type = escape_attr(msg_type);
+    let url = escape(url);
+    format!(
+        "<message{from_attr} to='{to}' type='{msg_type}'{id_attr}>\
+         <body>{url}</body>\
+         <x xmlns='jabber:x:oob'><url>{url}</url></x>\
+         </message>"
+    )
+}
+
+// ── Message Archive Management (XEP-0313) ───────────────
+
+pub const NS_MAM: &str = "urn:xmpp:mam:2";
+pub const NS_RSM: &str = "http://jabber.org/protocol/rsm";
+const NS_FORWARD: &str = "urn:xmpp:forward:0";
+const NS_DELAY: &str = "urn:xmpp:delay";
+const NS_SID: &str = "urn:xmpp:sid:0";
+
+/// Filters and paging (XEP-0059) of an archive query
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MamQuery {
+    /// Only messages exchanged with this JID (1:1 archives)
+    pub with: Option<String>,
+    /// Only messages sent at or after this time (RFC 3339)
+    pub start: Option<String>,
+    /// Page size
+    pub max: usize,
+    /// Page following this archive id
+    pub after: Option<String>,
+    /// Request the last page (most recent messages) instead of the first
+    pub last_page: bool,
+}
+
+/// Payload of an archive query IQ (`set`).
+pub fn build_mam_query(query_id: &str, query: &MamQuery) -> String {
+    let mut out = format!(
+        "<query xmlns='{NS_MAM}' queryid='{}'><x xmlns='{NS_DATA}' type='submit'>\
+         <field var='FORM_TYPE' type='hidden'><value>{NS_MAM}</value></field>",
+        escape_attr(query_id)
+    );
+    if let Some(with) = &query.with {
+        out.push_str(&format!("<field var='with'><value>{}</value></field>", escape(with)));
+    }
+    if let Some(start) = &query.start {
+        out.push_str(&format!("<field var='start'><value>{}</value></field>", escape(start)));
+    }
+    out.push_str(&format!("</x><set xmlns='{NS_RSM}'><max>{}</max>", query.max));
+    if let Some(after) = &query.after {
+        out.push_str(&format!("<after>{}</after>", escape(after)));
+    } else if query.last_page {
+        out.push_str("<before/>");
+    }
+    out.push_str("</set></query>");
+    out
+}
+
+/// A message returned by an archive query
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchivedMessage {
+    /// `queryid` of the query this result answers
+    pub query_id: String,
+    /// Archive id of the message, used for paging
+    pub archive_id: String,
+    /// Sender of the result: the room for MUC archives, None (or the own
+    /// bare JID) for the account's archive
+    pub archive: Option<String>,
+    pub from: String,
+    pub to: String,
+    pub body: String,
+    /// Original `id` of the archived message
+    pub id: Option<String>,
+    pub message_type: MessageType,
+    /// When the message was originally sent (RFC 3339), from `<delay/>`
+    pub stamp: Option<String>,
+}
+
+/// Parses the `<result/>` of an archive query, wrapping a forwarded
+/// message (XEP-0297). Messages without a body are skipped.
+fn parse_mam_result(result: &ChildElement, archive: Option<&str>) -> Option<ArchivedMessage> {
+    let forwarded = result
+        .children
+        .iter()
+        .find(|c| c.name == "forwarded" && c.namespace.as_deref() == Some(NS_FORWARD))?;
+    let message = forwarded.find_child("message")?;
+    let body = message.find_child("body")?.text.trim().to_string();
+    if body.is_empty() {
+        return None;
+    }
+    let stamp = forwarded
+        .children
+        .iter()
+        .find(|c| c.name == "delay" && c.namespace.as_deref() == Some(NS_DELAY))
+        .and_then(|d| d.get_attr("stamp"))
+        .map(String::from);
+    let message_type = match message.get_attr("type") {
+        Some("groupchat") => MessageType::GroupChat,
+        _ => MessageType::Chat,
+    };
+    Some(ArchivedMessage {
+        query_id: result.get_attr("queryid").unwrap_or_default().to_string(),
+        archive_id: result.get_attr("id")?.to_string(),
+        archive: archive.map(String::from),
+        from: message.get_attr("from").unwrap_or_default().to_string(),
+        to: message.get_attr("to").unwrap_or_default().to_string(),
+        body,
+        id: message.get_attr("id").map(String::from),
+        message_type,
+        stamp,
+    })
+}
+
+/// End of an archive query page (`<fin/>` in the IQ result)
+#[derive(Debug, Clone, PartialEq)]
+pub struct MamFin {
+    /// No more pages in the requested direction
+    pub complete: bool,
+    /// Archive id of the last message of the page
+    pub last: Option<String>,
+}
+
+/// Parses the `<fin/>` payload of an archive query result.
+pub fn parse_mam_fin(fin: &ChildElement) -> Option<MamFin> {
+    if fin.name != "fin" || fin.namespace.as_deref() != Some(NS_MAM) {
+        return None;
+    }
+    let last = fin
+        .children
+        .iter()
+        .find(|c| c.name == "set" && c.namespace.as_deref() == Some(NS_RSM))
+        .and_then(|set| set.find_child("last"))
+        .map(|l| l.text.trim().to_string())
+        .filter(|l| !l.is_empty());
+    Some(MamFin {
+        complete: matches!(fin.get_attr("complete"), Some("true") | Some("1")),
+        last,
+    })
+}
+
+// ── Message Carbons (XEP-0280) ──────────────────────────
+
+pub const NS_CARBONS: &str = "urn:xmpp:carbons:2";
+
+/// Builds the `<enable/>` IQ payload turning carbons on for this session.
+pub fn build_carbons_enable() -> String {
+    format!("<enable xmlns='{NS_CARBONS}'/>")
+}
+
+/// Whether a carbon copy was received by, or sent from, another resource
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CarbonDirection {
+    /// A message addressed to another resource of the account
+    Received,
+    /// A message sent from another resource of the account
+    Sent,
+}
+
+/// A message copied to the agent by its server (XEP-0280)
+#[derive(Debug, Clone)]
+pub struct CarbonMessage {
+    pub direction: CarbonDirection,
+    /// The forwarded message, as sent or received by the other resource
+    pub message: IncomingMessage,
+}
+
+/// Parses a `<received/>` or `<sent/>` carbon wrapping a forwarded
+/// message (XEP-0297). Only 1:1 messages with a body are kept.
+fn parse_carbon(wrapper: &ChildElement, direction: CarbonDirection) -> Option<CarbonMessage> {
+    let forwarded = wrapper
+        .children
+        .iter()
+        .find(|c| c.name == "forwarded" && c.namespace.as_deref() == Some(NS_FORWARD))?;
+    let message = forwarded.find_child("message")?;
+    if matches!(message.get_attr("type"), Some("groupchat" | "error")) {
+        return None;
+    }
+    let body = message.find_child("body")?.text.trim().to_string();
+    if body.is_empty() {
+        return None;
+    }
+    Some(CarbonMessage {
+        direction,
+        message: IncomingMessage {
+            from: message.get_attr("from")?.to_string(),
+            to: message.get_attr("to")?.to_string(),
+            body,
+            id: message.get_attr("id").map(String::from),
+            message_type: MessageType::Chat,
+            oob: Vec::new(),
+            delay: None,
+            stanza_id: None,
+            replace_id: None,
+            thread: None,
+            receipt_requested: false,
+            markable: false,
+            mentions: Vec::new(),
+            occupant_id: None,
+        },
+    })
+}
+
 // ── Roster (RFC 6121) ───────────────────────────────────
 
 /// Roster query request — fetch the bot's contact list
@@ -391,21 +1619,33 @@ pub enum XmppStanza {
     Message(IncomingMessage),
     Presence(IncomingPresence),
     Reaction(IncomingReaction),
+    Iq(IncomingIq),
+    /// A message returned by an archive query (XEP-0313)
+    Archived(ArchivedMessage),
+    /// A message copied from another resource of the account (XEP-0280)
+    Carbon(CarbonMessage),
+    /// A sender withdrew one of its messages (XEP-0424)
+    Retraction(IncomingRetraction),
+    /// A delivery receipt (XEP-0184) or chat marker (XEP-0333)
+    Marker(IncomingMarker),
     StreamError(String),
-    /// IQ, SM ack/req, or any other stanza we don't process
+    /// SM ack/req, or any other stanza we don't process
     Ignored,
     /// Stream-level elements: `<stream:stream>`, `<?xml?>`, `</stream:stream>`
     StreamLevel,
 }
 
 /// Accumulated child element data during stanza parsing.
-#[derive(Debug, Default)]
-struct ChildElement {
-    name: String,
-    namespace: Option<String>,
-    attrs: Vec<(String, String)>,
-    text: String,
-    children: Vec<ChildElement>,
+/// Also exposed as the payload of incoming IQs.
+#[derive(Debug, Default, Clone)]
+pub struct ChildElement {
+    /// Element name (including any prefix)
+    pub name: String,
+    /// Value of the `xmlns` attribute, if declared on the element
+    pub namespace: Option<String>,
+    pub attrs: Vec<(String, String)>,
+    pub text: String,
+    pub children: Vec<ChildElement>,
 }
 
 /// Accumulates events for a single top-level stanza.
@@ -450,14 +1690,14 @@ impl StanzaBuilder {
 }
 
 impl ChildElement {
-    fn get_attr(&self, name: &str) -> Option<&str> {
+    pub fn get_attr(&self, name: &str) -> Option<&str> {
         self.attrs
             .iter()
             .find(|(k, _)| k == name)
             .map(|(_, v)| v.as_str())
     }
 
-    fn find_child(&self, name: &str) -> Option<&ChildElement> {
+    pub fn find_child(&self, name: &str) -> Option<&ChildElement> {
         self.children.iter().find(|c| c.name == name)
     }
 }
@@ -663,12 +1903,21 @@ fn finalize_stanza(builder: &StanzaBuilder) -> XmppStanza {
     match builder.root_name.as_str() {
         "message" => finalize_message(builder),
         "presence" => finalize_presence(builder),
+        "iq" => finalize_iq(builder),
         "stream:error" => finalize_stream_error(builder),
         _ => XmppStanza::Ignored,
     }
 }
 
 fn finalize_message(builder: &StanzaBuilder) -> XmppStanza {
+    // Archive query results wrap the archived message; the account's own
+    // archive sends them without `from`
+    if let Some(result) = builder.find_children_ns("result", NS_MAM).first() {
+        return parse_mam_result(result, builder.get_root_attr("from"))
+            .map(XmppStanza::Archived)
+            .unwrap_or(XmppStanza::Ignored);
+    }
+
     let from = match builder.get_root_attr("from") {
         Some(f) => f.to_string(),
         None => return XmppStanza::Ignored,
@@ -676,13 +1925,26 @@ fn finalize_message(builder: &StanzaBuilder) -> XmppStanza {
     let to = builder.get_root_attr("to").unwrap_or("").to_string();
     let id = builder.get_root_attr("id").map(String::from);
 
+    // Carbons (XEP-0280) must come from our own bare JID; anyone else
+    // could use them to inject messages in our name
+    for (name, direction) in [("received", CarbonDirection::Received), ("sent", CarbonDirection::Sent)] {
+        if let Some(wrapper) = builder.find_children_ns(name, NS_CARBONS).first() {
+            if from != bare_jid(&to) {
+                return XmppStanza::Ignored;
+            }
+            return parse_carbon(wrapper, direction)
+                .map(XmppStanza::Carbon)
+                .unwrap_or(XmppStanza::Ignored);
+        }
+    }
+
     let message_type = match builder.get_root_attr("type") {
         Some("groupchat") => MessageType::GroupChat,
         _ => MessageType::Chat,
     };
 
     // Check for reactions (XEP-0444)
-    let reaction_elements = builder.find_children_ns("reactions", "urn:xmpp:reactions:0");
+    let reaction_elements = builder.find_children_ns("reactions", NS_REACTIONS);
     if let Some(reactions_el) = reaction_elements.first() {
         if let Some(target_id) = reactions_el.get_attr("id") {
             let emojis: Vec<String> = reactions_el
@@ -704,6 +1966,62 @@ fn finalize_message(builder: &StanzaBuilder) -> XmppStanza {
         }
     }
 
+    // Check for retractions (XEP-0424); the body is only a fallback text.
+    // Version 0 wraps the retraction in a fastening (XEP-0422).
+    let retracted = builder
+        .find_children_ns("retract", NS_RETRACT)
+        .first()
+        .and_then(|r| r.get_attr("id"))
+        .or_else(|| {
+            builder
+                .find_children_ns("apply-to", NS_FASTEN)
+                .into_iter()
+                .find(|a| {
+                    a.children.iter().any(|c| {
+                        c.name == "retract" && c.namespace.as_deref() == Some(NS_RETRACT_0)
+                    })
+                })
+                .and_then(|a| a.get_attr("id"))
+        });
+    if let Some(target_id) = retracted {
+        let occupant_id = match message_type {
+            MessageType::GroupChat => occupant_id(builder),
+            _ => None,
+        };
+        return XmppStanza::Retraction(IncomingRetraction {
+            from,
+            message_id: target_id.to_string(),
+            message_type,
+            occupant_id,
+        });
+    }
+
+    // Check for delivery receipts (XEP-0184) and chat markers (XEP-0333)
+    let receipt = builder
+        .find_children_ns("received", NS_RECEIPTS)
+        .first()
+        .and_then(|r| r.get_attr("id"))
+        .map(|id| (id, ChatMarker::Received));
+    let marker = receipt.or_else(|| {
+        [ChatMarker::Received, ChatMarker::Displayed, ChatMarker::Acknowledged]
+            .into_iter()
+            .find_map(|marker| {
+                builder
+                    .find_children_ns(marker.as_str(), NS_CHAT_MARKERS)
+                    .first()
+                    .and_then(|m| m.get_attr("id"))
+                    .map(|id| (id, marker))
+            })
+    });
+    if let Some((target_id, marker)) = marker {
+        return XmppStanza::Marker(IncomingMarker {
+            from,
+            message_id: target_id.to_string(),
+            marker,
+            message_type,
+        });
+    }
+
     // Check for chat state notification (XEP-0085): composing/paused/active/inactive/gone
     let chat_state_names = ["composing", "paused", "active", "inactive", "gone"];
     let has_chat_state = builder.has_child_with_name(&chat_state_names);
@@ -764,6 +2082,56 @@ fn finalize_message(builder: &StanzaBuilder) -> XmppStanza {
         return XmppStanza::Ignored;
     }
 
+    // Delayed delivery (XEP-0203): MUC history, offline messages
+    let delay = builder
+        .find_children_ns("delay", NS_DELAY)
+        .first()
+        .and_then(|d| d.get_attr("stamp"))
+        .map(String::from);
+
+    // Stanza id (XEP-0359): the room sets it on groupchat messages, our
+    // server on 1:1 messages. Ids set by anyone else could be forged.
+    let assigner = match message_type {
+        MessageType::GroupChat => bare_jid(&from),
+        _ => bare_jid(&to),
+    };
+    let stanza_id = builder
+        .find_children_ns("stanza-id", NS_SID)
+        .into_iter()
+        .find(|s| s.get_attr("by") == Some(assigner))
+        .and_then(|s| s.get_attr("id"))
+        .map(String::from);
+
+    // Last message correction (XEP-0308)
+    let replace_id = builder
+        .find_children_ns("replace", NS_CORRECT)
+        .first()
+        .and_then(|r| r.get_attr("id"))
+        .map(String::from);
+
+    // Conversation thread (XEP-0201)
+    let thread = builder
+        .find_child("thread")
+        .map(|t| t.text.trim().to_string())
+        .filter(|t| !t.is_empty());
+
+    // Receipt request (XEP-0184) and markable hint (XEP-0333)
+    let receipt_requested = !builder.find_children_ns("request", NS_RECEIPTS).is_empty();
+    let markable = !builder.find_children_ns("markable", NS_CHAT_MARKERS).is_empty();
+
+    // Mentions (XEP-0372) and the sender's occupant id (XEP-0421), which
+    // only the room can set
+    let mentions = builder
+        .find_children_ns("reference", NS_REFERENCE)
+        .into_iter()
+        .filter(|r| r.get_attr("type") == Some("mention"))
+        .filter_map(|r| parse_xmpp_uri(r.get_attr("uri")?))
+        .collect();
+    let occupant_id = match message_type {
+        MessageType::GroupChat => occupant_id(builder),
+        _ => None,
+    };
+
     XmppStanza::Message(IncomingMessage {
         from,
         to,
@@ -771,9 +2139,26 @@ fn finalize_message(builder: &StanzaBuilder) -> XmppStanza {
         id,
         message_type,
         oob,
+        delay,
+        stanza_id,
+        replace_id,
+        thread,
+        receipt_requested,
+        markable,
+        mentions,
+        occupant_id,
     })
 }
 
+/// The `<occupant-id/>` (XEP-0421) of a room stanza.
+fn occupant_id(builder: &StanzaBuilder) -> Option<String> {
+    builder
+        .find_children_ns("occupant-id", NS_OCCUPANT_ID)
+        .first()
+        .and_then(|o| o.get_attr("id"))
+        .map(String::from)
+}
+
 fn finalize_presence(builder: &StanzaBuilder) -> XmppStanza {
     let from = match builder.get_root_attr("from") {
         Some(f) => f.to_string(),
@@ -789,9 +2174,54 @@ fn finalize_presence(builder: &StanzaBuilder) -> XmppStanza {
         _ => PresenceType::Available,
     };
 
+    let muc = builder.find_children_ns("x", NS_MUC_USER).first().map(|x| {
+        let item = x.find_child("item");
+        let attr = |name| item.and_then(|i| i.get_attr(name));
+        MucPresence {
+            role: MucRole::parse(attr("role")),
+            affiliation: MucAffiliation::parse(attr("affiliation")),
+            jid: attr("jid").map(String::from),
+            new_nick: attr("nick").map(String::from),
+            occupant_id: occupant_id(builder),
+            status_codes: x
+                .children
+                .iter()
+                .filter(|c| c.name == "status")
+                .filter_map(|c| c.get_attr("code")?.parse().ok())
+                .collect(),
+        }
+    });
+
     XmppStanza::Presence(IncomingPresence {
         from,
         presence_type,
+        muc,
+    })
+}
+
+fn finalize_iq(builder: &StanzaBuilder) -> XmppStanza {
+    let Some(id) = builder.get_root_attr("id") else {
+        return XmppStanza::Ignored;
+    };
+    let iq_type = match builder.get_root_attr("type") {
+        Some("get") => IqType::Get,
+        Some("set") => IqType::Set,
+        Some("result") => IqType::Result,
+        Some("error") => IqType::Error,
+        _ => return XmppStanza::Ignored,
+    };
+    let error = builder
+        .find_child("error")
+        .and_then(|e| e.children.iter().find(|c| c.name != "text"))
+        .map(|c| c.name.clone());
+
+    XmppStanza::Iq(IncomingIq {
+        from: builder.get_root_attr("from").map(String::from),
+        to: builder.get_root_attr("to").unwrap_or("").to_string(),
+        id: id.to_string(),
+        iq_type,
+        payload: builder.children.iter().find(|c| c.name != "error").cloned(),
+        error,
     })
 }
 
@@ -928,14 +2358,14 @@ mod tests {
 
     #[test]
     fn test_build_message_with_from() {
-        let xml = build_message(Some("agent.localhost"), "user@localhost", "Hi!", None);
+        let xml = build_message(Some("agent.localhost"), "user@localhost", "Hi!", None, None);
         assert!(xml.contains("from='agent.localhost'"));
         assert!(xml.contains("<body>Hi!</body>"));
     }
 
     #[test]
     fn test_build_message_without_from() {
-        let xml = build_message(None, "user@localhost", "Hi!", None);
+        let xml = build_message(None, "user@localhost", "Hi!", None, None);
         assert!(!xml.contains("from="));
         assert!(xml.contains("to='user@localhost'"));
         assert!(xml.contains("<body>Hi!</body>"));
@@ -945,26 +2375,26 @@ mod tests {
 
     #[test]
     fn test_build_message_escapes_body() {
-        let xml = build_message(None, "user@localhost", "Tom & Jerry's <show>", None);
+        let xml = build_message(None, "user@localhost", "Tom & Jerry's <show>", None, None);
         assert!(xml.contains("<body>Tom &amp; Jerry&apos;s &lt;show&gt;</body>"));
     }
 
     #[test]
     fn test_build_message_escapes_to_attribute() {
-        let xml = build_message(None, "user@localhost/it's", "Hi", None);
+        let xml = build_message(None, "user@localhost/it's", "Hi", None, None);
         assert!(xml.contains("to='user@localhost/it&apos;s'"));
     }
 
     #[test]
     fn test_build_muc_message_escapes_body() {
-        let xml = build_muc_message(None, "room@conf.local", "2 > 1 & 1 < 2", None);
+        let xml = build_muc_message(None, "room@conf.local", "2 > 1 & 1 < 2", None, None, None, None);
         assert!(xml.contains("<body>2 &gt; 1 &amp; 1 &lt; 2</body>"));
     }
 
     #[test]
     fn test_build_message_normal_text_unchanged() {
         // No special chars => body is verbatim
-        let xml = build_message(None, "user@localhost", "Hello world", None);
+        let xml = build_message(None, "user@localhost", "Hello world", None, None);
         assert!(xml.contains("<body>Hello world</body>"));
     }
 
@@ -1011,14 +2441,14 @@ mod tests {
 
     #[test]
     fn test_build_message_includes_active_chat_state() {
-        let xml = build_message(None, "user@localhost", "Hello", None);
+        let xml = build_message(None, "user@localhost", "Hello", None, None);
         assert!(xml.contains("<active xmlns='http://jabber.org/protocol/chatstates'/>"));
         assert!(xml.contains("<body>Hello</body>"));
     }
 
     #[test]
     fn test_build_message_with_from_includes_active_chat_state() {
-        let xml = build_message(Some("agent.localhost"), "user@localhost", "Hi", None);
+        let xml = build_message(Some("agent.localhost"), "user@localhost", "Hi", None, None);
         assert!(xml.contains("<active xmlns='http://jabber.org/protocol/chatstates'/>"));
         assert!(xml.contains("from='agent.localhost'"));
     }
@@ -1222,8 +2652,55 @@ mod tests {
     }
 
     #[test]
-    fn test_build_initial_presence() {
-        assert_eq!(build_initial_presence(), "<presence/>");
+    fn test_build_presence() {
+        assert_eq!(build_presence(None, None), "<presence/>");
+        let caps = EntityCaps {
+            node: "https://github.com/processone/fluux-agent".to_string(),
+            ver: "QgayPKawpkPSDYmwT/WM94uAlu0=".to_string(),
+        };
+        assert_eq!(
+            build_presence(None, Some(&caps)),
+            "<presence><c xmlns='http://jabber.org/protocol/caps' hash='sha-1' \
+             node='https://github.com/processone/fluux-agent' ver='QgayPKawpkPSDYmwT/WM94uAlu0='/>\
+             </presence>"
+        );
+        assert_eq!(
+            caps.disco_node(),
+            "https://github.com/processone/fluux-agent#QgayPKawpkPSDYmwT/WM94uAlu0="
+        );
+    }
+
+    #[test]
+    fn test_caps_ver() {
+        // XEP-0115 §5.2 simple example
+        let mut info = DiscoInfo {
+            identities: vec![DiscoIdentity {
+                category: "client".to_string(),
+                kind: "pc".to_string(),
+                name: "Exodus 0.9.1".to_string(),
+            }],
+            features: [
+                "http://jabber.org/protocol/muc",
+                "http://jabber.org/protocol/disco#info",
+                "http://jabber.org/protocol/caps",
+                "http://jabber.org/protocol/disco#items",
+            ]
+            .iter()
+            .map(|f| f.to_string())
+            .collect(),
+            forms: Vec::new(),
+        };
+        assert_eq!(info.caps_ver(), "QgayPKawpkPSDYmwT/WM94uAlu0=");
+
+        // Any change to the advertised forms changes the hash
+        info.forms.push(DataForm {
+            form_type: "urn:fluux:agent:skills:0".to_string(),
+            fields: vec![("skills".to_string(), vec!["web_search".to_string()])],
+        });
+        let with_skill = info.caps_ver();
+        assert_ne!(with_skill, "QgayPKawpkPSDYmwT/WM94uAlu0=");
+        info.forms[0].fields[0].1.push("url_fetch".to_string());
+        assert_ne!(info.caps_ver(), with_skill);
     }
 
     // ── Helper function tests ───────────────────────────
@@ -1273,25 +2750,49 @@ mod tests {
 
     #[test]
     fn test_build_muc_join_c2s() {
-        let xml = build_muc_join("lobby@conference.localhost", "bot", None);
+        let xml = build_muc_join("lobby@conference.localhost", "bot", None, &MucHistory::None, None);
         assert!(!xml.contains("from="));
+        assert!(!xml.contains("<c "));
         assert!(xml.contains("to='lobby@conference.localhost/bot'"));
         assert!(xml.contains("http://jabber.org/protocol/muc"));
         assert!(xml.contains("<history maxstanzas='0'/>"));
     }
 
+    #[test]
+    fn test_build_muc_join_history() {
+        let since = MucHistory::Since("2026-10-18T10:00:00Z".to_string());
+        let xml = build_muc_join("lobby@conference.localhost", "bot", None, &since, None);
+        assert!(xml.contains(
+            "<x xmlns='http://jabber.org/protocol/muc'><history since='2026-10-18T10:00:00Z'/></x>"
+        ));
+
+        let xml = build_muc_join("lobby@conference.localhost", "bot", None, &MucHistory::Full, None);
+        assert!(xml.contains("<x xmlns='http://jabber.org/protocol/muc'></x>"));
+    }
+
     #[test]
     fn test_build_muc_join_component() {
-        let xml = build_muc_join("lobby@conference.localhost", "bot", Some("agent.localhost"));
+        let caps = EntityCaps {
+            node: "https://example.com/agent".to_string(),
+            ver: "abc=".to_string(),
+        };
+        let xml = build_muc_join(
+            "lobby@conference.localhost",
+            "bot",
+            Some("agent.localhost"),
+            &MucHistory::None,
+            Some(&caps),
+        );
         assert!(xml.contains("from='agent.localhost'"));
         assert!(xml.contains("to='lobby@conference.localhost/bot'"));
         assert!(xml.contains("http://jabber.org/protocol/muc"));
         assert!(xml.contains("<history maxstanzas='0'/>"));
+        assert!(xml.contains("</x><c xmlns='http://jabber.org/protocol/caps'"));
     }
 
     #[test]
     fn test_build_muc_message_c2s() {
-        let xml = build_muc_message(None, "lobby@conference.localhost", "Hello room!", None);
+        let xml = build_muc_message(None, "lobby@conference.localhost", "Hello room!", None, None, None, None);
         assert!(!xml.contains("from="));
         assert!(xml.contains("to='lobby@conference.localhost'"));
         assert!(xml.contains("type='groupchat'"));
@@ -1301,7 +2802,7 @@ mod tests {
 
     #[test]
     fn test_build_muc_message_component() {
-        let xml = build_muc_message(Some("agent.localhost"), "lobby@conference.localhost", "Hi!", None);
+        let xml = build_muc_message(Some("agent.localhost"), "lobby@conference.localhost", "Hi!", None, None, None, None);
         assert!(xml.contains("from='agent.localhost'"));
         assert!(xml.contains("type='groupchat'"));
         assert!(xml.contains("<body>Hi!</body>"));
@@ -1590,14 +3091,595 @@ mod tests {
     }
 
     #[test]
-    fn test_sp_iq_ignored() {
+    fn test_sp_iq_result() {
         let xml = "<iq type='result' id='1'><query/></iq>";
         match parse_xml_to_stanza(xml).unwrap() {
-            XmppStanza::Ignored => {}
-            other => panic!("Expected Ignored, got {other:?}"),
+            XmppStanza::Iq(iq) => {
+                assert_eq!(iq.id, "1");
+                assert_eq!(iq.iq_type, IqType::Result);
+                assert_eq!(iq.from, None);
+                assert_eq!(iq.payload.unwrap().name, "query");
+            }
+            other => panic!("Expected Iq, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_sp_iq_error_condition() {
+        let xml = "<iq type='error' id='up1' from='upload.localhost'>\
+                   <request xmlns='urn:xmpp:http:upload:0' filename='a.txt' size='5'/>\
+                   <error type='modify'>\
+                   <not-acceptable xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/>\
+                   <text xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'>File too large</text>\
+                   </error></iq>";
+        match parse_xml_to_stanza(xml).unwrap() {
+            XmppStanza::Iq(iq) => {
+                assert_eq!(iq.iq_type, IqType::Error);
+                assert_eq!(iq.from.as_deref(), Some("upload.localhost"));
+                assert_eq!(iq.error.as_deref(), Some("not-acceptable"));
+                assert_eq!(iq.payload.unwrap().name, "request");
+            }
+            other => panic!("Expected Iq, got {other:?}"),
         }
     }
 
+    #[test]
+    fn test_sp_iq_without_id_ignored() {
+        let xml = "<iq type='get'><ping xmlns='urn:xmpp:ping'/></iq>";
+        assert!(matches!(parse_xml_to_stanza(xml).unwrap(), XmppStanza::Ignored));
+    }
+
+    fn iq_payload(xml: &str) -> ChildElement {
+        match parse_xml_to_stanza(xml).unwrap() {
+            XmppStanza::Iq(iq) => iq.payload.expect("payload"),
+            other => panic!("Expected Iq, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_build_iq() {
+        let iq = build_iq(None, "upload.localhost", "q1", IqType::Get, &disco_info_query());
+        assert_eq!(
+            iq,
+            "<iq to='upload.localhost' id='q1' type='get'>\
+             <query xmlns='http://jabber.org/protocol/disco#info'/></iq>"
+        );
+        let iq = build_iq(Some("agent.localhost"), "localhost", "q2", IqType::Get, "");
+        assert!(iq.starts_with("<iq from='agent.localhost' to='localhost'"));
+        // Replies to the own server omit `to`
+        let iq = build_iq(None, "", "p1", IqType::Result, "");
+        assert_eq!(iq, "<iq id='p1' type='result'></iq>");
+    }
+
+    #[test]
+    fn test_build_iq_replies() {
+        assert_eq!(
+            build_stanza_error("cancel", "service-unavailable"),
+            "<error type='cancel'>\
+             <service-unavailable xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/></error>"
+        );
+        let info = DiscoInfo {
+            identities: vec![DiscoIdentity {
+                category: "client".to_string(),
+                kind: "bot".to_string(),
+                name: "R&D bot".to_string(),
+            }],
+            features: vec![NS_PING.to_string()],
+            forms: vec![DataForm {
+                form_type: "urn:example".to_string(),
+                fields: vec![("skills".to_string(), vec!["a".to_string(), "b".to_string()])],
+            }],
+        };
+        assert_eq!(
+            build_disco_info_result(Some("n1"), &info),
+            "<query xmlns='http://jabber.org/protocol/disco#info' node='n1'>\
+             <identity category='client' type='bot' name='R&amp;D bot'/>\
+             <feature var='urn:xmpp:ping'/>\
+             <x xmlns='jabber:x:data' type='result'>\
+             <field var='FORM_TYPE' type='hidden'><value>urn:example</value></field>\
+             <field var='skills'><value>a</value><value>b</value></field></x></query>"
+        );
+        assert_eq!(
+            build_time_result("+02:00", "2026-10-18T10:00:00Z"),
+            "<time xmlns='urn:xmpp:time'><tzo>+02:00</tzo><utc>2026-10-18T10:00:00Z</utc></time>"
+        );
+    }
+
+    #[test]
+    fn test_parse_delayed_message_with_stanza_id() {
+        let stanza = parse_xml_to_stanza(
+            "<message from='lobby@conference.localhost/alice' to='bot@localhost/agent' \
+             type='groupchat' id='m1'><body>Hi</body>\
+             <delay xmlns='urn:xmpp:delay' from='lobby@conference.localhost' \
+             stamp='2026-10-18T10:00:00Z'/>\
+             <stanza-id xmlns='urn:xmpp:sid:0' by='alice@localhost' id='forged'/>\
+             <stanza-id xmlns='urn:xmpp:sid:0' by='lobby@conference.localhost' id='s-1'/>\
+             </message>",
+        )
+        .unwrap();
+        let XmppStanza::Message(msg) = stanza else {
+            panic!("Expected Message, got {stanza:?}");
+        };
+        assert_eq!(msg.delay.as_deref(), Some("2026-10-18T10:00:00Z"));
+        assert_eq!(msg.stanza_id.as_deref(), Some("s-1"));
+
+        // A 1:1 stanza id is only trusted from our own server
+        let stanza = parse_xml_to_stanza(
+            "<message from='alice@localhost/phone' to='bot@localhost/agent' type='chat'>\
+             <body>Hi</body>\
+             <stanza-id xmlns='urn:xmpp:sid:0' by='alice@localhost' id='forged'/>\
+             </message>",
+        )
+        .unwrap();
+        let XmppStanza::Message(msg) = stanza else {
+            panic!("Expected Message, got {stanza:?}");
+        };
+        assert_eq!(msg.delay, None);
+        assert_eq!(msg.stanza_id, None);
+    }
+
+    #[test]
+    fn test_parse_carbons() {
+        let carbon = |outer_from: &str, wrapper: &str| {
+            parse_xml_to_stanza(&format!(
+                "<message from='{outer_from}' to='bot@localhost/agent' type='chat'>\
+                 <{wrapper} xmlns='urn:xmpp:carbons:2'>\
+                 <forwarded xmlns='urn:xmpp:forward:0'>\
+                 <message from='bot@localhost/gajim' to='alice@localhost/phone' type='chat' id='op1'>\
+                 <body>I'll handle this one</body></message>\
+                 </forwarded></{wrapper}></message>"
+            ))
+            .unwrap()
+        };
+
+        let XmppStanza::Carbon(sent) = carbon("bot@localhost", "sent") else {
+            panic!("Expected Carbon");
+        };
+        assert_eq!(sent.direction, CarbonDirection::Sent);
+        assert_eq!(sent.message.from, "bot@localhost/gajim");
+        assert_eq!(sent.message.to, "alice@localhost/phone");
+        assert_eq!(sent.message.body, "I'll handle this one");
+        assert_eq!(sent.message.id.as_deref(), Some("op1"));
+
+        let XmppStanza::Carbon(received) = carbon("bot@localhost", "received") else {
+            panic!("Expected Carbon");
+        };
+        assert_eq!(received.direction, CarbonDirection::Received);
+
+        // Carbons forwarded by anyone but our own bare JID are spoofed
+        assert!(matches!(carbon("mallory@localhost", "sent"), XmppStanza::Ignored));
+        assert!(matches!(carbon("bot@localhost/gajim", "sent"), XmppStanza::Ignored));
+    }
+
+    #[test]
+    fn test_parse_correction_and_retractions() {
+        let stanza = parse_xml_to_stanza(
+            "<message from='alice@localhost/phone' to='bot@localhost' type='chat' id='m2'>\
+             <body>What is the capital of France?</body>\
+             <replace xmlns='urn:xmpp:message-correct:0' id='m1'/></message>",
+        )
+        .unwrap();
+        let XmppStanza::Message(msg) = stanza else {
+            panic!("Expected Message, got {stanza:?}");
+        };
+        assert_eq!(msg.replace_id.as_deref(), Some("m1"));
+        assert_eq!(msg.body, "What is the capital of France?");
+
+        // Version 1, with a fallback body for older clients
+        let stanza = parse_xml_to_stanza(
+            "<message from='alice@localhost/phone' to='bot@localhost' type='chat'>\
+             <retract xmlns='urn:xmpp:message-retract:1' id='m1'/>\
+             <fallback xmlns='urn:xmpp:fallback:0' for='urn:xmpp:message-retract:1'/>\
+             <body>This person attempted to retract a previous message</body></message>",
+        )
+        .unwrap();
+        let XmppStanza::Retraction(retraction) = stanza else {
+            panic!("Expected Retraction, got {stanza:?}");
+        };
+        assert_eq!(retraction.message_id, "m1");
+        assert_eq!(retraction.from, "alice@localhost/phone");
+
+        // Version 0, wrapped in a fastening
+        let stanza = parse_xml_to_stanza(
+            "<message from='lobby@conference.localhost/alice' to='bot@localhost' type='groupchat'>\
+             <apply-to xmlns='urn:xmpp:fasten:0' id='s-1'>\
+             <retract xmlns='urn:xmpp:message-retract:0'/></apply-to>\
+             <occupant-id xmlns='urn:xmpp:occupant-id:0' id='occ-alice'/></message>",
+        )
+        .unwrap();
+        let XmppStanza::Retraction(retraction) = stanza else {
+            panic!("Expected Retraction, got {stanza:?}");
+        };
+        assert_eq!(retraction.message_id, "s-1");
+        assert_eq!(retraction.message_type, MessageType::GroupChat);
+        assert_eq!(retraction.occupant_id.as_deref(), Some("occ-alice"));
+    }
+
+    #[test]
+    fn test_build_correction() {
+        let xml = build_correction(None, "alice@localhost/phone", "Paris & Lyon", Some("r2"), "r1");
+        assert_eq!(
+            xml,
+            "<message to='alice@localhost/phone' type='chat' id='r2'>\
+             <body>Paris &amp; Lyon</body>\
+             <replace xmlns='urn:xmpp:message-correct:0' id='r1'/>\
+             <active xmlns='http://jabber.org/protocol/chatstates'/>\
+             </message>"
+        );
+    }
+
+    #[test]
+    fn test_parse_receipt_request_and_markers() {
+        let stanza = parse_xml_to_stanza(
+            "<message from='alice@localhost/phone' to='bot@localhost' type='chat' id='m1'>\
+             <body>Hello</body><request xmlns='urn:xmpp:receipts'/>\
+             <markable xmlns='urn:xmpp:chat-markers:0'/></message>",
+        )
+        .unwrap();
+        let XmppStanza::Message(msg) = stanza else {
+            panic!("Expected Message, got {stanza:?}");
+        };
+        assert!(msg.receipt_requested);
+        assert!(msg.markable);
+
+        // A receipt is reported as a `received` marker
+        let stanza = parse_xml_to_stanza(
+            "<message from='alice@localhost/phone' to='bot@localhost'>\
+             <received xmlns='urn:xmpp:receipts' id='r1'/></message>",
+        )
+        .unwrap();
+        let XmppStanza::Marker(marker) = stanza else {
+            panic!("Expected Marker, got {stanza:?}");
+        };
+        assert_eq!(marker.message_id, "r1");
+        assert_eq!(marker.marker, ChatMarker::Received);
+
+        let stanza = parse_xml_to_stanza(
+            "<message from='alice@localhost/phone' to='bot@localhost' type='chat'>\
+             <displayed xmlns='urn:xmpp:chat-markers:0' id='r2'/></message>",
+        )
+        .unwrap();
+        let XmppStanza::Marker(marker) = stanza else {
+            panic!("Expected Marker, got {stanza:?}");
+        };
+        assert_eq!(marker.message_id, "r2");
+        assert_eq!(marker.marker, ChatMarker::Displayed);
+    }
+
+    #[test]
+    fn test_build_receipt_and_marker() {
+        assert_eq!(
+            build_receipt(None, "alice@localhost/phone", "m1"),
+            "<message to='alice@localhost/phone'>\
+             <received xmlns='urn:xmpp:receipts' id='m1'/></message>"
+        );
+        let xml = build_chat_marker(
+            Some("agent.localhost"),
+            "lobby@conference.localhost",
+            "s-1",
+            ChatMarker::Displayed,
+            "groupchat",
+        );
+        assert!(xml.contains("type='groupchat'"));
+        assert!(xml.contains("<displayed xmlns='urn:xmpp:chat-markers:0' id='s-1'/>"));
+    }
+
+    #[test]
+    fn test_build_reactions_round_trip() {
+        let emojis = vec!["👀".to_string(), "✅".to_string()];
+        let xml = build_reactions(None, "alice@localhost/phone", "m<1>", &emojis, "chat");
+        assert!(xml.contains("<reactions xmlns='urn:xmpp:reactions:0' id='m&lt;1&gt;'>"));
+        assert!(xml.contains("<reaction>👀</reaction><reaction>✅</reaction>"));
+
+        // What we send is what the inbound parser reads
+        let xml = xml.replacen("<message ", "<message from='bot@localhost/agent' ", 1);
+        let XmppStanza::Reaction(reaction) = parse_xml_to_stanza(&xml).unwrap() else {
+            panic!("Expected Reaction");
+        };
+        assert_eq!(reaction.message_id, "m<1>");
+        assert_eq!(reaction.emojis, emojis);
+
+        // An empty set removes our reactions
+        let xml = build_reactions(None, "lobby@conference.localhost", "s-1", &[], "groupchat");
+        assert!(xml.contains("<reactions xmlns='urn:xmpp:reactions:0' id='s-1'></reactions>"));
+    }
+
+    #[test]
+    fn test_build_message_requests_receipt_only_with_id() {
+        let xml = build_message(None, "user@localhost", "Hi", Some("r1"), None);
+        assert!(xml.contains("<request xmlns='urn:xmpp:receipts'/>"));
+        assert!(xml.contains("<markable xmlns='urn:xmpp:chat-markers:0'/>"));
+        let xml = build_message(None, "user@localhost", "Hi", None, None);
+        assert!(!xml.contains("urn:xmpp:receipts"));
+    }
+
+    #[test]
+    fn test_parse_thread_and_build_threaded_reply() {
+        let stanza = parse_xml_to_stanza(
+            "<message from='alice@localhost/phone' to='bot@localhost' type='chat' id='m1'>\
+             <body>Hello</body><thread>t-1</thread></message>",
+        )
+        .unwrap();
+        let XmppStanza::Message(msg) = stanza else {
+            panic!("Expected Message, got {stanza:?}");
+        };
+        assert_eq!(msg.thread.as_deref(), Some("t-1"));
+
+        let xml = build_message(None, "alice@localhost", "Hi", None, Some("t<1>"));
+        assert!(xml.contains("<thread>t&lt;1&gt;</thread>"));
+        let reply = MessageReply {
+            to: "lobby@conference.localhost/alice".to_string(),
+            id: "s-1".to_string(),
+        };
+        let xml = build_muc_message(
+            None,
+            "lobby@conference.localhost",
+            "alice: Hi",
+            None,
+            Some("t-1"),
+            Some(&reply),
+            None,
+        );
+        assert!(xml.contains("<thread>t-1</thread>"));
+        assert!(xml.contains(
+            "<reply xmlns='urn:xmpp:reply:0' to='lobby@conference.localhost/alice' id='s-1'/>"
+        ));
+    }
+
+    #[test]
+    fn test_parse_muc_presence() {
+        let stanza = parse_xml_to_stanza(
+            "<presence from='lobby@conference.localhost/alice' to='bot@localhost/agent'>\
+             <x xmlns='http://jabber.org/protocol/muc#user'>\
+             <item affiliation='owner' role='moderator' jid='alice@localhost/phone'/>\
+             </x>\
+             <occupant-id xmlns='urn:xmpp:occupant-id:0' id='occ-1'/>\
+             </presence>",
+        )
+        .unwrap();
+        let XmppStanza::Presence(pres) = stanza else {
+            panic!("Expected Presence, got {stanza:?}");
+        };
+        let muc = pres.muc.unwrap();
+        assert_eq!(muc.role, MucRole::Moderator);
+        assert_eq!(muc.affiliation, MucAffiliation::Owner);
+        assert_eq!(muc.jid.as_deref(), Some("alice@localhost/phone"));
+        assert_eq!(muc.occupant_id.as_deref(), Some("occ-1"));
+        assert!(!muc.is_self());
+
+        // Our own nick change: status 110 and 303 with the new nick
+        let stanza = parse_xml_to_stanza(
+            "<presence from='lobby@conference.localhost/bot' type='unavailable'>\
+             <x xmlns='http://jabber.org/protocol/muc#user'>\
+             <item affiliation='none' role='participant' nick='bot-2'/>\
+             <status code='303'/><status code='110'/>\
+             </x></presence>",
+        )
+        .unwrap();
+        let XmppStanza::Presence(pres) = stanza else {
+            panic!("Expected Presence, got {stanza:?}");
+        };
+        assert_eq!(pres.presence_type, PresenceType::Unavailable);
+        let muc = pres.muc.unwrap();
+        assert!(muc.is_self());
+        assert_eq!(muc.new_nick.as_deref(), Some("bot-2"));
+        assert_eq!(muc.status_codes, [303, 110]);
+    }
+
+    #[test]
+    fn test_mention_references() {
+        let stanza = parse_xml_to_stanza(
+            "<message from='lobby@conference.localhost/alice' to='bot@localhost/agent' type='groupchat'>\
+             <body>fluux agent: hello</body>\
+             <reference xmlns='urn:xmpp:reference:0' type='mention' begin='0' end='11' \
+             uri='xmpp:lobby@conference.localhost/fluux%20agent'/>\
+             <reference xmlns='urn:xmpp:reference:0' type='data' uri='https://example.com'/>\
+             <occupant-id xmlns='urn:xmpp:occupant-id:0' id='occ-1'/>\
+             </message>",
+        )
+        .unwrap();
+        let XmppStanza::Message(msg) = stanza else {
+            panic!("Expected Message, got {stanza:?}");
+        };
+        assert_eq!(msg.mentions, ["lobby@conference.localhost/fluux agent"]);
+        assert_eq!(msg.occupant_id.as_deref(), Some("occ-1"));
+
+        let jid = "lobby@conference.localhost/Zoë & co";
+        assert_eq!(xmpp_uri(jid), "xmpp:lobby@conference.localhost/Zo%C3%AB%20%26%20co");
+        assert_eq!(parse_xmpp_uri(&xmpp_uri(jid)).as_deref(), Some(jid));
+        assert_eq!(
+            parse_xmpp_uri("xmpp://bot@localhost/alice@localhost?message").as_deref(),
+            Some("alice@localhost")
+        );
+        assert_eq!(parse_xmpp_uri("https://example.com"), None);
+
+        let xml = build_muc_message(
+            None,
+            "lobby@conference.localhost",
+            "Zoë & co: done",
+            None,
+            None,
+            None,
+            Some(jid),
+        );
+        assert!(xml.contains(
+            "<reference xmlns='urn:xmpp:reference:0' type='mention' begin='0' end='8' \
+             uri='xmpp:lobby@conference.localhost/Zo%C3%AB%20%26%20co'/>"
+        ));
+    }
+
+    #[test]
+    fn test_parse_mam_result_and_fin() {
+        let stanza = parse_xml_to_stanza(
+            "<message to='bot@localhost/agent' from='lobby@conference.localhost'>\
+             <result xmlns='urn:xmpp:mam:2' queryid='q1' id='a-42'>\
+             <forwarded xmlns='urn:xmpp:forward:0'>\
+             <delay xmlns='urn:xmpp:delay' stamp='2026-10-18T10:00:00Z'/>\
+             <message from='lobby@conference.localhost/alice' type='groupchat' id='m1'>\
+             <body>Hello bot</body></message></forwarded></result></message>",
+        )
+        .unwrap();
+        let XmppStanza::Archived(archived) = stanza else {
+            panic!("Expected Archived, got {stanza:?}");
+        };
+        assert_eq!(archived.query_id, "q1");
+        assert_eq!(archived.archive_id, "a-42");
+        assert_eq!(archived.archive.as_deref(), Some("lobby@conference.localhost"));
+        assert_eq!(archived.from, "lobby@conference.localhost/alice");
+        assert_eq!(archived.body, "Hello bot");
+        assert_eq!(archived.id.as_deref(), Some("m1"));
+        assert_eq!(archived.message_type, MessageType::GroupChat);
+        assert_eq!(archived.stamp.as_deref(), Some("2026-10-18T10:00:00Z"));
+
+        let fin = iq_payload(
+            "<iq type='result' id='i1'><fin xmlns='urn:xmpp:mam:2'>\
+             <set xmlns='http://jabber.org/protocol/rsm'><first>a-1</first>\
+             <last>a-42</last></set></fin></iq>",
+        );
+        let fin = parse_mam_fin(&fin).unwrap();
+        assert!(!fin.complete);
+        assert_eq!(fin.last.as_deref(), Some("a-42"));
+    }
+
+    #[test]
+    fn test_parse_command() {
+        let payload = iq_payload(
+            "<iq type='set' id='c1' from='admin@localhost/res'>\
+             <command xmlns='http://jabber.org/protocol/commands' node='tier' \
+             sessionid='s1' action='complete'>\
+             <x xmlns='jabber:x:data' type='submit'>\
+             <field var='tier'><value> deep </value></field>\
+             <field var='confirm'><value>1</value></field>\
+             <field var='empty'><value></value></field>\
+             </x></command></iq>",
+        );
+        let request = parse_command(&payload).unwrap();
+        assert_eq!(request.node, "tier");
+        assert_eq!(request.session_id.as_deref(), Some("s1"));
+        assert_eq!(request.action, "complete");
+        assert_eq!(request.value("tier"), Some("deep"));
+        assert_eq!(request.value("empty"), None);
+        assert!(request.flag("confirm"));
+        assert!(!request.flag("tier"));
+
+        let payload = iq_payload(
+            "<iq type='set' id='c2'>\
+             <command xmlns='http://jabber.org/protocol/commands' node='status'/></iq>",
+        );
+        let request = parse_command(&payload).unwrap();
+        assert_eq!(request.action, "execute");
+        assert_eq!(request.session_id, None);
+
+        let payload = iq_payload(
+            "<iq type='set' id='c3'><command xmlns='http://jabber.org/protocol/commands'/></iq>",
+        );
+        assert!(parse_command(&payload).is_none());
+    }
+
+    #[test]
+    fn test_build_command_response() {
+        let form = CommandForm {
+            title: "Model tier".to_string(),
+            instructions: Some("Pick one".to_string()),
+            fields: vec![FormField {
+                var: "tier".to_string(),
+                kind: "list-single".to_string(),
+                label: Some("Tier".to_string()),
+                required: true,
+                values: vec!["auto".to_string()],
+                options: vec![("Auto".to_string(), "auto".to_string())],
+            }],
+        };
+        assert_eq!(
+            build_command_response("tier", "s1", &CommandResponse::form(form, "complete")),
+            "<command xmlns='http://jabber.org/protocol/commands' node='tier' sessionid='s1' \
+             status='executing'><actions execute='complete'><complete/></actions>\
+             <x xmlns='jabber:x:data' type='form'><title>Model tier</title>\
+             <instructions>Pick one</instructions>\
+             <field var='tier' type='list-single' label='Tier'><required/><value>auto</value>\
+             <option label='Auto'><value>auto</value></option></field></x></command>"
+        );
+        assert_eq!(
+            build_command_response("new", "s2", &CommandResponse::completed("Done <ok>")),
+            "<command xmlns='http://jabber.org/protocol/commands' node='new' sessionid='s2' \
+             status='completed'><note type='info'>Done &lt;ok&gt;</note></command>"
+        );
+        assert_eq!(
+            build_command_error("modify", "bad-request", "bad-sessionid"),
+            "<error type='modify'><bad-request xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/>\
+             <bad-sessionid xmlns='http://jabber.org/protocol/commands'/></error>"
+        );
+        assert_eq!(
+            build_command_items("bot@localhost/agent", &[("status", "Status")]),
+            "<query xmlns='http://jabber.org/protocol/disco#items' \
+             node='http://jabber.org/protocol/commands'>\
+             <item jid='bot@localhost/agent' node='status' name='Status'/></query>"
+        );
+    }
+
+    #[test]
+    fn test_disco_info_parsing() {
+        let query = iq_payload(
+            "<iq type='result' id='d1' from='upload.localhost'>\
+             <query xmlns='http://jabber.org/protocol/disco#info'>\
+             <identity category='store' type='file'/>\
+             <feature var='urn:xmpp:http:upload:0'/>\
+             <x xmlns='jabber:x:data' type='result'>\
+             <field var='FORM_TYPE' type='hidden'><value>urn:xmpp:http:upload:0</value></field>\
+             <field var='max-file-size'><value>5242880</value></field>\
+             </x></query></iq>",
+        );
+        assert_eq!(parse_disco_features(&query), vec![NS_HTTP_UPLOAD]);
+        assert_eq!(
+            disco_form_field(&query, NS_HTTP_UPLOAD, "max-file-size").as_deref(),
+            Some("5242880")
+        );
+        assert_eq!(disco_form_field(&query, "urn:other", "max-file-size"), None);
+
+        let items = iq_payload(
+            "<iq type='result' id='d2'><query xmlns='http://jabber.org/protocol/disco#items'>\
+             <item jid='conference.localhost'/><item jid='upload.localhost'/>\
+             </query></iq>",
+        );
+        assert_eq!(parse_disco_items(&items), vec!["conference.localhost", "upload.localhost"]);
+    }
+
+    #[test]
+    fn test_upload_slot() {
+        let request = build_upload_slot_request("a'b.txt", 42, "text/plain");
+        assert!(request.contains("filename='a&apos;b.txt' size='42' content-type='text/plain'"));
+
+        let slot = iq_payload(
+            "<iq type='result' id='u1'><slot xmlns='urn:xmpp:http:upload:0'>\
+             <put url='https://upload.localhost/put/a.txt'>\
+             <header name='Authorization'>Basic Zm9v</header>\
+             <header name='Host'>evil.example</header>\
+             </put>\
+             <get url='https://upload.localhost/get/a.txt'/>\
+             </slot></iq>",
+        );
+        let slot = parse_upload_slot(&slot).unwrap();
+        assert_eq!(slot.put_url, "https://upload.localhost/put/a.txt");
+        assert_eq!(slot.get_url, "https://upload.localhost/get/a.txt");
+        // Only the headers allowed by XEP-0363 are kept
+        assert_eq!(
+            slot.put_headers,
+            vec![("Authorization".to_string(), "Basic Zm9v".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_build_oob_message() {
+        let msg = build_oob_message(None, "user@localhost/phone", "https://h/a?x=1&y=2", "chat", Some("o1"));
+        assert_eq!(
+            msg,
+            "<message to='user@localhost/phone' type='chat' id='o1'>\
+             <body>https://h/a?x=1&amp;y=2</body>\
+             <x xmlns='jabber:x:oob'><url>https://h/a?x=1&amp;y=2</url></x>\
+             </message>"
+        );
+    }
+
     #[test]
     fn test_sp_self_closing_ignored() {
         let xml = "<r xmlns='urn:xmpp:sm:3'/>";
@@ -0,0 +1,588 @@
+//! Outbound Message Styling (XEP-0393).
+//!
+//! Models answer in Markdown, which most XMPP clients show verbatim
+//! (`**bold**`, `### Heading`, pipe tables). [`markdown_to_styling`]
+//! rewrites an answer with the directives XEP-0393 clients render —
+//! `*bold*`, `_italic_`, `~strike~`, `` `code` ``, ```` ``` ```` blocks and
+//! `>` quotes — and turns what has no equivalent (headings, lists, tables,
+//! links, rules) into readable plain text. Code is never touched.
+
+pub const NS_STYLING: &str = "urn:xmpp:styling:0";
+
+/// Tables up to this width (in characters) are rendered as aligned
+/// columns in a preformatted block; wider ones as one line per row.
+const MAX_TABLE_WIDTH: usize = 60;
+
+/// Replacement for horizontal rules (`---`).
+const RULE: &str = "──────────";
+
+/// Converts a Markdown answer to XEP-0393 Message Styling.
+pub fn markdown_to_styling(markdown: &str) -> String {
+    let lines: Vec<&str> = markdown.lines().collect();
+    let mut out: Vec<String> = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim_start();
+
+        // Fenced code block: copied as is, dedented to the fence
+        if let Some(fence) = opening_fence(trimmed) {
+            let indent = line.len() - trimmed.len();
+            let info = trimmed[fence.len()..].trim();
+            out.push(format!("```{info}"));
+            i += 1;
+            while i < lines.len() && !closes_fence(lines[i].trim_start(), fence) {
+                let content = strip_indent(lines[i], indent);
+                // A nested fence line would end the XEP-0393 block early
+                if content.starts_with("```") {
+                    out.push(format!(" {content}"));
+                } else {
+                    out.push(content.to_string());
+                }
+                i += 1;
+            }
+            // Skip the closing fence (an unclosed block ends with the text)
+            i += 1;
+            out.push("```".to_string());
+            continue;
+        }
+
+        // Pipe table: a header row followed by a separator row
+        if is_table_row(trimmed) && lines.get(i + 1).is_some_and(|l| is_table_separator(l)) {
+            let mut rows = vec![split_row(trimmed)];
+            i += 2;
+            while i < lines.len() && is_table_row(lines[i].trim_start()) {
+                rows.push(split_row(lines[i].trim_start()));
+                i += 1;
+            }
+            push_block(&mut out, render_table(&rows));
+            continue;
+        }
+
+        let converted = convert_line(line);
+        // Collapse runs of blank lines
+        if !(converted.is_empty() && out.last().is_none_or(|l| l.is_empty())) {
+            out.push(converted);
+        }
+        i += 1;
+    }
+
+    while out.last().is_some_and(|l| l.is_empty()) {
+        out.pop();
+    }
+    out.join("\n")
+}
+
+/// Appends rendered lines, separated from the previous text by a blank line.
+fn push_block(out: &mut Vec<String>, block: Vec<String>) {
+    if out.last().is_some_and(|l| !l.is_empty()) {
+        out.push(String::new());
+    }
+    out.extend(block);
+}
+
+/// The fence (```` ``` ```` or `~~~`, three or more) opening a code block.
+pub(super) fn opening_fence(line: &str) -> Option<&str> {
+    let marker = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = line.chars().take_while(|c| *c == marker).count();
+    if len < 3 {
+        return None;
+    }
+    let fence = &line[..len];
+    // Backtick fences cannot have backticks in their info string
+    if marker == '`' && line[len..].contains('`') {
+        return None;
+    }
+    Some(fence)
+}
+
+pub(super) fn closes_fence(line: &str, fence: &str) -> bool {
+    let marker = fence.chars().next().unwrap_or('`');
+    let line = line.trim_end();
+    line.len() >= fence.len() && line.chars().all(|c| c == marker)
+}
+
+/// Removes up to `indent` leading spaces.
+fn strip_indent(line: &str, indent: usize) -> &str {
+    let spaces = line.len() - line.trim_start_matches(' ').len();
+    &line[spaces.min(indent)..]
+}
+
+/// Converts a line outside code blocks and tables.
+fn convert_line(line: &str) -> String {
+    let trimmed = line.trim();
+    if trimmed.is_empty() {
+        return String::new();
+    }
+    if is_rule(trimmed) {
+        return RULE.to_string();
+    }
+
+    // Quotes keep their `>` markers, with the quoted text converted
+    if trimmed.starts_with('>') {
+        let mut depth = 0;
+        let mut rest = trimmed;
+        while let Some(r) = rest.strip_prefix('>') {
+            depth += 1;
+            rest = r.trim_start();
+        }
+        let quoted = convert_line(rest);
+        let marker = ">".repeat(depth);
+        return if quoted.is_empty() {
+            marker
+        } else {
+            format!("{marker} {quoted}")
+        };
+    }
+
+    // Headings become a bold line
+    if let Some(text) = heading_text(trimmed) {
+        let text = text.replace("**", "").replace("__", "");
+        let text = convert_inline(&text, true);
+        return if text.is_empty() {
+            String::new()
+        } else {
+            format!("*{text}*")
+        };
+    }
+
+    let indent = &line[..line.len() - line.trim_start().len()];
+    if let Some(item) = bullet_item(trimmed) {
+        let item = match item {
+            t if t.starts_with("[ ] ") => format!("☐ {}", &t[4..]),
+            t if t.starts_with("[x] ") || t.starts_with("[X] ") => format!("☑ {}", &t[4..]),
+            t => t.to_string(),
+        };
+        return format!("{indent}• {}", convert_inline(&item, true));
+    }
+    if let Some((number, item)) = ordered_item(trimmed) {
+        return format!("{indent}{number}. {}", convert_inline(item, true));
+    }
+
+    format!("{indent}{}", convert_inline(trimmed, true))
+}
+
+/// `---`, `***` or `___` (three or more, spaces allowed).
+fn is_rule(line: &str) -> bool {
+    let marker = match line.chars().next() {
+        Some(c @ ('-' | '*' | '_')) => c,
+        _ => return false,
+    };
+    line.chars().filter(|c| *c == marker).count() >= 3
+        && line.chars().all(|c| c == marker || c == ' ')
+}
+
+/// Text of an ATX heading (`## Title ##`).
+fn heading_text(line: &str) -> Option<&str> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let rest = &line[level..];
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    let text = rest.trim();
+    // Optional closing sequence
+    let text = match text.trim_end_matches('#') {
+        t if t.is_empty() || t.ends_with(' ') => t.trim_end(),
+        _ => text,
+    };
+    Some(text)
+}
+
+/// Text of a `-`, `*` or `+` list item.
+fn bullet_item(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix(['-', '*', '+'])?;
+    rest.strip_prefix(' ').map(str::trim_start)
+}
+
+/// Number and text of an ordered list item (`1.` or `1)`).
+fn ordered_item(line: &str) -> Option<(&str, &str)> {
+    let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    if digits == 0 || digits > 9 {
+        return None;
+    }
+    let rest = line[digits..].strip_prefix(['.', ')'])?;
+    let text = rest.strip_prefix(' ')?;
+    Some((&line[..digits], text.trim_start()))
+}
+
+// ── Inline spans ─────────────────────────────────────────
+
+/// Converts inline Markdown. With `styled`, emphasis and code become
+/// XEP-0393 spans; without, only their text is kept (for preformatted
+/// table cells).
+fn convert_inline(text: &str, styled: bool) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' if chars.get(i + 1).is_some_and(|n| n.is_ascii_punctuation()) => {
+                // XEP-0393 has no escapes: a directive stays after the
+                // backslash, where it cannot open a span
+                if styled && matches!(chars[i + 1], '*' | '_' | '~' | '`') {
+                    out.push('\\');
+                }
+                out.push(chars[i + 1]);
+                i += 2;
+            }
+            '`' => {
+                let run = run_length(&chars, i, '`');
+                match find_run(&chars, i + run, '`', run) {
+                    Some(end) => {
+                        let code: String = chars[i + run..end].iter().collect();
+                        let code = strip_code_padding(&code);
+                        if styled {
+                            out.push('`');
+                            out.push_str(code);
+                            out.push('`');
+                        } else {
+                            out.push_str(code);
+                        }
+                        i = end + run;
+                    }
+                    None => {
+                        out.extend(&chars[i..i + run]);
+                        i += run;
+                    }
+                }
+            }
+            '*' | '_' | '~' => match emphasis(&chars, i) {
+                Some((open, inner_start, inner_end)) => {
+                    let inner: String = chars[inner_start..inner_end].iter().collect();
+                    let inner = convert_inline(&inner, styled);
+                    if styled {
+                        let (before, after) = match (c, open) {
+                            ('~', _) => ("~", "~"),
+                            (_, 1) => ("_", "_"),
+                            (_, 2) => ("*", "*"),
+                            _ => ("*_", "_*"),
+                        };
+                        out.push_str(before);
+                        out.push_str(&inner);
+                        out.push_str(after);
+                    } else {
+                        out.push_str(&inner);
+                    }
+                    i = inner_end + open;
+                }
+                None => {
+                    let run = run_length(&chars, i, c);
+                    out.extend(&chars[i..i + run]);
+                    i += run;
+                }
+            },
+            '!' if chars.get(i + 1) == Some(&'[') => match link(&chars, i + 1) {
+                Some((label, url, end)) => {
+                    out.push_str(&format_link(&convert_inline(&label, styled), &url));
+                    i = end;
+                }
+                None => {
+                    out.push(c);
+                    i += 1;
+                }
+            },
+            '[' => match link(&chars, i) {
+                Some((label, url, end)) => {
+                    out.push_str(&format_link(&convert_inline(&label, styled), &url));
+                    i = end;
+                }
+                None => {
+                    out.push(c);
+                    i += 1;
+                }
+            },
+            '<' => match autolink(&chars, i) {
+                Some((url, end)) => {
+                    out.push_str(&url);
+                    i = end;
+                }
+                None => {
+                    out.push(c);
+                    i += 1;
+                }
+            },
+            _ => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Number of consecutive `c` starting at `start`.
+fn run_length(chars: &[char], start: usize, c: char) -> usize {
+    chars[start..].iter().take_while(|x| **x == c).count()
+}
+
+/// Start of the next run of exactly `len` characters `c`, from `start`.
+fn find_run(chars: &[char], start: usize, c: char, len: usize) -> Option<usize> {
+    let mut i = start;
+    while i < chars.len() {
+        if chars[i] == c {
+            let run = run_length(chars, i, c);
+            if run == len {
+                return Some(i);
+            }
+            i += run;
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+/// A code span's content without the single space padding Markdown allows.
+fn strip_code_padding(code: &str) -> &str {
+    if code.len() > 2 && code.starts_with(' ') && code.ends_with(' ') && !code.trim().is_empty() {
+        &code[1..code.len() - 1]
+    } else {
+        code
+    }
+}
+
+/// An emphasis span opening at `start`: delimiter length and the range of
+/// its content. Delimiters must hug the text and sit at word boundaries,
+/// so `snake_case` and `2*3*4` are left alone.
+fn emphasis(chars: &[char], start: usize) -> Option<(usize, usize, usize)> {
+    let c = chars[start];
+    let run = run_length(chars, start, c);
+    let open = match c {
+        '~' if run == 2 => 2,
+        '~' => return None,
+        _ if run > 3 => return None,
+        _ => run,
+    };
+    if start > 0 && chars[start - 1].is_alphanumeric() {
+        return None;
+    }
+    let inner_start = start + open;
+    if chars.get(inner_start).is_none_or(|n| n.is_whitespace()) {
+        return None;
+    }
+
+    let mut i = inner_start + 1;
+    while i < chars.len() {
+        if chars[i] == '`' {
+            // Delimiters inside code spans do not count
+            let run = run_length(chars, i, '`');
+            i = find_run(chars, i + run, '`', run).map_or(i + run, |end| end + run);
+            continue;
+        }
+        if chars[i] == c {
+            let close = run_length(chars, i, c);
+            let closes = close == open
+                && !chars[i - 1].is_whitespace()
+                && chars.get(i + close).is_none_or(|n| !n.is_alphanumeric());
+            if closes {
+                return Some((open, inner_start, i));
+            }
+            i += close;
+            continue;
+        }
+        i += 1;
+    }
+    None
+}
+
+/// A `[label](url)` link starting at `start`: label, URL and end index.
+fn link(chars: &[char], start: usize) -> Option<(String, String, usize)> {
+    let close = start + 1 + chars[start + 1..].iter().position(|c| *c == ']')?;
+    if chars.get(close + 1) != Some(&'(') {
+        return None;
+    }
+    let end = close + 2 + chars[close + 2..].iter().position(|c| *c == ')')?;
+    let label: String = chars[start + 1..close].iter().collect();
+    let target: String = chars[close + 2..end].iter().collect();
+    // Drop an optional title: [label](url "title")
+    let url = target.split_whitespace().next()?.to_string();
+    Some((label, url, end + 1))
+}
+
+/// A `<scheme:...>` autolink starting at `start`: URL and end index.
+fn autolink(chars: &[char], start: usize) -> Option<(String, usize)> {
+    let end = start + 1 + chars[start + 1..].iter().position(|c| *c == '>')?;
+    let url: String = chars[start + 1..end].iter().collect();
+    let is_url = url.starts_with("http://") || url.starts_with("https://") || url.starts_with("xmpp:");
+    (is_url && !url.contains(char::is_whitespace)).then_some((url, end + 1))
+}
+
+/// Plain text for a link: the URL alone when the label repeats it.
+fn format_link(label: &str, url: &str) -> String {
+    let bare = url.trim_start_matches("mailto:");
+    if label.is_empty() || label == url || label == bare {
+        url.to_string()
+    } else {
+        format!("{label} ({url})")
+    }
+}
+
+// ── Tables ───────────────────────────────────────────────
+
+fn is_table_row(line: &str) -> bool {
+    line.contains('|') && !line.trim().is_empty()
+}
+
+/// `|---|:---:|` — cells of dashes with optional alignment colons.
+fn is_table_separator(line: &str) -> bool {
+    if !line.contains('|') {
+        return false;
+    }
+    let cells = split_row(line);
+    !cells.is_empty()
+        && cells.iter().all(|cell| {
+            let cell = cell.trim_matches(':');
+            !cell.is_empty() && cell.chars().all(|c| c == '-')
+        })
+}
+
+/// Cells of a table row, trimmed, without the outer pipes.
+fn split_row(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = line.strip_suffix('|').unwrap_or(line);
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'|') => {
+                cell.push('|');
+                chars.next();
+            }
+            '|' => cells.push(std::mem::take(&mut cell).trim().to_string()),
+            _ => cell.push(c),
+        }
+    }
+    cells.push(cell.trim().to_string());
+    cells
+}
+
+/// Renders a table (header row first): aligned columns in a preformatted
+/// block when narrow enough, otherwise one bullet line per row with the
+/// first cell as its label.
+fn render_table(rows: &[Vec<String>]) -> Vec<String> {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let cell = |row: &Vec<String>, col: usize| row.get(col).cloned().unwrap_or_default();
+
+    let plain: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| (0..columns).map(|col| convert_inline(&cell(row, col), false)).collect())
+        .collect();
+    let widths: Vec<usize> = (0..columns)
+        .map(|col| plain.iter().map(|row| row[col].chars().count()).max().unwrap_or(0))
+        .collect();
+    let total = widths.iter().sum::<usize>() + 2 * columns.saturating_sub(1);
+
+    if total <= MAX_TABLE_WIDTH {
+        let format_row = |row: &[String]| -> String {
+            let padded: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(text, width)| format!("{text}{}", " ".repeat(width - text.chars().count())))
+                .collect();
+            padded.join("  ").trim_end().to_string()
+        };
+        let rule: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+        let mut out = vec!["```".to_string(), format_row(&plain[0]), rule.join("  ")];
+        out.extend(plain[1..].iter().map(|row| format_row(row)));
+        out.push("```".to_string());
+        return out;
+    }
+
+    let headers = &plain[0];
+    rows[1..]
+        .iter()
+        .map(|row| {
+            let label = convert_inline(&cell(row, 0), true);
+            let fields: Vec<String> = (1..columns)
+                .filter(|col| !cell(row, *col).is_empty())
+                .map(|col| format!("{}: {}", headers[col], convert_inline(&cell(row, col), true)))
+                .collect();
+            match (label.is_empty(), fields.is_empty()) {
+                (_, true) => format!("• {label}"),
+                (true, false) => format!("• {}", fields.join(" · ")),
+                (false, false) => format!("• {label} — {}", fields.join(" · ")),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_inline_spans() {
+        let convert = |s: &str| convert_inline(s, true);
+        assert_eq!(convert("**bold** and *italic*"), "*bold* and _italic_");
+        assert_eq!(convert("__bold__ and _italic_"), "*bold* and _italic_");
+        assert_eq!(convert("***both*** ~~gone~~"), "*_both_* ~gone~");
+        assert_eq!(convert("**bold with _italic_ inside**"), "*bold with _italic_ inside*");
+        assert_eq!(convert("`a **b**` stays"), "`a **b**` stays");
+        assert_eq!(convert("``code with ` tick``"), "`code with ` tick`");
+        // Not emphasis: intraword, spaced or unclosed delimiters
+        assert_eq!(convert("snake_case_name"), "snake_case_name");
+        assert_eq!(convert("2*3*4 and 2 * 3"), "2*3*4 and 2 * 3");
+        assert_eq!(convert("a ** b ** c"), "a ** b ** c");
+        assert_eq!(convert("*unclosed"), "*unclosed");
+        // Escaped directives keep their backslash, so they are not styled
+        assert_eq!(convert(r"\*literal\*"), r"\*literal\*");
+        assert_eq!(convert(r"a \_b\_ and \# \[x\]"), r"a \_b\_ and # [x]");
+        assert_eq!(convert_inline(r"\*cell\*", false), "*cell*");
+    }
+
+    #[test]
+    fn test_links() {
+        let convert = |s: &str| convert_inline(s, true);
+        assert_eq!(
+            convert("See [the docs](https://example.com/docs \"Docs\")."),
+            "See the docs (https://example.com/docs)."
+        );
+        assert_eq!(convert("[https://a.b](https://a.b)"), "https://a.b");
+        assert_eq!(convert("<https://a.b/c>"), "https://a.b/c");
+        assert_eq!(convert("![diagram](https://a.b/d.png)"), "diagram (https://a.b/d.png)");
+        assert_eq!(convert("a < b and [not a link]"), "a < b and [not a link]");
+    }
+
+    #[test]
+    fn test_plain_text_is_unchanged() {
+        let text = "Hello! The answer is 42.\n\nAnything else?";
+        assert_eq!(markdown_to_styling(text), text);
+    }
+
+    /// Golden files: each `testdata/styling/{name}.md` is converted and
+    /// compared with `{name}.txt`. Run with `UPDATE_GOLDEN=1` to rewrite
+    /// the expected output after an intended change.
+    #[test]
+    fn test_golden_files() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/xmpp/testdata/styling");
+        let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+        let mut cases: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|e| e == "md"))
+            .collect();
+        cases.sort();
+        assert!(!cases.is_empty());
+
+        for input in cases {
+            let expected_path = input.with_extension("txt");
+            let actual = markdown_to_styling(&std::fs::read_to_string(&input).unwrap());
+            if update {
+                std::fs::write(&expected_path, format!("{actual}\n")).unwrap();
+                continue;
+            }
+            let expected = std::fs::read_to_string(&expected_path)
+                .unwrap_or_else(|_| panic!("missing {}", expected_path.display()));
+            assert_eq!(actual, expected.trim_end_matches('\n'), "{}", input.display());
+        }
+    }
+}
@@ -0,0 +1,479 @@
+/// Outbound file sharing via HTTP File Upload (XEP-0363).
+///
+/// To send a file, the agent:
+///
+/// 1. discovers the upload service — `disco#items` on the server, then
+///    `disco#info` on each item until one advertises `urn:xmpp:http:upload:0`
+///    (skipped when `[upload] service` is set; the result is cached),
+/// 2. requests an upload slot from the service (IQ `get`),
+/// 3. PUTs the bytes to the slot's PUT URL,
+/// 4. shares the GET URL in a message with `<x xmlns='jabber:x:oob'>`.
+///
+/// IQs go through the [`IqTracker`], so uploads must run in a spawned task,
+/// never on the runtime event loop (see `agent::runtime`).
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::Client;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use super::component::XmppCommand;
+use super::iq::IqTracker;
+use super::stanzas::{self, IqType, UploadSlot, NS_HTTP_UPLOAD};
+use crate::config::Config;
+use crate::skills::OutgoingFile;
+
+/// Upload timeout: 60 seconds
+const UPLOAD_TIMEOUT_SECS: u64 = 60;
+
+/// A discovered HTTP upload service
+#[derive(Debug, Clone, PartialEq)]
+pub struct UploadService {
+    /// JID of the service (e.g. "upload.localhost")
+    pub jid: String,
+    /// Maximum file size advertised by the service, in bytes
+    pub max_file_size: Option<u64>,
+}
+
+/// Uploads files and shares them with XMPP contacts.
+pub struct HttpUploader {
+    client: Client,
+    iq: Arc<IqTracker>,
+    /// Domain queried for the upload service
+    server_domain: String,
+    /// Configured service JID, if any (skips discovery)
+    configured_service: Option<String>,
+    /// Service found by discovery, cached until the next connection
+    service: Mutex<Option<UploadService>>,
+}
+
+impl HttpUploader {
+    /// Creates an uploader for the configured server.
+    ///
+    /// TLS verification follows the connection's `tls_verify` setting, as
+    /// for attachment downloads.
+    pub fn new(config: &Config, iq: Arc<IqTracker>) -> Self {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(UPLOAD_TIMEOUT_SECS))
+            .connect_timeout(Duration::from_secs(10))
+            .danger_accept_invalid_certs(!config.server.tls_verify())
+            .build()
+            .unwrap_or_else(|_| Client::new());
+
+        Self {
+            client,
+            iq,
+            server_domain: config.server.server_domain().to_string(),
+            configured_service: config.upload.service.clone(),
+            service: Mutex::new(None),
+        }
+    }
+
+    /// Forgets the discovered service (e.g. after a reconnection).
+    pub fn reset(&self) {
+        *self.service.lock().unwrap() = None;
+    }
+
+    /// Uploads a file and sends its link to `to` as an OOB message.
+    /// `msg_type` is `"chat"` for 1:1 or `"groupchat"` for MUC.
+    ///
+    /// Returns the GET URL of the uploaded file.
+    pub async fn send_file(
+        &self,
+        cmd_tx: &mpsc::Sender<XmppCommand>,
+        to: &str,
+        msg_type: &str,
+        file: &OutgoingFile,
+    ) -> Result<String> {
+        let url = self.upload(cmd_tx, file).await?;
+        cmd_tx
+            .send(XmppCommand::SendOobMessage {
+                to: to.to_string(),
+                url: url.clone(),
+                msg_type: msg_type.to_string(),
+                id: Some(uuid::Uuid::new_v4().to_string()),
+            })
+            .await
+            .map_err(|_| anyhow!("XMPP connection closed"))?;
+        info!("Sent file {} to {to}: {url}", file.filename);
+        Ok(url)
+    }
+
+    /// Uploads a file and returns its GET URL.
+    pub async fn upload(
+        &self,
+        cmd_tx: &mpsc::Sender<XmppCommand>,
+        file: &OutgoingFile,
+    ) -> Result<String> {
+        let service = self.service(cmd_tx).await?;
+        let size = file.data.len() as u64;
+        if let Some(max) = service.max_file_size {
+            if size > max {
+                bail!("File too large for the upload service: {size} bytes (max {max})");
+            }
+        }
+
+        let slot = self.request_slot(cmd_tx, &service, file).await?;
+        self.put(&slot, file).await?;
+        Ok(slot.get_url)
+    }
+
+    /// Returns the upload service, discovering it on first use.
+    pub async fn service(&self, cmd_tx: &mpsc::Sender<XmppCommand>) -> Result<UploadService> {
+        if let Some(service) = self.service.lock().unwrap().clone() {
+            return Ok(service);
+        }
+
+        let service = match &self.configured_service {
+            Some(jid) => match self.query_service(cmd_tx, jid).await {
+                Ok(Some(service)) => service,
+                // Configured explicitly: use it even if disco fails
+                Ok(None) | Err(_) => UploadService {
+                    jid: jid.clone(),
+                    max_file_size: None,
+                },
+            },
+            None => self.discover(cmd_tx).await?,
+        };
+        info!(
+            "HTTP upload service: {} (max file size: {})",
+            service.jid,
+            service
+                .max_file_size
+                .map(|m| format!("{m} bytes"))
+                .unwrap_or_else(|| "unknown".to_string())
+        );
+        *self.service.lock().unwrap() = Some(service.clone());
+        Ok(service)
+    }
+
+    /// Finds the upload service among the server's disco items.
+    async fn discover(&self, cmd_tx: &mpsc::Sender<XmppCommand>) -> Result<UploadService> {
+        // Some servers advertise the feature on their own domain
+        let mut candidates = vec![self.server_domain.clone()];
+        let items = self
+            .iq
+            .request(
+                cmd_tx,
+                &self.server_domain,
+                IqType::Get,
+                stanzas::disco_items_query(),
+            )
+            .await
+            .context("Service discovery failed")?;
+        if let Some(query) = &items.payload {
+            candidates.extend(stanzas::parse_disco_items(query));
+        }
+
+        for jid in candidates {
+            match self.query_service(cmd_tx, &jid).await {
+                Ok(Some(service)) => return Ok(service),
+                Ok(None) => {}
+                Err(e) => debug!("disco#info on {jid} failed: {e}"),
+            }
+        }
+        bail!(
+            "No HTTP upload service found on {} (set [upload] service to configure one)",
+            self.server_domain
+        )
+    }
+
+    /// Queries disco#info on `jid`; returns the service if it supports
+    /// HTTP File Upload.
+    async fn query_service(
+        &self,
+        cmd_tx: &mpsc::Sender<XmppCommand>,
+        jid: &str,
+    ) -> Result<Option<UploadService>> {
+        let info = self
+            .iq
+            .request(cmd_tx, jid, IqType::Get, stanzas::disco_info_query())
+            .await?;
+        let Some(query) = &info.payload else {
+            return Ok(None);
+        };
+        if !stanzas::parse_disco_features(query)
+            .iter()
+            .any(|f| f == NS_HTTP_UPLOAD)
+        {
+            return Ok(None);
+        }
+        let max_file_size = stanzas::disco_form_field(query, NS_HTTP_UPLOAD, "max-file-size")
+            .and_then(|v| v.parse().ok());
+        Ok(Some(UploadService {
+            jid: jid.to_string(),
+            max_file_size,
+        }))
+    }
+
+    /// Requests an upload slot for a file.
+    async fn request_slot(
+        &self,
+        cmd_tx: &mpsc::Sender<XmppCommand>,
+        service: &UploadService,
+        file: &OutgoingFile,
+    ) -> Result<UploadSlot> {
+        let request = stanzas::build_upload_slot_request(
+            &file.filename,
+            file.data.len() as u64,
+            &file.mime_type,
+        );
+        let result = self
+            .iq
+            .request(cmd_tx, &service.jid, IqType::Get, request)
+            .await
+            .context("Upload slot request failed")?;
+        result
+            .payload
+            .as_ref()
+            .and_then(stanzas::parse_upload_slot)
+            .ok_or_else(|| anyhow!("Invalid upload slot from {}", service.jid))
+    }
+
+    /// PUTs the file to the slot's upload URL.
+    pub async fn put(&self, slot: &UploadSlot, file: &OutgoingFile) -> Result<()> {
+        let parsed =
+            url::Url::parse(&slot.put_url).map_err(|e| anyhow!("Invalid upload URL: {e}"))?;
+        if !matches!(parsed.scheme(), "https" | "http") {
+            bail!("Unsupported upload URL scheme: {}://", parsed.scheme());
+        }
+        if parsed.scheme() == "http" {
+            warn!("Uploading {} over plain HTTP", file.filename);
+        }
+
+        let mut request = self
+            .client
+            .put(parsed)
+            .header("Content-Type", &file.mime_type)
+            .body(file.data.clone());
+        for (name, value) in &slot.put_headers {
+            request = request.header(name, value);
+        }
+
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            bail!("Upload failed: HTTP {}", response.status());
+        }
+        debug!("Uploaded {} ({} bytes)", file.filename, file.data.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xmpp::stanzas::{ChildElement, IncomingIq};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// A captured HTTP request: request line, headers, body.
+    type Captured = (String, Vec<String>, Vec<u8>);
+
+    /// Accepts one HTTP request on a local port, answers `201 Created`,
+    /// and returns the request through the join handle.
+    async fn http_server_once() -> (String, tokio::task::JoinHandle<Captured>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut data = Vec::new();
+            let mut chunk = [0u8; 4096];
+            let header_end = loop {
+                let n = socket.read(&mut chunk).await.unwrap();
+                data.extend_from_slice(&chunk[..n]);
+                if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break pos + 4;
+                }
+            };
+            let head = String::from_utf8_lossy(&data[..header_end]).to_string();
+            let mut lines = head.lines();
+            let request_line = lines.next().unwrap().to_string();
+            let headers: Vec<String> = lines.filter(|l| !l.is_empty()).map(String::from).collect();
+            let length: usize = headers
+                .iter()
+                .find_map(|h| {
+                    h.to_lowercase()
+                        .strip_prefix("content-length: ")
+                        .map(|v| v.parse().unwrap())
+                })
+                .unwrap_or(0);
+            while data.len() < header_end + length {
+                let n = socket.read(&mut chunk).await.unwrap();
+                data.extend_from_slice(&chunk[..n]);
+            }
+            socket
+                .write_all(
+                    b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            (
+                request_line,
+                headers,
+                data[header_end..header_end + length].to_vec(),
+            )
+        });
+        (base, handle)
+    }
+
+    fn parse_payload(xml: &str) -> ChildElement {
+        let wrapped = format!("<iq type='result' id='x'>{xml}</iq>");
+        let mut reader = quick_xml::Reader::from_str(&wrapped);
+        let mut parser = stanzas::StanzaParser::new();
+        loop {
+            let event = reader.read_event().unwrap();
+            if let Some(stanzas::XmppStanza::Iq(iq)) = parser.feed(event) {
+                return iq.payload.unwrap();
+            }
+        }
+    }
+
+    /// Plays the server side: answers the uploader's IQs like a server
+    /// with an upload component at `upload.localhost`.
+    async fn fake_server(
+        iq: Arc<IqTracker>,
+        mut cmd_rx: mpsc::Receiver<XmppCommand>,
+        put_url: String,
+    ) -> Vec<XmppCommand> {
+        let mut others = Vec::new();
+        while let Some(cmd) = cmd_rx.recv().await {
+            let XmppCommand::SendIq {
+                to, id, payload, ..
+            } = cmd
+            else {
+                others.push(cmd);
+                continue;
+            };
+            let response = if payload.contains("disco#items") {
+                "<query xmlns='http://jabber.org/protocol/disco#items'>\
+                 <item jid='conference.localhost'/><item jid='upload.localhost'/></query>"
+                    .to_string()
+            } else if payload.contains("disco#info") && to == "upload.localhost" {
+                "<query xmlns='http://jabber.org/protocol/disco#info'>\
+                 <identity category='store' type='file'/>\
+                 <feature var='urn:xmpp:http:upload:0'/>\
+                 <x type='result' xmlns='jabber:x:data'>\
+                 <field var='FORM_TYPE' type='hidden'><value>urn:xmpp:http:upload:0</value></field>\
+                 <field var='max-file-size'><value>1024</value></field></x></query>"
+                    .to_string()
+            } else if payload.contains("disco#info") {
+                "<query xmlns='http://jabber.org/protocol/disco#info'>\
+                 <feature var='http://jabber.org/protocol/muc'/></query>"
+                    .to_string()
+            } else {
+                assert!(payload.contains("filename='report.csv'"));
+                assert!(payload.contains("size='10'"));
+                format!(
+                    "<slot xmlns='urn:xmpp:http:upload:0'>\
+                     <put url='{put_url}'><header name='Authorization'>Basic token</header>\
+                     <header name='Host'>evil</header></put>\
+                     <get url='https://upload.localhost/abc/report.csv'/></slot>"
+                )
+            };
+            let resolved = iq.resolve(IncomingIq {
+                from: Some(to),
+                to: "bot@localhost/agent".to_string(),
+                id,
+                iq_type: IqType::Result,
+                payload: Some(parse_payload(&response)),
+                error: None,
+            });
+            assert!(resolved.is_none());
+        }
+        others
+    }
+
+    fn test_config() -> Config {
+        let toml = r#"
+            [server]
+            host = "localhost"
+            port = 5222
+            mode = "client"
+            jid = "bot@localhost"
+            password = "secret"
+
+            [llm]
+            provider = "anthropic"
+            model = "claude-sonnet-4-5-20250929"
+            api_key = "test"
+            max_tokens_per_request = 1024
+
+            [agent]
+            name = "Test"
+            allowed_jids = []
+
+            [memory]
+            path = "/tmp/fluux-test"
+        "#;
+        toml::from_str(toml).unwrap()
+    }
+
+    fn csv_file() -> OutgoingFile {
+        OutgoingFile {
+            filename: "report.csv".to_string(),
+            mime_type: "text/csv".to_string(),
+            data: b"a,b\n1,2\n3\n".to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_file_discovers_uploads_and_shares() {
+        let (base, http) = http_server_once().await;
+        let iq = Arc::new(IqTracker::new());
+        let uploader = HttpUploader::new(&test_config(), Arc::clone(&iq));
+        let (cmd_tx, cmd_rx) = mpsc::channel(8);
+        let server = tokio::spawn(fake_server(iq, cmd_rx, format!("{base}/put/report.csv")));
+
+        let url = uploader
+            .send_file(&cmd_tx, "user@localhost", "chat", &csv_file())
+            .await
+            .unwrap();
+        assert_eq!(url, "https://upload.localhost/abc/report.csv");
+        assert_eq!(
+            uploader.service.lock().unwrap().clone(),
+            Some(UploadService {
+                jid: "upload.localhost".to_string(),
+                max_file_size: Some(1024),
+            })
+        );
+
+        // The bytes were PUT with the slot's allowed headers only
+        let (request_line, headers, body) = http.await.unwrap();
+        assert_eq!(request_line, "PUT /put/report.csv HTTP/1.1");
+        assert!(headers
+            .iter()
+            .any(|h| h.eq_ignore_ascii_case("authorization: Basic token")));
+        assert!(headers
+            .iter()
+            .any(|h| h.eq_ignore_ascii_case("content-type: text/csv")));
+        assert!(!headers.iter().any(|h| h.eq_ignore_ascii_case("host: evil")));
+        assert_eq!(body, b"a,b\n1,2\n3\n");
+
+        // ...and the link shared as an OOB message
+        drop(cmd_tx);
+        let others = server.await.unwrap();
+        assert!(matches!(
+            &others[..],
+            [XmppCommand::SendOobMessage { to, url, msg_type, .. }]
+                if to == "user@localhost" && url.ends_with("/report.csv") && msg_type == "chat"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_upload_rejects_file_over_service_limit() {
+        let iq = Arc::new(IqTracker::new());
+        let uploader = HttpUploader::new(&test_config(), iq);
+        *uploader.service.lock().unwrap() = Some(UploadService {
+            jid: "upload.localhost".to_string(),
+            max_file_size: Some(4),
+        });
+        let (cmd_tx, _cmd_rx) = mpsc::channel(8);
+
+        let err = uploader.upload(&cmd_tx, &csv_file()).await.unwrap_err();
+        assert!(err.to_string().contains("File too large"));
+
+        uploader.reset();
+        assert!(uploader.service.lock().unwrap().is_none());
+    }
+}